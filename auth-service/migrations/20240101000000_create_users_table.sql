@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS users (
+    email TEXT NOT NULL PRIMARY KEY,
+    password TEXT NOT NULL,
+    requires_2fa BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,2 @@
+-- Passwords are now stored as Argon2id PHC strings
+ALTER TABLE users RENAME COLUMN password TO password_hash;
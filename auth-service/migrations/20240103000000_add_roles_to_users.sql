@@ -0,0 +1,2 @@
+-- Roles are copied into the `roles` claim of every JWT issued to the user
+ALTER TABLE users ADD COLUMN roles TEXT[] NOT NULL DEFAULT ARRAY['user'];
@@ -0,0 +1,4 @@
+-- Accounts created before verification existed stay usable, new ones start unverified
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ALTER COLUMN email_verified SET DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN verification_email_sent_at TIMESTAMPTZ;
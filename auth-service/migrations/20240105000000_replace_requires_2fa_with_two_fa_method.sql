@@ -0,0 +1,7 @@
+ALTER TABLE users ADD COLUMN two_fa_method TEXT NOT NULL DEFAULT 'none'
+    CHECK (two_fa_method IN ('none', 'email', 'totp'));
+UPDATE users SET two_fa_method = 'email' WHERE requires_2fa;
+ALTER TABLE users DROP COLUMN requires_2fa;
+
+-- Base32 TOTP secret, set while enrolling an authenticator app
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
@@ -0,0 +1,6 @@
+-- SHA-256 hashes of each user's unused 2FA recovery codes. Used codes are deleted.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE ON UPDATE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (email, code_hash)
+);
@@ -0,0 +1,2 @@
+-- JWTs carry the version they were issued under; bumping it rejects every older token
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- JWT signing keys added or retired at runtime, loaded into the keyring at startup.
+-- The private key is a PKCS#8 PEM.
+CREATE TABLE IF NOT EXISTS signing_keys (
+    kid TEXT PRIMARY KEY,
+    private_key_pem TEXT NOT NULL,
+    not_before TIMESTAMPTZ NOT NULL,
+    retire_after TIMESTAMPTZ
+);
@@ -0,0 +1,2 @@
+-- The time step of the last TOTP code accepted, so the same code can't be used twice
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
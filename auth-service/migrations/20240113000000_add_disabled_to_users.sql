@@ -0,0 +1,2 @@
+-- Disabled accounts can't log in or refresh their sessions until an admin enables them again
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- A stable id for every user, as their email address can change. Whatever is kept
+-- about a user outside this table is keyed by it.
+ALTER TABLE users ADD COLUMN id UUID NOT NULL DEFAULT gen_random_uuid();
+ALTER TABLE users ADD CONSTRAINT users_id_key UNIQUE (id);
+
+-- Addresses given up in an email change, held for their old owner while the change
+-- can still be undone, so nobody else can sign up with them in the meantime
+CREATE TABLE IF NOT EXISTS email_reservations (
+    email TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reserved_until TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Credentials belong to the user rather than to their current address
+ALTER TABLE webauthn_credentials ADD COLUMN user_id UUID REFERENCES users (id) ON DELETE CASCADE;
+UPDATE webauthn_credentials SET user_id = users.id FROM users WHERE users.email = webauthn_credentials.email;
+ALTER TABLE webauthn_credentials ALTER COLUMN user_id SET NOT NULL;
+
+DROP INDEX IF EXISTS webauthn_credentials_email_idx;
+ALTER TABLE webauthn_credentials DROP COLUMN email;
+CREATE INDEX IF NOT EXISTS webauthn_credentials_user_id_idx ON webauthn_credentials (user_id);
@@ -1,7 +1,13 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::domain::{BannedTokenStore, EmailClient, TwoFACodeStore, UserStore};
+use crate::{
+    domain::{
+        BannedTokenStore, CredentialStore, EmailClient, EmailOutboxStore, OneTimeTokenStore, RateLimitStore, RefreshTokenStore,
+        SessionStore, SigningKeyStore, TwoFACodeStore, UserStore,
+    },
+    utils::{email_templates::EmailTemplates, keyring::JwtKeyring, metrics::Gauges, rate_limit::RateLimitConfig},
+};
 
 
 // Using a type alias to improve readability!
@@ -9,6 +15,16 @@ pub type UserStoreType = Arc<RwLock<dyn UserStore  + Send + Sync>>;
 pub type BannedTokenStoreType = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
 pub type TwoFACodeStoreType = Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>; 
 pub type EmailClientType = Arc<RwLock<dyn EmailClient + Send + Sync>>;
+pub type EmailOutboxStoreType = Arc<RwLock<dyn EmailOutboxStore + Send + Sync>>;
+pub type RefreshTokenStoreType = Arc<RwLock<dyn RefreshTokenStore + Send + Sync>>;
+pub type OneTimeTokenStoreType = Arc<RwLock<dyn OneTimeTokenStore + Send + Sync>>;
+pub type RateLimitStoreType = Arc<RwLock<dyn RateLimitStore + Send + Sync>>;
+pub type CredentialStoreType = Arc<RwLock<dyn CredentialStore + Send + Sync>>;
+pub type SessionStoreType = Arc<RwLock<dyn SessionStore + Send + Sync>>;
+pub type SigningKeyStoreType = Arc<RwLock<dyn SigningKeyStore + Send + Sync>>;
+pub type JwtKeyringType = Arc<RwLock<JwtKeyring>>;
+pub type EmailTemplatesType = Arc<EmailTemplates>;
+pub type GaugesType = Arc<Gauges>;
 
 
 #[derive(Clone)]
@@ -17,10 +33,23 @@ pub struct AppState {
     pub banned_token_store: BannedTokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
     pub email_client: EmailClientType,
+    pub email_outbox: EmailOutboxStoreType,
+    pub refresh_token_store: RefreshTokenStoreType,
+    pub one_time_token_store: OneTimeTokenStoreType,
+    pub jwt_keyring: JwtKeyringType,
+    pub signing_key_store: SigningKeyStoreType,
+    pub admin_api_key: Option<String>,
+    pub rate_limit_store: RateLimitStoreType,
+    pub rate_limits: RateLimitConfig,
+    pub email_templates: EmailTemplatesType,
+    pub credential_store: CredentialStoreType,
+    pub session_store: SessionStoreType,
+    pub gauges: GaugesType,
 }
 
 impl AppState {
-    pub fn new(user_store:UserStoreType, banned_token_store : BannedTokenStoreType, two_fa_code_store : TwoFACodeStoreType, email_client: EmailClientType,) -> Self {
-        Self { user_store, banned_token_store, two_fa_code_store, email_client }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(user_store:UserStoreType, banned_token_store : BannedTokenStoreType, two_fa_code_store : TwoFACodeStoreType, email_client: EmailClientType, email_outbox: EmailOutboxStoreType, refresh_token_store: RefreshTokenStoreType, one_time_token_store: OneTimeTokenStoreType, jwt_keyring: JwtKeyringType, signing_key_store: SigningKeyStoreType, admin_api_key: Option<String>, rate_limit_store: RateLimitStoreType, rate_limits: RateLimitConfig, email_templates: EmailTemplatesType, credential_store: CredentialStoreType, session_store: SessionStoreType, gauges: GaugesType) -> Self {
+        Self { user_store, banned_token_store, two_fa_code_store, email_client, email_outbox, refresh_token_store, one_time_token_store, jwt_keyring, signing_key_store, admin_api_key, rate_limit_store, rate_limits, email_templates, credential_store, session_store, gauges }
     }
 }
\ No newline at end of file
@@ -1,15 +1,66 @@
+use chrono::{DateTime, Utc};
 use rand::Rng;
+use ring::digest;
 use uuid::Uuid;
 
-use crate::domain::{Email, Password};
+use crate::{
+    domain::{Email, UserId},
+    utils::auth::REFRESH_TOKEN_TTL_SECONDS,
+};
 
-use super::User;
+use super::{HashedPassword, TwoFactorMethod, User};
 
 #[async_trait::async_trait]
 pub trait UserStore {
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError>;
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError>;
-    async fn validate_user(&self, email: &Email, password: &Password) -> Result<(), UserStoreError>;
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError>;
+    async fn update_password(&mut self, email: &Email, password: HashedPassword) -> Result<(), UserStoreError>;
+    // Swaps an outdated hash of the same password for `password`, unless the hash is no longer
+    // `current` because the password was changed in the meantime
+    async fn upgrade_password_hash(
+        &mut self,
+        email: &Email,
+        current: &HashedPassword,
+        password: HashedPassword,
+    ) -> Result<(), UserStoreError>;
+    async fn mark_email_verified(&mut self, email: &Email) -> Result<(), UserStoreError>;
+    async fn set_verification_email_sent_at(
+        &mut self,
+        email: &Email,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError>;
+    async fn set_totp_secret(&mut self, email: &Email, secret: Option<String>) -> Result<(), UserStoreError>;
+    async fn set_two_fa_method(&mut self, email: &Email, method: TwoFactorMethod) -> Result<(), UserStoreError>;
+    // Recovery codes are only ever handed to the store as `RecoveryCode::hash` values
+    async fn replace_recovery_codes(&mut self, email: &Email, code_hashes: Vec<String>) -> Result<(), UserStoreError>;
+    // Fails with `InvalidRecoveryCode` unless the code exists and hasn't been used yet
+    async fn consume_recovery_code(&mut self, email: &Email, code_hash: &str) -> Result<(), UserStoreError>;
+    async fn count_recovery_codes(&self, email: &Email) -> Result<usize, UserStoreError>;
+    // Returns the number of wrong passwords in a row, including this one
+    async fn record_failed_login(&mut self, email: &Email) -> Result<u32, UserStoreError>;
+    // Sets `locked_until`, counts the lockout and starts counting failed logins from zero again
+    async fn lock_account(&mut self, email: &Email, locked_until: DateTime<Utc>) -> Result<(), UserStoreError>;
+    // Forgets failed logins and lockouts, lifting any current lock
+    async fn reset_failed_logins(&mut self, email: &Email) -> Result<(), UserStoreError>;
+    // Records the time step of an accepted TOTP code. Fails with `TotpCodeReused` unless it's
+    // later than the last step recorded, so a code can't be replayed (RFC 6238, section 5.2).
+    async fn record_totp_step(&mut self, email: &Email, step: u64) -> Result<(), UserStoreError>;
+    // Increments `token_version`, rejecting every JWT issued before, and returns the new version
+    async fn bump_token_version(&mut self, email: &Email) -> Result<u32, UserStoreError>;
+    async fn set_disabled(&mut self, email: &Email, disabled: bool) -> Result<(), UserStoreError>;
+    // Moves the user to `new_email` in one step and marks it verified. The address they
+    // leave is kept for them until `reserve_old_until`, if given, so nobody else can sign up
+    // with it while its owner can still undo the change. Fails with `UserAlreadyExists` if
+    // another account has `new_email` or has it reserved.
+    async fn change_email(
+        &mut self,
+        id: &UserId,
+        new_email: &Email,
+        reserve_old_until: Option<DateTime<Utc>>,
+    ) -> Result<(), UserStoreError>;
+    // Deletes the user along with their recovery codes and any addresses kept for them
+    async fn delete_user(&mut self, id: &UserId) -> Result<(), UserStoreError>;
 }
 
 #[derive(Debug, PartialEq)]
@@ -17,6 +68,8 @@ pub enum UserStoreError {
     UserAlreadyExists,
     UserNotFound,
     InvalidCredentials,
+    InvalidRecoveryCode,
+    TotpCodeReused,
     UnexpectedError,
 }
 
@@ -25,10 +78,16 @@ pub enum BannedTokenStoreError {
    UnexpectedError
 }
 
+// Tokens are banned by their `jti`, or all tokens of a session by its `sid`. An
+// entry only needs to live until the token expires, after which signature validation rejects the token anyway.
 #[async_trait::async_trait]
 pub trait BannedTokenStore {
-    async fn add_token(&mut self, token: String) -> Result<(), BannedTokenStoreError>;
-    async fn is_banned_token(&self, token: &str) -> Result<bool, BannedTokenStoreError>;
+    // Banning a token again succeeds, and keeps whichever expiry is later
+    async fn add_token(&mut self, jti: String, expires_at: DateTime<Utc>) -> Result<(), BannedTokenStoreError>;
+    async fn is_banned_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError>;
+    // Drop entries for tokens that have expired, returning how many were removed
+    async fn prune_expired(&mut self) -> Result<usize, BannedTokenStoreError>;
+    async fn count(&self) -> Result<usize, BannedTokenStoreError>;
 }
 
 // This trait represents the interface all concrete 2FA code stores should implement
@@ -36,20 +95,25 @@ pub trait BannedTokenStore {
 pub trait TwoFACodeStore {
     async fn add_code(
         &mut self,
-        email: Email,
+        user_id: UserId,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
+    async fn remove_code(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError>;
+    // Fails with `CodeExpired` or `TooManyAttempts` once a code can no longer be used
     async fn get_code(
         &self,
-        email: &Email,
+        user_id: &UserId,
     ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError>;
+    // Counts a wrong guess against the pending code of `user_id`
+    async fn record_failed_attempt(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError>;
 }
 
 #[derive(Debug, PartialEq)]
 pub enum TwoFACodeStoreError {
     LoginAttemptIdNotFound,
+    CodeExpired,
+    TooManyAttempts,
     UnexpectedError,
 }
 
@@ -112,4 +176,502 @@ impl AsRef<str> for TwoFACode {
     fn as_ref(&self) -> &str {
         &self.0
     }
-}
\ No newline at end of file
+}
+
+// Single-use code that stands in for a 2FA code when the user can't get one,
+// e.g. after losing the phone with their authenticator app
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecoveryCode(String);
+
+impl RecoveryCode {
+    pub fn parse(code: String) -> Result<Self, String> {
+        // Codes are shown in dashed groups, but accept them typed any which way
+        let code: String = code
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if code.len() != 20 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Invalid recovery code".to_owned());
+        }
+        Ok(Self(code))
+    }
+
+    // With 80 bits of randomness a code can't be brute forced from its hash, so a
+    // plain SHA-256 is enough and lets stores look codes up by hash directly
+    pub fn hash(&self) -> String {
+        digest::digest(&digest::SHA256, self.0.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+impl Default for RecoveryCode {
+    fn default() -> Self {
+        let bytes: [u8; 10] = rand::thread_rng().gen();
+        Self(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+// Shown to the user as four groups of five, e.g. 3f9a1-0c2e7-b4d58-91e6a
+impl std::fmt::Display for RecoveryCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let groups: Vec<&str> = (0..self.0.len())
+            .step_by(5)
+            .map(|start| &self.0[start..start + 5])
+            .collect();
+        write!(f, "{}", groups.join("-"))
+    }
+}
+
+// This trait represents the interface all concrete refresh token stores should implement
+#[async_trait::async_trait]
+pub trait RefreshTokenStore {
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        record: RefreshTokenRecord,
+    ) -> Result<(), RefreshTokenStoreError>;
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenRecord, RefreshTokenStoreError>;
+    async fn mark_token_used(&mut self, token: &RefreshToken) -> Result<(), RefreshTokenStoreError>;
+    // Removes every token that descends from the same login
+    async fn revoke_family(&mut self, family_id: &str) -> Result<(), RefreshTokenStoreError>;
+    // Removes every token issued to `user_id`, ending all of the user's sessions
+    async fn revoke_user_tokens(&mut self, user_id: &UserId) -> Result<(), RefreshTokenStoreError>;
+    // Drop tokens that have expired, returning how many were removed
+    async fn prune_expired(&mut self) -> Result<usize, RefreshTokenStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RefreshTokenStoreError {
+    TokenNotFound,
+    UnexpectedError,
+}
+
+// Server-side state for a refresh token. Tokens issued by rotating an earlier
+// token share its `family_id`, which lets us revoke the whole chain on reuse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefreshTokenRecord {
+    pub user_id: UserId,
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub used: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RefreshToken(String);
+
+impl RefreshToken {
+    pub fn parse(token: String) -> Result<Self, String> {
+        // Ensure `token` looks like one we generated: 64 hex characters
+        if token.len() != 64 || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Invalid refresh token".to_owned());
+        }
+        Ok(Self(token))
+    }
+}
+
+impl Default for RefreshToken {
+    fn default() -> Self {
+        // 256 bits of randomness, hex encoded
+        let bytes: [u8; 32] = rand::thread_rng().gen();
+        Self(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+impl AsRef<str> for RefreshToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Stores short-lived tokens that are emailed to a user to prove they own the
+// address, e.g. password reset links, or handed out as WebAuthn challenges. Each
+// token can only be consumed once.
+#[async_trait::async_trait]
+pub trait OneTimeTokenStore {
+    async fn add_token(
+        &mut self,
+        token: OneTimeToken,
+        record: OneTimeTokenRecord,
+    ) -> Result<(), OneTimeTokenStoreError>;
+    // Removes the token and returns its record. Fails with `TokenExpired` once the token
+    // has expired, unless the store has already dropped it, and with `TokenNotFound` if
+    // it was issued for a different purpose.
+    async fn consume_token(
+        &mut self,
+        token: &OneTimeToken,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<OneTimeTokenRecord, OneTimeTokenStoreError>;
+    // Invalidates every outstanding token issued to `email` for `purpose`
+    async fn revoke_tokens(
+        &mut self,
+        email: &Email,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<(), OneTimeTokenStoreError>;
+    // Invalidates every outstanding confirm and cancel token of `user_id`'s email changes,
+    // whichever address they were sent to
+    async fn revoke_email_changes(&mut self, user_id: &UserId) -> Result<(), OneTimeTokenStoreError>;
+    // Drop tokens that have expired, returning how many were removed
+    async fn prune_expired(&mut self) -> Result<usize, OneTimeTokenStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OneTimeTokenStoreError {
+    TokenNotFound,
+    TokenExpired,
+    UnexpectedError,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OneTimeTokenPurpose {
+    PasswordReset,
+    AccountUnlock,
+    MagicLinkLogin,
+    WebauthnRegistration,
+    WebauthnLogin,
+    // Sent to the new address to confirm an email change
+    EmailChange,
+    // Sent to the old address to call an email change off, or undo it
+    EmailChangeCancel,
+}
+
+impl OneTimeTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OneTimeTokenPurpose::PasswordReset => "password_reset",
+            OneTimeTokenPurpose::AccountUnlock => "account_unlock",
+            OneTimeTokenPurpose::MagicLinkLogin => "magic_link_login",
+            OneTimeTokenPurpose::WebauthnRegistration => "webauthn_registration",
+            OneTimeTokenPurpose::WebauthnLogin => "webauthn_login",
+            OneTimeTokenPurpose::EmailChange => "email_change",
+            OneTimeTokenPurpose::EmailChangeCancel => "email_change_cancel",
+        }
+    }
+
+    pub fn parse(purpose: &str) -> Result<OneTimeTokenPurpose, String> {
+        match purpose {
+            "password_reset" => Ok(OneTimeTokenPurpose::PasswordReset),
+            "account_unlock" => Ok(OneTimeTokenPurpose::AccountUnlock),
+            "magic_link_login" => Ok(OneTimeTokenPurpose::MagicLinkLogin),
+            "webauthn_registration" => Ok(OneTimeTokenPurpose::WebauthnRegistration),
+            "webauthn_login" => Ok(OneTimeTokenPurpose::WebauthnLogin),
+            "email_change" => Ok(OneTimeTokenPurpose::EmailChange),
+            "email_change_cancel" => Ok(OneTimeTokenPurpose::EmailChangeCancel),
+            _ => Err(format!("Unknown one-time token purpose: {}", purpose)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OneTimeTokenRecord {
+    pub email: Email,
+    pub purpose: OneTimeTokenPurpose,
+    pub expires_at: DateTime<Utc>,
+    // Set for the tokens of an email change, `None` for every other purpose
+    pub email_change: Option<EmailChange>,
+}
+
+// The account an email change applies to, found by its id as its address may have
+// changed again by the time a link is followed, and the address it moves to
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmailChange {
+    pub user_id: UserId,
+    pub new_email: Email,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OneTimeToken(String);
+
+impl OneTimeToken {
+    pub fn parse(token: String) -> Result<Self, String> {
+        // Ensure `token` looks like one we generated: 64 hex characters
+        if token.len() != 64 || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Invalid one-time token".to_owned());
+        }
+        Ok(Self(token))
+    }
+}
+
+impl Default for OneTimeToken {
+    fn default() -> Self {
+        // 256 bits of randomness, hex encoded
+        let bytes: [u8; 32] = rand::thread_rng().gen();
+        Self(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+impl AsRef<str> for OneTimeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Token buckets, one per key. A bucket holds up to `limit.capacity` tokens, every
+// request takes one, and spent tokens come back at `limit` requests per period.
+#[async_trait::async_trait]
+pub trait RateLimitStore {
+    // Fails with `RateLimited` when the bucket for `key` is empty
+    async fn take_token(&mut self, key: &str, limit: &RateLimit)
+        -> Result<(), RateLimitStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitStoreError {
+    // How long until the bucket has a token again
+    RateLimited { retry_after: std::time::Duration },
+    UnexpectedError,
+}
+
+// `capacity` requests per `period`, which is also the largest burst allowed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: std::time::Duration,
+}
+
+impl RateLimit {
+    // Parses "<requests>/<seconds>", e.g. "10/60" for ten requests a minute
+    pub fn parse(limit: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid rate limit {:?}, expected <requests>/<seconds>", limit);
+
+        let (capacity, seconds) = limit.split_once('/').ok_or_else(invalid)?;
+        let capacity: u32 = capacity.trim().parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+        if capacity == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            capacity,
+            period: std::time::Duration::from_secs(seconds),
+        })
+    }
+
+    // Time it takes for a single spent token to come back
+    pub fn refill_interval(&self) -> std::time::Duration {
+        self.period / self.capacity
+    }
+}
+
+// Emails waiting for the outbox worker to deliver them. Messages are claimed before
+// each delivery attempt and are either removed once sent or rescheduled after a
+// failure. Those that keep failing are dead-lettered until an admin replays them.
+#[async_trait::async_trait]
+pub trait EmailOutboxStore {
+    async fn enqueue(&mut self, message: OutboxMessage) -> Result<(), EmailOutboxStoreError>;
+    // Returns up to `limit` queued messages due by `now`, oldest first, and pushes their
+    // next attempt back by `lease` so no other worker sends them at the same time
+    async fn claim_due(
+        &mut self,
+        now: DateTime<Utc>,
+        lease: chrono::Duration,
+        limit: u32,
+    ) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError>;
+    async fn mark_sent(&mut self, id: &Uuid) -> Result<(), EmailOutboxStoreError>;
+    // Counts a failed attempt. The message is tried again at `retry_at`, or
+    // dead-lettered when that is `None`.
+    async fn record_failure(
+        &mut self,
+        id: &Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError>;
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError>;
+    // Messages waiting to be sent, leaving out dead letters
+    async fn count_queued(&self) -> Result<usize, EmailOutboxStoreError>;
+    async fn count_dead_letters(&self) -> Result<usize, EmailOutboxStoreError>;
+    // Queues a dead-lettered message again, with a fresh attempt count
+    async fn replay(&mut self, id: &Uuid, now: DateTime<Utc>) -> Result<(), EmailOutboxStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EmailOutboxStoreError {
+    MessageNotFound,
+    UnexpectedError,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub recipient: Email,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub dead_lettered_at: Option<DateTime<Utc>>,
+}
+
+impl OutboxMessage {
+    // A message due right away
+    pub fn new(recipient: Email, subject: String, text: String, html: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            recipient,
+            subject,
+            text,
+            html,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            next_attempt_at: now,
+            dead_lettered_at: None,
+        }
+    }
+}
+
+// Passkeys and security keys registered through WebAuthn
+#[async_trait::async_trait]
+pub trait CredentialStore {
+    // Fails with `CredentialAlreadyExists` if the credential ID is taken, by any user
+    async fn add_credential(&mut self, credential: WebauthnCredential) -> Result<(), CredentialStoreError>;
+    async fn get_credential(&self, id: &[u8]) -> Result<WebauthnCredential, CredentialStoreError>;
+    // Every credential of `user_id`, oldest first
+    async fn get_credentials(&self, user_id: &UserId) -> Result<Vec<WebauthnCredential>, CredentialStoreError>;
+    async fn update_sign_count(&mut self, id: &[u8], sign_count: u32) -> Result<(), CredentialStoreError>;
+    async fn remove_credentials(&mut self, user_id: &UserId) -> Result<(), CredentialStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CredentialStoreError {
+    CredentialNotFound,
+    CredentialAlreadyExists,
+    UnexpectedError,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebauthnCredential {
+    // Chosen by the authenticator, up to 1023 bytes
+    pub id: Vec<u8>,
+    pub user_id: UserId,
+    // Uncompressed P-256 point: 0x04, then the x and y coordinates
+    pub public_key: Vec<u8>,
+    // The authenticator's signature counter at the last login, 0 if it doesn't keep one
+    pub sign_count: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+// One record per login. A session's ID doubles as the family ID of its refresh
+// tokens and is carried in the `sid` claim of its JWTs.
+#[async_trait::async_trait]
+pub trait SessionStore {
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError>;
+    async fn get_session(&self, id: &Uuid) -> Result<Session, SessionStoreError>;
+    // Every session of `user_id`, oldest first
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError>;
+    async fn touch_session(&mut self, id: &Uuid, last_seen_at: DateTime<Utc>) -> Result<(), SessionStoreError>;
+    async fn remove_session(&mut self, id: &Uuid) -> Result<(), SessionStoreError>;
+    // Removes and returns every session of `user_id`
+    async fn remove_user_sessions(&mut self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError>;
+    // Drop sessions that have expired, returning how many were removed
+    async fn prune_expired(&mut self) -> Result<usize, SessionStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SessionStoreError {
+    SessionNotFound,
+    UnexpectedError,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub created_at: DateTime<Utc>,
+    // Updated whenever the session's tokens are refreshed
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl Session {
+    // A session can't be refreshed once the refresh token from its last refresh has expired
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.last_seen_at + chrono::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS)
+    }
+}
+
+// Every JWT signing key and its validity window, so keys added or retired at runtime
+// survive a restart. Keys are never deleted, only retired.
+#[async_trait::async_trait]
+pub trait SigningKeyStore {
+    // Fails with `KeyAlreadyExists` if a key with the same `kid` is stored
+    async fn add_key(&mut self, key: SigningKeyRecord) -> Result<(), SigningKeyStoreError>;
+    async fn retire_key(&mut self, kid: &str, retire_after: DateTime<Utc>) -> Result<(), SigningKeyStoreError>;
+    // Every stored key, oldest `not_before` first
+    async fn get_keys(&self) -> Result<Vec<SigningKeyRecord>, SigningKeyStoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SigningKeyStoreError {
+    KeyNotFound,
+    KeyAlreadyExists,
+    UnexpectedError,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SigningKeyRecord {
+    pub kid: String,
+    // PKCS#8 PEM of the Ed25519 private key
+    pub private_key_pem: String,
+    pub not_before: DateTime<Utc>,
+    pub retire_after: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_code_parse() {
+        let code = RecoveryCode::default();
+        let shown = code.to_string();
+        assert_eq!(shown.len(), 23);
+
+        assert_eq!(RecoveryCode::parse(shown.clone()), Ok(code.clone()));
+        assert_eq!(RecoveryCode::parse(shown.to_uppercase().replace('-', " ")), Ok(code.clone()));
+        assert_eq!(RecoveryCode::parse(shown.replace('-', "")).unwrap().hash(), code.hash());
+
+        assert!(RecoveryCode::parse("123456".to_owned()).is_err());
+        assert!(RecoveryCode::parse("zzzzz-zzzzz-zzzzz-zzzzz".to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_parse() {
+        let limit = RateLimit::parse("10/60").unwrap();
+        assert_eq!(limit.capacity, 10);
+        assert_eq!(limit.period, std::time::Duration::from_secs(60));
+        assert_eq!(limit.refill_interval(), std::time::Duration::from_secs(6));
+
+        assert!(RateLimit::parse("10").is_err());
+        assert!(RateLimit::parse("0/60").is_err());
+        assert!(RateLimit::parse("10/0").is_err());
+        assert!(RateLimit::parse("ten/60").is_err());
+    }
+
+    #[test]
+    fn test_one_time_token_purpose_parse() {
+        for purpose in [
+            OneTimeTokenPurpose::PasswordReset,
+            OneTimeTokenPurpose::AccountUnlock,
+            OneTimeTokenPurpose::MagicLinkLogin,
+            OneTimeTokenPurpose::WebauthnRegistration,
+            OneTimeTokenPurpose::WebauthnLogin,
+            OneTimeTokenPurpose::EmailChange,
+            OneTimeTokenPurpose::EmailChangeCancel,
+        ] {
+            assert_eq!(OneTimeTokenPurpose::parse(purpose.as_str()), Ok(purpose));
+        }
+        assert!(OneTimeTokenPurpose::parse("unknown").is_err());
+    }
+}
@@ -9,4 +9,16 @@ pub trait EmailClient {
         subject: &str,
         content: &str,
     ) -> Result<(), String>;
+
+    // Sends both bodies as alternatives of one message, so mail clients can pick
+    // the one they display. Clients without HTML support only send the text.
+    async fn send_html_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        text_content: &str,
+        _html_content: &str,
+    ) -> Result<(), String> {
+        self.send_email(recipient, subject, text_content).await
+    }
 }
\ No newline at end of file
@@ -5,5 +5,15 @@ pub enum AuthAPIError {
     BadRequest,
     IncorrectCredentials,
     MissingToken,
-    InvalidToken
+    InvalidToken,
+    TwoFACodeExpired,
+    TooManyTwoFAAttempts,
+    InvalidAdminKey,
+    EmailNotVerified,
+    TotpAlreadyEnabled,
+    CredentialAlreadyRegistered,
+    SessionNotFound,
+    TooManyRequests { retry_after_seconds: u64 },
+    AccountLocked { retry_after_seconds: u64 },
+    AccountDisabled,
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+
+use tokio::sync::OnceCell;
+
+use super::Password;
+
+// Argon2id cost parameters used for every new hash. Raising these makes stored
+// hashes report `needs_rehash`, so they are upgraded the next time the user logs in.
+const ARGON2_MEMORY_COST_KIB: u32 = 15000;
+const ARGON2_TIME_COST: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+// Stores a password as an Argon2id PHC string, never the raw value
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashedPassword(String);
+
+impl HashedPassword {
+    // Hash a raw password. Argon2 is CPU-bound, so it runs on the blocking thread pool.
+    pub async fn parse(password: Password) -> Result<Self, String> {
+        tokio::task::spawn_blocking(move || compute_password_hash(&password, current_params()))
+            .await
+            .map_err(|e| e.to_string())?
+            .map(Self)
+    }
+
+    // Wrap a hash that was previously produced by `parse`, e.g. when loading a user from storage
+    pub fn parse_password_hash(hash: String) -> Result<Self, String> {
+        PasswordHash::new(&hash).map_err(|e| e.to_string())?;
+        Ok(Self(hash))
+    }
+
+    pub async fn verify_raw_password(&self, password_candidate: &Password) -> Result<(), String> {
+        let password_hash = self.0.clone();
+        let password_candidate = password_candidate.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let expected_password_hash =
+                PasswordHash::new(&password_hash).map_err(|e| e.to_string())?;
+
+            // The PHC string carries its own algorithm and parameters, so older hashes still verify
+            Argon2::default()
+                .verify_password(password_candidate.as_ref().as_bytes(), &expected_password_hash)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    // Checks `password_candidate` against a hash of a password nobody has, with today's parameters.
+    // Used when there is no stored hash, so that case takes as long as a wrong password.
+    pub async fn verify_dummy_password(password_candidate: &Password) {
+        static DUMMY_HASH: OnceCell<HashedPassword> = OnceCell::const_new();
+
+        let dummy_hash = DUMMY_HASH
+            .get_or_try_init(|| async {
+                let password = Password::parse(&uuid::Uuid::new_v4().to_string())?;
+                HashedPassword::parse(password).await
+            })
+            .await;
+        if let Ok(dummy_hash) = dummy_hash {
+            let _ = dummy_hash.verify_raw_password(password_candidate).await;
+        }
+    }
+
+    // True when the hash was produced with a different algorithm or weaker parameters than we use today
+    pub fn needs_rehash(&self) -> bool {
+        let Ok(hash) = PasswordHash::new(&self.0) else {
+            return true;
+        };
+
+        if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+
+        match Params::try_from(&hash) {
+            Ok(params) => {
+                let current = current_params();
+                params.m_cost() != current.m_cost()
+                    || params.t_cost() != current.t_cost()
+                    || params.p_cost() != current.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+impl AsRef<str> for HashedPassword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn current_params() -> Params {
+    Params::new(ARGON2_MEMORY_COST_KIB, ARGON2_TIME_COST, ARGON2_PARALLELISM, None)
+        .expect("Argon2 parameters are valid")
+}
+
+fn compute_password_hash(password: &Password, params: Params) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_ref().as_bytes(), &salt)
+        .map_err(|e| e.to_string())?
+        .to_string();
+
+    Ok(password_hash)
+}
+
+#[cfg(test)]
+pub(crate) fn hash_with_weak_params(password: &Password) -> HashedPassword {
+    HashedPassword(compute_password_hash(password, Params::new(1024, 1, 1, None).unwrap()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_produces_argon2id_phc_string() {
+        let password = Password::parse("password123").unwrap();
+        let hashed_password = HashedPassword::parse(password.clone()).await.unwrap();
+
+        assert_ne!(hashed_password.as_ref(), password.as_ref());
+        assert!(hashed_password.as_ref().starts_with("$argon2id$v=19$"));
+        assert!(!hashed_password.needs_rehash());
+    }
+
+    #[tokio::test]
+    async fn test_verify_raw_password() {
+        let password = Password::parse("password123").unwrap();
+        let hashed_password = HashedPassword::parse(password.clone()).await.unwrap();
+
+        assert!(hashed_password.verify_raw_password(&password).await.is_ok());
+        assert!(hashed_password
+            .verify_raw_password(&Password::parse("wrong_password").unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_password_hash() {
+        let password = Password::parse("password123").unwrap();
+        let hashed_password = HashedPassword::parse(password.clone()).await.unwrap();
+
+        let restored = HashedPassword::parse_password_hash(hashed_password.as_ref().to_owned()).unwrap();
+        assert_eq!(restored, hashed_password);
+        assert!(restored.verify_raw_password(&password).await.is_ok());
+
+        assert!(HashedPassword::parse_password_hash("password123".to_owned()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_weaker_params_need_rehash() {
+        let password = Password::parse("password123").unwrap();
+        let hashed_password = hash_with_weak_params(&password);
+
+        assert!(hashed_password.needs_rehash());
+        assert!(hashed_password.verify_raw_password(&password).await.is_ok());
+    }
+}
@@ -1,9 +1,11 @@
 mod user;
+mod hashed_password;
 mod data_stores;
 mod errors;
 pub mod email_client;
 
 pub use user::*;
+pub use hashed_password::*;
 pub use data_stores::*;
 pub use errors::*;
 pub use email_client::*;
\ No newline at end of file
@@ -1,7 +1,48 @@
-use std::ops::Deref;
-
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use validator::{validate_email, validate_length};
 
+use super::HashedPassword;
+use crate::utils::constants::{ACCOUNT_LOCKOUT_BASE_SECONDS, ACCOUNT_LOCKOUT_MAX_SECONDS};
+
+// Identifies a user for good, while their email address can change. Everything
+// stored about a user outside the user store is keyed by it.
+#[derive(PartialEq, Debug, Clone, Copy, Eq, Hash)]
+pub struct UserId(Uuid);
+
+impl UserId {
+    pub fn parse(id: &str) -> Result<UserId, String> {
+        Uuid::parse_str(id)
+            .map(UserId)
+            .map_err(|_| String::from("Invalid user id"))
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for UserId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl AsRef<Uuid> for UserId {
+    fn as_ref(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, Eq, Hash)]
 pub struct Email(String);
 
@@ -49,22 +90,111 @@ mod tests {
             Ok(Email(String::from("hello@gmail.com")))
         );
     }
+
+    #[test]
+    fn test_next_lockout_duration() {
+        let password = HashedPassword::parse_password_hash(
+            "$argon2id$v=19$m=15000,t=2,p=1$c2FsdHNhbHQ$YjM3ZTQ5NjFhZGJlOTE2Mw".to_owned(),
+        )
+        .unwrap();
+        let mut user = User::new(Email::parse("hello@gmail.com").unwrap(), password, TwoFactorMethod::None);
+
+        assert_eq!(user.next_lockout_duration(), Duration::seconds(ACCOUNT_LOCKOUT_BASE_SECONDS));
+        user.lockout_count = 1;
+        assert_eq!(user.next_lockout_duration(), Duration::seconds(2 * ACCOUNT_LOCKOUT_BASE_SECONDS));
+        user.lockout_count = 2;
+        assert_eq!(user.next_lockout_duration(), Duration::seconds(4 * ACCOUNT_LOCKOUT_BASE_SECONDS));
+        user.lockout_count = 100;
+        assert_eq!(user.next_lockout_duration(), Duration::seconds(ACCOUNT_LOCKOUT_MAX_SECONDS));
+    }
 }
-// The User struct should contain 3 fields. email, which is a String;
-// password, which is also a String; and requires_2fa, which is a boolean.
+// Role given to every account on signup
+pub const DEFAULT_USER_ROLE: &str = "user";
+
+// The second factor a user has to present after their password
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TwoFactorMethod {
+    None,
+    // A code emailed on every login
+    Email,
+    // A code from an authenticator app, see `User::totp_secret`
+    Totp,
+}
+
+impl TwoFactorMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TwoFactorMethod::None => "none",
+            TwoFactorMethod::Email => "email",
+            TwoFactorMethod::Totp => "totp",
+        }
+    }
+
+    pub fn parse(method: &str) -> Result<TwoFactorMethod, String> {
+        match method {
+            "none" => Ok(TwoFactorMethod::None),
+            "email" => Ok(TwoFactorMethod::Email),
+            "totp" => Ok(TwoFactorMethod::Totp),
+            _ => Err(format!("Unknown 2FA method: {}", method)),
+        }
+    }
+}
+
+// The User struct contains the user's id, which never changes; email; password, which is an Argon2 hash of the user's password;
+// two_fa_method; and roles, which end up in the `roles` claim of the user's JWTs.
+// New accounts can't log in until email_verified is set through the link we email them.
 #[derive(Clone, Debug, PartialEq)]
 pub struct User {
+    pub id: UserId,
     pub email: Email,
-    pub password: Password,
-    pub requires_2fa: bool,
+    pub password: HashedPassword,
+    pub two_fa_method: TwoFactorMethod,
+    // Base32 TOTP secret. Set when enrollment starts, but only used once it's
+    // confirmed and two_fa_method is switched to `Totp`.
+    pub totp_secret: Option<String>,
+    pub roles: Vec<String>,
+    pub email_verified: bool,
+    // When the last verification email went out, used to throttle resends
+    pub verification_email_sent_at: Option<DateTime<Utc>>,
+    // Wrong passwords since the last successful login or lockout
+    pub failed_login_attempts: u32,
+    // Lockouts since the last successful login, each one lasting longer than the one before
+    pub lockout_count: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+    // Embedded in the user's JWTs, which are only accepted while it matches
+    pub token_version: u32,
+    // The time step of the last TOTP code accepted, as no code may be used twice
+    pub totp_last_step: Option<u64>,
+    // Set by an admin. A disabled account can't log in or refresh its tokens.
+    pub disabled: bool,
 }
 
 impl User {
-    pub fn new(email: Email, password: Password, requires_2fa: bool) -> Self {
+    pub fn new(email: Email, password: HashedPassword, two_fa_method: TwoFactorMethod) -> Self {
         Self {
+            id: UserId::default(),
             email,
             password,
-            requires_2fa,
+            two_fa_method,
+            totp_secret: None,
+            roles: vec![DEFAULT_USER_ROLE.to_owned()],
+            email_verified: false,
+            verification_email_sent_at: None,
+            failed_login_attempts: 0,
+            lockout_count: 0,
+            locked_until: None,
+            token_version: 0,
+            totp_last_step: None,
+            disabled: false,
         }
     }
+
+    // Doubles with every lockout, up to ACCOUNT_LOCKOUT_MAX_SECONDS
+    pub fn next_lockout_duration(&self) -> Duration {
+        let seconds = ACCOUNT_LOCKOUT_BASE_SECONDS
+            .saturating_mul(1 << self.lockout_count.min(32))
+            .min(ACCOUNT_LOCKOUT_MAX_SECONDS);
+        Duration::seconds(seconds)
+    }
 }
@@ -1,19 +1,32 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use axum::{
-    http::{Method, StatusCode},
+    extract::connect_info::{ConnectInfo, IntoMakeServiceWithConnectInfo},
+    http::{header::RETRY_AFTER, Method, StatusCode},
+    middleware::{self, AddExtension},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{delete, get, post},
     serve::Serve,
     Json, Router,
 };
 use tower_http::{cors::CorsLayer, services::ServeDir};
 
 use serde::{Deserialize, Serialize};
+use redis::aio::ConnectionManager;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 
-use crate::routes::{login, logout, signup, verify_2fa, verify_token};
+use crate::routes::{
+    add_signing_key, cancel_email_change, disable_user, enable_user, change_password, confirm_email_change,
+    confirm_password_reset, confirm_totp, delete_account, enroll_totp, finish_webauthn_login,
+    finish_webauthn_registration, jwks, list_dead_letters, list_sessions, list_signing_keys, login,
+    logout, metrics, refresh, regenerate_recovery_codes, replay_dead_letter, request_email_change,
+    request_magic_link, request_password_reset, resend_verification_email, retire_signing_key,
+    revoke_all_sessions, revoke_session, signup, start_webauthn_login, start_webauthn_registration,
+    unlock_account, verify_2fa, verify_email, verify_magic_link, verify_token,
+};
 use app_state::AppState;
 use domain::AuthAPIError;
+use utils::rate_limit::rate_limit;
 
 pub mod routes;
 pub mod services;
@@ -28,6 +41,11 @@ pub struct ErrorResponse {
 
 impl IntoResponse for AuthAPIError {
     fn into_response(self) -> Response {
+        let retry_after_seconds = match self {
+            AuthAPIError::TooManyRequests { retry_after_seconds }
+            | AuthAPIError::AccountLocked { retry_after_seconds } => Some(retry_after_seconds),
+            _ => None,
+        };
         let (status, error_message) = match self {
             AuthAPIError::UserAlreadyExists => (StatusCode::CONFLICT, "User already exists"),
             AuthAPIError::InvalidCredentials => (StatusCode::BAD_REQUEST, "Invalid credentials"),
@@ -35,6 +53,25 @@ impl IntoResponse for AuthAPIError {
             AuthAPIError::IncorrectCredentials => (StatusCode::UNAUTHORIZED, "Incorrect credentials"),
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token used"),
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token"),
+            AuthAPIError::TwoFACodeExpired => (StatusCode::UNAUTHORIZED, "2FA code expired"),
+            AuthAPIError::TooManyTwoFAAttempts => {
+                (StatusCode::FORBIDDEN, "Too many incorrect 2FA attempts")
+            }
+            AuthAPIError::InvalidAdminKey => (StatusCode::UNAUTHORIZED, "Invalid admin key"),
+            AuthAPIError::EmailNotVerified => (StatusCode::FORBIDDEN, "Email address not verified"),
+            AuthAPIError::TotpAlreadyEnabled => (StatusCode::CONFLICT, "TOTP is already enabled"),
+            AuthAPIError::CredentialAlreadyRegistered => {
+                (StatusCode::CONFLICT, "This passkey is already registered")
+            }
+            AuthAPIError::SessionNotFound => (StatusCode::NOT_FOUND, "Session not found"),
+            AuthAPIError::TooManyRequests { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many requests, try again later")
+            }
+            AuthAPIError::AccountLocked { .. } => (
+                StatusCode::LOCKED,
+                "Account locked after too many failed logins, check your email to unlock it",
+            ),
+            AuthAPIError::AccountDisabled => (StatusCode::FORBIDDEN, "Account disabled"),
             AuthAPIError::UnexpectedError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
             }
@@ -42,13 +79,19 @@ impl IntoResponse for AuthAPIError {
         let body = Json(ErrorResponse {
             error: error_message.to_string(),
         });
-        (status, body).into_response()
+        match retry_after_seconds {
+            Some(seconds) => (status, [(RETRY_AFTER, seconds.to_string())], body).into_response(),
+            None => (status, body).into_response(),
+        }
     }
 }
 
 // This struct encapsulates our application-related logic.
 pub struct Application {
-    server: Serve<Router, Router>,
+    server: Serve<
+        IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+        AddExtension<Router, ConnectInfo<SocketAddr>>,
+    >,
     // address is exposed as a public field
     // so we have access to it in tests.
     pub address: String,
@@ -64,24 +107,66 @@ impl Application {
         ];
 
         let cors = CorsLayer::new()
-            // Allow GET and POST requests
-            .allow_methods([Method::GET, Method::POST])
+            // Allow GET, POST and DELETE requests
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
             // Allow cookies to be included in requests
             .allow_credentials(true)
             .allow_origin(allowed_origins);
+
+        // Routes that check a password or a code, or email a sign-in link, throttled per
+        // client IP and per email
+        let rate_limited = Router::new()
+            .route("/signup", post(signup))
+            .route("/login", post(login))
+            .route("/login/magic-link", post(request_magic_link))
+            .route("/login/magic-link/verify", get(verify_magic_link))
+            .route("/verify-2fa", post(verify_2fa))
+            .route("/webauthn/login/start", post(start_webauthn_login))
+            .route("/webauthn/login/finish", post(finish_webauthn_login))
+            .route("/account/password", post(change_password))
+            .route("/account", delete(delete_account))
+            .route("/account/email", post(request_email_change))
+            .route("/verify-email/resend", post(resend_verification_email))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit));
+
         let router = Router::new()
             .nest_service("/", ServeDir::new("assets"))
             
-            .route("/signup", post(signup))
-            .route("/login", post(login))
+            .merge(rate_limited)
             .route("/logout", post(logout))
-            .route("/verify-2fa", post(verify_2fa))
             .route("/verify-token", post(verify_token))
-            .with_state(app_state);
+            .route("/verify-email", get(verify_email))
+            .route("/unlock-account", get(unlock_account))
+            .route("/account/email/confirm", get(confirm_email_change))
+            .route("/account/email/cancel", get(cancel_email_change))
+            .route("/refresh", post(refresh))
+            .route("/sessions", get(list_sessions).delete(revoke_all_sessions))
+            .route("/sessions/:id", delete(revoke_session))
+            .route("/2fa/totp/enroll", post(enroll_totp))
+            .route("/2fa/totp/confirm", post(confirm_totp))
+            .route("/2fa/recovery-codes", post(regenerate_recovery_codes))
+            .route("/webauthn/register/start", post(start_webauthn_registration))
+            .route("/webauthn/register/finish", post(finish_webauthn_registration))
+            .route("/password-reset/request", post(request_password_reset))
+            .route("/password-reset/confirm", post(confirm_password_reset))
+            .route("/.well-known/jwks.json", get(jwks))
+            .route("/metrics", get(metrics))
+            .route("/admin/signing-keys", get(list_signing_keys).post(add_signing_key))
+            .route("/admin/signing-keys/retire", post(retire_signing_key))
+            .route("/admin/email-outbox/dead-letters", get(list_dead_letters))
+            .route("/admin/email-outbox/replay", post(replay_dead_letter))
+            .route("/admin/users/disable", post(disable_user))
+            .route("/admin/users/enable", post(enable_user))
+            .with_state(app_state)
+            .layer(cors);
 
         let listener = tokio::net::TcpListener::bind(address).await?;
         let address = listener.local_addr()?.to_string();
-        let server = axum::serve(listener, router);
+        // Rate limiting needs the client's address
+        let server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
         Ok(Self {server, address})
     }
@@ -90,4 +175,20 @@ impl Application {
         println!("listening on {}", &self.address);
         self.server.await
     }
+}
+
+pub async fn get_postgres_pool(url: &str) -> Result<PgPool, sqlx::Error> {
+    // Create a new PostgreSQL connection pool
+    PgPoolOptions::new().max_connections(5).connect(url).await
+}
+
+// The connection manager reconnects on its own and is cheap to clone, so stores can share it
+pub async fn get_redis_connection(redis_hostname: &str) -> redis::RedisResult<ConnectionManager> {
+    let redis_url = format!("redis://{}/", redis_hostname);
+    redis::Client::open(redis_url)?.get_connection_manager().await
+}
+
+// Apply any pending schema migrations from the `migrations` directory
+pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!().run(pool).await
 }
\ No newline at end of file
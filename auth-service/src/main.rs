@@ -1,36 +1,109 @@
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-};
+use std::{sync::Arc, time::Duration};
 
 use auth_service::{
-    Application, app_state::AppState, services::{
-        hashmap_two_fa_code_store::HashmapTwoFACodeStore, hashmap_user_store::HashmapUserStore,
-        hashset_banned_token_store::HashsetBannedTokenStore, mock_email_client::MockEmailClient,
-    }, utils::constants::prod
+    Application, app_state::{
+        AppState, BannedTokenStoreType, EmailClientType, EmailOutboxStoreType, OneTimeTokenStoreType, RateLimitStoreType,
+        RefreshTokenStoreType, SessionStoreType, SigningKeyStoreType, TwoFACodeStoreType,
+    },
+    get_postgres_pool, get_redis_connection, run_migrations, services::{
+        hashmap_one_time_token_store::HashmapOneTimeTokenStore,
+        hashmap_refresh_token_store::HashmapRefreshTokenStore,
+        hashmap_session_store::HashmapSessionStore,
+        hashmap_rate_limit_store::HashmapRateLimitStore,
+        hashmap_two_fa_code_store::HashmapTwoFACodeStore,
+        hashmap_banned_token_store::HashmapBannedTokenStore, maildir_email_client::MaildirEmailClient,
+        mock_email_client::MockEmailClient,
+        postgres_credential_store::PostgresCredentialStore, postgres_signing_key_store::PostgresSigningKeyStore,
+        postgres_email_outbox_store::PostgresEmailOutboxStore, postgres_user_store::PostgresUserStore, redis_banned_token_store::RedisBannedTokenStore,
+        redis_one_time_token_store::RedisOneTimeTokenStore, redis_rate_limit_store::RedisRateLimitStore,
+        redis_refresh_token_store::RedisRefreshTokenStore, redis_session_store::RedisSessionStore,
+        redis_two_fa_code_store::RedisTwoFACodeStore,
+        smtp_email_client::SmtpEmailClient,
+    }, utils::{
+        email_outbox_worker::spawn_email_outbox_worker,
+        constants::{
+            prod, ADMIN_API_KEY, DATABASE_URL,
+            EMAIL_MAILDIR, EMAIL_OUTBOX_POLL_INTERVAL_MILLIS, EMAIL_TEMPLATES_DIR, JWT_SIGNING_KEY,
+            KEYRING_RELOAD_INTERVAL_SECONDS, METRICS_REFRESH_INTERVAL_SECONDS, REDIS_HOST_NAME,
+            SMTP_CONFIG, SWEEP_INTERVAL_SECONDS,
+        },
+        email_templates::EmailTemplates,
+        keyring::{spawn_keyring_reloader, JwtKeyring},
+        metrics::{spawn_gauge_refresher, Gauges},
+        rate_limit::RateLimitConfig,
+        signing_key::JwtSigningKey,
+        sweeper::{spawn_sweeper, SweptStore},
+    }
 };
-use axum::response::Html;
+use redis::aio::ConnectionManager;
+use sqlx::PgPool;
 use tokio::sync::RwLock;
-use tower_http::services::ServeDir;
 
 #[tokio::main]
 async fn main() {
-    let user_store = Arc::new(RwLock::new(HashmapUserStore {
-        users: HashMap::new(),
-    }));
-    let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore {
-        tokens: HashSet::new(),
-    }));
-    let two_fa_code_store = Arc::new(RwLock::new(HashmapTwoFACodeStore {
-        codes: HashMap::new(),
-    }));
-    let email_client =  Arc::new(RwLock::new(MockEmailClient));
+    let pg_pool = configure_postgresql().await;
+
+    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool.clone())));
+    let credential_store = Arc::new(RwLock::new(PostgresCredentialStore::new(pg_pool.clone())));
+    let mut signing_key_store = PostgresSigningKeyStore::new(pg_pool.clone());
+    // Queued emails survive restarts, and every replica's worker takes from the same queue
+    let email_outbox: EmailOutboxStoreType =
+        Arc::new(RwLock::new(PostgresEmailOutboxStore::new(pg_pool)));
+    let redis_conn = configure_redis().await;
+    let (banned_token_store, two_fa_code_store, rate_limit_store) =
+        configure_ephemeral_stores(redis_conn.clone());
+    let (refresh_token_store, one_time_token_store, session_store) = configure_session_stores(redis_conn);
+    let email_client = configure_email_client();
+    spawn_email_outbox_worker(
+        email_outbox.clone(),
+        email_client.clone(),
+        Duration::from_millis(EMAIL_OUTBOX_POLL_INTERVAL_MILLIS),
+    );
+    let gauges = Arc::new(Gauges::default());
+    spawn_gauge_refresher(
+        gauges.clone(),
+        banned_token_store.clone(),
+        email_outbox.clone(),
+        Duration::from_secs(METRICS_REFRESH_INTERVAL_SECONDS),
+    );
+    let email_templates = Arc::new(
+        EmailTemplates::load(EMAIL_TEMPLATES_DIR.as_str()).expect("Failed to load email templates"),
+    );
+    // Further keys can be added and old ones retired at runtime through the admin routes.
+    // Those changes are stored, so the keyring is built from the store on every start and
+    // reloaded from it regularly to pick up changes made through other replicas.
+    let jwt_keyring = Arc::new(RwLock::new(
+        JwtKeyring::load(
+            JwtSigningKey::from_pem(&JWT_SIGNING_KEY).expect("Failed to load JWT signing key"),
+            &mut signing_key_store,
+        )
+        .await
+        .expect("Failed to load the JWT keyring"),
+    ));
+    let signing_key_store: SigningKeyStoreType = Arc::new(RwLock::new(signing_key_store));
+    spawn_keyring_reloader(
+        jwt_keyring.clone(),
+        signing_key_store.clone(),
+        Duration::from_secs(KEYRING_RELOAD_INTERVAL_SECONDS),
+    );
 
     let app_state = AppState {
-        user_store: user_store,
-        banned_token_store: banned_token_store,
+        user_store,
+        banned_token_store,
         two_fa_code_store,
-        email_client
+        email_client,
+        email_outbox,
+        refresh_token_store,
+        one_time_token_store,
+        jwt_keyring,
+        signing_key_store,
+        admin_api_key: ADMIN_API_KEY.clone(),
+        rate_limit_store,
+        rate_limits: RateLimitConfig::default(),
+        email_templates,
+        credential_store,
+        session_store,
+        gauges,
     };
 
     let app = Application::build(app_state, prod::APP_ADDRESS)
@@ -40,7 +113,105 @@ async fn main() {
     app.run().await.expect("Failed to run app");
 }
 
-async fn hello_handler() -> Html<&'static str> {
-    // TODO: Update this to a custom message!
-    Html("<h1>Hello, World!</h1>")
+// Emails go out over SMTP when SMTP_HOST is set. Otherwise they are written to
+// EMAIL_MAILDIR if that is set, and only printed if neither is.
+fn configure_email_client() -> EmailClientType {
+    if let Some(config) = SMTP_CONFIG.clone() {
+        return Arc::new(RwLock::new(
+            SmtpEmailClient::new(config).expect("Failed to configure SMTP email client"),
+        ));
+    }
+    match EMAIL_MAILDIR.as_deref() {
+        Some(dir) => Arc::new(RwLock::new(
+            MaildirEmailClient::new(dir).expect("Failed to create the Maildir"),
+        )),
+        None => Arc::new(RwLock::new(MockEmailClient)),
+    }
+}
+
+async fn configure_postgresql() -> PgPool {
+    // Create a new database connection pool
+    let pg_pool = get_postgres_pool(&DATABASE_URL)
+        .await
+        .expect("Failed to create Postgres connection pool!");
+
+    // Run database migrations against our database on startup
+    run_migrations(&pg_pool)
+        .await
+        .expect("Failed to run migrations");
+
+    pg_pool
+}
+
+async fn configure_redis() -> Option<ConnectionManager> {
+    let redis_host_name = REDIS_HOST_NAME.as_deref()?;
+    Some(
+        get_redis_connection(redis_host_name)
+            .await
+            .expect("Failed to connect to Redis"),
+    )
+}
+
+// Banned tokens, pending 2FA codes and rate limit buckets go to Redis when
+// REDIS_HOST_NAME is set, so they are shared between replicas. Otherwise they are
+// kept in memory.
+fn configure_ephemeral_stores(
+    redis_conn: Option<ConnectionManager>,
+) -> (BannedTokenStoreType, TwoFACodeStoreType, RateLimitStoreType) {
+    match redis_conn {
+        Some(conn) => (
+            Arc::new(RwLock::new(RedisBannedTokenStore::new(conn.clone()))),
+            Arc::new(RwLock::new(RedisTwoFACodeStore::new(conn.clone()))),
+            Arc::new(RwLock::new(RedisRateLimitStore::new(conn))),
+        ),
+        None => {
+            let banned_token_store: BannedTokenStoreType =
+                Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+
+            // Redis expires entries by itself; the in-memory store needs sweeping
+            spawn_sweeper(
+                vec![SweptStore::BannedTokens(banned_token_store.clone())],
+                Duration::from_secs(SWEEP_INTERVAL_SECONDS),
+            );
+
+            (
+                banned_token_store,
+                Arc::new(RwLock::new(HashmapTwoFACodeStore::default())),
+                Arc::new(RwLock::new(HashmapRateLimitStore::default())),
+            )
+        }
+    }
+}
+
+// Refresh tokens, one-time tokens and sessions go to Redis as well when REDIS_HOST_NAME
+// is set, so logins survive a restart and work on every replica. The in-memory stores
+// are swept for expired entries instead.
+fn configure_session_stores(
+    redis_conn: Option<ConnectionManager>,
+) -> (RefreshTokenStoreType, OneTimeTokenStoreType, SessionStoreType) {
+    match redis_conn {
+        Some(conn) => (
+            Arc::new(RwLock::new(RedisRefreshTokenStore::new(conn.clone()))),
+            Arc::new(RwLock::new(RedisOneTimeTokenStore::new(conn.clone()))),
+            Arc::new(RwLock::new(RedisSessionStore::new(conn))),
+        ),
+        None => {
+            let refresh_token_store: RefreshTokenStoreType =
+                Arc::new(RwLock::new(HashmapRefreshTokenStore::default()));
+            let one_time_token_store: OneTimeTokenStoreType =
+                Arc::new(RwLock::new(HashmapOneTimeTokenStore::default()));
+            let session_store: SessionStoreType = Arc::new(RwLock::new(HashmapSessionStore::default()));
+
+            spawn_sweeper(
+                vec![
+                    SweptStore::RefreshTokens(refresh_token_store.clone()),
+                    SweptStore::OneTimeTokens(one_time_token_store.clone()),
+                    SweptStore::Sessions(session_store.clone()),
+                ],
+                Duration::from_secs(SWEEP_INTERVAL_SECONDS),
+            );
+
+            (refresh_token_store, one_time_token_store, session_store)
+        }
+    }
 }
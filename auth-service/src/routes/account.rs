@@ -0,0 +1,441 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Email, EmailChange, HashedPassword, OneTimeToken, OneTimeTokenPurpose,
+        OneTimeTokenRecord, Password, UserId, UserStore, UserStoreError,
+    },
+    utils::{
+        auth::{
+            end_other_sessions, end_user_sessions, generate_auth_cookie, validate_user,
+            AuthenticatedUser, SessionClient,
+        },
+        constants::{
+            AUTH_SERVICE_URL, EMAIL_CHANGE_CANCEL_TOKEN_TTL_SECONDS, EMAIL_CHANGE_TOKEN_TTL_SECONDS,
+            JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME,
+        },
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChangePasswordResponse {
+    pub message: String,
+}
+
+// Changing the password logs out every other session and rejects every earlier JWT.
+// The current session stays logged in with a new JWT.
+pub async fn change_password(
+    jar: CookieJar,
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    client: SessionClient,
+    locale: Locale,
+    Json(request): Json<ChangePasswordRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    // A password that doesn't pass validation can't be the current one
+    let current_password = match Password::parse(&request.current_password) {
+        Ok(password) => password,
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    };
+    let new_password = match Password::parse(&request.new_password) {
+        Ok(password) => password,
+        Err(_) => return (jar, Err(AuthAPIError::BadRequest)),
+    };
+
+    // A stolen session alone mustn't be enough to take the account over
+    match validate_user(&user.email, &current_password, &state.user_store).await {
+        Ok(_) => {}
+        Err(UserStoreError::InvalidCredentials) => {
+            return (jar, Err(AuthAPIError::IncorrectCredentials))
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+
+    let new_password = match HashedPassword::parse(new_password).await {
+        Ok(password) => password,
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+
+    let mut user_store = state.user_store.write().await;
+    if user_store
+        .update_password(&user.email, new_password)
+        .await
+        .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    if user_store.bump_token_version(&user.email).await.is_err() {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    let updated_user = match user_store.get_user(&user.email).await {
+        Ok(user) => user,
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+    drop(user_store);
+
+    if let Err(e) = end_other_sessions(&user.id, &user.claims.sid, &state).await {
+        return (jar, Err(e));
+    }
+
+    // The bump rejected this session's JWT as well, so hand it one with the new version
+    let auth_cookie =
+        match generate_auth_cookie(&updated_user, &user.claims.sid, &*state.jwt_keyring.read().await) {
+            Ok(cookie) => cookie,
+            Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+        };
+
+    let context = context! {
+        changed_at => Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+        ip_address => client.ip_address.unwrap_or_else(|| "unknown".to_owned()),
+        user_agent => client.user_agent.unwrap_or_else(|| "unknown".to_owned()),
+    };
+    // The password has changed either way, so a failed send only gets logged
+    if let Err(e) =
+        queue_templated_email(&state, &user.email, EmailTemplate::PasswordChanged, &locale, context).await
+    {
+        eprintln!("Failed to send password changed email: {}", e);
+    }
+
+    (
+        jar.add(auth_cookie),
+        Ok(Json(ChangePasswordResponse {
+            message: "Password has been changed".to_owned(),
+        })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeleteAccountResponse {
+    pub message: String,
+}
+
+// Deletes the account for good and logs out every one of its sessions. Like other
+// changes that can't be undone, it needs the password as well as a session.
+pub async fn delete_account(
+    jar: CookieJar,
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    locale: Locale,
+    Json(request): Json<DeleteAccountRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let password = match Password::parse(&request.password) {
+        Ok(password) => password,
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    };
+
+    match validate_user(&user.email, &password, &state.user_store).await {
+        Ok(_) => {}
+        Err(UserStoreError::InvalidCredentials) => {
+            return (jar, Err(AuthAPIError::IncorrectCredentials))
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+
+    // Once the user is gone, none of their JWTs pass validation anymore
+    if state.user_store.write().await.delete_user(&user.id).await.is_err() {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+
+    if let Err(e) = end_user_sessions(&user.id, &state).await {
+        return (jar, Err(e));
+    }
+    if state
+        .credential_store
+        .write()
+        .await
+        .remove_credentials(&user.id)
+        .await
+        .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    if state
+        .one_time_token_store
+        .write()
+        .await
+        .revoke_email_changes(&user.id)
+        .await
+        .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    // There is only a code if a login was halfway through 2FA
+    let _ = state.two_fa_code_store.write().await.remove_code(&user.id).await;
+
+    let context = context! {
+        deleted_at => Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+    };
+    // The account is gone either way, so a failed send only gets logged
+    if let Err(e) =
+        queue_templated_email(&state, &user.email, EmailTemplate::AccountDeleted, &locale, context).await
+    {
+        eprintln!("Failed to send account deleted email: {}", e);
+    }
+
+    (
+        jar.remove(JWT_COOKIE_NAME).remove(REFRESH_TOKEN_COOKIE_NAME),
+        Ok(Json(DeleteAccountResponse {
+            message: "Account has been deleted".to_owned(),
+        })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub new_email: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChangeEmailResponse {
+    pub message: String,
+}
+
+// Starts an email change. Nothing changes until the link sent to the new address is
+// followed, and the old address is told about it with a link to call the change off.
+pub async fn request_email_change(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    locale: Locale,
+    Json(request): Json<ChangeEmailRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let password = Password::parse(&request.password).map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    let new_email = Email::parse(&request.new_email).map_err(|_| AuthAPIError::BadRequest)?;
+    if new_email == user.email {
+        return Err(AuthAPIError::BadRequest);
+    }
+
+    match validate_user(&user.email, &password, &state.user_store).await {
+        Ok(_) => {}
+        Err(UserStoreError::InvalidCredentials) => return Err(AuthAPIError::IncorrectCredentials),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    // Checked again when the change is confirmed, as the address may be taken by then
+    match state.user_store.read().await.get_user(&new_email).await {
+        Ok(_) => return Err(AuthAPIError::UserAlreadyExists),
+        Err(UserStoreError::UserNotFound) => {}
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    let expires_at = |ttl_seconds| {
+        Utc::now()
+            .checked_add_signed(chrono::Duration::seconds(ttl_seconds))
+            .ok_or(AuthAPIError::UnexpectedError)
+    };
+    let confirm_token = OneTimeToken::default();
+    let confirm_record = OneTimeTokenRecord {
+        email: user.email.clone(),
+        purpose: OneTimeTokenPurpose::EmailChange,
+        expires_at: expires_at(EMAIL_CHANGE_TOKEN_TTL_SECONDS)?,
+        email_change: Some(EmailChange {
+            user_id: user.id,
+            new_email: new_email.clone(),
+        }),
+    };
+    let cancel_token = OneTimeToken::default();
+    let cancel_record = OneTimeTokenRecord {
+        purpose: OneTimeTokenPurpose::EmailChangeCancel,
+        expires_at: expires_at(EMAIL_CHANGE_CANCEL_TOKEN_TTL_SECONDS)?,
+        ..confirm_record.clone()
+    };
+
+    {
+        let mut one_time_token_store = state.one_time_token_store.write().await;
+
+        // Only the most recent request can be confirmed or cancelled
+        for purpose in [OneTimeTokenPurpose::EmailChange, OneTimeTokenPurpose::EmailChangeCancel] {
+            one_time_token_store
+                .revoke_tokens(&user.email, purpose)
+                .await
+                .map_err(|_| AuthAPIError::UnexpectedError)?;
+        }
+        one_time_token_store
+            .add_token(confirm_token.clone(), confirm_record)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+        one_time_token_store
+            .add_token(cancel_token.clone(), cancel_record)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+    }
+
+    let context = context! {
+        new_email => new_email.as_ref(),
+        link => format!("{}/account/email/cancel?token={}", AUTH_SERVICE_URL.as_str(), cancel_token.as_ref()),
+        expires_in_days => EMAIL_CHANGE_CANCEL_TOKEN_TTL_SECONDS / 86_400,
+    };
+    queue_templated_email(&state, &user.email, EmailTemplate::EmailChangeRequested, &locale, context)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    let context = context! {
+        link => format!("{}/account/email/confirm?token={}", AUTH_SERVICE_URL.as_str(), confirm_token.as_ref()),
+        expires_in_hours => EMAIL_CHANGE_TOKEN_TTL_SECONDS / 3600,
+    };
+    queue_templated_email(&state, &new_email, EmailTemplate::EmailChangeConfirmation, &locale, context)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(ChangeEmailResponse {
+        message: "A confirmation link has been sent to the new email address".to_owned(),
+    }))
+}
+
+// Handles the link sent to the new address. The account moves to it in one step, and
+// the old address stays reserved for the account while it can still undo the change.
+// Every JWT naming the old address is rejected; the sessions carry on once refreshed.
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Query(query): Query<ChangeEmailQuery>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let token = OneTimeToken::parse(query.token).map_err(|_| AuthAPIError::InvalidToken)?;
+    let record = state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&token, OneTimeTokenPurpose::EmailChange)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    let change = record.email_change.ok_or(AuthAPIError::UnexpectedError)?;
+
+    let reserve_old_until = Utc::now()
+        .checked_add_signed(chrono::Duration::seconds(EMAIL_CHANGE_CANCEL_TOKEN_TTL_SECONDS))
+        .ok_or(AuthAPIError::UnexpectedError)?;
+
+    {
+        let mut user_store = state.user_store.write().await;
+        // The account was deleted or has moved again since the link was sent
+        match user_store.get_user_by_id(&change.user_id).await {
+            Ok(user) if user.email == record.email => {}
+            Ok(_) | Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+            Err(_) => return Err(AuthAPIError::UnexpectedError),
+        }
+        change_email(&mut *user_store, &change.user_id, &change.new_email, Some(reserve_old_until)).await?;
+    }
+
+    revoke_address_tokens(&record.email, &state).await?;
+
+    Ok(Json(ChangeEmailResponse {
+        message: "Email address has been changed".to_owned(),
+    }))
+}
+
+// Handles the link sent to the old address. A pending change is simply dropped. A
+// confirmed one is undone, along with any change made after it, and since the account
+// may have been taken over, every session is logged out.
+pub async fn cancel_email_change(
+    State(state): State<AppState>,
+    Query(query): Query<ChangeEmailQuery>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let token = OneTimeToken::parse(query.token).map_err(|_| AuthAPIError::InvalidToken)?;
+    let record = state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&token, OneTimeTokenPurpose::EmailChangeCancel)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    let change = record.email_change.ok_or(AuthAPIError::UnexpectedError)?;
+
+    let user = match state.user_store.read().await.get_user_by_id(&change.user_id).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    };
+
+    if user.email == record.email {
+        state
+            .one_time_token_store
+            .write()
+            .await
+            .revoke_tokens(&record.email, OneTimeTokenPurpose::EmailChange)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+    } else {
+        // The old address has been kept for the account since the change was confirmed
+        change_email(&mut *state.user_store.write().await, &change.user_id, &record.email, None).await?;
+
+        revoke_address_tokens(&user.email, &state).await?;
+        // Whoever made the later changes mustn't be able to move the account back
+        state
+            .one_time_token_store
+            .write()
+            .await
+            .revoke_email_changes(&change.user_id)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+        end_user_sessions(&change.user_id, &state).await?;
+    }
+
+    Ok(Json(ChangeEmailResponse {
+        message: "Email change has been cancelled".to_owned(),
+    }))
+}
+
+// Moves the account to `new_email` and rejects every JWT issued for it before, as those
+// name the address it left
+async fn change_email(
+    user_store: &mut (dyn UserStore + Send + Sync),
+    user_id: &UserId,
+    new_email: &Email,
+    reserve_old_until: Option<DateTime<Utc>>,
+) -> Result<(), AuthAPIError> {
+    match user_store.change_email(user_id, new_email, reserve_old_until).await {
+        Ok(()) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(UserStoreError::UserAlreadyExists) => return Err(AuthAPIError::UserAlreadyExists),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+    user_store
+        .bump_token_version(new_email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    Ok(())
+}
+
+// Links already sent to an address the account has left stop working, except the one
+// that lets its owner undo the change
+async fn revoke_address_tokens(email: &Email, state: &AppState) -> Result<(), AuthAPIError> {
+    let mut one_time_token_store = state.one_time_token_store.write().await;
+    for purpose in [
+        OneTimeTokenPurpose::PasswordReset,
+        OneTimeTokenPurpose::AccountUnlock,
+        OneTimeTokenPurpose::MagicLinkLogin,
+        OneTimeTokenPurpose::WebauthnRegistration,
+        OneTimeTokenPurpose::WebauthnLogin,
+        OneTimeTokenPurpose::EmailChange,
+    ] {
+        one_time_token_store
+            .revoke_tokens(email, purpose)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, EmailOutboxStoreError, OutboxMessage},
+    utils::auth::RequireAdmin,
+};
+
+#[derive(Deserialize)]
+pub struct ReplayEmailRequest {
+    pub id: String,
+}
+
+// Bodies are left out on purpose: they hold login codes and sign-in links
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeadLetterResponse {
+    pub id: String,
+    pub recipient: String,
+    pub subject: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub dead_lettered_at: Option<DateTime<Utc>>,
+}
+
+impl From<&OutboxMessage> for DeadLetterResponse {
+    fn from(message: &OutboxMessage) -> Self {
+        Self {
+            id: message.id.to_string(),
+            recipient: message.recipient.as_ref().to_owned(),
+            subject: message.subject.clone(),
+            attempts: message.attempts,
+            last_error: message.last_error.clone(),
+            created_at: message.created_at,
+            dead_lettered_at: message.dead_lettered_at,
+        }
+    }
+}
+
+pub async fn list_dead_letters(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let dead_letters = state
+        .email_outbox
+        .read()
+        .await
+        .dead_letters()
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(
+        dead_letters
+            .iter()
+            .map(DeadLetterResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+// Puts a dead-lettered email back in the queue, e.g. once the mail server is fixed
+pub async fn replay_dead_letter(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Json(request): Json<ReplayEmailRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let id = Uuid::parse_str(&request.id).map_err(|_| AuthAPIError::BadRequest)?;
+
+    match state.email_outbox.write().await.replay(&id, Utc::now()).await {
+        Ok(()) => Ok(StatusCode::OK),
+        Err(EmailOutboxStoreError::MessageNotFound) => Err(AuthAPIError::BadRequest),
+        Err(EmailOutboxStoreError::UnexpectedError) => Err(AuthAPIError::UnexpectedError),
+    }
+}
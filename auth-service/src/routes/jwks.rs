@@ -0,0 +1,10 @@
+use axum::{extract::State, Json};
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+
+use crate::app_state::AppState;
+
+// Publishes the public keys that verify our JWTs, so other services don't need to call /verify-token
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.jwt_keyring.read().await.to_jwk_set(Utc::now()))
+}
\ No newline at end of file
@@ -1,11 +1,21 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use minijinja::context;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, Password, TwoFACode},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        AuthAPIError, Email, HashedPassword, LoginAttemptId, Password, TwoFACode, TwoFactorMethod,
+        User, UserStoreError,
+    },
+    routes::{low_recovery_codes_warning, send_unlock_email},
+    utils::{
+        auth::{start_session, verify_password, SessionClient},
+        constants::{ACCOUNT_LOCKOUT_THRESHOLD, TWO_FA_CODE_TTL_SECONDS},
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
 };
 
 #[derive(Serialize, Deserialize)]
@@ -17,6 +27,8 @@ pub struct LoginRequest {
 pub async fn login(
     state: State<AppState>,
     jar: CookieJar,
+    client: SessionClient,
+    locale: Locale,
     Json(login_request): Json<LoginRequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
     let LoginRequest { email, password } = login_request;
@@ -31,76 +43,166 @@ pub async fn login(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let store = state.user_store.write().await;
+    let user = state.user_store.read().await.get_user(&res_email).await;
+    let user = match user {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => {
+            // Do the same Argon2 work as for a wrong password, so timing doesn't reveal which emails have accounts
+            HashedPassword::verify_dummy_password(&res_password).await;
+            return (jar, Err(AuthAPIError::IncorrectCredentials));
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
 
-    if let Err(_) = store.validate_user(&res_email, &res_password).await {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
+    // A locked account turns away even the right password, so guessing can't go on
+    let now = Utc::now();
+    if let Some(locked_until) = user.locked_until.filter(|locked_until| now < *locked_until) {
+        let retry_after_seconds = (locked_until - now).num_seconds().max(1) as u64;
+        return (jar, Err(AuthAPIError::AccountLocked { retry_after_seconds }));
     }
 
-    let user = match store.get_user(&res_email).await {
-        Ok(user) => user,
-        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
-    };
+    match verify_password(&user, &res_password, &state.user_store).await {
+        Ok(()) => {}
+        Err(UserStoreError::InvalidCredentials) => {
+            let mut store = state.user_store.write().await;
+            let failed_login_attempts = match store.record_failed_login(&res_email).await {
+                Ok(attempts) => attempts,
+                Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+            };
+            if failed_login_attempts < ACCOUNT_LOCKOUT_THRESHOLD {
+                return (jar, Err(AuthAPIError::IncorrectCredentials));
+            }
+
+            let lockout_duration = user.next_lockout_duration();
+            let locked_until = now + lockout_duration;
+            if store.lock_account(&res_email, locked_until).await.is_err() {
+                return (jar, Err(AuthAPIError::UnexpectedError));
+            }
+            drop(store);
+
+            // The lock holds either way, so a failed send only gets logged
+            if let Err(e) = send_unlock_email(&state, &res_email, &locale, locked_until).await {
+                eprintln!("Failed to send account unlock email: {}", e);
+            }
+
+            let retry_after_seconds = lockout_duration.num_seconds() as u64;
+            return (jar, Err(AuthAPIError::AccountLocked { retry_after_seconds }));
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+
+    // Only consecutive failures count towards a lockout
+    if (user.failed_login_attempts > 0 || user.lockout_count > 0)
+        && state
+            .user_store
+            .write()
+            .await
+            .reset_failed_logins(&res_email)
+            .await
+            .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+
+    // Checked after the password so the response doesn't reveal whether an unverified account exists
+    if !user.email_verified {
+        return (jar, Err(AuthAPIError::EmailNotVerified));
+    }
+    if user.disabled {
+        return (jar, Err(AuthAPIError::AccountDisabled));
+    }
 
     // Handle request based on user's 2FA configuration
-    match user.requires_2fa {
-        true => handle_2fa(&user.email, &state, jar).await,
-        false => handle_no_2fa(&user.email, jar).await,
+    match user.two_fa_method {
+        TwoFactorMethod::None => handle_no_2fa(&user, &state, client, &locale, jar).await,
+        TwoFactorMethod::Email | TwoFactorMethod::Totp => handle_2fa(&user, &state, &locale, jar).await,
     }
 }
 
-async fn handle_2fa(
-    email: &Email,
+pub(crate) async fn handle_2fa(
+    user: &User,
     state: &AppState,
+    locale: &Locale,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
+    let email = &user.email;
+
+    // Only advisory, so a failed lookup doesn't get in the way of logging in
+    let warning = state
+        .user_store
+        .read()
+        .await
+        .count_recovery_codes(email)
+        .await
+        .ok()
+        .and_then(low_recovery_codes_warning);
+
     let login_attempt_id = LoginAttemptId::default();
+    // TOTP users never see this code, but storing it still ties the login attempt
+    // to the password check and counts wrong guesses against it
     let two_fa_code = TwoFACode::default();
 
-    if let Err(_) = state
+    if state
         .two_fa_code_store
         .write()
         .await
-        .add_code(email.clone(), login_attempt_id.clone(), two_fa_code.clone())
+        .add_code(user.id, login_attempt_id.clone(), two_fa_code.clone())
         .await
+        .is_err()
     {
         return (jar, Err(AuthAPIError::UnexpectedError));
     }
 
-    let email_client = state.email_client.read().await;
-    if let Err(_) = email_client.send_email(&email, &"Verification Code".to_owned(), two_fa_code.as_ref()).await {
-        return (jar, Err(AuthAPIError::UnexpectedError));
+    if user.two_fa_method == TwoFactorMethod::Email {
+        let context = context! {
+            code => two_fa_code.as_ref(),
+            expires_in_minutes => TWO_FA_CODE_TTL_SECONDS / 60,
+        };
+        if queue_templated_email(state, email, EmailTemplate::TwoFACode, locale, context)
+            .await
+            .is_err()
+        {
+            return (jar, Err(AuthAPIError::UnexpectedError));
+        }
     }
-    
-    return (
+
+    (
         jar,
         Ok((
             StatusCode::PARTIAL_CONTENT,
             Json(LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
                 message: "2FA required".to_owned(),
                 login_attempt_id: login_attempt_id.as_ref().to_string(),
+                method: user.two_fa_method,
+                warning,
             })),
         )),
-    );
+    )
 }
 
-async fn handle_no_2fa(
-    email: &Email,
+pub(crate) async fn handle_no_2fa(
+    user: &User,
+    state: &AppState,
+    client: SessionClient,
+    locale: &Locale,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
-    match generate_auth_cookie(&email) {
-        Ok(cookie) => (
-            jar.add(cookie),
-            Ok((StatusCode::OK, Json(LoginResponse::RegularAuth))),
-        ),
-        Err(_) => (jar, Err(AuthAPIError::UnexpectedError)),
-    }
+    // Every login starts a new session
+    let (auth_cookie, refresh_cookie) = match start_session(user, client, locale, state).await {
+        Ok(cookies) => cookies,
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+
+    (
+        jar.add(auth_cookie).add(refresh_cookie),
+        Ok((StatusCode::OK, Json(LoginResponse::RegularAuth))),
+    )
 }
 
 // The login route can return 2 possible success responses.
@@ -118,4 +220,9 @@ pub struct TwoFactorAuthResponse {
     pub message: String,
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: String,
+    // Where the code comes from: "email" or "totp"
+    pub method: TwoFactorMethod,
+    // Set when the user is running out of recovery codes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
 }
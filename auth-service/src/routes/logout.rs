@@ -4,7 +4,10 @@ use axum_extra::extract::CookieJar;
 use crate::{
     app_state::AppState,
     domain::AuthAPIError,
-    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    utils::{
+        auth::{end_session, reload_keyring_for_unknown_kid, validate_token},
+        constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    },
 };
 
 pub async fn logout(
@@ -18,16 +21,28 @@ pub async fn logout(
 
     let token = cookie.value().to_owned();
 
-    let jar = jar.remove(JWT_COOKIE_NAME);
+    let jar = jar
+        .remove(JWT_COOKIE_NAME)
+        .remove(REFRESH_TOKEN_COOKIE_NAME);
 
-    match validate_token(&token, app_state.banned_token_store.clone()).await {
-        Ok(_claim) => {
-            let mut banned_token_store = app_state.banned_token_store.write().await;
-            banned_token_store
-                .add_token(token.to_string())
-                .await
-                .unwrap();
-            return (jar, Ok(StatusCode::OK));
+    reload_keyring_for_unknown_kid(&token, &app_state).await;
+    let validation = validate_token(
+        &token,
+        &*app_state.jwt_keyring.read().await,
+        app_state.banned_token_store.clone(),
+        app_state.user_store.clone(),
+    )
+    .await;
+
+    match validation {
+        Ok(claims) => {
+            // Ending the session bans every token issued to it, this one included, and
+            // revokes its refresh token so it can't be used to silently log back in
+            if let Err(e) = end_session(&claims.sid, &app_state).await {
+                return (jar, Err(e));
+            }
+
+            (jar, Ok(StatusCode::OK))
         }
         Err(error) => {
             eprintln!("Token validation failed: {:?}", error);
@@ -0,0 +1,153 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Email, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord,
+        TwoFactorMethod, UserStoreError,
+    },
+    routes::{handle_2fa, handle_no_2fa},
+    utils::{
+        auth::SessionClient,
+        constants::{AUTH_SERVICE_URL, MAGIC_LINK_TOKEN_TTL_SECONDS},
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct MagicLinkResponse {
+    pub message: String,
+}
+
+// Emails a single-use sign-in link. Like the password reset route, it always answers
+// the same way, and straight away, so it can't be used to find out which emails have an account.
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    locale: Locale,
+    Json(request): Json<MagicLinkRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    tokio::spawn(async move {
+        if let Err(e) = send_magic_link(&state, &email, &locale).await {
+            eprintln!("Failed to send magic link email: {}", e);
+        }
+    });
+
+    Ok((
+        StatusCode::OK,
+        Json(MagicLinkResponse {
+            message: "If an account exists for this email, a sign-in link has been sent".to_owned(),
+        }),
+    ))
+}
+
+// Email a sign-in link to `email` if it has an account, replacing any earlier link
+async fn send_magic_link(state: &AppState, email: &Email, locale: &Locale) -> Result<(), String> {
+    match state.user_store.read().await.get_user(email).await {
+        Ok(_) => {}
+        Err(UserStoreError::UserNotFound) => return Ok(()),
+        Err(e) => return Err(format!("{:?}", e)),
+    }
+
+    let expires_at = Utc::now()
+        .checked_add_signed(chrono::Duration::seconds(MAGIC_LINK_TOKEN_TTL_SECONDS))
+        .ok_or("Sign-in token expiry out of range")?;
+
+    let token = OneTimeToken::default();
+    let record = OneTimeTokenRecord {
+        email: email.clone(),
+        purpose: OneTimeTokenPurpose::MagicLinkLogin,
+        expires_at,
+        email_change: None,
+    };
+
+    {
+        let mut one_time_token_store = state.one_time_token_store.write().await;
+
+        // Only the most recently requested link works
+        one_time_token_store
+            .revoke_tokens(email, OneTimeTokenPurpose::MagicLinkLogin)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        one_time_token_store
+            .add_token(token.clone(), record)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+    }
+
+    let context = context! {
+        link => format!("{}/login/magic-link/verify?token={}", AUTH_SERVICE_URL.as_str(), token.as_ref()),
+        expires_in_minutes => MAGIC_LINK_TOKEN_TTL_SECONDS / 60,
+    };
+
+    queue_templated_email(state, email, EmailTemplate::MagicLink, locale, context).await
+}
+
+// Handles the link from the sign-in email. It stands in for the password, so the
+// response is the same as the login route's: cookies, or a 2FA challenge.
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    client: SessionClient,
+    locale: Locale,
+    Query(query): Query<MagicLinkQuery>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let token = match OneTimeToken::parse(query.token) {
+        Ok(token) => token,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let record = match state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&token, OneTimeTokenPurpose::MagicLinkLogin)
+        .await
+    {
+        Ok(record) => record,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let mut store = state.user_store.write().await;
+
+    // The account may have been deleted since the link was sent
+    let user = match store.get_user(&record.email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return (jar, Err(AuthAPIError::InvalidToken)),
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+    if user.disabled {
+        return (jar, Err(AuthAPIError::AccountDisabled));
+    }
+
+    // Following the link proves the user owns the address
+    if !user.email_verified && store.mark_email_verified(&user.email).await.is_err() {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    drop(store);
+
+    match user.two_fa_method {
+        TwoFactorMethod::None => handle_no_2fa(&user, &state, client, &locale, jar).await,
+        TwoFactorMethod::Email | TwoFactorMethod::Totp => handle_2fa(&user, &state, &locale, jar).await,
+    }
+}
@@ -0,0 +1,26 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{app_state::AppState, utils::auth::RequireAdmin};
+
+// Prometheus text exposition of the service's gauges, for admins only. The values are
+// the last ones counted in the background, so scraping costs nothing.
+pub async fn metrics(_: RequireAdmin, State(state): State<AppState>) -> impl IntoResponse {
+    let banned_tokens = state.gauges.banned_tokens();
+    let queued_emails = state.gauges.queued_emails();
+    let dead_lettered_emails = state.gauges.dead_lettered_emails();
+
+    let body = format!(
+        "# HELP auth_banned_tokens Number of tokens currently on the denylist\n\
+         # TYPE auth_banned_tokens gauge\n\
+         auth_banned_tokens {}\n\
+         # HELP auth_email_outbox_queued Number of emails waiting to be sent\n\
+         # TYPE auth_email_outbox_queued gauge\n\
+         auth_email_outbox_queued {}\n\
+         # HELP auth_email_outbox_dead_letters Number of emails that failed for good and wait for a replay\n\
+         # TYPE auth_email_outbox_dead_letters gauge\n\
+         auth_email_outbox_dead_letters {}\n",
+        banned_tokens, queued_emails, dead_lettered_emails
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -1,12 +1,42 @@
+mod account;
+mod email_outbox;
+mod jwks;
 mod login;
 mod logout;
+mod magic_link;
+mod metrics;
+mod password_reset;
+mod recovery_codes;
+mod refresh;
+mod sessions;
+mod signing_keys;
 mod signup;
+mod totp;
+mod unlock_account;
+mod users;
 mod verify_2fa;
+mod verify_email;
 mod verify_token;
+mod webauthn;
 
 // re-export items from sub-modules
+pub use account::*;
+pub use email_outbox::*;
+pub use jwks::*;
 pub use login::*;
 pub use logout::*;
+pub use magic_link::*;
+pub use metrics::*;
+pub use password_reset::*;
+pub use recovery_codes::*;
+pub use refresh::*;
+pub use sessions::*;
+pub use signing_keys::*;
 pub use signup::*;
+pub use totp::*;
+pub use unlock_account::*;
+pub use users::*;
 pub use verify_2fa::*;
-pub use verify_token::*;
\ No newline at end of file
+pub use verify_email::*;
+pub use verify_token::*;
+pub use webauthn::*;
\ No newline at end of file
@@ -0,0 +1,142 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Email, HashedPassword, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord,
+        Password, UserStoreError,
+    },
+    utils::{
+        auth::end_user_sessions,
+        constants::{AUTH_SERVICE_URL, PASSWORD_RESET_TOKEN_TTL_SECONDS},
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirmRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PasswordResetResponse {
+    pub message: String,
+}
+
+// Always answers the same way, so the route can't be used to find out which emails have an account.
+// The lookup and the email are left to a background task, since how long they take would
+// give the answer away just the same.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    locale: Locale,
+    Json(request): Json<PasswordResetRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset_link(&state, &email, &locale).await {
+            eprintln!("Failed to send password reset email: {}", e);
+        }
+    });
+
+    Ok((
+        StatusCode::OK,
+        Json(PasswordResetResponse {
+            message: "If an account exists for this email, a password reset link has been sent".to_owned(),
+        }),
+    ))
+}
+
+// Email a reset link to `email` if it has an account, replacing any earlier link
+async fn send_password_reset_link(state: &AppState, email: &Email, locale: &Locale) -> Result<(), String> {
+    match state.user_store.read().await.get_user(email).await {
+        Ok(_) => {}
+        Err(UserStoreError::UserNotFound) => return Ok(()),
+        Err(e) => return Err(format!("{:?}", e)),
+    }
+
+    let expires_at = Utc::now()
+        .checked_add_signed(chrono::Duration::seconds(PASSWORD_RESET_TOKEN_TTL_SECONDS))
+        .ok_or("Reset token expiry out of range")?;
+
+    let token = OneTimeToken::default();
+    let record = OneTimeTokenRecord {
+        email: email.clone(),
+        purpose: OneTimeTokenPurpose::PasswordReset,
+        expires_at,
+        email_change: None,
+    };
+
+    {
+        let mut one_time_token_store = state.one_time_token_store.write().await;
+
+        // Only the most recently requested link works
+        one_time_token_store
+            .revoke_tokens(email, OneTimeTokenPurpose::PasswordReset)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        one_time_token_store
+            .add_token(token.clone(), record)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+    }
+
+    let context = context! {
+        link => format!("{}/password-reset?token={}", AUTH_SERVICE_URL.as_str(), token.as_ref()),
+        expires_in_minutes => PASSWORD_RESET_TOKEN_TTL_SECONDS / 60,
+    };
+
+    queue_templated_email(state, email, EmailTemplate::PasswordReset, locale, context).await
+}
+
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(request): Json<PasswordResetConfirmRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let token = OneTimeToken::parse(request.token).map_err(|_| AuthAPIError::InvalidToken)?;
+    let new_password = Password::parse(&request.new_password).map_err(|_| AuthAPIError::BadRequest)?;
+
+    let record = state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&token, OneTimeTokenPurpose::PasswordReset)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    let password = HashedPassword::parse(new_password)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    let mut user_store = state.user_store.write().await;
+    let user = user_store
+        .get_user(&record.email)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    user_store
+        .update_password(&record.email, password)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    // Whoever knew the old password may still hold a token, so reject them all
+    user_store
+        .bump_token_version(&record.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    drop(user_store);
+
+    // ...and end every session, so none can be refreshed either
+    end_user_sessions(&user.id, &state).await?;
+
+    Ok(Json(PasswordResetResponse {
+        message: "Password has been reset".to_owned(),
+    }))
+}
@@ -0,0 +1,69 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, RecoveryCode, TwoFactorMethod, UserStore, UserStoreError},
+    utils::{
+        auth::AuthenticatedUser,
+        constants::{RECOVERY_CODES_LOW_THRESHOLD, RECOVERY_CODE_COUNT},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// Replaces every earlier recovery code. The new codes are only shown to the user this once.
+pub async fn regenerate_recovery_codes(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let mut user_store = state.user_store.write().await;
+
+    let account = user_store
+        .get_user(&user.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    // Without 2FA there's nothing to recover from
+    if account.two_fa_method == TwoFactorMethod::None {
+        return Err(AuthAPIError::BadRequest);
+    }
+
+    let recovery_codes = issue_recovery_codes(&mut *user_store, &user.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Generate a fresh set of recovery codes for `email`, replacing the old ones.
+// Returns the codes formatted for display; only their hashes are stored.
+pub(crate) async fn issue_recovery_codes(
+    user_store: &mut (dyn UserStore + Send + Sync),
+    email: &Email,
+) -> Result<Vec<String>, UserStoreError> {
+    let codes: Vec<RecoveryCode> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| RecoveryCode::default())
+        .collect();
+
+    user_store
+        .replace_recovery_codes(email, codes.iter().map(RecoveryCode::hash).collect())
+        .await?;
+
+    Ok(codes.iter().map(RecoveryCode::to_string).collect())
+}
+
+// Warning for the login flow once a 2FA user is about to run out of recovery codes
+pub(crate) fn low_recovery_codes_warning(remaining: usize) -> Option<String> {
+    match remaining {
+        0 => Some("No recovery codes left. Generate new ones so you don't get locked out.".to_owned()),
+        n if n <= RECOVERY_CODES_LOW_THRESHOLD => Some(format!(
+            "Only {} recovery codes left. Generate new ones so you don't get locked out.",
+            n
+        )),
+        _ => None,
+    }
+}
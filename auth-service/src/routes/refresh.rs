@@ -0,0 +1,102 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, RefreshToken, SessionStoreError},
+    utils::{
+        auth::{generate_auth_cookie, generate_refresh_cookie},
+        constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    },
+};
+
+pub async fn refresh(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let cookie = match jar.get(REFRESH_TOKEN_COOKIE_NAME) {
+        Some(cookie) => cookie,
+        None => return (jar, Err(AuthAPIError::MissingToken)),
+    };
+
+    let refresh_token = match RefreshToken::parse(cookie.value().to_owned()) {
+        Ok(token) => token,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let mut refresh_token_store = state.refresh_token_store.write().await;
+
+    let record = match refresh_token_store.get_token(&refresh_token).await {
+        Ok(record) => record,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    // A refresh token is only ever presented once by its legitimate holder. Seeing it
+    // again means it leaked, so revoke every token descended from the same login.
+    if record.used {
+        if refresh_token_store
+            .revoke_family(&record.family_id)
+            .await
+            .is_err()
+        {
+            return (jar, Err(AuthAPIError::UnexpectedError));
+        }
+        let jar = jar
+            .remove(JWT_COOKIE_NAME)
+            .remove(REFRESH_TOKEN_COOKIE_NAME);
+        return (jar, Err(AuthAPIError::InvalidToken));
+    }
+
+    if record.expires_at <= Utc::now() {
+        return (jar, Err(AuthAPIError::InvalidToken));
+    }
+
+    if refresh_token_store
+        .mark_token_used(&refresh_token)
+        .await
+        .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+
+    // Release the lock before `generate_refresh_cookie` takes it again
+    drop(refresh_token_store);
+
+    // The family ID is the session's ID, and an ended session can't be refreshed
+    let session_id = match Uuid::parse_str(&record.family_id) {
+        Ok(session_id) => session_id,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+    match state.session_store.write().await.touch_session(&session_id, Utc::now()).await {
+        Ok(()) => {}
+        Err(SessionStoreError::SessionNotFound) => return (jar, Err(AuthAPIError::InvalidToken)),
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+
+    // Load the user again so the new token carries their current roles
+    let user = match state.user_store.read().await.get_user_by_id(&record.user_id).await {
+        Ok(user) => user,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+    if user.disabled {
+        return (jar, Err(AuthAPIError::AccountDisabled));
+    }
+
+    let auth_cookie = match generate_auth_cookie(&user, &session_id, &*state.jwt_keyring.read().await) {
+        Ok(cookie) => cookie,
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+
+    match generate_refresh_cookie(
+        &user.id,
+        &record.family_id,
+        state.refresh_token_store.clone(),
+    )
+    .await
+    {
+        Ok(refresh_cookie) => (jar.add(auth_cookie).add(refresh_cookie), Ok(StatusCode::OK)),
+        Err(_) => (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+}
\ No newline at end of file
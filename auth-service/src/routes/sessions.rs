@@ -0,0 +1,115 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Session, SessionStoreError},
+    utils::{
+        auth::{end_session, end_user_sessions, AuthenticatedUser},
+        constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<SessionResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // Whether this is the session making the request
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session: Session, current_session_id: &Uuid) -> Self {
+        Self {
+            current: session.id == *current_session_id,
+            id: session.id,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+        }
+    }
+}
+
+// Where the user is logged in, oldest session first
+pub async fn list_sessions(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let sessions = state
+        .session_store
+        .read()
+        .await
+        .get_sessions(&user.id)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(SessionsResponse {
+        sessions: sessions
+            .into_iter()
+            .map(|session| SessionResponse::new(session, &user.claims.sid))
+            .collect(),
+    }))
+}
+
+// Logs one of the user's sessions out. Ending the current session clears its cookies,
+// the same as logging out.
+pub async fn revoke_session(
+    jar: CookieJar,
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    // Other users' sessions are reported as missing, so their IDs can't be probed
+    match state.session_store.read().await.get_session(&session_id).await {
+        Ok(session) if session.user_id == user.id => {}
+        Ok(_) | Err(SessionStoreError::SessionNotFound) => {
+            return (jar, Err(AuthAPIError::SessionNotFound))
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    }
+
+    if let Err(e) = end_session(&session_id, &state).await {
+        return (jar, Err(e));
+    }
+
+    let jar = if session_id == user.claims.sid {
+        jar.remove(JWT_COOKIE_NAME).remove(REFRESH_TOKEN_COOKIE_NAME)
+    } else {
+        jar
+    };
+    (jar, Ok(StatusCode::OK))
+}
+
+// "Log out everywhere": ends every session of the user, this one included
+pub async fn revoke_all_sessions(
+    jar: CookieJar,
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    if let Err(e) = end_user_sessions(&user.id, &state).await {
+        return (jar, Err(e));
+    }
+
+    let jar = jar
+        .remove(JWT_COOKIE_NAME)
+        .remove(REFRESH_TOKEN_COOKIE_NAME);
+    (jar, Ok(StatusCode::OK))
+}
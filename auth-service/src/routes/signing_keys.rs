@@ -0,0 +1,161 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, SigningKeyRecord, SigningKeyStoreError},
+    utils::{
+        auth::RequireAdmin,
+        keyring::{reload_keyring, JwtKeyring, KeyringEntry, KeyringError},
+        signing_key::JwtSigningKey,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct AddSigningKeyRequest {
+    // PEM encoded Ed25519 private key. A fresh key is generated when it's left out.
+    pub private_key_pem: Option<String>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub retire_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct RetireSigningKeyRequest {
+    pub kid: String,
+    // Defaults to now. Set it to the future to keep accepting tokens the key already signed.
+    pub retire_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SigningKeyResponse {
+    pub kid: String,
+    pub not_before: DateTime<Utc>,
+    pub retire_after: Option<DateTime<Utc>>,
+}
+
+impl From<&KeyringEntry> for SigningKeyResponse {
+    fn from(entry: &KeyringEntry) -> Self {
+        Self {
+            kid: entry.key.kid().to_owned(),
+            not_before: entry.not_before,
+            retire_after: entry.retire_after,
+        }
+    }
+}
+
+// Lists the stored keys, which may have been changed through another replica
+pub async fn list_signing_keys(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    reload_keyring(&state.jwt_keyring, &state.signing_key_store)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    let keyring = state.jwt_keyring.read().await;
+    Ok(Json(
+        keyring
+            .entries()
+            .iter()
+            .map(SigningKeyResponse::from)
+            .collect::<Vec<SigningKeyResponse>>(),
+    ))
+}
+
+// Keys are stored before they go into the keyring, so they are still there after a restart
+// and other replicas pick them up when they next reload theirs
+pub async fn add_signing_key(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Json(request): Json<AddSigningKeyRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let key = match request.private_key_pem {
+        Some(pem) => JwtSigningKey::from_pem(&pem).map_err(|_| AuthAPIError::BadRequest)?,
+        None => JwtSigningKey::generate().map_err(|_| AuthAPIError::UnexpectedError)?,
+    };
+
+    let response = SigningKeyResponse {
+        kid: key.kid().to_owned(),
+        not_before: request.not_before.unwrap_or_else(Utc::now),
+        retire_after: request.retire_after,
+    };
+
+    let mut keyring = state.jwt_keyring.write().await;
+    *keyring = stored_keyring(&state).await?;
+    keyring
+        .check_new_key(key.kid(), response.not_before, response.retire_after)
+        .map_err(map_keyring_error)?;
+
+    let record = SigningKeyRecord {
+        kid: response.kid.clone(),
+        private_key_pem: key.to_pem(),
+        not_before: response.not_before,
+        retire_after: response.retire_after,
+    };
+    state
+        .signing_key_store
+        .write()
+        .await
+        .add_key(record)
+        .await
+        .map_err(map_signing_key_store_error)?;
+
+    keyring
+        .add_key(key, response.not_before, response.retire_after)
+        .map_err(map_keyring_error)?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn retire_signing_key(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Json(request): Json<RetireSigningKeyRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let retire_after = request.retire_after.unwrap_or_else(Utc::now);
+
+    let mut keyring = state.jwt_keyring.write().await;
+    *keyring = stored_keyring(&state).await?;
+    keyring
+        .check_retire_key(&request.kid, retire_after)
+        .map_err(map_keyring_error)?;
+
+    state
+        .signing_key_store
+        .write()
+        .await
+        .retire_key(&request.kid, retire_after)
+        .await
+        .map_err(map_signing_key_store_error)?;
+
+    keyring
+        .retire_key(&request.kid, retire_after)
+        .map_err(map_keyring_error)?;
+
+    Ok(StatusCode::OK)
+}
+
+// Changes are checked against the stored keys rather than this replica's copy, which may
+// be missing changes made through another replica
+async fn stored_keyring(state: &AppState) -> Result<JwtKeyring, AuthAPIError> {
+    JwtKeyring::from_store(&*state.signing_key_store.read().await)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)
+}
+
+fn map_signing_key_store_error(error: SigningKeyStoreError) -> AuthAPIError {
+    match error {
+        SigningKeyStoreError::KeyNotFound | SigningKeyStoreError::KeyAlreadyExists => AuthAPIError::BadRequest,
+        SigningKeyStoreError::UnexpectedError => AuthAPIError::UnexpectedError,
+    }
+}
+
+fn map_keyring_error(error: KeyringError) -> AuthAPIError {
+    match error {
+        KeyringError::DuplicateKey
+        | KeyringError::KeyNotFound
+        | KeyringError::InvalidValidityWindow
+        | KeyringError::NoActiveSigningKey => AuthAPIError::BadRequest,
+    }
+}
@@ -1,12 +1,14 @@
 
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, Password, User, UserStoreError}, 
+    domain::{AuthAPIError, Email, HashedPassword, Password, TwoFactorMethod, User, UserStoreError}, 
+    routes::{issue_recovery_codes, send_verification_email},
+    utils::email_templates::Locale,
 };
-use crate::domain::UserStore;
 
 #[derive(Deserialize)]
 pub struct SignupRequest{
@@ -18,13 +20,17 @@ pub struct SignupRequest{
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct SignupResponse{
-    pub message : String
+    pub message : String,
+    // Handed out once, to accounts that signed up with 2FA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
 }
 
 
 pub async fn signup(
     // TODO: Use Axum's state extractor to pass in AppState
     state : State<AppState>,
+    locale: Locale,
     Json(request): Json<SignupRequest>,
 ) -> Result<impl IntoResponse, AuthAPIError> {
     // Create a new `User` instance using data in the `request`
@@ -34,25 +40,59 @@ pub async fn signup(
     if email.is_err() || password.is_err() {
         return Err(AuthAPIError::BadRequest);
     }
-    
-    let user = User {
-        email : email.unwrap(),
-        password: password.unwrap(),
-        requires_2fa: request.requires_2fa
+
+    // Only the Argon2 hash of the password is ever stored
+    let password = HashedPassword::parse(password.unwrap())
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    // Authenticator apps are enrolled after signup, so the choice here is only between email codes and nothing
+    let two_fa_method = match request.requires_2fa {
+        true => TwoFactorMethod::Email,
+        false => TwoFactorMethod::None,
     };
+    let mut user = User::new(email.unwrap(), password, two_fa_method);
+    // The first verification email goes out below
+    user.verification_email_sent_at = Some(Utc::now());
+
+    let email = user.email.clone();
+    let two_fa_enabled = user.two_fa_method != TwoFactorMethod::None;
 
-    let mut user_store = state.user_store.write().await;
+    let recovery_codes = {
+        let mut user_store = state.user_store.write().await;
 
-    match user_store.get_user(&user.email).await {
-        Ok(user) => return Err(AuthAPIError::UserAlreadyExists),
-        Err(UserStoreError::UserNotFound) =>  {
-            user_store.add_user(user).await.unwrap();
-            let response = Json(SignupResponse {
-                message: "User created successfully!".to_string(),
-            });
+        match user_store.get_user(&email).await {
+            Ok(_) => return Err(AuthAPIError::UserAlreadyExists),
+            Err(UserStoreError::UserNotFound) => {}
+            Err(_) => return Err(AuthAPIError::UnexpectedError),
+        }
 
-            Ok((StatusCode::CREATED, response))
-        },
-        _ => Err(AuthAPIError::UnexpectedError)
+        // Also refused while the address is kept for an account that moved away from it
+        match user_store.add_user(user).await {
+            Ok(()) => {}
+            Err(UserStoreError::UserAlreadyExists) => return Err(AuthAPIError::UserAlreadyExists),
+            Err(_) => return Err(AuthAPIError::UnexpectedError),
+        }
+
+        match two_fa_enabled {
+            true => Some(
+                issue_recovery_codes(&mut *user_store, &email)
+                    .await
+                    .map_err(|_| AuthAPIError::UnexpectedError)?,
+            ),
+            false => None,
+        }
+    };
+
+    // The account exists either way, and the user can ask for another link if this one never arrives
+    if let Err(e) = send_verification_email(&state, &email, &locale).await {
+        eprintln!("Failed to send verification email: {}", e);
     }
-}
\ No newline at end of file
+
+    let response = Json(SignupResponse {
+        message: "User created successfully!".to_string(),
+        recovery_codes,
+    });
+
+    Ok((StatusCode::CREATED, response))
+}
@@ -0,0 +1,118 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Password, TwoFactorMethod, UserStoreError},
+    routes::issue_recovery_codes,
+    utils::{
+        auth::{validate_user, AuthenticatedUser},
+        totp::{generate_totp_secret, otpauth_uri, qr_code_png, verify_totp_code},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollmentResponse {
+    // Base32 secret, for apps where the QR code can't be scanned
+    pub secret: String,
+    pub otpauth_uri: String,
+    // Base64 encoded PNG of a QR code holding `otpauth_uri`
+    pub qr_code_png: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ConfirmTotpResponse {
+    pub message: String,
+    // A new set, replacing any codes handed out for email 2FA
+    pub recovery_codes: Vec<String>,
+}
+
+// Starts enrollment with a new secret. Logins keep using the current 2FA method
+// until the secret is confirmed, so an abandoned enrollment locks nobody out.
+pub async fn enroll_totp(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let mut user_store = state.user_store.write().await;
+
+    let account = user_store
+        .get_user(&user.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    if account.two_fa_method == TwoFactorMethod::Totp {
+        return Err(AuthAPIError::TotpAlreadyEnabled);
+    }
+
+    let secret = generate_totp_secret();
+    let otpauth_uri = otpauth_uri(&secret, &user.email).map_err(|_| AuthAPIError::UnexpectedError)?;
+    let qr_code_png = qr_code_png(&otpauth_uri).map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    user_store
+        .set_totp_secret(&user.email, Some(secret.clone()))
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(TotpEnrollmentResponse {
+        secret,
+        otpauth_uri,
+        qr_code_png: STANDARD.encode(qr_code_png),
+    }))
+}
+
+// A first valid code proves the authenticator app holds the secret, after which it replaces the current 2FA method.
+// Whoever holds a session could otherwise enroll an app of their own, so the password is needed as well.
+pub async fn confirm_totp(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<ConfirmTotpRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let password = Password::parse(&request.password).map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    match validate_user(&user.email, &password, &state.user_store).await {
+        Ok(_) => {}
+        Err(UserStoreError::InvalidCredentials) => return Err(AuthAPIError::IncorrectCredentials),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    let mut user_store = state.user_store.write().await;
+
+    let account = user_store
+        .get_user(&user.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    if account.two_fa_method == TwoFactorMethod::Totp {
+        return Err(AuthAPIError::TotpAlreadyEnabled);
+    }
+
+    // Enrollment hasn't been started
+    let secret = account.totp_secret.ok_or(AuthAPIError::BadRequest)?;
+
+    let step = verify_totp_code(&secret, &user.email, &request.code)
+        .map_err(|_| AuthAPIError::UnexpectedError)?
+        .ok_or(AuthAPIError::IncorrectCredentials)?;
+    match user_store.record_totp_step(&user.email, step).await {
+        Ok(()) => {}
+        Err(UserStoreError::TotpCodeReused) => return Err(AuthAPIError::IncorrectCredentials),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    user_store
+        .set_two_fa_method(&user.email, TwoFactorMethod::Totp)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    let recovery_codes = issue_recovery_codes(&mut *user_store, &user.email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(ConfirmTotpResponse {
+        message: "Authenticator app enabled".to_owned(),
+        recovery_codes,
+    }))
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Email, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord, UserStoreError,
+    },
+    utils::{
+        constants::{AUTH_SERVICE_URL, ACCOUNT_UNLOCK_TOKEN_TTL_SECONDS},
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct UnlockAccountQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct UnlockAccountResponse {
+    pub message: String,
+}
+
+// Handles the link from the lockout email. Following it proves the user owns the
+// address, so it also resets the lockout backoff.
+pub async fn unlock_account(
+    State(state): State<AppState>,
+    Query(query): Query<UnlockAccountQuery>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let token = OneTimeToken::parse(query.token).map_err(|_| AuthAPIError::InvalidToken)?;
+
+    let record = state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&token, OneTimeTokenPurpose::AccountUnlock)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    // The account may have been deleted since the link was sent
+    match state.user_store.write().await.reset_failed_logins(&record.email).await {
+        Ok(()) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    Ok(Json(UnlockAccountResponse {
+        message: "Account unlocked".to_owned(),
+    }))
+}
+
+// Email an unlock link to the owner of an account that was just locked
+pub(crate) async fn send_unlock_email(
+    state: &AppState,
+    email: &Email,
+    locale: &Locale,
+    locked_until: DateTime<Utc>,
+) -> Result<(), String> {
+    let expires_at = Utc::now()
+        .checked_add_signed(chrono::Duration::seconds(ACCOUNT_UNLOCK_TOKEN_TTL_SECONDS))
+        .ok_or("Unlock link expiry is out of range")?;
+
+    let token = OneTimeToken::default();
+    let record = OneTimeTokenRecord {
+        email: email.clone(),
+        purpose: OneTimeTokenPurpose::AccountUnlock,
+        expires_at,
+        email_change: None,
+    };
+
+    {
+        let mut one_time_token_store = state.one_time_token_store.write().await;
+
+        // Only the link from the latest lockout works
+        one_time_token_store
+            .revoke_tokens(email, OneTimeTokenPurpose::AccountUnlock)
+            .await
+            .map_err(|e| format!("Failed to revoke unlock tokens: {:?}", e))?;
+        one_time_token_store
+            .add_token(token.clone(), record)
+            .await
+            .map_err(|e| format!("Failed to store unlock token: {:?}", e))?;
+    }
+
+    let context = context! {
+        link => format!("{}/unlock-account?token={}", AUTH_SERVICE_URL.as_str(), token.as_ref()),
+        locked_until => locked_until.format("%Y-%m-%d %H:%M UTC").to_string(),
+    };
+    queue_templated_email(state, email, EmailTemplate::AccountUnlock, locale, context).await
+}
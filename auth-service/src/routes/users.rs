@@ -0,0 +1,65 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, UserStore, UserStoreError},
+    utils::auth::{end_user_sessions, RequireAdmin},
+};
+
+#[derive(Deserialize)]
+pub struct AdminUserRequest {
+    pub email: String,
+}
+
+// Locks a user out: login and refresh are refused from now on, every session ends and
+// every JWT already issued stops being accepted. Disabling a disabled user succeeds.
+pub async fn disable_user(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Json(request): Json<AdminUserRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    // The flag goes first so no new session can start while the old ones are ended
+    let mut user_store = state.user_store.write().await;
+    set_disabled(&mut *user_store, &email, true).await?;
+    let user = user_store
+        .get_user(&email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    user_store
+        .bump_token_version(&email)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+    drop(user_store);
+
+    end_user_sessions(&user.id, &state).await?;
+
+    Ok(StatusCode::OK)
+}
+
+// Lets a disabled user log in again. Their old sessions stay ended.
+pub async fn enable_user(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Json(request): Json<AdminUserRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    set_disabled(&mut *state.user_store.write().await, &email, false).await?;
+
+    Ok(StatusCode::OK)
+}
+
+async fn set_disabled(
+    user_store: &mut (dyn UserStore + Send + Sync),
+    email: &Email,
+    disabled: bool,
+) -> Result<(), AuthAPIError> {
+    match user_store.set_disabled(email, disabled).await {
+        Ok(()) => Ok(()),
+        Err(UserStoreError::UserNotFound) => Err(AuthAPIError::BadRequest),
+        Err(_) => Err(AuthAPIError::UnexpectedError),
+    }
+}
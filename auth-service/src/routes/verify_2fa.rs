@@ -4,8 +4,16 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        AuthAPIError, Email, LoginAttemptId, RecoveryCode, TwoFACode, TwoFACodeStoreError,
+        TwoFactorMethod, UserStoreError,
+    },
+    routes::low_recovery_codes_warning,
+    utils::{
+        auth::{start_session, SessionClient},
+        email_templates::Locale,
+        totp::verify_totp_code,
+    },
 };
 
 #[derive(Serialize, Deserialize)]
@@ -17,9 +25,23 @@ pub struct Verify2FARequest {
     two_fa_code: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Verify2FAResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+// The 2FACode field takes either the usual code or one of the user's recovery codes
+enum SecondFactor {
+    Code(TwoFACode),
+    RecoveryCode(RecoveryCode),
+}
+
 pub async fn verify_2fa(
     jar: CookieJar,
     State(state): State<AppState>,
+    client: SessionClient,
+    locale: Locale,
     Json(request): Json<Verify2FARequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
     let email = match Email::parse(&request.email) {
@@ -31,34 +53,105 @@ pub async fn verify_2fa(
         Err(_) => return (jar, Err(AuthAPIError::BadRequest)),
     };
 
-    let two_fa_code = match TwoFACode::parse(request.two_fa_code) {
-        Ok(code) => code,
-        Err(_) => return (jar, Err(AuthAPIError::BadRequest)),
+    let second_factor = match RecoveryCode::parse(request.two_fa_code.clone()) {
+        Ok(code) => SecondFactor::RecoveryCode(code),
+        Err(_) => match TwoFACode::parse(request.two_fa_code) {
+            Ok(code) => SecondFactor::Code(code),
+            Err(_) => return (jar, Err(AuthAPIError::BadRequest)),
+        },
     };
 
+    // Locked before the 2FA code store, the same order login takes the two locks in
+    let mut user_store = state.user_store.write().await;
+    let user = match user_store.get_user(&email).await {
+        Ok(user) => user,
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    };
+    // The account may have been disabled since the password was checked
+    if user.disabled {
+        return (jar, Err(AuthAPIError::AccountDisabled));
+    }
+
     let mut two_fa_code_store = state.two_fa_code_store.write().await;
-    let code_tuple = match two_fa_code_store.get_code(&email).await {
+    let code_tuple = match two_fa_code_store.get_code(&user.id).await {
         Ok(tuple) => tuple,
+        Err(TwoFACodeStoreError::CodeExpired) => {
+            return (jar, Err(AuthAPIError::TwoFACodeExpired))
+        }
+        Err(TwoFACodeStoreError::TooManyAttempts) => {
+            return (jar, Err(AuthAPIError::TooManyTwoFAAttempts))
+        }
         Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
     };
 
     let (attempt_id, code): (LoginAttemptId, TwoFACode) = code_tuple;
 
-    if login_attempt_id != attempt_id || two_fa_code != code {
+    // A recovery code is only spent once the login attempt id checks out
+    let code_matches = login_attempt_id == attempt_id
+        && match &second_factor {
+            SecondFactor::Code(two_fa_code) => match (user.two_fa_method, &user.totp_secret) {
+                (TwoFactorMethod::Totp, Some(secret)) => {
+                    match verify_totp_code(secret, &email, two_fa_code.as_ref()).unwrap_or(None) {
+                        Some(step) => match user_store.record_totp_step(&email, step).await {
+                            Ok(()) => true,
+                            Err(UserStoreError::TotpCodeReused) => false,
+                            Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+                        },
+                        None => false,
+                    }
+                }
+                (TwoFactorMethod::Totp, None) => false,
+                _ => *two_fa_code == code,
+            },
+            SecondFactor::RecoveryCode(recovery_code) => {
+                match user_store
+                    .consume_recovery_code(&email, &recovery_code.hash())
+                    .await
+                {
+                    Ok(()) => true,
+                    Err(UserStoreError::InvalidRecoveryCode) => false,
+                    Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+                }
+            }
+        };
+
+    if !code_matches {
+        // Every wrong guess counts towards invalidating the pending code
+        if two_fa_code_store
+            .record_failed_attempt(&user.id)
+            .await
+            .is_err()
+        {
+            return (jar, Err(AuthAPIError::UnexpectedError));
+        }
         return (jar, Err(AuthAPIError::IncorrectCredentials));
     }
 
-    match generate_auth_cookie(&email) {
-        Ok(cookie) => {
-            if let Err(_) = two_fa_code_store
-                .remove_code(&email)
-                .await
-            {
-                return (jar, Err(AuthAPIError::UnexpectedError));
-            }
+    if two_fa_code_store
+        .remove_code(&user.id)
+        .await
+        .is_err()
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    drop(two_fa_code_store);
 
-            (jar.add(cookie), Ok(StatusCode::OK))
-        }
+    let warning = match second_factor {
+        SecondFactor::RecoveryCode(_) => user_store
+            .count_recovery_codes(&email)
+            .await
+            .ok()
+            .and_then(low_recovery_codes_warning),
+        SecondFactor::Code(_) => None,
+    };
+    drop(user_store);
+
+    // Completing 2FA finishes the login, so it starts a new session
+    match start_session(&user, client, &locale, &state).await {
+        Ok((auth_cookie, refresh_cookie)) => (
+            jar.add(auth_cookie).add(refresh_cookie),
+            Ok((StatusCode::OK, Json(Verify2FAResponse { warning }))),
+        ),
         Err(_) => (jar, Err(AuthAPIError::UnexpectedError)),
     }
 }
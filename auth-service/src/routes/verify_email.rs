@@ -0,0 +1,127 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, UserStoreError},
+    utils::{
+        auth::{
+            generate_email_verification_token, reload_keyring_for_unknown_kid,
+            validate_email_verification_token,
+        },
+        constants::{
+            AUTH_SERVICE_URL, EMAIL_VERIFICATION_TOKEN_TTL_SECONDS,
+            VERIFICATION_EMAIL_RESEND_INTERVAL_SECONDS,
+        },
+        email_templates::{queue_templated_email, EmailTemplate, Locale},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationEmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct VerifyEmailResponse {
+    pub message: String,
+}
+
+// Handles the link from the verification email
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    reload_keyring_for_unknown_kid(&query.token, &state).await;
+    let email = validate_email_verification_token(&query.token, &*state.jwt_keyring.read().await)
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    // The account may have been deleted since the link was sent
+    match state.user_store.write().await.mark_email_verified(&email).await {
+        Ok(()) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    Ok(Json(VerifyEmailResponse {
+        message: "Email address verified".to_owned(),
+    }))
+}
+
+// Answers the same way for unknown, already verified and recently emailed accounts,
+// so the route can't be used to find out which emails have an account
+pub async fn resend_verification_email(
+    State(state): State<AppState>,
+    locale: Locale,
+    Json(request): Json<ResendVerificationEmailRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    let response = Json(VerifyEmailResponse {
+        message: "If this email belongs to an unverified account, a verification link has been sent"
+            .to_owned(),
+    });
+
+    {
+        // Check and record the send under one lock so concurrent requests can't both get through
+        let mut user_store = state.user_store.write().await;
+
+        let user = match user_store.get_user(&email).await {
+            Ok(user) => user,
+            Err(UserStoreError::UserNotFound) => return Ok((StatusCode::OK, response)),
+            Err(_) => return Err(AuthAPIError::UnexpectedError),
+        };
+
+        if user.email_verified {
+            return Ok((StatusCode::OK, response));
+        }
+
+        let now = Utc::now();
+        let resend_interval = chrono::Duration::seconds(VERIFICATION_EMAIL_RESEND_INTERVAL_SECONDS);
+        if user
+            .verification_email_sent_at
+            .is_some_and(|sent_at| now < sent_at + resend_interval)
+        {
+            return Ok((StatusCode::OK, response));
+        }
+
+        user_store
+            .set_verification_email_sent_at(&email, now)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?;
+    }
+
+    send_verification_email(&state, &email, &locale)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok((StatusCode::OK, response))
+}
+
+// Email a fresh verification link to `email`. Callers record when it was sent.
+pub(crate) async fn send_verification_email(
+    state: &AppState,
+    email: &Email,
+    locale: &Locale,
+) -> Result<(), String> {
+    let token = generate_email_verification_token(email, &*state.jwt_keyring.read().await)
+        .map_err(|e| format!("Failed to sign verification token: {:?}", e))?;
+
+    let context = context! {
+        link => format!("{}/verify-email?token={}", AUTH_SERVICE_URL.as_str(), token),
+        expires_in_hours => EMAIL_VERIFICATION_TOKEN_TTL_SECONDS / 3600,
+    };
+    queue_templated_email(state, email, EmailTemplate::EmailVerification, locale, context).await
+}
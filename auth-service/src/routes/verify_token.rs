@@ -1,7 +1,7 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, response::IntoResponse, Json};
 use serde::Deserialize;
 
-use crate::{app_state::AppState, domain::AuthAPIError, utils::auth::validate_token};
+use crate::{app_state::AppState, domain::AuthAPIError, utils::auth::{reload_keyring_for_unknown_kid, validate_token}};
 
 
 #[derive(Deserialize)]
@@ -17,13 +17,12 @@ pub async fn verify_token(
 Result<impl IntoResponse, AuthAPIError> {
     let VerifyToken { token } = request;
     let banned_token_store = state.banned_token_store.clone();
+    let user_store = state.user_store.clone();
 
-    match validate_token(&token, banned_token_store).await {
-        Ok(_) => {
-            return Ok(StatusCode::OK);
-        },
-        Err(_error) => {
-            return Err(AuthAPIError::InvalidToken);
-        }
+    reload_keyring_for_unknown_kid(&token, &state).await;
+    match validate_token(&token, &*state.jwt_keyring.read().await, banned_token_store, user_store).await {
+        // Hand the decoded claims back so callers can act on roles etc. without decoding the JWT themselves
+        Ok(claims) => Ok(Json(claims)),
+        Err(_error) => Err(AuthAPIError::InvalidToken),
     }
 }
\ No newline at end of file
@@ -0,0 +1,402 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, CredentialStoreError, Email, OneTimeToken, OneTimeTokenPurpose,
+        OneTimeTokenRecord, UserId, UserStoreError, WebauthnCredential,
+    },
+    routes::handle_no_2fa,
+    utils::{
+        auth::{AuthenticatedUser, SessionClient},
+        constants::{JWT_ISSUER, WEBAUTHN_CHALLENGE_TTL_SECONDS, WEBAUTHN_RP_ID},
+        email_templates::Locale,
+        webauthn::{
+            decode, encode_challenge, verify_assertion, verify_client_data, verify_registration,
+            Ceremony, WebauthnError, COSE_ALG_ES256,
+        },
+    },
+};
+
+// The options below are the JSON forms from the WebAuthn spec, which browsers turn into
+// arguments for navigator.credentials.create() and .get() with
+// PublicKeyCredential.parseCreationOptionsFromJSON() and parseRequestOptionsFromJSON().
+// Binary values are base64url encoded without padding.
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptionsResponse {
+    pub public_key: CreationOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u64,
+    // Keeps an authenticator from registering twice for the same user
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginOptionsResponse {
+    pub public_key: RequestOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Deserialize)]
+pub struct WebauthnLoginStartRequest {
+    pub email: String,
+}
+
+// What PublicKeyCredential.toJSON() gives for a new credential
+#[derive(Deserialize)]
+pub struct RegistrationCredential {
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+// What PublicKeyCredential.toJSON() gives for a login
+#[derive(Deserialize)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct WebauthnRegistrationResponse {
+    pub message: String,
+}
+
+// Starts registering a passkey or security key for the logged in user
+pub async fn start_webauthn_registration(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let credentials = state
+        .credential_store
+        .read()
+        .await
+        .get_credentials(&user.id)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    let challenge = issue_challenge(&state, &user.email, OneTimeTokenPurpose::WebauthnRegistration).await?;
+
+    Ok(Json(RegistrationOptionsResponse {
+        public_key: CreationOptions {
+            rp: RelyingParty {
+                id: WEBAUTHN_RP_ID.clone(),
+                name: JWT_ISSUER.clone(),
+            },
+            user: UserEntity {
+                id: user_handle(&user.id),
+                name: user.email.as_ref().to_owned(),
+                display_name: user.email.as_ref().to_owned(),
+            },
+            challenge: encode_challenge(&challenge),
+            pub_key_cred_params: vec![CredentialParameters {
+                credential_type: "public-key".to_owned(),
+                alg: COSE_ALG_ES256,
+            }],
+            timeout: timeout_millis(),
+            exclude_credentials: credentials.iter().map(descriptor).collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred".to_owned(),
+                user_verification: "required".to_owned(),
+            },
+            attestation: "none".to_owned(),
+        },
+    }))
+}
+
+pub async fn finish_webauthn_registration(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(credential): Json<RegistrationCredential>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let client_data_json = decode(&credential.response.client_data_json).map_err(ceremony_error)?;
+    let attestation_object = decode(&credential.response.attestation_object).map_err(ceremony_error)?;
+
+    let challenge = verify_client_data(&client_data_json, Ceremony::Registration).map_err(ceremony_error)?;
+    let record = state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&challenge, OneTimeTokenPurpose::WebauthnRegistration)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    // A challenge only works for the user it was issued to
+    if record.email != user.email {
+        return Err(AuthAPIError::InvalidToken);
+    }
+
+    let new_credential = verify_registration(&attestation_object).map_err(ceremony_error)?;
+
+    let result = state
+        .credential_store
+        .write()
+        .await
+        .add_credential(WebauthnCredential {
+            id: new_credential.id,
+            user_id: user.id,
+            public_key: new_credential.public_key,
+            sign_count: new_credential.sign_count,
+            created_at: Utc::now(),
+        })
+        .await;
+    match result {
+        Ok(()) => {}
+        Err(CredentialStoreError::CredentialAlreadyExists) => {
+            return Err(AuthAPIError::CredentialAlreadyRegistered)
+        }
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(WebauthnRegistrationResponse {
+            message: "Passkey registered".to_owned(),
+        }),
+    ))
+}
+
+// Starts a login with one of the user's passkeys. The list of credentials shows
+// whether an account has any, so like /login this is rate limited per email.
+pub async fn start_webauthn_login(
+    State(state): State<AppState>,
+    Json(request): Json<WebauthnLoginStartRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(&request.email).map_err(|_| AuthAPIError::BadRequest)?;
+
+    // An unknown address gets the same answer as an account without passkeys
+    let credentials = match state.user_store.read().await.get_user(&email).await {
+        Ok(user) => state
+            .credential_store
+            .read()
+            .await
+            .get_credentials(&user.id)
+            .await
+            .map_err(|_| AuthAPIError::UnexpectedError)?,
+        Err(UserStoreError::UserNotFound) => Vec::new(),
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    };
+
+    // Without credentials the login can't finish, so the challenge isn't worth storing
+    let challenge = if credentials.is_empty() {
+        OneTimeToken::default()
+    } else {
+        issue_challenge(&state, &email, OneTimeTokenPurpose::WebauthnLogin).await?
+    };
+
+    Ok(Json(LoginOptionsResponse {
+        public_key: RequestOptions {
+            challenge: encode_challenge(&challenge),
+            rp_id: WEBAUTHN_RP_ID.clone(),
+            timeout: timeout_millis(),
+            allow_credentials: credentials.iter().map(descriptor).collect(),
+            user_verification: "required".to_owned(),
+        },
+    }))
+}
+
+// A valid assertion logs the user in the same way a password does for users without
+// 2FA. The passkey already stands for something the user has, so no code is asked for.
+pub async fn finish_webauthn_login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    client: SessionClient,
+    locale: Locale,
+    Json(credential): Json<AuthenticationCredential>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let (credential_id, client_data_json, authenticator_data, signature) = match decode_assertion(&credential) {
+        Ok(decoded) => decoded,
+        Err(e) => return (jar, Err(ceremony_error(e))),
+    };
+
+    let challenge = match verify_client_data(&client_data_json, Ceremony::Authentication) {
+        Ok(challenge) => challenge,
+        Err(e) => return (jar, Err(ceremony_error(e))),
+    };
+    let record = match state
+        .one_time_token_store
+        .write()
+        .await
+        .consume_token(&challenge, OneTimeTokenPurpose::WebauthnLogin)
+        .await
+    {
+        Ok(record) => record,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let user = match state.user_store.read().await.get_user(&record.email).await {
+        Ok(user) => user,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let mut credential_store = state.credential_store.write().await;
+    let stored = match credential_store.get_credential(&credential_id).await {
+        // The challenge was issued for one user, so their credentials are the only ones that count
+        Ok(stored) if stored.user_id == user.id => stored,
+        Ok(_) | Err(CredentialStoreError::CredentialNotFound) => {
+            return (jar, Err(AuthAPIError::IncorrectCredentials))
+        }
+        Err(_) => return (jar, Err(AuthAPIError::UnexpectedError)),
+    };
+
+    let sign_count = match verify_assertion(
+        &stored.public_key,
+        stored.sign_count,
+        &authenticator_data,
+        &client_data_json,
+        &signature,
+    ) {
+        Ok(sign_count) => sign_count,
+        Err(e) => return (jar, Err(ceremony_error(e))),
+    };
+    if credential_store.update_sign_count(&stored.id, sign_count).await.is_err() {
+        return (jar, Err(AuthAPIError::UnexpectedError));
+    }
+    drop(credential_store);
+
+    if user.disabled {
+        return (jar, Err(AuthAPIError::AccountDisabled));
+    }
+
+    handle_no_2fa(&user, &state, client, &locale, jar).await
+}
+
+// The credential ID, client data, authenticator data and signature
+type DecodedAssertion = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn decode_assertion(credential: &AuthenticationCredential) -> Result<DecodedAssertion, WebauthnError> {
+    Ok((
+        decode(&credential.id)?,
+        decode(&credential.response.client_data_json)?,
+        decode(&credential.response.authenticator_data)?,
+        decode(&credential.response.signature)?,
+    ))
+}
+
+async fn issue_challenge(
+    state: &AppState,
+    email: &Email,
+    purpose: OneTimeTokenPurpose,
+) -> Result<OneTimeToken, AuthAPIError> {
+    let expires_at = Utc::now()
+        .checked_add_signed(chrono::Duration::seconds(WEBAUTHN_CHALLENGE_TTL_SECONDS))
+        .ok_or(AuthAPIError::UnexpectedError)?;
+
+    let challenge = OneTimeToken::default();
+    state
+        .one_time_token_store
+        .write()
+        .await
+        .add_token(
+            challenge.clone(),
+            OneTimeTokenRecord {
+                email: email.clone(),
+                purpose,
+                expires_at,
+                email_change: None,
+            },
+        )
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    Ok(challenge)
+}
+
+// Authenticators store this with the passkey. The user's ID keeps the email address
+// off the authenticator, as the spec asks, and stays the same if the address changes.
+fn user_handle(id: &UserId) -> String {
+    URL_SAFE_NO_PAD.encode(id.as_ref().as_bytes())
+}
+
+fn descriptor(credential: &WebauthnCredential) -> CredentialDescriptor {
+    CredentialDescriptor {
+        credential_type: "public-key".to_owned(),
+        id: URL_SAFE_NO_PAD.encode(&credential.id),
+    }
+}
+
+fn timeout_millis() -> u64 {
+    WEBAUTHN_CHALLENGE_TTL_SECONDS as u64 * 1000
+}
+
+fn ceremony_error(error: WebauthnError) -> AuthAPIError {
+    match error {
+        WebauthnError::Malformed => AuthAPIError::BadRequest,
+        _ => AuthAPIError::IncorrectCredentials,
+    }
+}
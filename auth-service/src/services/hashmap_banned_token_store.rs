@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::domain::{BannedTokenStore, BannedTokenStoreError};
+
+// Maps the `jti` of each banned token to the time the token expires anyway
+#[derive(Default, Debug)]
+pub struct HashmapBannedTokenStore {
+    pub tokens: HashMap<String, DateTime<Utc>>,
+}
+
+#[async_trait::async_trait]
+impl BannedTokenStore for HashmapBannedTokenStore {
+    async fn add_token(&mut self, jti: String, expires_at: DateTime<Utc>) -> Result<(), BannedTokenStoreError> {
+        self.tokens
+            .entry(jti)
+            .and_modify(|banned_until| *banned_until = (*banned_until).max(expires_at))
+            .or_insert(expires_at);
+        Ok(())
+    }
+
+    async fn is_banned_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        Ok(self.tokens.contains_key(jti))
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize, BannedTokenStoreError> {
+        let now = Utc::now();
+        let before = self.tokens.len();
+        self.tokens.retain(|_, expires_at| *expires_at > now);
+        Ok(before - self.tokens.len())
+    }
+
+    async fn count(&self) -> Result<usize, BannedTokenStoreError> {
+        Ok(self.tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_add_token() {
+        let mut store = HashmapBannedTokenStore::default();
+        let expires_at = Utc::now() + Duration::minutes(10);
+
+        store.add_token("jti1".to_owned(), expires_at).await.unwrap();
+        assert_eq!(store.tokens.get("jti1"), Some(&expires_at));
+    }
+
+    #[tokio::test]
+    async fn test_add_token_again_keeps_later_expiry() {
+        let mut store = HashmapBannedTokenStore::default();
+        let expires_at = Utc::now() + Duration::minutes(10);
+
+        store.add_token("jti1".to_owned(), expires_at).await.unwrap();
+        assert_eq!(
+            store.add_token("jti1".to_owned(), expires_at - Duration::minutes(5)).await,
+            Ok(())
+        );
+        assert_eq!(store.tokens.get("jti1"), Some(&expires_at));
+
+        let later = expires_at + Duration::minutes(5);
+        assert_eq!(store.add_token("jti1".to_owned(), later).await, Ok(()));
+        assert_eq!(store.tokens.get("jti1"), Some(&later));
+    }
+
+    #[tokio::test]
+    async fn test_is_banned_token() {
+        let mut store = HashmapBannedTokenStore::default();
+
+        store
+            .add_token("jti1".to_owned(), Utc::now() + Duration::minutes(10))
+            .await
+            .unwrap();
+
+        assert!(store.is_banned_token("jti1").await.unwrap());
+        assert!(!store.is_banned_token("jti2").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let mut store = HashmapBannedTokenStore::default();
+
+        store
+            .add_token("expired".to_owned(), Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        store
+            .add_token("live".to_owned(), Utc::now() + Duration::minutes(10))
+            .await
+            .unwrap();
+        assert_eq!(store.count().await, Ok(2));
+
+        assert_eq!(store.prune_expired().await, Ok(1));
+        assert_eq!(store.count().await, Ok(1));
+        assert!(store.is_banned_token("live").await.unwrap());
+        assert!(!store.is_banned_token("expired").await.unwrap());
+    }
+}
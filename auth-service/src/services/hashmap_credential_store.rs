@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::domain::{CredentialStore, CredentialStoreError, UserId, WebauthnCredential};
+
+#[derive(Default)]
+pub struct HashmapCredentialStore {
+    credentials: HashMap<Vec<u8>, WebauthnCredential>,
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for HashmapCredentialStore {
+    async fn add_credential(&mut self, credential: WebauthnCredential) -> Result<(), CredentialStoreError> {
+        if self.credentials.contains_key(&credential.id) {
+            return Err(CredentialStoreError::CredentialAlreadyExists);
+        }
+        self.credentials.insert(credential.id.clone(), credential);
+        Ok(())
+    }
+
+    async fn get_credential(&self, id: &[u8]) -> Result<WebauthnCredential, CredentialStoreError> {
+        self.credentials
+            .get(id)
+            .cloned()
+            .ok_or(CredentialStoreError::CredentialNotFound)
+    }
+
+    async fn get_credentials(&self, user_id: &UserId) -> Result<Vec<WebauthnCredential>, CredentialStoreError> {
+        let mut credentials: Vec<WebauthnCredential> = self
+            .credentials
+            .values()
+            .filter(|credential| credential.user_id == *user_id)
+            .cloned()
+            .collect();
+        credentials.sort_by_key(|credential| credential.created_at);
+        Ok(credentials)
+    }
+
+    async fn update_sign_count(&mut self, id: &[u8], sign_count: u32) -> Result<(), CredentialStoreError> {
+        let credential = self
+            .credentials
+            .get_mut(id)
+            .ok_or(CredentialStoreError::CredentialNotFound)?;
+        credential.sign_count = sign_count;
+        Ok(())
+    }
+
+    async fn remove_credentials(&mut self, user_id: &UserId) -> Result<(), CredentialStoreError> {
+        self.credentials.retain(|_, credential| credential.user_id != *user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+
+    fn credential(id: &[u8], user_id: &UserId) -> WebauthnCredential {
+        WebauthnCredential {
+            id: id.to_vec(),
+            user_id: *user_id,
+            public_key: vec![4; 65],
+            sign_count: 0,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_credentials() {
+        let mut store = HashmapCredentialStore::default();
+        let user_id = UserId::default();
+        let other_user_id = UserId::default();
+        let first = credential(b"first", &user_id);
+        let second = WebauthnCredential {
+            created_at: first.created_at + Duration::seconds(1),
+            ..credential(b"second", &user_id)
+        };
+        store.add_credential(second.clone()).await.unwrap();
+        store.add_credential(first.clone()).await.unwrap();
+        store.add_credential(credential(b"other", &other_user_id)).await.unwrap();
+
+        assert_eq!(store.get_credential(b"first").await, Ok(first.clone()));
+        assert_eq!(store.get_credential(b"unknown").await, Err(CredentialStoreError::CredentialNotFound));
+        assert_eq!(
+            store.get_credentials(&user_id).await,
+            Ok(vec![first, second])
+        );
+
+        // Credential IDs are unique across users
+        assert_eq!(
+            store.add_credential(credential(b"first", &other_user_id)).await,
+            Err(CredentialStoreError::CredentialAlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_sign_count() {
+        let mut store = HashmapCredentialStore::default();
+        store.add_credential(credential(b"id", &UserId::default())).await.unwrap();
+
+        store.update_sign_count(b"id", 7).await.unwrap();
+        assert_eq!(store.get_credential(b"id").await.unwrap().sign_count, 7);
+        assert_eq!(store.update_sign_count(b"unknown", 1).await, Err(CredentialStoreError::CredentialNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_remove_credentials() {
+        let mut store = HashmapCredentialStore::default();
+        let user_id = UserId::default();
+        let other_user_id = UserId::default();
+        store.add_credential(credential(b"first", &user_id)).await.unwrap();
+        store.add_credential(credential(b"second", &user_id)).await.unwrap();
+        store.add_credential(credential(b"other", &other_user_id)).await.unwrap();
+
+        store.remove_credentials(&user_id).await.unwrap();
+
+        assert_eq!(store.get_credentials(&user_id).await, Ok(vec![]));
+        assert_eq!(store.get_credentials(&other_user_id).await.unwrap().len(), 1);
+    }
+}
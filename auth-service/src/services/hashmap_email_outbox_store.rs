@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::domain::{EmailOutboxStore, EmailOutboxStoreError, OutboxMessage};
+
+#[derive(Default)]
+pub struct HashmapEmailOutboxStore {
+    messages: HashMap<Uuid, OutboxMessage>,
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for HashmapEmailOutboxStore {
+    async fn enqueue(&mut self, message: OutboxMessage) -> Result<(), EmailOutboxStoreError> {
+        self.messages.insert(message.id, message);
+        Ok(())
+    }
+
+    async fn claim_due(
+        &mut self,
+        now: DateTime<Utc>,
+        lease: chrono::Duration,
+        limit: u32,
+    ) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError> {
+        let mut due: Vec<&mut OutboxMessage> = self
+            .messages
+            .values_mut()
+            .filter(|message| message.dead_lettered_at.is_none() && message.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|message| message.created_at);
+
+        Ok(due
+            .into_iter()
+            .take(limit as usize)
+            .map(|message| {
+                message.next_attempt_at = now + lease;
+                message.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_sent(&mut self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        self.messages
+            .remove(id)
+            .map(|_| ())
+            .ok_or(EmailOutboxStoreError::MessageNotFound)
+    }
+
+    async fn record_failure(
+        &mut self,
+        id: &Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let message = self
+            .messages
+            .get_mut(id)
+            .ok_or(EmailOutboxStoreError::MessageNotFound)?;
+
+        message.attempts += 1;
+        message.last_error = Some(error.to_owned());
+        match retry_at {
+            Some(retry_at) => message.next_attempt_at = retry_at,
+            None => message.dead_lettered_at = Some(Utc::now()),
+        }
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError> {
+        let mut dead_letters: Vec<OutboxMessage> = self
+            .messages
+            .values()
+            .filter(|message| message.dead_lettered_at.is_some())
+            .cloned()
+            .collect();
+        dead_letters.sort_by_key(|message| message.dead_lettered_at);
+        Ok(dead_letters)
+    }
+
+    async fn count_queued(&self) -> Result<usize, EmailOutboxStoreError> {
+        Ok(self
+            .messages
+            .values()
+            .filter(|message| message.dead_lettered_at.is_none())
+            .count())
+    }
+
+    async fn count_dead_letters(&self) -> Result<usize, EmailOutboxStoreError> {
+        Ok(self
+            .messages
+            .values()
+            .filter(|message| message.dead_lettered_at.is_some())
+            .count())
+    }
+
+    async fn replay(&mut self, id: &Uuid, now: DateTime<Utc>) -> Result<(), EmailOutboxStoreError> {
+        // Queued messages are retried anyway, so only dead letters can be replayed
+        let message = self
+            .messages
+            .get_mut(id)
+            .filter(|message| message.dead_lettered_at.is_some())
+            .ok_or(EmailOutboxStoreError::MessageNotFound)?;
+
+        message.attempts = 0;
+        message.next_attempt_at = now;
+        message.dead_lettered_at = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::domain::Email;
+
+    fn message(subject: &str) -> OutboxMessage {
+        OutboxMessage::new(
+            Email::parse("test@example.com").unwrap(),
+            subject.to_owned(),
+            "text".to_owned(),
+            "<p>html</p>".to_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_leases_messages() {
+        let mut store = HashmapEmailOutboxStore::default();
+        let message = message("Hello");
+        store.enqueue(message.clone()).await.unwrap();
+
+        let now = Utc::now();
+        let claimed = store.claim_due(now, Duration::seconds(60), 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, message.id);
+
+        // Leased to the first claimant until the lease runs out
+        assert!(store.claim_due(now, Duration::seconds(60), 10).await.unwrap().is_empty());
+        let later = now + Duration::seconds(61);
+        assert_eq!(store.claim_due(later, Duration::seconds(60), 10).await.unwrap().len(), 1);
+
+        store.mark_sent(&message.id).await.unwrap();
+        let much_later = now + Duration::hours(1);
+        assert!(store.claim_due(much_later, Duration::seconds(60), 10).await.unwrap().is_empty());
+        assert_eq!(store.mark_sent(&message.id).await, Err(EmailOutboxStoreError::MessageNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_respects_limit_and_order() {
+        let mut store = HashmapEmailOutboxStore::default();
+        let first = message("First");
+        let mut second = message("Second");
+        second.created_at = first.created_at + Duration::seconds(1);
+        store.enqueue(second.clone()).await.unwrap();
+        store.enqueue(first.clone()).await.unwrap();
+
+        let now = Utc::now() + Duration::seconds(2);
+        let claimed = store.claim_due(now, Duration::seconds(60), 1).await.unwrap();
+        assert_eq!(claimed, vec![OutboxMessage { next_attempt_at: now + Duration::seconds(60), ..first }]);
+    }
+
+    #[tokio::test]
+    async fn test_failures_reschedule_then_dead_letter() {
+        let mut store = HashmapEmailOutboxStore::default();
+        let message = message("Hello");
+        store.enqueue(message.clone()).await.unwrap();
+
+        let retry_at = Utc::now() + Duration::seconds(30);
+        store.record_failure(&message.id, "connection refused", Some(retry_at)).await.unwrap();
+        assert!(store.claim_due(Utc::now(), Duration::seconds(60), 10).await.unwrap().is_empty());
+        assert!(store.dead_letters().await.unwrap().is_empty());
+
+        assert_eq!(store.count_queued().await, Ok(1));
+        store.record_failure(&message.id, "mailbox unavailable", None).await.unwrap();
+        assert_eq!(store.count_queued().await, Ok(0));
+        assert_eq!(store.count_dead_letters().await, Ok(1));
+        let dead_letters = store.dead_letters().await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(dead_letters[0].last_error.as_deref(), Some("mailbox unavailable"));
+
+        // Dead letters are never claimed
+        let much_later = Utc::now() + Duration::days(1);
+        assert!(store.claim_due(much_later, Duration::seconds(60), 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_requeues_dead_letters_only() {
+        let mut store = HashmapEmailOutboxStore::default();
+        let message = message("Hello");
+        store.enqueue(message.clone()).await.unwrap();
+
+        let now = Utc::now();
+        assert_eq!(store.replay(&message.id, now).await, Err(EmailOutboxStoreError::MessageNotFound));
+        assert_eq!(store.replay(&Uuid::new_v4(), now).await, Err(EmailOutboxStoreError::MessageNotFound));
+
+        store.record_failure(&message.id, "mailbox unavailable", None).await.unwrap();
+        store.replay(&message.id, now).await.unwrap();
+
+        assert!(store.dead_letters().await.unwrap().is_empty());
+        let claimed = store.claim_due(now, Duration::seconds(60), 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].attempts, 0);
+    }
+}
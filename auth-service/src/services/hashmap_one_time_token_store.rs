@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+
+use crate::domain::{
+    Email, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord, OneTimeTokenStore,
+    OneTimeTokenStoreError, UserId,
+};
+
+#[derive(Default)]
+pub struct HashmapOneTimeTokenStore {
+    pub tokens: HashMap<OneTimeToken, OneTimeTokenRecord>,
+}
+
+#[async_trait::async_trait]
+impl OneTimeTokenStore for HashmapOneTimeTokenStore {
+    async fn add_token(
+        &mut self,
+        token: OneTimeToken,
+        record: OneTimeTokenRecord,
+    ) -> Result<(), OneTimeTokenStoreError> {
+        self.tokens.insert(token, record);
+        Ok(())
+    }
+
+    async fn consume_token(
+        &mut self,
+        token: &OneTimeToken,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<OneTimeTokenRecord, OneTimeTokenStoreError> {
+        match self.tokens.get(token) {
+            Some(record) if record.purpose == purpose => {}
+            _ => return Err(OneTimeTokenStoreError::TokenNotFound),
+        }
+
+        let record = self
+            .tokens
+            .remove(token)
+            .ok_or(OneTimeTokenStoreError::UnexpectedError)?;
+
+        if record.expires_at <= Utc::now() {
+            return Err(OneTimeTokenStoreError::TokenExpired);
+        }
+
+        Ok(record)
+    }
+
+    async fn revoke_tokens(
+        &mut self,
+        email: &Email,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<(), OneTimeTokenStoreError> {
+        self.tokens
+            .retain(|_, record| record.email != *email || record.purpose != purpose);
+        Ok(())
+    }
+
+    async fn revoke_email_changes(&mut self, user_id: &UserId) -> Result<(), OneTimeTokenStoreError> {
+        self.tokens
+            .retain(|_, record| record.email_change.as_ref().is_none_or(|change| change.user_id != *user_id));
+        Ok(())
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize, OneTimeTokenStoreError> {
+        let now = Utc::now();
+        let before = self.tokens.len();
+        self.tokens.retain(|_, record| record.expires_at > now);
+        Ok(before - self.tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+
+    use super::*;
+    use crate::domain::EmailChange;
+
+    fn record(email: &str, expires_at: DateTime<Utc>) -> OneTimeTokenRecord {
+        OneTimeTokenRecord {
+            email: Email::parse(email).unwrap(),
+            purpose: OneTimeTokenPurpose::PasswordReset,
+            expires_at,
+            email_change: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consume_token_only_once() {
+        let mut store = HashmapOneTimeTokenStore::default();
+        let token = OneTimeToken::default();
+        let record = record("test@example.com", Utc::now() + Duration::minutes(30));
+
+        store.add_token(token.clone(), record.clone()).await.unwrap();
+
+        assert_eq!(
+            store
+                .consume_token(&token, OneTimeTokenPurpose::PasswordReset)
+                .await,
+            Ok(record)
+        );
+        assert_eq!(
+            store
+                .consume_token(&token, OneTimeTokenPurpose::PasswordReset)
+                .await,
+            Err(OneTimeTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_consume_expired_token() {
+        let mut store = HashmapOneTimeTokenStore::default();
+        let token = OneTimeToken::default();
+
+        store
+            .add_token(token.clone(), record("test@example.com", Utc::now() - Duration::seconds(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .consume_token(&token, OneTimeTokenPurpose::PasswordReset)
+                .await,
+            Err(OneTimeTokenStoreError::TokenExpired)
+        );
+        assert!(store.tokens.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_tokens() {
+        let mut store = HashmapOneTimeTokenStore::default();
+        let token_1 = OneTimeToken::default();
+        let token_2 = OneTimeToken::default();
+        let other_token = OneTimeToken::default();
+        let expires_at = Utc::now() + Duration::minutes(30);
+
+        store.add_token(token_1.clone(), record("test@example.com", expires_at)).await.unwrap();
+        store.add_token(token_2.clone(), record("test@example.com", expires_at)).await.unwrap();
+        store.add_token(other_token.clone(), record("other@example.com", expires_at)).await.unwrap();
+
+        store
+            .revoke_tokens(&Email::parse("test@example.com").unwrap(), OneTimeTokenPurpose::PasswordReset)
+            .await
+            .unwrap();
+
+        assert_eq!(store.tokens.len(), 1);
+        assert!(store.tokens.contains_key(&other_token));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_email_changes() {
+        let mut store = HashmapOneTimeTokenStore::default();
+        let user_id = UserId::default();
+        let expires_at = Utc::now() + Duration::minutes(30);
+        let email_change = |email: &str, new_email: &str, user_id: UserId| OneTimeTokenRecord {
+            purpose: OneTimeTokenPurpose::EmailChangeCancel,
+            email_change: Some(EmailChange {
+                user_id,
+                new_email: Email::parse(new_email).unwrap(),
+            }),
+            ..record(email, expires_at)
+        };
+        let other_user_token = OneTimeToken::default();
+        let reset_token = OneTimeToken::default();
+
+        // The cancel tokens of a chain of changes were each sent to a different address
+        store
+            .add_token(OneTimeToken::default(), email_change("a@example.com", "b@example.com", user_id))
+            .await
+            .unwrap();
+        store
+            .add_token(OneTimeToken::default(), email_change("b@example.com", "c@example.com", user_id))
+            .await
+            .unwrap();
+        store
+            .add_token(
+                other_user_token.clone(),
+                email_change("d@example.com", "e@example.com", UserId::default()),
+            )
+            .await
+            .unwrap();
+        store.add_token(reset_token.clone(), record("a@example.com", expires_at)).await.unwrap();
+
+        store.revoke_email_changes(&user_id).await.unwrap();
+
+        assert_eq!(store.tokens.len(), 2);
+        assert!(store.tokens.contains_key(&other_user_token));
+        assert!(store.tokens.contains_key(&reset_token));
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let mut store = HashmapOneTimeTokenStore::default();
+        let live = OneTimeToken::default();
+
+        store
+            .add_token(OneTimeToken::default(), record("test@example.com", Utc::now() - Duration::seconds(1)))
+            .await
+            .unwrap();
+        store
+            .add_token(live.clone(), record("test@example.com", Utc::now() + Duration::minutes(30)))
+            .await
+            .unwrap();
+
+        assert_eq!(store.prune_expired().await, Ok(1));
+        assert_eq!(store.tokens.len(), 1);
+        assert!(store.tokens.contains_key(&live));
+    }
+
+    #[test]
+    fn test_one_time_token_parse() {
+        let token = OneTimeToken::default();
+        assert_eq!(OneTimeToken::parse(token.as_ref().to_owned()), Ok(token));
+        assert!(OneTimeToken::parse("not-a-token".to_owned()).is_err());
+    }
+}
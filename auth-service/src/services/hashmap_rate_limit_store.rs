@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::domain::{RateLimit, RateLimitStore, RateLimitStoreError};
+
+// Full buckets carry no state, so every so often they are dropped to keep the map
+// from growing with every client that has ever made a request
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    // Of the limit the bucket was last used with. Routes have limits of their own,
+    // so the sweep can't go by the limit of whichever request triggers it.
+    period: Duration,
+}
+
+pub struct HashmapRateLimitStore {
+    buckets: HashMap<String, Bucket>,
+    last_swept_at: Instant,
+}
+
+impl Default for HashmapRateLimitStore {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            last_swept_at: Instant::now(),
+        }
+    }
+}
+
+impl HashmapRateLimitStore {
+    fn sweep(&mut self, now: Instant) {
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.updated_at) < bucket.period);
+        self.last_swept_at = now;
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for HashmapRateLimitStore {
+    async fn take_token(
+        &mut self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<(), RateLimitStoreError> {
+        let now = Instant::now();
+        if now.duration_since(self.last_swept_at) >= SWEEP_INTERVAL {
+            self.sweep(now);
+        }
+
+        let capacity = f64::from(limit.capacity);
+        let refill_interval = limit.refill_interval().as_secs_f64();
+
+        let bucket = self.buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+            period: limit.period,
+        });
+        let refilled = now.duration_since(bucket.updated_at).as_secs_f64() / refill_interval;
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated_at = now;
+        bucket.period = limit.period;
+
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) * refill_interval);
+            return Err(RateLimitStoreError::RateLimited { retry_after });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit() -> RateLimit {
+        RateLimit {
+            capacity: 2,
+            period: Duration::from_millis(400),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bucket_empties_and_refills() {
+        let mut store = HashmapRateLimitStore::default();
+
+        assert_eq!(store.take_token("key", &limit()).await, Ok(()));
+        assert_eq!(store.take_token("key", &limit()).await, Ok(()));
+
+        match store.take_token("key", &limit()).await {
+            Err(RateLimitStoreError::RateLimited { retry_after }) => {
+                assert!(retry_after > Duration::ZERO && retry_after <= limit().refill_interval())
+            }
+            other => panic!("expected the bucket to be empty, got {:?}", other),
+        }
+
+        // One token comes back every 200ms
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(store.take_token("key", &limit()).await, Ok(()));
+        assert!(store.take_token("key", &limit()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keys_have_separate_buckets() {
+        let mut store = HashmapRateLimitStore::default();
+
+        store.take_token("first", &limit()).await.unwrap();
+        store.take_token("first", &limit()).await.unwrap();
+        assert!(store.take_token("first", &limit()).await.is_err());
+
+        assert_eq!(store.take_token("second", &limit()).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_drops_full_buckets() {
+        let mut store = HashmapRateLimitStore::default();
+        store.take_token("key", &limit()).await.unwrap();
+
+        store.sweep(Instant::now());
+        assert_eq!(store.buckets.len(), 1);
+
+        tokio::time::sleep(limit().period).await;
+        store.sweep(Instant::now());
+        assert!(store.buckets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_keeps_buckets_of_longer_limits() {
+        let mut store = HashmapRateLimitStore::default();
+        let long_limit = RateLimit {
+            capacity: 2,
+            period: Duration::from_secs(3600),
+        };
+        store.take_token("short", &limit()).await.unwrap();
+        store.take_token("long", &long_limit).await.unwrap();
+
+        tokio::time::sleep(limit().period).await;
+        store.sweep(Instant::now());
+        assert!(!store.buckets.contains_key("short"));
+        assert!(store.buckets.contains_key("long"));
+    }
+}
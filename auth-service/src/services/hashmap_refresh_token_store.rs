@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+
+use crate::domain::{
+    RefreshToken, RefreshTokenRecord, RefreshTokenStore, RefreshTokenStoreError, UserId,
+};
+
+#[derive(Default)]
+pub struct HashmapRefreshTokenStore {
+    pub tokens: HashMap<RefreshToken, RefreshTokenRecord>,
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenStore for HashmapRefreshTokenStore {
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        record: RefreshTokenRecord,
+    ) -> Result<(), RefreshTokenStoreError> {
+        self.tokens.insert(token, record);
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenRecord, RefreshTokenStoreError> {
+        self.tokens
+            .get(token)
+            .cloned()
+            .ok_or(RefreshTokenStoreError::TokenNotFound)
+    }
+
+    async fn mark_token_used(&mut self, token: &RefreshToken) -> Result<(), RefreshTokenStoreError> {
+        match self.tokens.get_mut(token) {
+            Some(record) => {
+                record.used = true;
+                Ok(())
+            }
+            None => Err(RefreshTokenStoreError::TokenNotFound),
+        }
+    }
+
+    async fn revoke_family(&mut self, family_id: &str) -> Result<(), RefreshTokenStoreError> {
+        self.tokens.retain(|_, record| record.family_id != family_id);
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(&mut self, user_id: &UserId) -> Result<(), RefreshTokenStoreError> {
+        self.tokens.retain(|_, record| record.user_id != *user_id);
+        Ok(())
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize, RefreshTokenStoreError> {
+        let now = Utc::now();
+        let before = self.tokens.len();
+        self.tokens.retain(|_, record| record.expires_at > now);
+        Ok(before - self.tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn record(family_id: &str) -> RefreshTokenRecord {
+        record_for(&UserId::default(), family_id)
+    }
+
+    fn record_for(user_id: &UserId, family_id: &str) -> RefreshTokenRecord {
+        RefreshTokenRecord {
+            user_id: *user_id,
+            family_id: family_id.to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            used: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_token() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token = RefreshToken::default();
+
+        let record = record("family");
+
+        store.add_token(token.clone(), record.clone()).await.unwrap();
+
+        assert_eq!(store.get_token(&token).await, Ok(record));
+    }
+
+    #[tokio::test]
+    async fn test_get_token_not_found() {
+        let store = HashmapRefreshTokenStore::default();
+
+        let result = store.get_token(&RefreshToken::default()).await;
+        assert_eq!(result, Err(RefreshTokenStoreError::TokenNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_mark_token_used() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token = RefreshToken::default();
+
+        store.add_token(token.clone(), record("family")).await.unwrap();
+        store.mark_token_used(&token).await.unwrap();
+
+        assert!(store.get_token(&token).await.unwrap().used);
+        assert_eq!(
+            store.mark_token_used(&RefreshToken::default()).await,
+            Err(RefreshTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoke_family() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token_1 = RefreshToken::default();
+        let token_2 = RefreshToken::default();
+        let other_token = RefreshToken::default();
+
+        store.add_token(token_1.clone(), record("family")).await.unwrap();
+        store.add_token(token_2.clone(), record("family")).await.unwrap();
+        store.add_token(other_token.clone(), record("other_family")).await.unwrap();
+
+        store.revoke_family("family").await.unwrap();
+
+        assert!(store.get_token(&token_1).await.is_err());
+        assert!(store.get_token(&token_2).await.is_err());
+        assert!(store.get_token(&other_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_user_tokens() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token_1 = RefreshToken::default();
+        let token_2 = RefreshToken::default();
+        let other_token = RefreshToken::default();
+
+        let user_id = UserId::default();
+        store.add_token(token_1.clone(), record_for(&user_id, "family_1")).await.unwrap();
+        store.add_token(token_2.clone(), record_for(&user_id, "family_2")).await.unwrap();
+        store.add_token(other_token.clone(), record("family_3")).await.unwrap();
+
+        store.revoke_user_tokens(&user_id).await.unwrap();
+
+        assert!(store.get_token(&token_1).await.is_err());
+        assert!(store.get_token(&token_2).await.is_err());
+        assert!(store.get_token(&other_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let expired = RefreshToken::default();
+        let live = RefreshToken::default();
+
+        store
+            .add_token(
+                expired.clone(),
+                RefreshTokenRecord {
+                    expires_at: Utc::now() - Duration::seconds(1),
+                    ..record("family")
+                },
+            )
+            .await
+            .unwrap();
+        store.add_token(live.clone(), record("family")).await.unwrap();
+
+        assert_eq!(store.prune_expired().await, Ok(1));
+        assert!(store.get_token(&expired).await.is_err());
+        assert!(store.get_token(&live).await.is_ok());
+    }
+
+    #[test]
+    fn test_refresh_token_parse() {
+        let token = RefreshToken::default();
+        assert_eq!(RefreshToken::parse(token.as_ref().to_owned()), Ok(token));
+        assert!(RefreshToken::parse("not-a-token".to_owned()).is_err());
+    }
+}
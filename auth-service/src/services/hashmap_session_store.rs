@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::domain::{Session, SessionStore, SessionStoreError, UserId};
+
+#[derive(Default)]
+pub struct HashmapSessionStore {
+    sessions: HashMap<Uuid, Session>,
+}
+
+#[async_trait::async_trait]
+impl SessionStore for HashmapSessionStore {
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError> {
+        self.sessions.insert(session.id, session);
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &Uuid) -> Result<Session, SessionStoreError> {
+        self.sessions
+            .get(id)
+            .cloned()
+            .ok_or(SessionStoreError::SessionNotFound)
+    }
+
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .values()
+            .filter(|session| session.user_id == *user_id)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| session.created_at);
+        Ok(sessions)
+    }
+
+    async fn touch_session(&mut self, id: &Uuid, last_seen_at: DateTime<Utc>) -> Result<(), SessionStoreError> {
+        let session = self
+            .sessions
+            .get_mut(id)
+            .ok_or(SessionStoreError::SessionNotFound)?;
+        session.last_seen_at = last_seen_at;
+        Ok(())
+    }
+
+    async fn remove_session(&mut self, id: &Uuid) -> Result<(), SessionStoreError> {
+        self.sessions
+            .remove(id)
+            .map(|_| ())
+            .ok_or(SessionStoreError::SessionNotFound)
+    }
+
+    async fn remove_user_sessions(&mut self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        let ids: Vec<Uuid> = self
+            .sessions
+            .values()
+            .filter(|session| session.user_id == *user_id)
+            .map(|session| session.id)
+            .collect();
+        Ok(ids.iter().filter_map(|id| self.sessions.remove(id)).collect())
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize, SessionStoreError> {
+        let now = Utc::now();
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| session.expires_at() > now);
+        Ok(before - self.sessions.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::utils::auth::REFRESH_TOKEN_TTL_SECONDS;
+
+    fn session(user_id: &UserId, created_at: DateTime<Utc>) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            user_id: *user_id,
+            created_at,
+            last_seen_at: created_at,
+            user_agent: Some("test-agent".to_owned()),
+            ip_address: Some("127.0.0.1".to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_get_and_touch_sessions() {
+        let mut store = HashmapSessionStore::default();
+        let now = Utc::now();
+        let user_id = UserId::default();
+        let newer = session(&user_id, now);
+        let older = session(&user_id, now - Duration::hours(1));
+        let other = session(&UserId::default(), now);
+
+        for session in [&newer, &older, &other] {
+            store.add_session(session.clone()).await.unwrap();
+        }
+
+        assert_eq!(store.get_sessions(&user_id).await, Ok(vec![older.clone(), newer.clone()]));
+
+        let later = now + Duration::minutes(5);
+        assert_eq!(store.touch_session(&older.id, later).await, Ok(()));
+        assert_eq!(store.get_session(&older.id).await.unwrap().last_seen_at, later);
+        assert_eq!(
+            store.touch_session(&Uuid::new_v4(), later).await,
+            Err(SessionStoreError::SessionNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_sessions() {
+        let mut store = HashmapSessionStore::default();
+        let user_id = UserId::default();
+        let first = session(&user_id, Utc::now());
+        let second = session(&user_id, Utc::now());
+        let other = session(&UserId::default(), Utc::now());
+
+        for session in [&first, &second, &other] {
+            store.add_session(session.clone()).await.unwrap();
+        }
+
+        assert_eq!(store.remove_session(&first.id).await, Ok(()));
+        assert_eq!(store.remove_session(&first.id).await, Err(SessionStoreError::SessionNotFound));
+
+        assert_eq!(store.remove_user_sessions(&user_id).await, Ok(vec![second]));
+        assert!(store.get_sessions(&user_id).await.unwrap().is_empty());
+        assert_eq!(store.get_session(&other.id).await, Ok(other));
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let mut store = HashmapSessionStore::default();
+        let user_id = UserId::default();
+        let live = session(&user_id, Utc::now());
+        // Not refreshed for longer than a refresh token lasts
+        let expired = session(&user_id, Utc::now() - Duration::seconds(REFRESH_TOKEN_TTL_SECONDS + 1));
+
+        for session in [&live, &expired] {
+            store.add_session(session.clone()).await.unwrap();
+        }
+
+        assert_eq!(store.prune_expired().await, Ok(1));
+        assert_eq!(store.get_sessions(&user_id).await, Ok(vec![live]));
+    }
+}
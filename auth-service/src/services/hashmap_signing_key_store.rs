@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::domain::{SigningKeyRecord, SigningKeyStore, SigningKeyStoreError};
+
+#[derive(Default)]
+pub struct HashmapSigningKeyStore {
+    keys: HashMap<String, SigningKeyRecord>,
+}
+
+#[async_trait::async_trait]
+impl SigningKeyStore for HashmapSigningKeyStore {
+    async fn add_key(&mut self, key: SigningKeyRecord) -> Result<(), SigningKeyStoreError> {
+        if self.keys.contains_key(&key.kid) {
+            return Err(SigningKeyStoreError::KeyAlreadyExists);
+        }
+        self.keys.insert(key.kid.clone(), key);
+        Ok(())
+    }
+
+    async fn retire_key(&mut self, kid: &str, retire_after: DateTime<Utc>) -> Result<(), SigningKeyStoreError> {
+        let key = self.keys.get_mut(kid).ok_or(SigningKeyStoreError::KeyNotFound)?;
+        key.retire_after = Some(retire_after);
+        Ok(())
+    }
+
+    async fn get_keys(&self) -> Result<Vec<SigningKeyRecord>, SigningKeyStoreError> {
+        let mut keys: Vec<SigningKeyRecord> = self.keys.values().cloned().collect();
+        keys.sort_by_key(|key| key.not_before);
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn record(kid: &str, not_before: DateTime<Utc>) -> SigningKeyRecord {
+        SigningKeyRecord {
+            kid: kid.to_owned(),
+            private_key_pem: format!("pem of {}", kid),
+            not_before,
+            retire_after: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_keys() {
+        let mut store = HashmapSigningKeyStore::default();
+        let now = Utc::now();
+
+        store.add_key(record("new", now)).await.unwrap();
+        store.add_key(record("old", now - Duration::days(1))).await.unwrap();
+        assert_eq!(
+            store.add_key(record("old", now)).await,
+            Err(SigningKeyStoreError::KeyAlreadyExists)
+        );
+
+        assert_eq!(
+            store.get_keys().await.unwrap(),
+            vec![record("old", now - Duration::days(1)), record("new", now)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retire_key() {
+        let mut store = HashmapSigningKeyStore::default();
+        let now = Utc::now();
+        store.add_key(record("kid", now)).await.unwrap();
+
+        let retire_after = now + Duration::minutes(10);
+        store.retire_key("kid", retire_after).await.unwrap();
+        assert_eq!(store.get_keys().await.unwrap()[0].retire_after, Some(retire_after));
+
+        assert_eq!(
+            store.retire_key("unknown", retire_after).await,
+            Err(SigningKeyStoreError::KeyNotFound)
+        );
+    }
+}
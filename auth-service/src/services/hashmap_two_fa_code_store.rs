@@ -1,52 +1,100 @@
 use std::collections::HashMap;
 
-use crate::domain::{
-    Email, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    domain::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, UserId},
+    utils::constants::{TWO_FA_CODE_MAX_ATTEMPTS, TWO_FA_CODE_TTL_SECONDS},
 };
 
-#[derive(Default)]
+#[derive(Clone, Debug)]
+pub struct TwoFACodeEntry {
+    pub login_attempt_id: LoginAttemptId,
+    pub code: TwoFACode,
+    pub created_at: DateTime<Utc>,
+    pub failed_attempts: u32,
+}
+
 pub struct HashmapTwoFACodeStore {
-    pub codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
+    pub codes: HashMap<UserId, TwoFACodeEntry>,
+    ttl: Duration,
+    max_attempts: u32,
+}
+
+impl HashmapTwoFACodeStore {
+    pub fn new(ttl: Duration, max_attempts: u32) -> Self {
+        Self {
+            codes: HashMap::new(),
+            ttl,
+            max_attempts,
+        }
+    }
+}
+
+impl Default for HashmapTwoFACodeStore {
+    fn default() -> Self {
+        Self::new(
+            Duration::seconds(TWO_FA_CODE_TTL_SECONDS),
+            TWO_FA_CODE_MAX_ATTEMPTS,
+        )
+    }
 }
 
-// TODO: implement TwoFACodeStore for HashmapTwoFACodeStore
 #[async_trait::async_trait]
 impl TwoFACodeStore for HashmapTwoFACodeStore {
     async fn add_code(
         &mut self,
-        email: Email,
+        user_id: UserId,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        self.codes.insert(email, (login_attempt_id, code));
+        let entry = TwoFACodeEntry {
+            login_attempt_id,
+            code,
+            created_at: Utc::now(),
+            failed_attempts: 0,
+        };
+        self.codes.insert(user_id, entry);
         Ok(())
     }
 
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>{
-        match self.codes.get(&email) {
-            Some((_login_attempt_id, _two_fa_code)) => {
-                self.codes.remove(&email);
-                Ok(())
-            },
-            None => {
-                return Err(TwoFACodeStoreError::UnexpectedError);
-            }
+    async fn remove_code(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError>{
+        match self.codes.remove(user_id) {
+            Some(_entry) => Ok(()),
+            None => Err(TwoFACodeStoreError::UnexpectedError),
         }
     }
 
     async fn get_code(
         &self,
-        email: &Email,
+        user_id: &UserId,
     ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError>{
-       match self.codes.get(&email) {
-       Some(result) => {
-            return Ok(result.clone());
-       },
-       None => {
-            return Err(TwoFACodeStoreError::LoginAttemptIdNotFound);
-       } 
-       } 
+        let entry = self
+            .codes
+            .get(user_id)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+
+        if entry.failed_attempts >= self.max_attempts {
+            return Err(TwoFACodeStoreError::TooManyAttempts);
+        }
+
+        if Utc::now() >= entry.created_at + self.ttl {
+            return Err(TwoFACodeStoreError::CodeExpired);
+        }
+
+        Ok((entry.login_attempt_id.clone(), entry.code.clone()))
     }
+
+    async fn record_failed_attempt(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError> {
+        let entry = self
+            .codes
+            .get_mut(user_id)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+
+        entry.failed_attempts += 1;
+        Ok(())
+    }
+
 }
 
 #[cfg(test)]
@@ -56,33 +104,33 @@ mod tests {
     #[tokio::test]
     async fn test_add_code_success() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"test@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
         let login_attempt_id = LoginAttemptId::default();
         let code = TwoFACode::default();
 
-        let result = store.add_code(email.clone(), login_attempt_id.clone(), code.clone()).await;
+        let result = store.add_code(user_id, login_attempt_id.clone(), code.clone()).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_add_code_overwrites_existing() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"test@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
         let login_attempt_id_1 = LoginAttemptId::default();
         let code_1 = TwoFACode::default();
 
         // Add first code
-        store.add_code(email.clone(), login_attempt_id_1.clone(), code_1.clone()).await.unwrap();
+        store.add_code(user_id, login_attempt_id_1.clone(), code_1.clone()).await.unwrap();
 
         // Add second code with different values
         let login_attempt_id_2 = LoginAttemptId::default();
         let code_2 = TwoFACode::default();
-        let result = store.add_code(email.clone(), login_attempt_id_2.clone(), code_2.clone()).await;
+        let result = store.add_code(user_id, login_attempt_id_2.clone(), code_2.clone()).await;
         
         assert!(result.is_ok());
 
         // Verify the second code is stored, not the first
-        let (stored_id, stored_code) = store.get_code(&email).await.unwrap();
+        let (stored_id, stored_code) = store.get_code(&user_id).await.unwrap();
         assert_eq!(stored_id, login_attempt_id_2);
         assert_eq!(stored_code, code_2);
     }
@@ -90,13 +138,13 @@ mod tests {
     #[tokio::test]
     async fn test_get_code_success() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"test@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
         let login_attempt_id = LoginAttemptId::default();
         let code = TwoFACode::default();
 
-        store.add_code(email.clone(), login_attempt_id.clone(), code.clone()).await.unwrap();
+        store.add_code(user_id, login_attempt_id.clone(), code.clone()).await.unwrap();
 
-        let result = store.get_code(&email).await;
+        let result = store.get_code(&user_id).await;
         assert!(result.is_ok());
         let (stored_id, stored_code) = result.unwrap();
         assert_eq!(stored_id, login_attempt_id);
@@ -106,9 +154,9 @@ mod tests {
     #[tokio::test]
     async fn test_get_code_not_found() {
         let store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"nonexistent@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
 
-        let result = store.get_code(&email).await;
+        let result = store.get_code(&user_id).await;
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), TwoFACodeStoreError::LoginAttemptIdNotFound);
     }
@@ -116,26 +164,26 @@ mod tests {
     #[tokio::test]
     async fn test_remove_code_success() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"test@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
         let login_attempt_id = LoginAttemptId::default();
         let code = TwoFACode::default();
 
-        store.add_code(email.clone(), login_attempt_id, code).await.unwrap();
+        store.add_code(user_id, login_attempt_id, code).await.unwrap();
 
-        let result = store.remove_code(&email).await;
+        let result = store.remove_code(&user_id).await;
         assert!(result.is_ok());
 
         // Verify it's actually removed
-        let get_result = store.get_code(&email).await;
+        let get_result = store.get_code(&user_id).await;
         assert!(get_result.is_err());
     }
 
     #[tokio::test]
     async fn test_remove_code_not_found() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(&"nonexistent@example.com".to_string()).unwrap();
+        let user_id = UserId::default();
 
-        let result = store.remove_code(&email).await;
+        let result = store.remove_code(&user_id).await;
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), TwoFACodeStoreError::UnexpectedError);
     }
@@ -144,8 +192,8 @@ mod tests {
     async fn test_multiple_users() {
         let mut store = HashmapTwoFACodeStore::default();
         
-        let email1 = Email::parse(&"user1@example.com".to_string()).unwrap();
-        let email2 = Email::parse(&"user2@example.com".to_string()).unwrap();
+        let user_id_1 = UserId::default();
+        let user_id_2 = UserId::default();
         
         let id1 = LoginAttemptId::default();
         let id2 = LoginAttemptId::default();
@@ -154,16 +202,66 @@ mod tests {
         let code2 = TwoFACode::default();
 
         // Add codes for both users
-        store.add_code(email1.clone(), id1.clone(), code1.clone()).await.unwrap();
-        store.add_code(email2.clone(), id2.clone(), code2.clone()).await.unwrap();
+        store.add_code(user_id_1, id1.clone(), code1.clone()).await.unwrap();
+        store.add_code(user_id_2, id2.clone(), code2.clone()).await.unwrap();
 
         // Verify both are stored correctly
-        let (stored_id1, stored_code1) = store.get_code(&email1).await.unwrap();
-        let (stored_id2, stored_code2) = store.get_code(&email2).await.unwrap();
+        let (stored_id1, stored_code1) = store.get_code(&user_id_1).await.unwrap();
+        let (stored_id2, stored_code2) = store.get_code(&user_id_2).await.unwrap();
 
         assert_eq!(stored_id1, id1);
         assert_eq!(stored_code1, code1);
         assert_eq!(stored_id2, id2);
         assert_eq!(stored_code2, code2);
     }
+
+    #[tokio::test]
+    async fn test_get_code_expired() {
+        let mut store = HashmapTwoFACodeStore::new(Duration::zero(), TWO_FA_CODE_MAX_ATTEMPTS);
+        let user_id = UserId::default();
+
+        store.add_code(user_id, LoginAttemptId::default(), TwoFACode::default()).await.unwrap();
+
+        let result = store.get_code(&user_id).await;
+        assert_eq!(result.unwrap_err(), TwoFACodeStoreError::CodeExpired);
+    }
+
+    #[tokio::test]
+    async fn test_code_invalidated_after_max_failed_attempts() {
+        let mut store = HashmapTwoFACodeStore::new(Duration::minutes(10), 3);
+        let user_id = UserId::default();
+
+        store.add_code(user_id, LoginAttemptId::default(), TwoFACode::default()).await.unwrap();
+
+        for _ in 0..2 {
+            store.record_failed_attempt(&user_id).await.unwrap();
+            assert!(store.get_code(&user_id).await.is_ok());
+        }
+
+        store.record_failed_attempt(&user_id).await.unwrap();
+        let result = store.get_code(&user_id).await;
+        assert_eq!(result.unwrap_err(), TwoFACodeStoreError::TooManyAttempts);
+    }
+
+    #[tokio::test]
+    async fn test_add_code_resets_failed_attempts() {
+        let mut store = HashmapTwoFACodeStore::new(Duration::minutes(10), 1);
+        let user_id = UserId::default();
+
+        store.add_code(user_id, LoginAttemptId::default(), TwoFACode::default()).await.unwrap();
+        store.record_failed_attempt(&user_id).await.unwrap();
+        assert!(store.get_code(&user_id).await.is_err());
+
+        store.add_code(user_id, LoginAttemptId::default(), TwoFACode::default()).await.unwrap();
+        assert!(store.get_code(&user_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_record_failed_attempt_not_found() {
+        let mut store = HashmapTwoFACodeStore::default();
+        let user_id = UserId::default();
+
+        let result = store.record_failed_attempt(&user_id).await;
+        assert_eq!(result.unwrap_err(), TwoFACodeStoreError::LoginAttemptIdNotFound);
+    }
 }
\ No newline at end of file
@@ -1,62 +1,274 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::domain::{Email, Password, User, UserStoreError};
+use chrono::{DateTime, Utc};
+
+use crate::domain::{Email, HashedPassword, TwoFactorMethod, User, UserId, UserStoreError};
 use crate::domain::UserStore;
 
 #[derive(Default)]
 pub struct HashmapUserStore {
-    pub users: HashMap<Email, User>,
+    pub users: HashMap<UserId, User>,
+    // Which user each address belongs to. Kept in step with `users` by every write
+    // that changes an email, so lookups by address don't scan every user
+    pub user_ids: HashMap<Email, UserId>,
+    // Hashes of each user's unused recovery codes
+    pub recovery_codes: HashMap<UserId, HashSet<String>>,
+    // Addresses given up in an email change, kept for their old owner until the time given
+    pub reserved_emails: HashMap<Email, (UserId, DateTime<Utc>)>,
+}
+
+impl HashmapUserStore {
+    fn user_id(&self, email: &Email) -> Option<UserId> {
+        self.user_ids.get(email).copied()
+    }
+
+    fn user_mut(&mut self, email: &Email) -> Option<&mut User> {
+        let id = self.user_id(email)?;
+        self.users.get_mut(&id)
+    }
+
+    // Whether `email` is kept for an account other than `id`
+    fn is_reserved_for_another(&self, email: &Email, id: Option<&UserId>) -> bool {
+        self.reserved_emails
+            .get(email)
+            .is_some_and(|(owner, until)| Some(owner) != id && Utc::now() < *until)
+    }
 }
 
 #[async_trait::async_trait]
 impl UserStore for HashmapUserStore {
      async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        if self.users.contains_key(&user.email) {
+        if self.user_ids.contains_key(&user.email) || self.is_reserved_for_another(&user.email, None) {
             return Err(UserStoreError::UserAlreadyExists);
         }
 
-        self.users.insert(user.email.clone(), user);
+        self.user_ids.insert(user.email.clone(), user.id);
+        self.users.insert(user.id, user);
         Ok(())
     }
 
     async  fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
-        let res = match self.users.get(&email) {
+        let res = match self.user_id(email).and_then(|id| self.users.get(&id)) {
             Some(user) => Ok(user.clone()),
             None => Err(UserStoreError::UserNotFound),
         };
         return res;
     }
 
-    async fn validate_user(&self, email: &Email, password: &Password) -> Result<(), UserStoreError> {
-        match self.users.get(&email) {
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        self.users
+            .get(id)
+            .cloned()
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
+    async fn update_password(&mut self, email: &Email, password: HashedPassword) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
             Some(user) => {
-                if user.password.as_ref() == password.as_ref() {
-                    return Ok(());
-                } else {
-                    return Err(UserStoreError::InvalidCredentials);
+                user.password = password;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn upgrade_password_hash(
+        &mut self,
+        email: &Email,
+        current: &HashedPassword,
+        password: HashedPassword,
+    ) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                if user.password == *current {
+                    user.password = password;
                 }
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn mark_email_verified(&mut self, email: &Email) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.email_verified = true;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn set_verification_email_sent_at(
+        &mut self,
+        email: &Email,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.verification_email_sent_at = Some(sent_at);
+                Ok(())
             }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
 
-            None => Err(UserStoreError::InvalidCredentials),
+    async fn set_totp_secret(&mut self, email: &Email, secret: Option<String>) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.totp_secret = secret;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
         }
     }
+
+    async fn set_two_fa_method(&mut self, email: &Email, method: TwoFactorMethod) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.two_fa_method = method;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn replace_recovery_codes(&mut self, email: &Email, code_hashes: Vec<String>) -> Result<(), UserStoreError> {
+        let id = self.user_id(email).ok_or(UserStoreError::UserNotFound)?;
+        self.recovery_codes
+            .insert(id, code_hashes.into_iter().collect());
+        Ok(())
+    }
+
+    async fn consume_recovery_code(&mut self, email: &Email, code_hash: &str) -> Result<(), UserStoreError> {
+        let removed = self
+            .user_id(email)
+            .and_then(|id| self.recovery_codes.get_mut(&id))
+            .is_some_and(|codes| codes.remove(code_hash));
+
+        match removed {
+            true => Ok(()),
+            false => Err(UserStoreError::InvalidRecoveryCode),
+        }
+    }
+
+    async fn count_recovery_codes(&self, email: &Email) -> Result<usize, UserStoreError> {
+        let id = self.user_id(email).ok_or(UserStoreError::UserNotFound)?;
+        Ok(self.recovery_codes.get(&id).map_or(0, HashSet::len))
+    }
+
+    async fn record_failed_login(&mut self, email: &Email) -> Result<u32, UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.failed_login_attempts += 1;
+                Ok(user.failed_login_attempts)
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn lock_account(&mut self, email: &Email, locked_until: DateTime<Utc>) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.locked_until = Some(locked_until);
+                user.lockout_count += 1;
+                user.failed_login_attempts = 0;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn reset_failed_logins(&mut self, email: &Email) -> Result<(), UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.failed_login_attempts = 0;
+                user.lockout_count = 0;
+                user.locked_until = None;
+                Ok(())
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn record_totp_step(&mut self, email: &Email, step: u64) -> Result<(), UserStoreError> {
+        let user = self.user_mut(email).ok_or(UserStoreError::UserNotFound)?;
+        if user.totp_last_step.is_some_and(|last_step| step <= last_step) {
+            return Err(UserStoreError::TotpCodeReused);
+        }
+        user.totp_last_step = Some(step);
+        Ok(())
+    }
+
+    async fn bump_token_version(&mut self, email: &Email) -> Result<u32, UserStoreError> {
+        match self.user_mut(email) {
+            Some(user) => {
+                user.token_version += 1;
+                Ok(user.token_version)
+            }
+            None => Err(UserStoreError::UserNotFound),
+        }
+    }
+
+    async fn set_disabled(&mut self, email: &Email, disabled: bool) -> Result<(), UserStoreError> {
+        let user = self.user_mut(email).ok_or(UserStoreError::UserNotFound)?;
+        user.disabled = disabled;
+        Ok(())
+    }
+
+    async fn change_email(
+        &mut self,
+        id: &UserId,
+        new_email: &Email,
+        reserve_old_until: Option<DateTime<Utc>>,
+    ) -> Result<(), UserStoreError> {
+        if self.user_id(new_email).is_some_and(|owner| owner != *id)
+            || self.is_reserved_for_another(new_email, Some(id))
+        {
+            return Err(UserStoreError::UserAlreadyExists);
+        }
+
+        let user = self.users.get_mut(id).ok_or(UserStoreError::UserNotFound)?;
+        let email = std::mem::replace(&mut user.email, new_email.clone());
+        user.email_verified = true;
+        self.user_ids.remove(&email);
+        self.user_ids.insert(new_email.clone(), *id);
+
+        let now = Utc::now();
+        self.reserved_emails.retain(|reserved, (_, until)| reserved != new_email && now < *until);
+        if let Some(until) = reserve_old_until {
+            self.reserved_emails.insert(email, (*id, until));
+        }
+        Ok(())
+    }
+
+    async fn delete_user(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let user = self.users.remove(id).ok_or(UserStoreError::UserNotFound)?;
+        self.user_ids.remove(&user.email);
+        self.recovery_codes.remove(id);
+        self.reserved_emails.retain(|_, (owner, _)| owner != id);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::{hash_with_weak_params, Password};
+
+    async fn hash(password: &str) -> HashedPassword {
+        HashedPassword::parse(Password::parse(password).unwrap()).await.unwrap()
+    }
 
     #[tokio::test]
     async fn test_add_user() {
-        let mut store = HashmapUserStore {
-            users: HashMap::new(),
-        };
+        let mut store = HashmapUserStore::default();
 
-        let user_1 = User::new(Email::parse(&("email_1@gmail.com")).unwrap(), Password::parse(&("password_1")).unwrap(), false);
-        let user_2 = User::new(Email::parse(&("email_2@gmail.com")).unwrap(), Password::parse(&("password_2")).unwrap(), true);
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::Email);
 
-        store.add_user(user_1.clone()).await;
-        store.add_user(user_2.clone()).await;
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
 
         match store.get_user(&user_1.email).await {
             Ok(user) => assert_eq!(user_1.email, user.email),
@@ -71,39 +283,342 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user() {
-        let mut store = HashmapUserStore {
-            users: HashMap::new(),
-        };
+        let mut store = HashmapUserStore::default();
 
-        let user_1 = User::new(Email::parse(&("email_1@gmail.com")).unwrap(), Password::parse(&("password_1")).unwrap(), false);
-        let user_2 = User::new(Email::parse(&("email_2@gmail.com")).unwrap(), Password::parse(&("password_2")).unwrap(), true);
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
 
-        store.add_user(user_1.clone()).await;
+        store.add_user(user_1.clone()).await.unwrap();
     
         match store.get_user(&user_1.email).await {
             Ok(user) => assert_eq!(user.email, user_1.email),
             Err(e) => panic!("Expected Ok, got Err: {:?}", e)
         }
 
-        match store.get_user(&Email::parse(&("non_existent_email@gmail.com")).unwrap()).await {
+        match store.get_user(&Email::parse("non_existent_email@gmail.com").unwrap()).await {
             Ok(_user) => panic!("Expected UserNotFound error, but got Ok"),
             Err(e) => assert_eq!(UserStoreError::UserNotFound, e) 
         }
     }
 
     #[tokio::test]
-    async fn test_validate_user() {
-        let mut store = HashmapUserStore {
-            users: HashMap::new(),
-        };
+    async fn test_update_password() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.update_password(&user_1.email, hash("new_password").await).await, Ok(()));
+
+        let password = store.get_user(&user_1.email).await.unwrap().password;
+        assert!(password.verify_raw_password(&Password::parse("password_1").unwrap()).await.is_err());
+        assert!(password.verify_raw_password(&Password::parse("new_password").unwrap()).await.is_ok());
+        assert_eq!(
+            store
+                .update_password(&Email::parse("non_existent_email@gmail.com").unwrap(), hash("new_password").await)
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_email_verification() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        let sent_at = Utc::now();
+        assert_eq!(store.set_verification_email_sent_at(&user_1.email, sent_at).await, Ok(()));
+        assert_eq!(store.mark_email_verified(&user_1.email).await, Ok(()));
+
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert!(user.email_verified);
+        assert_eq!(user.verification_email_sent_at, Some(sent_at));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.mark_email_verified(&unknown).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(
+            store.set_verification_email_sent_at(&unknown, sent_at).await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_two_fa_settings() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Email);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.set_totp_secret(&user_1.email, Some("SECRET".to_owned())).await, Ok(()));
+        assert_eq!(store.set_two_fa_method(&user_1.email, TwoFactorMethod::Totp).await, Ok(()));
+
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.two_fa_method, TwoFactorMethod::Totp);
+        assert_eq!(user.totp_secret.as_deref(), Some("SECRET"));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.set_totp_secret(&unknown, None).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(
+            store.set_two_fa_method(&unknown, TwoFactorMethod::None).await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
 
-        let user_1 = User::new(Email::parse(&("email_1@gmail.com")).unwrap(), Password::parse(&("password_1")).unwrap(), false);
+    #[tokio::test]
+    async fn test_recovery_codes() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Email);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(0));
+
+        let hashes = vec!["hash_1".to_owned(), "hash_2".to_owned()];
+        assert_eq!(store.replace_recovery_codes(&user_1.email, hashes).await, Ok(()));
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(2));
+
+        // Each code works once
+        assert_eq!(store.consume_recovery_code(&user_1.email, "hash_1").await, Ok(()));
+        assert_eq!(
+            store.consume_recovery_code(&user_1.email, "hash_1").await,
+            Err(UserStoreError::InvalidRecoveryCode)
+        );
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(1));
+
+        // Regenerating invalidates the old codes
+        assert_eq!(store.replace_recovery_codes(&user_1.email, vec!["hash_3".to_owned()]).await, Ok(()));
+        assert_eq!(
+            store.consume_recovery_code(&user_1.email, "hash_2").await,
+            Err(UserStoreError::InvalidRecoveryCode)
+        );
+        assert_eq!(store.consume_recovery_code(&user_1.email, "hash_3").await, Ok(()));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(
+            store.replace_recovery_codes(&unknown, vec![]).await,
+            Err(UserStoreError::UserNotFound)
+        );
+        assert_eq!(store.count_recovery_codes(&unknown).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(1));
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(2));
+
+        let locked_until = Utc::now();
+        assert_eq!(store.lock_account(&user_1.email, locked_until).await, Ok(()));
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.failed_login_attempts, 0);
+        assert_eq!(user.lockout_count, 1);
+        assert_eq!(user.locked_until, Some(locked_until));
+
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(1));
+        assert_eq!(store.reset_failed_logins(&user_1.email).await, Ok(()));
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.failed_login_attempts, 0);
+        assert_eq!(user.lockout_count, 0);
+        assert_eq!(user.locked_until, None);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.record_failed_login(&unknown).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.lock_account(&unknown, locked_until).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.reset_failed_logins(&unknown).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_record_totp_step() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Totp);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.record_totp_step(&user_1.email, 100).await, Ok(()));
+        assert_eq!(store.record_totp_step(&user_1.email, 100).await, Err(UserStoreError::TotpCodeReused));
+        assert_eq!(store.record_totp_step(&user_1.email, 99).await, Err(UserStoreError::TotpCodeReused));
+        assert_eq!(store.record_totp_step(&user_1.email, 101).await, Ok(()));
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().totp_last_step, Some(101));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.record_totp_step(&unknown, 1).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_bump_token_version() {
+        let mut store = HashmapUserStore::default();
 
-        store.add_user(user_1.clone()).await;
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().token_version, 0);
 
-        match store.validate_user(&user_1.email, &user_1.password).await {
-            Ok(()) => (),
-            Err(e) => assert_eq!(UserStoreError::UnexpectedError, e)
-        } 
-   }
+        assert_eq!(store.bump_token_version(&user_1.email).await, Ok(1));
+        assert_eq!(store.bump_token_version(&user_1.email).await, Ok(2));
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().token_version, 2);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.bump_token_version(&unknown).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_set_disabled() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert!(!store.get_user(&user_1.email).await.unwrap().disabled);
+
+        assert_eq!(store.set_disabled(&user_1.email, true).await, Ok(()));
+        assert!(store.get_user(&user_1.email).await.unwrap().disabled);
+        assert_eq!(store.set_disabled(&user_1.email, false).await, Ok(()));
+        assert!(!store.get_user(&user_1.email).await.unwrap().disabled);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.set_disabled(&unknown, true).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.get_user_by_id(&user_1.id).await, Ok(user_1));
+        assert_eq!(store.get_user_by_id(&UserId::default()).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_change_email() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+        store.replace_recovery_codes(&user_1.email, vec!["hash_1".to_owned()]).await.unwrap();
+
+        // Another account's address can't be taken
+        assert_eq!(
+            store.change_email(&user_1.id, &user_2.email, None).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        assert_eq!(store.change_email(&user_1.id, &new_email, None).await, Ok(()));
+        assert_eq!(store.get_user(&user_1.email).await, Err(UserStoreError::UserNotFound));
+
+        let user = store.get_user(&new_email).await.unwrap();
+        assert_eq!(user.id, user_1.id);
+        assert_eq!(user.email, new_email);
+        assert!(user.email_verified);
+        assert_eq!(user.password, user_1.password);
+        assert_eq!(store.count_recovery_codes(&new_email).await, Ok(1));
+        assert_eq!(store.get_user_by_id(&user_1.id).await.unwrap().email, new_email);
+
+        // Without a reservation the old address is free again, for a different account
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3.clone()).await, Ok(()));
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().id, user_3.id);
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(0));
+
+        assert_eq!(
+            store.change_email(&UserId::default(), &Email::parse("other@gmail.com").unwrap(), None).await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_email_reserves_old_address() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        let until = Utc::now() + chrono::Duration::days(7);
+        assert_eq!(store.change_email(&user_1.id, &new_email, Some(until)).await, Ok(()));
+
+        // Nobody else can have the old address while it's reserved
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3.clone()).await, Err(UserStoreError::UserAlreadyExists));
+        assert_eq!(
+            store.change_email(&user_2.id, &user_1.email, None).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+
+        // Its owner can move back, which lifts the reservation
+        assert_eq!(store.change_email(&user_1.id, &user_1.email, None).await, Ok(()));
+        assert_eq!(store.get_user_by_id(&user_1.id).await.unwrap().email, user_1.email);
+        assert_eq!(store.change_email(&user_1.id, &new_email, None).await, Ok(()));
+        assert_eq!(store.add_user(user_3).await, Ok(()));
+
+        // An expired reservation holds nothing back
+        let other_email = Email::parse("other@gmail.com").unwrap();
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        assert_eq!(store.change_email(&user_2.id, &other_email, Some(past)).await, Ok(()));
+        let user_4 = User::new(user_2.email.clone(), hash("password_4").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_4).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_password_hash() {
+        let mut store = HashmapUserStore::default();
+
+        let password = Password::parse("password_1").unwrap();
+        let weak_hash = hash_with_weak_params(&password);
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), weak_hash.clone(), TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        let upgraded = hash(password.as_ref()).await;
+        store.upgrade_password_hash(&user_1.email, &weak_hash, upgraded.clone()).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().password, upgraded);
+
+        // A stale hash means the password was changed since it was read, so it is kept
+        store.upgrade_password_hash(&user_1.email, &weak_hash, hash("password_2").await).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().password, upgraded);
+
+        assert_eq!(
+            store
+                .upgrade_password_hash(&Email::parse("non_existent_email@gmail.com").unwrap(), &weak_hash, upgraded)
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let mut store = HashmapUserStore::default();
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+        store.replace_recovery_codes(&user_1.email, vec!["hash_1".to_owned()]).await.unwrap();
+
+        // The address given up in a change goes with the account
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        let until = Utc::now() + chrono::Duration::days(7);
+        store.change_email(&user_1.id, &new_email, Some(until)).await.unwrap();
+
+        assert_eq!(store.delete_user(&user_1.id).await, Ok(()));
+        assert_eq!(store.get_user_by_id(&user_1.id).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.get_user(&new_email).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.get_user(&user_2.email).await, Ok(user_2));
+
+        // Neither address carries anything over to a new account
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        let user_4 = User::new(new_email.clone(), hash("password_4").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3).await, Ok(()));
+        assert_eq!(store.add_user(user_4).await, Ok(()));
+        assert_eq!(store.count_recovery_codes(&new_email).await, Ok(0));
+
+        assert_eq!(store.delete_user(&user_1.id).await, Err(UserStoreError::UserNotFound));
+    }
 }
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::domain::{Email, EmailClient};
+
+// Delivers every email as a file in a Maildir (https://cr.yp.to/proto/maildir.html),
+// so local setups and tests can read what users would receive. Mail clients such
+// as mutt can open the directory too.
+#[derive(Clone, Debug)]
+pub struct MaildirEmailClient {
+    dir: PathBuf,
+}
+
+// An email read back from the Maildir
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaildirMessage {
+    pub recipient: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+impl MaildirEmailClient {
+    // Creates the Maildir's tmp, new and cur directories if they don't exist yet
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        for subdir in ["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(subdir))
+                .map_err(|e| format!("Failed to create {}: {}", dir.join(subdir).display(), e))?;
+        }
+        Ok(Self { dir })
+    }
+
+    // Every delivered email, oldest first
+    pub fn messages(&self) -> Result<Vec<MaildirMessage>, String> {
+        let mut paths = Vec::new();
+        for subdir in ["new", "cur"] {
+            let entries = fs::read_dir(self.dir.join(subdir))
+                .map_err(|e| format!("Failed to read the Maildir: {}", e))?;
+            for entry in entries {
+                paths.push(entry.map_err(|e| format!("Failed to read the Maildir: {}", e))?.path());
+            }
+        }
+        // File names start with the delivery time, so they sort chronologically
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+        paths
+            .iter()
+            .map(|path| {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                parse_message(&contents).ok_or_else(|| format!("Malformed message {}", path.display()))
+            })
+            .collect()
+    }
+
+    // Writes to tmp first and then moves the file to new, so readers never see half a message
+    async fn deliver(&self, contents: String) -> Result<(), String> {
+        let name = format!(
+            "{}.{}.auth-service",
+            Utc::now().format("%Y%m%d%H%M%S%6f"),
+            Uuid::new_v4().simple()
+        );
+        let tmp_path = self.dir.join("tmp").join(&name);
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", tmp_path.display(), e))?;
+        tokio::fs::rename(&tmp_path, self.dir.join("new").join(&name))
+            .await
+            .map_err(|e| format!("Failed to deliver {}: {}", name, e))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for MaildirEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        let message = format!(
+            "{}Content-Type: text/plain; charset=utf-8\n\
+             Content-Transfer-Encoding: 8bit\n\
+             \n\
+             {}",
+            headers(recipient, subject),
+            content
+        );
+        self.deliver(message).await
+    }
+
+    async fn send_html_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        text_content: &str,
+        html_content: &str,
+    ) -> Result<(), String> {
+        let boundary = Uuid::new_v4().simple().to_string();
+        let message = format!(
+            "{headers}Content-Type: multipart/alternative; boundary=\"{boundary}\"\n\
+             \n\
+             --{boundary}\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             Content-Transfer-Encoding: 8bit\n\
+             \n\
+             {text_content}\n\
+             --{boundary}\n\
+             Content-Type: text/html; charset=utf-8\n\
+             Content-Transfer-Encoding: 8bit\n\
+             \n\
+             {html_content}\n\
+             --{boundary}--\n",
+            headers = headers(recipient, subject),
+        );
+        self.deliver(message).await
+    }
+}
+
+// Bodies are written as 8bit UTF-8 rather than encoded, which keeps the files readable
+fn headers(recipient: &Email, subject: &str) -> String {
+    format!(
+        "Date: {}\n\
+         To: {}\n\
+         Subject: {}\n\
+         MIME-Version: 1.0\n",
+        Utc::now().to_rfc2822(),
+        recipient.as_ref(),
+        // A line break would end the header early
+        subject.replace(['\r', '\n'], " ")
+    )
+}
+
+// Reads back the messages written above. It is not a general MIME parser.
+fn parse_message(contents: &str) -> Option<MaildirMessage> {
+    let (headers, body) = contents.split_once("\n\n")?;
+    let header = |name: &str| {
+        headers.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim().to_owned())
+        })
+    };
+
+    let recipient = header("To")?;
+    let subject = header("Subject")?;
+    let content_type = header("Content-Type")?;
+
+    let Some(boundary) = content_type
+        .split_once("boundary=")
+        .map(|(_, boundary)| boundary.trim_matches('"').to_owned())
+    else {
+        return Some(MaildirMessage {
+            recipient,
+            subject,
+            text: body.to_owned(),
+            html: None,
+        });
+    };
+
+    let mut text = None;
+    let mut html = None;
+    for part in body.split(&format!("--{}", boundary)) {
+        let Some((part_headers, part_body)) = part.trim_start_matches('\n').split_once("\n\n") else {
+            continue;
+        };
+        // The line break before the next boundary belongs to the boundary
+        let part_body = part_body.strip_suffix('\n').unwrap_or(part_body).to_owned();
+        if part_headers.contains("text/plain") {
+            text = Some(part_body);
+        } else if part_headers.contains("text/html") {
+            html = Some(part_body);
+        }
+    }
+
+    Some(MaildirMessage {
+        recipient,
+        subject,
+        text: text?,
+        html,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> (MaildirEmailClient, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("maildir-{}", Uuid::new_v4()));
+        (MaildirEmailClient::new(&dir).unwrap(), dir)
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_read_back_in_order() {
+        let (client, dir) = client();
+        let recipient = Email::parse("test@example.com").unwrap();
+
+        client.send_email(&recipient, "First", "Plain body\n\nwith a blank line").await.unwrap();
+        client
+            .send_html_email(&recipient, "Second", "Text body", "<p>HTML body</p>")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.messages().unwrap(),
+            vec![
+                MaildirMessage {
+                    recipient: "test@example.com".to_owned(),
+                    subject: "First".to_owned(),
+                    text: "Plain body\n\nwith a blank line".to_owned(),
+                    html: None,
+                },
+                MaildirMessage {
+                    recipient: "test@example.com".to_owned(),
+                    subject: "Second".to_owned(),
+                    text: "Text body".to_owned(),
+                    html: Some("<p>HTML body</p>".to_owned()),
+                },
+            ]
+        );
+
+        // Nothing is left behind in tmp
+        assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subject_cannot_add_headers() {
+        let (client, dir) = client();
+        let recipient = Email::parse("test@example.com").unwrap();
+
+        client
+            .send_email(&recipient, "Hello\nBcc: attacker@example.com", "Body")
+            .await
+            .unwrap();
+
+        let messages = client.messages().unwrap();
+        assert_eq!(messages[0].subject, "Hello Bcc: attacker@example.com");
+        let file = fs::read_dir(dir.join("new")).unwrap().next().unwrap().unwrap().path();
+        assert!(!fs::read_to_string(file).unwrap().contains("\nBcc:"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,4 +1,23 @@
 pub mod hashmap_user_store;
-pub mod hashset_banned_token_store;
+pub mod hashmap_banned_token_store;
 pub mod hashmap_two_fa_code_store;
-pub mod mock_email_client;
\ No newline at end of file
+pub mod hashmap_refresh_token_store;
+pub mod hashmap_one_time_token_store;
+pub mod hashmap_rate_limit_store;
+pub mod hashmap_email_outbox_store;
+pub mod hashmap_credential_store;
+pub mod hashmap_session_store;
+pub mod hashmap_signing_key_store;
+pub mod maildir_email_client;
+pub mod mock_email_client;
+pub mod postgres_user_store;
+pub mod postgres_email_outbox_store;
+pub mod postgres_credential_store;
+pub mod postgres_signing_key_store;
+pub mod redis_banned_token_store;
+pub mod redis_one_time_token_store;
+pub mod redis_refresh_token_store;
+pub mod redis_two_fa_code_store;
+pub mod redis_rate_limit_store;
+pub mod redis_session_store;
+pub mod smtp_email_client;
\ No newline at end of file
@@ -0,0 +1,188 @@
+use sqlx::{postgres::PgRow, PgPool, Row};
+use uuid::Uuid;
+
+use crate::domain::{CredentialStore, CredentialStoreError, UserId, WebauthnCredential};
+
+pub struct PostgresCredentialStore {
+    pool: PgPool,
+}
+
+impl PostgresCredentialStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn credential_from_row(row: &PgRow) -> Result<WebauthnCredential, CredentialStoreError> {
+    let sign_count: i64 = row.get("sign_count");
+
+    Ok(WebauthnCredential {
+        id: row.get("id"),
+        user_id: UserId::from(row.get::<Uuid, _>("user_id")),
+        public_key: row.get("public_key"),
+        sign_count: u32::try_from(sign_count).map_err(|_| CredentialStoreError::UnexpectedError)?,
+        created_at: row.get("created_at"),
+    })
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for PostgresCredentialStore {
+    async fn add_credential(&mut self, credential: WebauthnCredential) -> Result<(), CredentialStoreError> {
+        sqlx::query(
+            "INSERT INTO webauthn_credentials (id, user_id, public_key, sign_count, created_at) \
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&credential.id)
+        .bind(credential.user_id.as_ref())
+        .bind(&credential.public_key)
+        .bind(i64::from(credential.sign_count))
+        .bind(credential.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => CredentialStoreError::CredentialAlreadyExists,
+            _ => CredentialStoreError::UnexpectedError,
+        })?;
+
+        Ok(())
+    }
+
+    async fn get_credential(&self, id: &[u8]) -> Result<WebauthnCredential, CredentialStoreError> {
+        let row = sqlx::query(
+            "SELECT id, user_id, public_key, sign_count, created_at FROM webauthn_credentials WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| CredentialStoreError::UnexpectedError)?
+        .ok_or(CredentialStoreError::CredentialNotFound)?;
+
+        credential_from_row(&row)
+    }
+
+    async fn get_credentials(&self, user_id: &UserId) -> Result<Vec<WebauthnCredential>, CredentialStoreError> {
+        let rows = sqlx::query(
+            "SELECT id, user_id, public_key, sign_count, created_at FROM webauthn_credentials \
+             WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id.as_ref())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| CredentialStoreError::UnexpectedError)?;
+
+        rows.iter().map(credential_from_row).collect()
+    }
+
+    async fn update_sign_count(&mut self, id: &[u8], sign_count: u32) -> Result<(), CredentialStoreError> {
+        let result = sqlx::query("UPDATE webauthn_credentials SET sign_count = $2 WHERE id = $1")
+            .bind(id)
+            .bind(i64::from(sign_count))
+            .execute(&self.pool)
+            .await
+            .map_err(|_| CredentialStoreError::UnexpectedError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(CredentialStoreError::CredentialNotFound);
+        }
+        Ok(())
+    }
+
+    async fn remove_credentials(&mut self, user_id: &UserId) -> Result<(), CredentialStoreError> {
+        sqlx::query("DELETE FROM webauthn_credentials WHERE user_id = $1")
+            .bind(user_id.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| CredentialStoreError::UnexpectedError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+    use crate::{
+        domain::{Email, HashedPassword, Password, TwoFactorMethod, User, UserStore},
+        services::postgres_user_store::PostgresUserStore,
+        utils::test_database::{create_test_database, delete_test_database},
+    };
+
+    async fn configure_store() -> (PostgresCredentialStore, String) {
+        let (pool, db_name) = create_test_database().await;
+        (PostgresCredentialStore::new(pool), db_name)
+    }
+
+    // Credentials belong to a user, so one has to exist first
+    async fn add_user(store: &PostgresCredentialStore, email: &str) -> UserId {
+        let password = HashedPassword::parse(Password::parse("password123").unwrap()).await.unwrap();
+        let user = User::new(Email::parse(email).unwrap(), password, TwoFactorMethod::None);
+        PostgresUserStore::new(store.pool.clone())
+            .add_user(user.clone())
+            .await
+            .unwrap();
+        user.id
+    }
+
+    fn credential(id: &[u8], user_id: &UserId) -> WebauthnCredential {
+        // Postgres keeps microseconds, so round the timestamp for comparisons
+        WebauthnCredential {
+            id: id.to_vec(),
+            user_id: *user_id,
+            public_key: vec![4; 65],
+            sign_count: 0,
+            created_at: DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_get_and_update_credentials() {
+        let (mut store, db_name) = configure_store().await;
+        let user_id = add_user(&store, "test@example.com").await;
+        let other_user_id = add_user(&store, "other@example.com").await;
+
+        let first = credential(b"first", &user_id);
+        let second = WebauthnCredential {
+            created_at: first.created_at + Duration::seconds(1),
+            ..credential(b"second", &user_id)
+        };
+        assert_eq!(store.add_credential(second.clone()).await, Ok(()));
+        assert_eq!(store.add_credential(first.clone()).await, Ok(()));
+        assert_eq!(store.add_credential(credential(b"other", &other_user_id)).await, Ok(()));
+        assert_eq!(
+            store.add_credential(credential(b"first", &other_user_id)).await,
+            Err(CredentialStoreError::CredentialAlreadyExists)
+        );
+
+        assert_eq!(store.get_credential(b"first").await, Ok(first.clone()));
+        assert_eq!(store.get_credential(b"unknown").await, Err(CredentialStoreError::CredentialNotFound));
+        assert_eq!(store.get_credentials(&user_id).await, Ok(vec![first, second]));
+
+        assert_eq!(store.update_sign_count(b"first", u32::MAX).await, Ok(()));
+        assert_eq!(store.get_credential(b"first").await.unwrap().sign_count, u32::MAX);
+        assert_eq!(
+            store.update_sign_count(b"unknown", 1).await,
+            Err(CredentialStoreError::CredentialNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_credentials_stay_with_user_after_email_change() {
+        let (mut store, db_name) = configure_store().await;
+        let user_id = add_user(&store, "test@example.com").await;
+
+        let first = credential(b"first", &user_id);
+        store.add_credential(first.clone()).await.unwrap();
+
+        PostgresUserStore::new(store.pool.clone())
+            .change_email(&user_id, &Email::parse("new@example.com").unwrap(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_credentials(&user_id).await, Ok(vec![first]));
+
+        delete_test_database(&db_name).await;
+    }
+}
@@ -0,0 +1,282 @@
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use uuid::Uuid;
+
+use crate::domain::{Email, EmailOutboxStore, EmailOutboxStoreError, OutboxMessage};
+
+const COLUMNS: &str = "id, recipient, subject, text_body, html_body, attempts, last_error, \
+                       created_at, next_attempt_at, dead_lettered_at";
+
+pub struct PostgresEmailOutboxStore {
+    pool: PgPool,
+}
+
+impl PostgresEmailOutboxStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn message_from_row(row: &PgRow) -> Result<OutboxMessage, EmailOutboxStoreError> {
+    let recipient: String = row.get("recipient");
+    let attempts: i32 = row.get("attempts");
+
+    Ok(OutboxMessage {
+        id: row.get("id"),
+        recipient: Email::parse(&recipient).map_err(|_| EmailOutboxStoreError::UnexpectedError)?,
+        subject: row.get("subject"),
+        text: row.get("text_body"),
+        html: row.get("html_body"),
+        attempts: u32::try_from(attempts).map_err(|_| EmailOutboxStoreError::UnexpectedError)?,
+        last_error: row.get("last_error"),
+        created_at: row.get("created_at"),
+        next_attempt_at: row.get("next_attempt_at"),
+        dead_lettered_at: row.get("dead_lettered_at"),
+    })
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for PostgresEmailOutboxStore {
+    async fn enqueue(&mut self, message: OutboxMessage) -> Result<(), EmailOutboxStoreError> {
+        sqlx::query(&format!(
+            "INSERT INTO email_outbox ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            COLUMNS
+        ))
+        .bind(message.id)
+        .bind(message.recipient.as_ref())
+        .bind(&message.subject)
+        .bind(&message.text)
+        .bind(&message.html)
+        .bind(i32::try_from(message.attempts).map_err(|_| EmailOutboxStoreError::UnexpectedError)?)
+        .bind(&message.last_error)
+        .bind(message.created_at)
+        .bind(message.next_attempt_at)
+        .bind(message.dead_lettered_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    async fn claim_due(
+        &mut self,
+        now: DateTime<Utc>,
+        lease: chrono::Duration,
+        limit: u32,
+    ) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError> {
+        // SKIP LOCKED lets workers on other replicas claim the rest of the queue meanwhile
+        let rows = sqlx::query(&format!(
+            "UPDATE email_outbox SET next_attempt_at = $2 \
+             WHERE id IN ( \
+                 SELECT id FROM email_outbox \
+                 WHERE dead_lettered_at IS NULL AND next_attempt_at <= $1 \
+                 ORDER BY created_at LIMIT $3 \
+                 FOR UPDATE SKIP LOCKED \
+             ) \
+             RETURNING {}",
+            COLUMNS
+        ))
+        .bind(now)
+        .bind(now + lease)
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        // RETURNING doesn't keep the subquery's order
+        let mut messages = rows
+            .iter()
+            .map(message_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        messages.sort_by_key(|message| message.created_at);
+        Ok(messages)
+    }
+
+    async fn mark_sent(&mut self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        let result = sqlx::query("DELETE FROM email_outbox WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(EmailOutboxStoreError::MessageNotFound);
+        }
+        Ok(())
+    }
+
+    async fn record_failure(
+        &mut self,
+        id: &Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        // A missing retry time dead-letters the message instead
+        let result = sqlx::query(
+            "UPDATE email_outbox SET attempts = attempts + 1, last_error = $2, \
+             next_attempt_at = COALESCE($3, next_attempt_at), \
+             dead_lettered_at = CASE WHEN $3 IS NULL THEN now() ELSE NULL END \
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(EmailOutboxStoreError::MessageNotFound);
+        }
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>, EmailOutboxStoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM email_outbox WHERE dead_lettered_at IS NOT NULL ORDER BY dead_lettered_at",
+            COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        rows.iter().map(message_from_row).collect()
+    }
+
+    async fn count_queued(&self) -> Result<usize, EmailOutboxStoreError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM email_outbox WHERE dead_lettered_at IS NULL")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        usize::try_from(count).map_err(|_| EmailOutboxStoreError::UnexpectedError)
+    }
+
+    async fn count_dead_letters(&self) -> Result<usize, EmailOutboxStoreError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM email_outbox WHERE dead_lettered_at IS NOT NULL")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        usize::try_from(count).map_err(|_| EmailOutboxStoreError::UnexpectedError)
+    }
+
+    async fn replay(&mut self, id: &Uuid, now: DateTime<Utc>) -> Result<(), EmailOutboxStoreError> {
+        // Queued messages are retried anyway, so only dead letters can be replayed
+        let result = sqlx::query(
+            "UPDATE email_outbox SET attempts = 0, next_attempt_at = $2, dead_lettered_at = NULL \
+             WHERE id = $1 AND dead_lettered_at IS NOT NULL",
+        )
+        .bind(id)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| EmailOutboxStoreError::UnexpectedError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(EmailOutboxStoreError::MessageNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::utils::test_database::{create_test_database, delete_test_database};
+
+    async fn configure_store() -> (PostgresEmailOutboxStore, String) {
+        let (pool, db_name) = create_test_database().await;
+        (PostgresEmailOutboxStore::new(pool), db_name)
+    }
+
+    fn message(subject: &str) -> OutboxMessage {
+        // Postgres keeps microseconds, so round the timestamps for comparisons
+        let now = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        OutboxMessage {
+            created_at: now,
+            next_attempt_at: now,
+            ..OutboxMessage::new(
+                Email::parse("test@example.com").unwrap(),
+                subject.to_owned(),
+                "text".to_owned(),
+                "<p>html</p>".to_owned(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_leases_messages() {
+        let (mut store, db_name) = configure_store().await;
+
+        let first = message("First");
+        let second = OutboxMessage { created_at: first.created_at + Duration::seconds(1), ..message("Second") };
+        store.enqueue(second.clone()).await.unwrap();
+        store.enqueue(first.clone()).await.unwrap();
+
+        let now = first.created_at + Duration::seconds(5);
+        let lease = Duration::seconds(60);
+        let claimed = store.claim_due(now, lease, 10).await.unwrap();
+        assert_eq!(
+            claimed,
+            vec![
+                OutboxMessage { next_attempt_at: now + lease, ..first.clone() },
+                OutboxMessage { next_attempt_at: now + lease, ..second.clone() },
+            ]
+        );
+
+        // Leased to the first claimant until the lease runs out
+        assert!(store.claim_due(now, lease, 10).await.unwrap().is_empty());
+        let later = now + Duration::seconds(61);
+        assert_eq!(store.claim_due(later, lease, 1).await.unwrap()[0].id, first.id);
+
+        assert_eq!(store.mark_sent(&first.id).await, Ok(()));
+        assert_eq!(store.mark_sent(&first.id).await, Err(EmailOutboxStoreError::MessageNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_failures_dead_letter_and_replay() {
+        let (mut store, db_name) = configure_store().await;
+
+        let message = message("Hello");
+        store.enqueue(message.clone()).await.unwrap();
+
+        let retry_at = message.created_at + Duration::seconds(30);
+        assert_eq!(store.record_failure(&message.id, "connection refused", Some(retry_at)).await, Ok(()));
+        assert!(store.claim_due(message.created_at, Duration::seconds(60), 10).await.unwrap().is_empty());
+        assert!(store.dead_letters().await.unwrap().is_empty());
+
+        assert_eq!(store.replay(&message.id, Utc::now()).await, Err(EmailOutboxStoreError::MessageNotFound));
+
+        assert_eq!(store.count_queued().await, Ok(1));
+        assert_eq!(store.record_failure(&message.id, "mailbox unavailable", None).await, Ok(()));
+        assert_eq!(store.count_queued().await, Ok(0));
+        assert_eq!(store.count_dead_letters().await, Ok(1));
+        let dead_letters = store.dead_letters().await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(dead_letters[0].last_error.as_deref(), Some("mailbox unavailable"));
+        assert!(dead_letters[0].dead_lettered_at.is_some());
+
+        let much_later = Utc::now() + Duration::days(1);
+        assert!(store.claim_due(much_later, Duration::seconds(60), 10).await.unwrap().is_empty());
+
+        assert_eq!(store.replay(&message.id, Utc::now()).await, Ok(()));
+        assert!(store.dead_letters().await.unwrap().is_empty());
+        let claimed = store.claim_due(much_later, Duration::seconds(60), 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].attempts, 0);
+
+        assert_eq!(
+            store.record_failure(&Uuid::new_v4(), "error", None).await,
+            Err(EmailOutboxStoreError::MessageNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Row};
+
+use crate::domain::{SigningKeyRecord, SigningKeyStore, SigningKeyStoreError};
+
+pub struct PostgresSigningKeyStore {
+    pool: PgPool,
+}
+
+impl PostgresSigningKeyStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn key_from_row(row: &PgRow) -> SigningKeyRecord {
+    SigningKeyRecord {
+        kid: row.get("kid"),
+        private_key_pem: row.get("private_key_pem"),
+        not_before: row.get("not_before"),
+        retire_after: row.get("retire_after"),
+    }
+}
+
+#[async_trait::async_trait]
+impl SigningKeyStore for PostgresSigningKeyStore {
+    async fn add_key(&mut self, key: SigningKeyRecord) -> Result<(), SigningKeyStoreError> {
+        sqlx::query(
+            "INSERT INTO signing_keys (kid, private_key_pem, not_before, retire_after) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(&key.kid)
+        .bind(&key.private_key_pem)
+        .bind(key.not_before)
+        .bind(key.retire_after)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => SigningKeyStoreError::KeyAlreadyExists,
+            _ => SigningKeyStoreError::UnexpectedError,
+        })?;
+
+        Ok(())
+    }
+
+    async fn retire_key(&mut self, kid: &str, retire_after: DateTime<Utc>) -> Result<(), SigningKeyStoreError> {
+        let result = sqlx::query("UPDATE signing_keys SET retire_after = $2 WHERE kid = $1")
+            .bind(kid)
+            .bind(retire_after)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| SigningKeyStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(SigningKeyStoreError::KeyNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_keys(&self) -> Result<Vec<SigningKeyRecord>, SigningKeyStoreError> {
+        let rows = sqlx::query(
+            "SELECT kid, private_key_pem, not_before, retire_after FROM signing_keys ORDER BY not_before",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| SigningKeyStoreError::UnexpectedError)?;
+
+        Ok(rows.iter().map(key_from_row).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::utils::test_database::{create_test_database, delete_test_database};
+
+    async fn configure_store() -> (PostgresSigningKeyStore, String) {
+        let (pool, db_name) = create_test_database().await;
+        (PostgresSigningKeyStore::new(pool), db_name)
+    }
+
+    fn record(kid: &str, not_before: DateTime<Utc>) -> SigningKeyRecord {
+        // Postgres keeps microseconds, so round the timestamp for comparisons
+        SigningKeyRecord {
+            kid: kid.to_owned(),
+            private_key_pem: format!("pem of {}", kid),
+            not_before: DateTime::from_timestamp(not_before.timestamp(), 0).unwrap(),
+            retire_after: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_keys() {
+        let (mut store, db_name) = configure_store().await;
+        let now = Utc::now();
+
+        store.add_key(record("new", now)).await.unwrap();
+        store.add_key(record("old", now - Duration::days(1))).await.unwrap();
+        assert_eq!(
+            store.add_key(record("old", now)).await,
+            Err(SigningKeyStoreError::KeyAlreadyExists)
+        );
+
+        assert_eq!(
+            store.get_keys().await.unwrap(),
+            vec![record("old", now - Duration::days(1)), record("new", now)]
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_retire_key() {
+        let (mut store, db_name) = configure_store().await;
+        let now = Utc::now();
+        store.add_key(record("kid", now)).await.unwrap();
+
+        let retire_after = record("kid", now + Duration::minutes(10)).not_before;
+        store.retire_key("kid", retire_after).await.unwrap();
+        assert_eq!(store.get_keys().await.unwrap()[0].retire_after, Some(retire_after));
+
+        assert_eq!(
+            store.retire_key("unknown", retire_after).await,
+            Err(SigningKeyStoreError::KeyNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+}
@@ -0,0 +1,859 @@
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use uuid::Uuid;
+
+use crate::domain::{Email, HashedPassword, TwoFactorMethod, User, UserId, UserStore, UserStoreError};
+
+pub struct PostgresUserStore {
+    pool: PgPool,
+}
+
+impl PostgresUserStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+const USER_COLUMNS: &str = "id, email, password_hash, two_fa_method, totp_secret, roles, email_verified, \
+     verification_email_sent_at, failed_login_attempts, lockout_count, locked_until, \
+     token_version, totp_last_step, disabled";
+
+fn user_from_row(row: &PgRow) -> Result<User, UserStoreError> {
+    let email: String = row.get("email");
+    let password_hash: String = row.get("password_hash");
+    let two_fa_method: String = row.get("two_fa_method");
+    let failed_login_attempts: i32 = row.get("failed_login_attempts");
+    let lockout_count: i32 = row.get("lockout_count");
+    let token_version: i32 = row.get("token_version");
+    let totp_last_step: Option<i64> = row.get("totp_last_step");
+
+    Ok(User {
+        id: UserId::from(row.get::<Uuid, _>("id")),
+        email: Email::parse(&email).map_err(|_| UserStoreError::UnexpectedError)?,
+        password: HashedPassword::parse_password_hash(password_hash)
+            .map_err(|_| UserStoreError::UnexpectedError)?,
+        two_fa_method: TwoFactorMethod::parse(&two_fa_method)
+            .map_err(|_| UserStoreError::UnexpectedError)?,
+        totp_secret: row.get("totp_secret"),
+        roles: row.get("roles"),
+        email_verified: row.get("email_verified"),
+        verification_email_sent_at: row.get("verification_email_sent_at"),
+        failed_login_attempts: failed_login_attempts
+            .try_into()
+            .map_err(|_| UserStoreError::UnexpectedError)?,
+        lockout_count: lockout_count.try_into().map_err(|_| UserStoreError::UnexpectedError)?,
+        locked_until: row.get("locked_until"),
+        token_version: token_version.try_into().map_err(|_| UserStoreError::UnexpectedError)?,
+        totp_last_step: totp_last_step
+            .map(u64::try_from)
+            .transpose()
+            .map_err(|_| UserStoreError::UnexpectedError)?,
+        disabled: row.get("disabled"),
+    })
+}
+
+#[async_trait::async_trait]
+impl UserStore for PostgresUserStore {
+    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
+        // An address reserved after an email change counts as taken
+        let result = sqlx::query(
+            "INSERT INTO users \
+             (email, password_hash, two_fa_method, totp_secret, roles, email_verified, verification_email_sent_at, \
+             failed_login_attempts, lockout_count, locked_until, token_version, totp_last_step, disabled, id) \
+             SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14 \
+             WHERE NOT EXISTS (SELECT 1 FROM email_reservations WHERE email = $1 AND reserved_until > NOW())",
+        )
+        .bind(user.email.as_ref())
+        .bind(user.password.as_ref())
+        .bind(user.two_fa_method.as_str())
+        .bind(&user.totp_secret)
+        .bind(&user.roles)
+        .bind(user.email_verified)
+        .bind(user.verification_email_sent_at)
+        .bind(i32::try_from(user.failed_login_attempts).map_err(|_| UserStoreError::UnexpectedError)?)
+        .bind(i32::try_from(user.lockout_count).map_err(|_| UserStoreError::UnexpectedError)?)
+        .bind(user.locked_until)
+        .bind(i32::try_from(user.token_version).map_err(|_| UserStoreError::UnexpectedError)?)
+        .bind(
+            user.totp_last_step
+                .map(i64::try_from)
+                .transpose()
+                .map_err(|_| UserStoreError::UnexpectedError)?,
+        )
+        .bind(user.disabled)
+        .bind(user.id.as_ref())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => UserStoreError::UserAlreadyExists,
+            _ => UserStoreError::UnexpectedError,
+        })?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserAlreadyExists),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE email = $1", USER_COLUMNS))
+            .bind(email.as_ref())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?
+            .ok_or(UserStoreError::UserNotFound)?;
+
+        user_from_row(&row)
+    }
+
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(id.as_ref())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?
+            .ok_or(UserStoreError::UserNotFound)?;
+
+        user_from_row(&row)
+    }
+
+    async fn update_password(&mut self, email: &Email, password: HashedPassword) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET password_hash = $1 WHERE email = $2")
+            .bind(password.as_ref())
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn upgrade_password_hash(
+        &mut self,
+        email: &Email,
+        current: &HashedPassword,
+        password: HashedPassword,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET password_hash = $1 WHERE email = $2 AND password_hash = $3")
+            .bind(password.as_ref())
+            .bind(email.as_ref())
+            .bind(current.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        // No match is fine as long as the user exists: the password was changed in the meantime
+        match result.rows_affected() {
+            0 => self.get_user(email).await.map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn mark_email_verified(&mut self, email: &Email) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET email_verified = TRUE WHERE email = $1")
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_verification_email_sent_at(
+        &mut self,
+        email: &Email,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET verification_email_sent_at = $1 WHERE email = $2")
+            .bind(sent_at)
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_totp_secret(&mut self, email: &Email, secret: Option<String>) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET totp_secret = $1 WHERE email = $2")
+            .bind(secret)
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_two_fa_method(&mut self, email: &Email, method: TwoFactorMethod) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET two_fa_method = $1 WHERE email = $2")
+            .bind(method.as_str())
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn replace_recovery_codes(&mut self, email: &Email, code_hashes: Vec<String>) -> Result<(), UserStoreError> {
+        // Old and new codes must never be valid at the same time
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        sqlx::query("SELECT 1 FROM users WHERE email = $1 FOR UPDATE")
+            .bind(email.as_ref())
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?
+            .ok_or(UserStoreError::UserNotFound)?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE email = $1")
+            .bind(email.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        sqlx::query("INSERT INTO recovery_codes (email, code_hash) SELECT $1, UNNEST($2::TEXT[])")
+            .bind(email.as_ref())
+            .bind(&code_hashes)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)
+    }
+
+    async fn consume_recovery_code(&mut self, email: &Email, code_hash: &str) -> Result<(), UserStoreError> {
+        let result = sqlx::query("DELETE FROM recovery_codes WHERE email = $1 AND code_hash = $2")
+            .bind(email.as_ref())
+            .bind(code_hash)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::InvalidRecoveryCode),
+            _ => Ok(()),
+        }
+    }
+
+    async fn count_recovery_codes(&self, email: &Email) -> Result<usize, UserStoreError> {
+        let row = sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1) AS user_exists, \
+             (SELECT COUNT(*) FROM recovery_codes WHERE email = $1) AS count",
+        )
+        .bind(email.as_ref())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        if !row.get::<bool, _>("user_exists") {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        let count: i64 = row.get("count");
+        count.try_into().map_err(|_| UserStoreError::UnexpectedError)
+    }
+
+    // Counted in the database so concurrent logins against one account can't lose a failure
+    async fn record_failed_login(&mut self, email: &Email) -> Result<u32, UserStoreError> {
+        let failed_login_attempts: i32 = sqlx::query_scalar(
+            "UPDATE users SET failed_login_attempts = failed_login_attempts + 1 \
+             WHERE email = $1 RETURNING failed_login_attempts",
+        )
+        .bind(email.as_ref())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?
+        .ok_or(UserStoreError::UserNotFound)?;
+
+        failed_login_attempts
+            .try_into()
+            .map_err(|_| UserStoreError::UnexpectedError)
+    }
+
+    async fn lock_account(&mut self, email: &Email, locked_until: DateTime<Utc>) -> Result<(), UserStoreError> {
+        let result = sqlx::query(
+            "UPDATE users SET locked_until = $1, lockout_count = lockout_count + 1, \
+             failed_login_attempts = 0 WHERE email = $2",
+        )
+        .bind(locked_until)
+        .bind(email.as_ref())
+        .execute(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn reset_failed_logins(&mut self, email: &Email) -> Result<(), UserStoreError> {
+        let result = sqlx::query(
+            "UPDATE users SET failed_login_attempts = 0, lockout_count = 0, locked_until = NULL \
+             WHERE email = $1",
+        )
+        .bind(email.as_ref())
+        .execute(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn record_totp_step(&mut self, email: &Email, step: u64) -> Result<(), UserStoreError> {
+        let step = i64::try_from(step).map_err(|_| UserStoreError::UnexpectedError)?;
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_step = $1 \
+             WHERE email = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+        )
+        .bind(step)
+        .bind(email.as_ref())
+        .execute(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => {
+                self.get_user(email).await?;
+                Err(UserStoreError::TotpCodeReused)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn bump_token_version(&mut self, email: &Email) -> Result<u32, UserStoreError> {
+        let token_version: i32 = sqlx::query_scalar(
+            "UPDATE users SET token_version = token_version + 1 WHERE email = $1 RETURNING token_version",
+        )
+        .bind(email.as_ref())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?
+        .ok_or(UserStoreError::UserNotFound)?;
+
+        token_version
+            .try_into()
+            .map_err(|_| UserStoreError::UnexpectedError)
+    }
+
+    async fn set_disabled(&mut self, email: &Email, disabled: bool) -> Result<(), UserStoreError> {
+        let result = sqlx::query("UPDATE users SET disabled = $1 WHERE email = $2")
+            .bind(disabled)
+            .bind(email.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    // Recovery codes follow through `ON UPDATE CASCADE`, and everything else about the
+    // user is keyed by their id
+    async fn change_email(
+        &mut self,
+        id: &UserId,
+        new_email: &Email,
+        reserve_old_until: Option<DateTime<Utc>>,
+    ) -> Result<(), UserStoreError> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1 FOR UPDATE")
+            .bind(id.as_ref())
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?
+            .ok_or(UserStoreError::UserNotFound)?;
+
+        // Locks the reservation too, so it can't be taken over halfway through
+        let reserved_for_another = sqlx::query(
+            "SELECT 1 FROM email_reservations \
+             WHERE email = $1 AND user_id <> $2 AND reserved_until > NOW() FOR UPDATE",
+        )
+        .bind(new_email.as_ref())
+        .bind(id.as_ref())
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|_| UserStoreError::UnexpectedError)?
+        .is_some();
+        if reserved_for_another {
+            return Err(UserStoreError::UserAlreadyExists);
+        }
+
+        sqlx::query("UPDATE users SET email = $1, email_verified = TRUE WHERE id = $2")
+            .bind(new_email.as_ref())
+            .bind(id.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| match e.as_database_error() {
+                Some(db_error) if db_error.is_unique_violation() => UserStoreError::UserAlreadyExists,
+                _ => UserStoreError::UnexpectedError,
+            })?;
+
+        sqlx::query("DELETE FROM email_reservations WHERE email = $1 OR reserved_until <= NOW()")
+            .bind(new_email.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        if let Some(reserved_until) = reserve_old_until {
+            sqlx::query(
+                "INSERT INTO email_reservations (email, user_id, reserved_until) VALUES ($1, $2, $3) \
+                 ON CONFLICT (email) DO UPDATE SET user_id = $2, reserved_until = $3",
+            )
+            .bind(&email)
+            .bind(id.as_ref())
+            .bind(reserved_until)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)
+    }
+
+    // Recovery codes, passkeys and reserved addresses go with it through `ON DELETE CASCADE`
+    async fn delete_user(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id.as_ref())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{domain::{hash_with_weak_params, Password}, utils::test_database::{create_test_database, delete_test_database}};
+
+    async fn hash(password: &str) -> HashedPassword {
+        HashedPassword::parse(Password::parse(password).unwrap()).await.unwrap()
+    }
+
+    async fn configure_store() -> (PostgresUserStore, String) {
+        let (pool, db_name) = create_test_database().await;
+        (PostgresUserStore::new(pool), db_name)
+    }
+
+    #[tokio::test]
+    async fn test_add_user() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::Email);
+
+        assert_eq!(store.add_user(user_1.clone()).await, Ok(()));
+        assert_eq!(store.add_user(user_2.clone()).await, Ok(()));
+        assert_eq!(store.add_user(user_1.clone()).await, Err(UserStoreError::UserAlreadyExists));
+
+        assert_eq!(store.get_user(&user_1.email).await, Ok(user_1));
+        assert_eq!(store.get_user(&user_2.email).await, Ok(user_2));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_user() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.get_user(&user_1.email).await, Ok(user_1));
+        assert_eq!(
+            store.get_user(&Email::parse("non_existent_email@gmail.com").unwrap()).await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_update_password() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.update_password(&user_1.email, hash("new_password").await).await, Ok(()));
+
+        let password = store.get_user(&user_1.email).await.unwrap().password;
+        assert!(password.verify_raw_password(&Password::parse("password_1").unwrap()).await.is_err());
+        assert!(password.verify_raw_password(&Password::parse("new_password").unwrap()).await.is_ok());
+        assert_eq!(
+            store
+                .update_password(&Email::parse("non_existent_email@gmail.com").unwrap(), hash("new_password").await)
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_email_verification() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert!(!store.get_user(&user_1.email).await.unwrap().email_verified);
+
+        // Postgres keeps microseconds, so use a whole second to compare the round trip
+        let sent_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(store.set_verification_email_sent_at(&user_1.email, sent_at).await, Ok(()));
+        assert_eq!(store.mark_email_verified(&user_1.email).await, Ok(()));
+
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert!(user.email_verified);
+        assert_eq!(user.verification_email_sent_at, Some(sent_at));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.mark_email_verified(&unknown).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(
+            store.set_verification_email_sent_at(&unknown, sent_at).await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_two_fa_settings() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Email);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.set_totp_secret(&user_1.email, Some("SECRET".to_owned())).await, Ok(()));
+        assert_eq!(store.set_two_fa_method(&user_1.email, TwoFactorMethod::Totp).await, Ok(()));
+
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.two_fa_method, TwoFactorMethod::Totp);
+        assert_eq!(user.totp_secret.as_deref(), Some("SECRET"));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.set_totp_secret(&unknown, None).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(
+            store.set_two_fa_method(&unknown, TwoFactorMethod::None).await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Email);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(0));
+
+        let hashes = vec!["hash_1".to_owned(), "hash_2".to_owned()];
+        assert_eq!(store.replace_recovery_codes(&user_1.email, hashes).await, Ok(()));
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(2));
+
+        // Each code works once
+        assert_eq!(store.consume_recovery_code(&user_1.email, "hash_1").await, Ok(()));
+        assert_eq!(
+            store.consume_recovery_code(&user_1.email, "hash_1").await,
+            Err(UserStoreError::InvalidRecoveryCode)
+        );
+        assert_eq!(store.count_recovery_codes(&user_1.email).await, Ok(1));
+
+        // Regenerating invalidates the old codes
+        assert_eq!(store.replace_recovery_codes(&user_1.email, vec!["hash_3".to_owned()]).await, Ok(()));
+        assert_eq!(
+            store.consume_recovery_code(&user_1.email, "hash_2").await,
+            Err(UserStoreError::InvalidRecoveryCode)
+        );
+        assert_eq!(store.consume_recovery_code(&user_1.email, "hash_3").await, Ok(()));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(
+            store.replace_recovery_codes(&unknown, vec![]).await,
+            Err(UserStoreError::UserNotFound)
+        );
+        assert_eq!(store.count_recovery_codes(&unknown).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_password_hash() {
+        let (mut store, db_name) = configure_store().await;
+
+        let password = Password::parse("password_1").unwrap();
+        let weak_hash = hash_with_weak_params(&password);
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), weak_hash.clone(), TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        let upgraded = hash(password.as_ref()).await;
+        store.upgrade_password_hash(&user_1.email, &weak_hash, upgraded.clone()).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().password, upgraded);
+
+        // A stale hash means the password was changed since it was read, so it is kept
+        store.upgrade_password_hash(&user_1.email, &weak_hash, hash("password_2").await).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().password, upgraded);
+
+        assert_eq!(
+            store
+                .upgrade_password_hash(&Email::parse("non_existent_email@gmail.com").unwrap(), &weak_hash, upgraded)
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(1));
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(2));
+
+        let locked_until = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(store.lock_account(&user_1.email, locked_until).await, Ok(()));
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.failed_login_attempts, 0);
+        assert_eq!(user.lockout_count, 1);
+        assert_eq!(user.locked_until, Some(locked_until));
+
+        assert_eq!(store.record_failed_login(&user_1.email).await, Ok(1));
+        assert_eq!(store.reset_failed_logins(&user_1.email).await, Ok(()));
+        let user = store.get_user(&user_1.email).await.unwrap();
+        assert_eq!(user.failed_login_attempts, 0);
+        assert_eq!(user.lockout_count, 0);
+        assert_eq!(user.locked_until, None);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.record_failed_login(&unknown).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.lock_account(&unknown, locked_until).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.reset_failed_logins(&unknown).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_record_totp_step() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::Totp);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.record_totp_step(&user_1.email, 100).await, Ok(()));
+        assert_eq!(store.record_totp_step(&user_1.email, 100).await, Err(UserStoreError::TotpCodeReused));
+        assert_eq!(store.record_totp_step(&user_1.email, 99).await, Err(UserStoreError::TotpCodeReused));
+        assert_eq!(store.record_totp_step(&user_1.email, 101).await, Ok(()));
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().totp_last_step, Some(101));
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.record_totp_step(&unknown, 1).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_bump_token_version() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().token_version, 0);
+
+        assert_eq!(store.bump_token_version(&user_1.email).await, Ok(1));
+        assert_eq!(store.bump_token_version(&user_1.email).await, Ok(2));
+        assert_eq!(store.get_user(&user_1.email).await.unwrap().token_version, 2);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.bump_token_version(&unknown).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_set_disabled() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        assert!(!store.get_user(&user_1.email).await.unwrap().disabled);
+
+        assert_eq!(store.set_disabled(&user_1.email, true).await, Ok(()));
+        assert!(store.get_user(&user_1.email).await.unwrap().disabled);
+        assert_eq!(store.set_disabled(&user_1.email, false).await, Ok(()));
+        assert!(!store.get_user(&user_1.email).await.unwrap().disabled);
+
+        let unknown = Email::parse("non_existent_email@gmail.com").unwrap();
+        assert_eq!(store.set_disabled(&unknown, true).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+
+        assert_eq!(store.get_user_by_id(&user_1.id).await, Ok(user_1));
+        assert_eq!(store.get_user_by_id(&UserId::default()).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_change_email() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+        store.replace_recovery_codes(&user_1.email, vec!["hash_1".to_owned()]).await.unwrap();
+
+        assert_eq!(
+            store.change_email(&user_1.id, &user_2.email, None).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        assert_eq!(store.change_email(&user_1.id, &new_email, None).await, Ok(()));
+        assert_eq!(store.get_user(&user_1.email).await, Err(UserStoreError::UserNotFound));
+
+        let user = store.get_user(&new_email).await.unwrap();
+        assert_eq!(user.id, user_1.id);
+        assert!(user.email_verified);
+        assert_eq!(user.password, user_1.password);
+        assert_eq!(store.count_recovery_codes(&new_email).await, Ok(1));
+
+        // Without a reservation the old address is free again
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3).await, Ok(()));
+
+        assert_eq!(
+            store.change_email(&UserId::default(), &Email::parse("other@gmail.com").unwrap(), None).await,
+            Err(UserStoreError::UserNotFound)
+        );
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_change_email_reserves_old_address() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        let until = Utc::now() + chrono::Duration::days(7);
+        assert_eq!(store.change_email(&user_1.id, &new_email, Some(until)).await, Ok(()));
+
+        // Nobody else can have the old address while it's reserved
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3.clone()).await, Err(UserStoreError::UserAlreadyExists));
+        assert_eq!(
+            store.change_email(&user_2.id, &user_1.email, None).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+
+        // Its owner can move back, which lifts the reservation
+        assert_eq!(store.change_email(&user_1.id, &user_1.email, None).await, Ok(()));
+        assert_eq!(store.get_user_by_id(&user_1.id).await.unwrap().email, user_1.email);
+        assert_eq!(store.change_email(&user_1.id, &new_email, None).await, Ok(()));
+        assert_eq!(store.add_user(user_3).await, Ok(()));
+
+        // An expired reservation holds nothing back
+        let other_email = Email::parse("other@gmail.com").unwrap();
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        assert_eq!(store.change_email(&user_2.id, &other_email, Some(past)).await, Ok(()));
+        let user_4 = User::new(user_2.email.clone(), hash("password_4").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_4).await, Ok(()));
+
+        delete_test_database(&db_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let (mut store, db_name) = configure_store().await;
+
+        let user_1 = User::new(Email::parse("email_1@gmail.com").unwrap(), hash("password_1").await, TwoFactorMethod::None);
+        let user_2 = User::new(Email::parse("email_2@gmail.com").unwrap(), hash("password_2").await, TwoFactorMethod::None);
+        store.add_user(user_1.clone()).await.unwrap();
+        store.add_user(user_2.clone()).await.unwrap();
+        store.replace_recovery_codes(&user_1.email, vec!["hash_1".to_owned()]).await.unwrap();
+
+        // The address given up in a change goes with the account
+        let new_email = Email::parse("new_email@gmail.com").unwrap();
+        let until = Utc::now() + chrono::Duration::days(7);
+        store.change_email(&user_1.id, &new_email, Some(until)).await.unwrap();
+
+        assert_eq!(store.delete_user(&user_1.id).await, Ok(()));
+        assert_eq!(store.get_user_by_id(&user_1.id).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.get_user(&new_email).await, Err(UserStoreError::UserNotFound));
+        assert_eq!(store.get_user(&user_2.email).await, Ok(user_2));
+
+        // Neither address carries anything over to a new account
+        let user_3 = User::new(user_1.email.clone(), hash("password_3").await, TwoFactorMethod::None);
+        let user_4 = User::new(new_email.clone(), hash("password_4").await, TwoFactorMethod::None);
+        assert_eq!(store.add_user(user_3).await, Ok(()));
+        assert_eq!(store.add_user(user_4).await, Ok(()));
+        assert_eq!(store.count_recovery_codes(&new_email).await, Ok(0));
+
+        assert_eq!(store.delete_user(&user_1.id).await, Err(UserStoreError::UserNotFound));
+
+        delete_test_database(&db_name).await;
+    }
+}
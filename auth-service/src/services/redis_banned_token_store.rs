@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+
+use crate::domain::{BannedTokenStore, BannedTokenStoreError};
+
+const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
+
+// Banning a token again only ever extends the TTL. TTL replies -2 for a missing key,
+// and a key never exists without a TTL here.
+const ADD_TOKEN_SCRIPT: &str = r"
+if redis.call('TTL', KEYS[1]) < tonumber(ARGV[1]) then
+    redis.call('SET', KEYS[1], 1, 'EX', ARGV[1])
+end
+return 1
+";
+
+// Each banned `jti` is its own key whose TTL ends when the token expires,
+// so Redis drops stale entries by itself and replicas share one denylist.
+pub struct RedisBannedTokenStore {
+    conn: ConnectionManager,
+    key_prefix: String,
+}
+
+impl RedisBannedTokenStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self {
+            conn,
+            key_prefix: BANNED_TOKEN_KEY_PREFIX.to_owned(),
+        }
+    }
+
+    fn key(&self, jti: &str) -> String {
+        format!("{}{}", self.key_prefix, jti)
+    }
+}
+
+#[async_trait::async_trait]
+impl BannedTokenStore for RedisBannedTokenStore {
+    async fn add_token(&mut self, jti: String, expires_at: DateTime<Utc>) -> Result<(), BannedTokenStoreError> {
+        let ttl_seconds = (expires_at - Utc::now()).num_seconds();
+        if ttl_seconds <= 0 {
+            // The token has already expired, so there is nothing left to ban
+            return Ok(());
+        }
+
+        let _: u32 = Script::new(ADD_TOKEN_SCRIPT)
+            .key(self.key(&jti))
+            .arg(ttl_seconds)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| BannedTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    async fn is_banned_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        self.conn
+            .clone()
+            .exists(self.key(jti))
+            .await
+            .map_err(|_| BannedTokenStoreError::UnexpectedError)
+    }
+
+    // Redis expires the keys itself
+    async fn prune_expired(&mut self) -> Result<usize, BannedTokenStoreError> {
+        Ok(0)
+    }
+
+    async fn count(&self) -> Result<usize, BannedTokenStoreError> {
+        let mut conn = self.conn.clone();
+        let mut keys = conn
+            .scan_match::<_, String>(format!("{}*", self.key_prefix))
+            .await
+            .map_err(|_| BannedTokenStoreError::UnexpectedError)?;
+
+        let mut count = 0;
+        while keys.next_item().await.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    // Every test uses its own key prefix so counts aren't affected by tests running in parallel
+    async fn configure_store() -> RedisBannedTokenStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisBannedTokenStore {
+            conn,
+            key_prefix: format!("test:{}:{}", Uuid::new_v4(), BANNED_TOKEN_KEY_PREFIX),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_add_token() {
+        let mut store = configure_store().await;
+        let expires_at = Utc::now() + Duration::minutes(10);
+
+        assert_eq!(store.add_token("jti1".to_owned(), expires_at).await, Ok(()));
+
+        let ttl: i64 = store.conn.ttl(store.key("jti1")).await.unwrap();
+        assert!(ttl > 590 && ttl <= 600);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_add_token_again_keeps_later_expiry() {
+        let mut store = configure_store().await;
+        let expires_at = Utc::now() + Duration::minutes(10);
+
+        store.add_token("jti1".to_owned(), expires_at).await.unwrap();
+        assert_eq!(
+            store.add_token("jti1".to_owned(), expires_at - Duration::minutes(5)).await,
+            Ok(())
+        );
+        let ttl: i64 = store.conn.ttl(store.key("jti1")).await.unwrap();
+        assert!(ttl > 590 && ttl <= 600);
+
+        assert_eq!(
+            store.add_token("jti1".to_owned(), expires_at + Duration::minutes(5)).await,
+            Ok(())
+        );
+        let ttl: i64 = store.conn.ttl(store.key("jti1")).await.unwrap();
+        assert!(ttl > 890 && ttl <= 900);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_is_banned_token() {
+        let mut store = configure_store().await;
+
+        store
+            .add_token("jti1".to_owned(), Utc::now() + Duration::minutes(10))
+            .await
+            .unwrap();
+
+        assert_eq!(store.is_banned_token("jti1").await, Ok(true));
+        assert_eq!(store.is_banned_token("jti2").await, Ok(false));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_expired_tokens_are_not_kept() {
+        let mut store = configure_store().await;
+
+        store
+            .add_token("expired".to_owned(), Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        store
+            .add_token("live".to_owned(), Utc::now() + Duration::minutes(10))
+            .await
+            .unwrap();
+
+        assert_eq!(store.is_banned_token("expired").await, Ok(false));
+        assert_eq!(store.count().await, Ok(1));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, Script};
+
+use crate::domain::{
+    Email, EmailChange, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord, OneTimeTokenStore,
+    OneTimeTokenStoreError, UserId,
+};
+
+const ONE_TIME_TOKEN_KEY_PREFIX: &str = "one_time_token:";
+const EMAIL_KEY_PREFIX: &str = "one_time_token_email:";
+const EMAIL_CHANGE_KEY_PREFIX: &str = "one_time_token_email_change:";
+
+const EMAIL_FIELD: &str = "email";
+const PURPOSE_FIELD: &str = "purpose";
+const EXPIRES_AT_FIELD: &str = "expires_at";
+const CHANGE_USER_ID_FIELD: &str = "change_user_id";
+const CHANGE_NEW_EMAIL_FIELD: &str = "change_new_email";
+
+// Stores the record and adds the token to the set of its address and, for an email
+// change, the set of its user. A set lives as long as its longest lived token, so its
+// TTL is only ever extended.
+const ADD_TOKEN_SCRIPT: &str = r"
+redis.call('HSET', KEYS[1], unpack(ARGV, 3))
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+for i = 2, #KEYS do
+    redis.call('SADD', KEYS[i], ARGV[1])
+    if redis.call('TTL', KEYS[i]) < tonumber(ARGV[2]) then
+        redis.call('EXPIRE', KEYS[i], ARGV[2])
+    end
+end
+return 1
+";
+
+// Returns the record and deletes it in one step, so a token can't be consumed twice.
+// A token issued for another purpose is left alone.
+const CONSUME_TOKEN_SCRIPT: &str = r"
+if redis.call('HGET', KEYS[1], ARGV[1]) ~= ARGV[2] then
+    return nil
+end
+local record = redis.call('HGETALL', KEYS[1])
+redis.call('DEL', KEYS[1])
+return record
+";
+
+// Deletes the tokens of an address issued for one purpose, dropping tokens that are
+// gone already from the set on the way
+const REVOKE_TOKENS_SCRIPT: &str = r"
+for _, token in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local key = ARGV[1] .. token
+    local purpose = redis.call('HGET', key, ARGV[2])
+    if purpose == ARGV[3] then
+        redis.call('DEL', key)
+        redis.call('SREM', KEYS[1], token)
+    elseif not purpose then
+        redis.call('SREM', KEYS[1], token)
+    end
+end
+return 1
+";
+
+// Deletes every token in a user's set of email change tokens, then the set itself
+const REVOKE_EMAIL_CHANGES_SCRIPT: &str = r"
+local tokens = redis.call('SMEMBERS', KEYS[1])
+for _, token in ipairs(tokens) do
+    redis.call('DEL', ARGV[1] .. token)
+end
+redis.call('DEL', KEYS[1])
+return #tokens
+";
+
+// Each token is a hash whose TTL ends when the token expires, so Redis drops stale
+// tokens by itself and an expired token is simply not found. Sets of tokens per
+// address, and per user for email changes, let them be revoked without a scan.
+pub struct RedisOneTimeTokenStore {
+    conn: ConnectionManager,
+}
+
+impl RedisOneTimeTokenStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl OneTimeTokenStore for RedisOneTimeTokenStore {
+    async fn add_token(
+        &mut self,
+        token: OneTimeToken,
+        record: OneTimeTokenRecord,
+    ) -> Result<(), OneTimeTokenStoreError> {
+        let ttl_seconds = (record.expires_at - Utc::now()).num_seconds();
+        if ttl_seconds <= 0 {
+            // The token could never be consumed, so there is nothing to keep
+            return Ok(());
+        }
+
+        let script = Script::new(ADD_TOKEN_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(token_key(&token))
+            .key(email_key(&record.email))
+            .arg(token.as_ref())
+            .arg(ttl_seconds)
+            .arg(EMAIL_FIELD)
+            .arg(record.email.as_ref())
+            .arg(PURPOSE_FIELD)
+            .arg(record.purpose.as_str())
+            .arg(EXPIRES_AT_FIELD)
+            .arg(record.expires_at.to_rfc3339());
+        if let Some(change) = &record.email_change {
+            invocation
+                .key(email_change_key(&change.user_id))
+                .arg(CHANGE_USER_ID_FIELD)
+                .arg(change.user_id.to_string())
+                .arg(CHANGE_NEW_EMAIL_FIELD)
+                .arg(change.new_email.as_ref());
+        }
+
+        let _: u32 = invocation
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| OneTimeTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    async fn consume_token(
+        &mut self,
+        token: &OneTimeToken,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<OneTimeTokenRecord, OneTimeTokenStoreError> {
+        let entry: Option<HashMap<String, String>> = Script::new(CONSUME_TOKEN_SCRIPT)
+            .key(token_key(token))
+            .arg(PURPOSE_FIELD)
+            .arg(purpose.as_str())
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| OneTimeTokenStoreError::UnexpectedError)?;
+
+        let record = entry
+            .ok_or(OneTimeTokenStoreError::TokenNotFound)
+            .and_then(|entry| parse_record(&entry).ok_or(OneTimeTokenStoreError::UnexpectedError))?;
+
+        // The key's TTL came from the clock of whichever replica added it, so check as well
+        if record.expires_at <= Utc::now() {
+            return Err(OneTimeTokenStoreError::TokenExpired);
+        }
+
+        Ok(record)
+    }
+
+    async fn revoke_tokens(
+        &mut self,
+        email: &Email,
+        purpose: OneTimeTokenPurpose,
+    ) -> Result<(), OneTimeTokenStoreError> {
+        let _: u32 = Script::new(REVOKE_TOKENS_SCRIPT)
+            .key(email_key(email))
+            .arg(ONE_TIME_TOKEN_KEY_PREFIX)
+            .arg(PURPOSE_FIELD)
+            .arg(purpose.as_str())
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| OneTimeTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    async fn revoke_email_changes(&mut self, user_id: &UserId) -> Result<(), OneTimeTokenStoreError> {
+        let _: u32 = Script::new(REVOKE_EMAIL_CHANGES_SCRIPT)
+            .key(email_change_key(user_id))
+            .arg(ONE_TIME_TOKEN_KEY_PREFIX)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| OneTimeTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    // Redis expires the keys itself
+    async fn prune_expired(&mut self) -> Result<usize, OneTimeTokenStoreError> {
+        Ok(0)
+    }
+}
+
+fn parse_record(entry: &HashMap<String, String>) -> Option<OneTimeTokenRecord> {
+    let email_change = match (entry.get(CHANGE_USER_ID_FIELD), entry.get(CHANGE_NEW_EMAIL_FIELD)) {
+        (Some(user_id), Some(new_email)) => Some(EmailChange {
+            user_id: UserId::parse(user_id).ok()?,
+            new_email: Email::parse(new_email).ok()?,
+        }),
+        _ => None,
+    };
+
+    Some(OneTimeTokenRecord {
+        email: Email::parse(entry.get(EMAIL_FIELD)?).ok()?,
+        purpose: OneTimeTokenPurpose::parse(entry.get(PURPOSE_FIELD)?).ok()?,
+        expires_at: DateTime::parse_from_rfc3339(entry.get(EXPIRES_AT_FIELD)?)
+            .ok()?
+            .with_timezone(&Utc),
+        email_change,
+    })
+}
+
+fn token_key(token: &OneTimeToken) -> String {
+    format!("{}{}", ONE_TIME_TOKEN_KEY_PREFIX, token.as_ref())
+}
+
+fn email_key(email: &Email) -> String {
+    format!("{}{}", EMAIL_KEY_PREFIX, email.as_ref())
+}
+
+fn email_change_key(user_id: &UserId) -> String {
+    format!("{}{}", EMAIL_CHANGE_KEY_PREFIX, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use redis::AsyncCommands;
+
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    async fn configure_store() -> RedisOneTimeTokenStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisOneTimeTokenStore::new(conn)
+    }
+
+    // Tokens are revoked by address, so every test gets its own
+    fn random_email() -> Email {
+        Email::parse(&format!("{}@example.com", uuid::Uuid::new_v4())).unwrap()
+    }
+
+    fn record(email: &Email, purpose: OneTimeTokenPurpose) -> OneTimeTokenRecord {
+        OneTimeTokenRecord {
+            email: email.clone(),
+            purpose,
+            expires_at: Utc::now() + Duration::minutes(30),
+            email_change: None,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_consume_token_only_once() {
+        let mut store = configure_store().await;
+        let token = OneTimeToken::default();
+        let record = record(&random_email(), OneTimeTokenPurpose::PasswordReset);
+
+        store.add_token(token.clone(), record.clone()).await.unwrap();
+
+        let ttl: i64 = store.conn.ttl(token_key(&token)).await.unwrap();
+        assert!(ttl > 1790 && ttl <= 1800);
+        assert_eq!(
+            store.consume_token(&token, OneTimeTokenPurpose::PasswordReset).await,
+            Ok(record)
+        );
+        assert_eq!(
+            store.consume_token(&token, OneTimeTokenPurpose::PasswordReset).await,
+            Err(OneTimeTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_consume_token_for_other_purpose() {
+        let mut store = configure_store().await;
+        let token = OneTimeToken::default();
+        let record = record(&random_email(), OneTimeTokenPurpose::PasswordReset);
+
+        store.add_token(token.clone(), record.clone()).await.unwrap();
+
+        assert_eq!(
+            store.consume_token(&token, OneTimeTokenPurpose::MagicLinkLogin).await,
+            Err(OneTimeTokenStoreError::TokenNotFound)
+        );
+        // The token is still there for its own purpose
+        assert_eq!(
+            store.consume_token(&token, OneTimeTokenPurpose::PasswordReset).await,
+            Ok(record)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_expired_tokens_are_not_kept() {
+        let mut store = configure_store().await;
+        let token = OneTimeToken::default();
+
+        store
+            .add_token(
+                token.clone(),
+                OneTimeTokenRecord {
+                    expires_at: Utc::now() - Duration::seconds(1),
+                    ..record(&random_email(), OneTimeTokenPurpose::PasswordReset)
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.consume_token(&token, OneTimeTokenPurpose::PasswordReset).await,
+            Err(OneTimeTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_revoke_tokens() {
+        let mut store = configure_store().await;
+        let email = random_email();
+        let token_1 = OneTimeToken::default();
+        let token_2 = OneTimeToken::default();
+        let other_purpose_token = OneTimeToken::default();
+        let other_email_token = OneTimeToken::default();
+
+        for (token, record) in [
+            (&token_1, record(&email, OneTimeTokenPurpose::PasswordReset)),
+            (&token_2, record(&email, OneTimeTokenPurpose::PasswordReset)),
+            (&other_purpose_token, record(&email, OneTimeTokenPurpose::MagicLinkLogin)),
+            (&other_email_token, record(&random_email(), OneTimeTokenPurpose::PasswordReset)),
+        ] {
+            store.add_token(token.clone(), record).await.unwrap();
+        }
+
+        store.revoke_tokens(&email, OneTimeTokenPurpose::PasswordReset).await.unwrap();
+
+        for token in [&token_1, &token_2] {
+            assert_eq!(
+                store.consume_token(token, OneTimeTokenPurpose::PasswordReset).await,
+                Err(OneTimeTokenStoreError::TokenNotFound)
+            );
+        }
+        assert!(store
+            .consume_token(&other_purpose_token, OneTimeTokenPurpose::MagicLinkLogin)
+            .await
+            .is_ok());
+        assert!(store
+            .consume_token(&other_email_token, OneTimeTokenPurpose::PasswordReset)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_revoke_email_changes() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let email_change = |email: &Email, user_id: UserId| OneTimeTokenRecord {
+            email_change: Some(EmailChange {
+                user_id,
+                new_email: random_email(),
+            }),
+            ..record(email, OneTimeTokenPurpose::EmailChangeCancel)
+        };
+        let first_token = OneTimeToken::default();
+        let second_token = OneTimeToken::default();
+        let other_user_token = OneTimeToken::default();
+
+        // The cancel tokens of a chain of changes were each sent to a different address
+        let first_record = email_change(&random_email(), user_id);
+        store.add_token(first_token.clone(), first_record.clone()).await.unwrap();
+        store.add_token(second_token.clone(), email_change(&random_email(), user_id)).await.unwrap();
+        store
+            .add_token(other_user_token.clone(), email_change(&random_email(), UserId::default()))
+            .await
+            .unwrap();
+
+        store.revoke_email_changes(&user_id).await.unwrap();
+
+        for token in [&first_token, &second_token] {
+            assert_eq!(
+                store.consume_token(token, OneTimeTokenPurpose::EmailChangeCancel).await,
+                Err(OneTimeTokenStoreError::TokenNotFound)
+            );
+        }
+        assert!(store
+            .consume_token(&other_user_token, OneTimeTokenPurpose::EmailChangeCancel)
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use redis::{aio::ConnectionManager, Script};
+
+use crate::domain::{RateLimit, RateLimitStore, RateLimitStoreError};
+
+const RATE_LIMIT_KEY_PREFIX: &str = "rate_limit:";
+
+// Refills and takes from the bucket in one step, so replicas sharing a bucket can't
+// both take its last token. Uses the Redis clock so replicas don't need to agree on
+// the time. Returns how many milliseconds to wait, or 0 if a token was taken.
+const TAKE_TOKEN_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local refill_interval_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + (now - updated_at) / refill_interval_ms)
+
+local retry_after_ms = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    retry_after_ms = math.ceil((1 - tokens) * refill_interval_ms)
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', now)
+-- Once the bucket would be full again it's the same as no bucket at all
+redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) * refill_interval_ms))
+return retry_after_ms
+";
+
+// Buckets are stored as a hash per key, so every replica shares the same limits
+pub struct RedisRateLimitStore {
+    conn: ConnectionManager,
+}
+
+impl RedisRateLimitStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    async fn take_token(
+        &mut self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<(), RateLimitStoreError> {
+        // Sub-millisecond refill intervals aren't useful, and would divide by zero
+        let refill_interval_ms = limit.refill_interval().as_millis().max(1) as u64;
+
+        let retry_after_ms: u64 = Script::new(TAKE_TOKEN_SCRIPT)
+            .key(get_key(key))
+            .arg(limit.capacity)
+            .arg(refill_interval_ms)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| RateLimitStoreError::UnexpectedError)?;
+
+        match retry_after_ms {
+            0 => Ok(()),
+            ms => Err(RateLimitStoreError::RateLimited {
+                retry_after: Duration::from_millis(ms),
+            }),
+        }
+    }
+}
+
+fn get_key(key: &str) -> String {
+    format!("{}{}", RATE_LIMIT_KEY_PREFIX, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::AsyncCommands;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    async fn configure_store() -> RedisRateLimitStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisRateLimitStore::new(conn)
+    }
+
+    fn limit() -> RateLimit {
+        RateLimit {
+            capacity: 2,
+            period: Duration::from_millis(400),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_bucket_empties_and_refills() {
+        let mut store = configure_store().await;
+        let key = Uuid::new_v4().to_string();
+
+        assert_eq!(store.take_token(&key, &limit()).await, Ok(()));
+        assert_eq!(store.take_token(&key, &limit()).await, Ok(()));
+
+        match store.take_token(&key, &limit()).await {
+            Err(RateLimitStoreError::RateLimited { retry_after }) => {
+                assert!(retry_after > Duration::ZERO && retry_after <= limit().refill_interval())
+            }
+            other => panic!("expected the bucket to be empty, got {:?}", other),
+        }
+
+        // One token comes back every 200ms
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(store.take_token(&key, &limit()).await, Ok(()));
+        assert!(store.take_token(&key, &limit()).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_bucket_expires_once_full() {
+        let mut store = configure_store().await;
+        let key = Uuid::new_v4().to_string();
+
+        store.take_token(&key, &limit()).await.unwrap();
+        let ttl: i64 = store.conn.pttl(get_key(&key)).await.unwrap();
+        assert!(ttl > 0 && ttl <= limit().refill_interval().as_millis() as i64);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let exists: bool = store.conn.exists(get_key(&key)).await.unwrap();
+        assert!(!exists);
+    }
+}
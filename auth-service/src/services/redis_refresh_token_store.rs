@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+
+use crate::domain::{
+    RefreshToken, RefreshTokenRecord, RefreshTokenStore, RefreshTokenStoreError, UserId,
+};
+
+const REFRESH_TOKEN_KEY_PREFIX: &str = "refresh_token:";
+const FAMILY_KEY_PREFIX: &str = "refresh_token_family:";
+const USER_KEY_PREFIX: &str = "refresh_token_user:";
+
+const USER_ID_FIELD: &str = "user_id";
+const FAMILY_ID_FIELD: &str = "family_id";
+const EXPIRES_AT_FIELD: &str = "expires_at";
+const USED_FIELD: &str = "used";
+
+// Stores the record and adds the token to its family's and its user's sets. A set
+// lives as long as its longest lived token, so its TTL is only ever extended.
+const ADD_TOKEN_SCRIPT: &str = r"
+redis.call('HSET', KEYS[1], unpack(ARGV, 3))
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+for i = 2, #KEYS do
+    redis.call('SADD', KEYS[i], ARGV[1])
+    if redis.call('TTL', KEYS[i]) < tonumber(ARGV[2]) then
+        redis.call('EXPIRE', KEYS[i], ARGV[2])
+    end
+end
+return 1
+";
+
+// Only mark the token if it still exists, so an expired token isn't brought back
+// as a hash without a TTL
+const MARK_TOKEN_USED_SCRIPT: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    redis.call('HSET', KEYS[1], ARGV[1], 1)
+    return 1
+end
+return 0
+";
+
+// Deletes every token in a family's or a user's set, then the set itself. Tokens that
+// have expired already are simply not found.
+const REVOKE_TOKENS_SCRIPT: &str = r"
+local tokens = redis.call('SMEMBERS', KEYS[1])
+for _, token in ipairs(tokens) do
+    redis.call('DEL', ARGV[1] .. token)
+end
+redis.call('DEL', KEYS[1])
+return #tokens
+";
+
+// Each token is a hash whose TTL ends when the token expires, so Redis drops stale
+// tokens by itself and replicas share them. Sets of tokens per family and per user
+// let either be revoked at once.
+pub struct RedisRefreshTokenStore {
+    conn: ConnectionManager,
+}
+
+impl RedisRefreshTokenStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenStore for RedisRefreshTokenStore {
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        record: RefreshTokenRecord,
+    ) -> Result<(), RefreshTokenStoreError> {
+        let ttl_seconds = (record.expires_at - Utc::now()).num_seconds();
+        if ttl_seconds <= 0 {
+            // The token could never be exchanged, so there is nothing to keep
+            return Ok(());
+        }
+
+        let _: u32 = Script::new(ADD_TOKEN_SCRIPT)
+            .key(token_key(&token))
+            .key(family_key(&record.family_id))
+            .key(user_key(&record.user_id))
+            .arg(token.as_ref())
+            .arg(ttl_seconds)
+            .arg(USER_ID_FIELD)
+            .arg(record.user_id.to_string())
+            .arg(FAMILY_ID_FIELD)
+            .arg(&record.family_id)
+            .arg(EXPIRES_AT_FIELD)
+            .arg(record.expires_at.to_rfc3339())
+            .arg(USED_FIELD)
+            .arg(u8::from(record.used))
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| RefreshTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenRecord, RefreshTokenStoreError> {
+        let entry: HashMap<String, String> = self
+            .conn
+            .clone()
+            .hgetall(token_key(token))
+            .await
+            .map_err(|_| RefreshTokenStoreError::UnexpectedError)?;
+
+        if entry.is_empty() {
+            return Err(RefreshTokenStoreError::TokenNotFound);
+        }
+
+        parse_record(&entry).ok_or(RefreshTokenStoreError::UnexpectedError)
+    }
+
+    async fn mark_token_used(&mut self, token: &RefreshToken) -> Result<(), RefreshTokenStoreError> {
+        let marked: u32 = Script::new(MARK_TOKEN_USED_SCRIPT)
+            .key(token_key(token))
+            .arg(USED_FIELD)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| RefreshTokenStoreError::UnexpectedError)?;
+
+        match marked {
+            0 => Err(RefreshTokenStoreError::TokenNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn revoke_family(&mut self, family_id: &str) -> Result<(), RefreshTokenStoreError> {
+        self.revoke_tokens(family_key(family_id)).await
+    }
+
+    async fn revoke_user_tokens(&mut self, user_id: &UserId) -> Result<(), RefreshTokenStoreError> {
+        self.revoke_tokens(user_key(user_id)).await
+    }
+
+    // Redis expires the keys itself
+    async fn prune_expired(&mut self) -> Result<usize, RefreshTokenStoreError> {
+        Ok(0)
+    }
+}
+
+impl RedisRefreshTokenStore {
+    async fn revoke_tokens(&mut self, set_key: String) -> Result<(), RefreshTokenStoreError> {
+        let _: u32 = Script::new(REVOKE_TOKENS_SCRIPT)
+            .key(set_key)
+            .arg(REFRESH_TOKEN_KEY_PREFIX)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| RefreshTokenStoreError::UnexpectedError)?;
+        Ok(())
+    }
+}
+
+fn parse_record(entry: &HashMap<String, String>) -> Option<RefreshTokenRecord> {
+    Some(RefreshTokenRecord {
+        user_id: UserId::parse(entry.get(USER_ID_FIELD)?).ok()?,
+        family_id: entry.get(FAMILY_ID_FIELD)?.to_owned(),
+        expires_at: DateTime::parse_from_rfc3339(entry.get(EXPIRES_AT_FIELD)?)
+            .ok()?
+            .with_timezone(&Utc),
+        used: entry.get(USED_FIELD)? == "1",
+    })
+}
+
+fn token_key(token: &RefreshToken) -> String {
+    format!("{}{}", REFRESH_TOKEN_KEY_PREFIX, token.as_ref())
+}
+
+fn family_key(family_id: &str) -> String {
+    format!("{}{}", FAMILY_KEY_PREFIX, family_id)
+}
+
+fn user_key(user_id: &UserId) -> String {
+    format!("{}{}", USER_KEY_PREFIX, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    async fn configure_store() -> RedisRefreshTokenStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisRefreshTokenStore::new(conn)
+    }
+
+    // Family IDs are session IDs, so every test gets its own
+    fn record_for(user_id: &UserId, family_id: &str) -> RefreshTokenRecord {
+        RefreshTokenRecord {
+            user_id: *user_id,
+            family_id: family_id.to_owned(),
+            expires_at: Utc::now() + Duration::days(1),
+            used: false,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_add_and_get_token() {
+        let mut store = configure_store().await;
+        let token = RefreshToken::default();
+        let record = record_for(&UserId::default(), &Uuid::new_v4().to_string());
+
+        store.add_token(token.clone(), record.clone()).await.unwrap();
+
+        assert_eq!(store.get_token(&token).await, Ok(record));
+        let ttl: i64 = store.conn.ttl(token_key(&token)).await.unwrap();
+        assert!(ttl > 86_390 && ttl <= 86_400);
+        assert_eq!(
+            store.get_token(&RefreshToken::default()).await,
+            Err(RefreshTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_expired_tokens_are_not_kept() {
+        let mut store = configure_store().await;
+        let token = RefreshToken::default();
+        let record = RefreshTokenRecord {
+            expires_at: Utc::now() - Duration::seconds(1),
+            ..record_for(&UserId::default(), &Uuid::new_v4().to_string())
+        };
+
+        store.add_token(token.clone(), record).await.unwrap();
+
+        assert_eq!(store.get_token(&token).await, Err(RefreshTokenStoreError::TokenNotFound));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_mark_token_used() {
+        let mut store = configure_store().await;
+        let token = RefreshToken::default();
+
+        store
+            .add_token(token.clone(), record_for(&UserId::default(), &Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+        store.mark_token_used(&token).await.unwrap();
+
+        assert!(store.get_token(&token).await.unwrap().used);
+
+        let missing = RefreshToken::default();
+        assert_eq!(
+            store.mark_token_used(&missing).await,
+            Err(RefreshTokenStoreError::TokenNotFound)
+        );
+        let exists: bool = store.conn.exists(token_key(&missing)).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_revoke_family() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let family_id = Uuid::new_v4().to_string();
+        let token_1 = RefreshToken::default();
+        let token_2 = RefreshToken::default();
+        let other_token = RefreshToken::default();
+
+        store.add_token(token_1.clone(), record_for(&user_id, &family_id)).await.unwrap();
+        store.add_token(token_2.clone(), record_for(&user_id, &family_id)).await.unwrap();
+        store
+            .add_token(other_token.clone(), record_for(&user_id, &Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+
+        store.revoke_family(&family_id).await.unwrap();
+
+        assert!(store.get_token(&token_1).await.is_err());
+        assert!(store.get_token(&token_2).await.is_err());
+        assert!(store.get_token(&other_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_revoke_user_tokens() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let token_1 = RefreshToken::default();
+        let token_2 = RefreshToken::default();
+        let other_token = RefreshToken::default();
+
+        store
+            .add_token(token_1.clone(), record_for(&user_id, &Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+        store
+            .add_token(token_2.clone(), record_for(&user_id, &Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+        store
+            .add_token(other_token.clone(), record_for(&UserId::default(), &Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+
+        store.revoke_user_tokens(&user_id).await.unwrap();
+
+        assert!(store.get_token(&token_1).await.is_err());
+        assert!(store.get_token(&token_2).await.is_err());
+        assert!(store.get_token(&other_token).await.is_ok());
+    }
+}
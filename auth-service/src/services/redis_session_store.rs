@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+use uuid::Uuid;
+
+use crate::{
+    domain::{Session, SessionStore, SessionStoreError, UserId},
+    utils::auth::REFRESH_TOKEN_TTL_SECONDS,
+};
+
+const SESSION_KEY_PREFIX: &str = "session:";
+const USER_KEY_PREFIX: &str = "user_sessions:";
+
+const ID_FIELD: &str = "id";
+const USER_ID_FIELD: &str = "user_id";
+const CREATED_AT_FIELD: &str = "created_at";
+const LAST_SEEN_AT_FIELD: &str = "last_seen_at";
+const USER_AGENT_FIELD: &str = "user_agent";
+const IP_ADDRESS_FIELD: &str = "ip_address";
+
+// Stores the session and adds it to its user's set. The set lives as long as the
+// user's longest lived session, so its TTL is only ever extended.
+const ADD_SESSION_SCRIPT: &str = r"
+redis.call('HSET', KEYS[1], unpack(ARGV, 3))
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+redis.call('SADD', KEYS[2], ARGV[1])
+if redis.call('TTL', KEYS[2]) < tonumber(ARGV[2]) then
+    redis.call('EXPIRE', KEYS[2], ARGV[2])
+end
+return 1
+";
+
+// Only touch the session if it still exists, so an expired session isn't brought
+// back as a hash without its other fields
+const TOUCH_SESSION_SCRIPT: &str = r"
+local user_id = redis.call('HGET', KEYS[1], ARGV[1])
+if not user_id then
+    return 0
+end
+redis.call('HSET', KEYS[1], ARGV[2], ARGV[3])
+redis.call('EXPIRE', KEYS[1], ARGV[4])
+local user_key = ARGV[5] .. user_id
+if redis.call('TTL', user_key) < tonumber(ARGV[4]) then
+    redis.call('EXPIRE', user_key, ARGV[4])
+end
+return 1
+";
+
+const REMOVE_SESSION_SCRIPT: &str = r"
+local user_id = redis.call('HGET', KEYS[1], ARGV[1])
+if not user_id then
+    return 0
+end
+redis.call('DEL', KEYS[1])
+redis.call('SREM', ARGV[2] .. user_id, ARGV[3])
+return 1
+";
+
+// Deletes and returns every session in a user's set, then deletes the set itself.
+// Sessions that have expired already are skipped.
+const REMOVE_USER_SESSIONS_SCRIPT: &str = r"
+local sessions = {}
+for _, id in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local key = ARGV[1] .. id
+    local session = redis.call('HGETALL', key)
+    if #session > 0 then
+        table.insert(sessions, session)
+        redis.call('DEL', key)
+    end
+end
+redis.call('DEL', KEYS[1])
+return sessions
+";
+
+// Each session is a hash whose TTL ends when its refresh token would, and is pushed
+// back whenever the session is refreshed, so Redis drops stale sessions by itself and
+// replicas share them. A set of session IDs per user lists a user's sessions.
+pub struct RedisSessionStore {
+    conn: ConnectionManager,
+}
+
+impl RedisSessionStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError> {
+        let ttl_seconds = ttl_seconds(&session);
+        if ttl_seconds <= 0 {
+            // The session could never be refreshed, so there is nothing to keep
+            return Ok(());
+        }
+
+        let script = Script::new(ADD_SESSION_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(session_key(&session.id))
+            .key(user_key(&session.user_id))
+            .arg(session.id.to_string())
+            .arg(ttl_seconds)
+            .arg(ID_FIELD)
+            .arg(session.id.to_string())
+            .arg(USER_ID_FIELD)
+            .arg(session.user_id.to_string())
+            .arg(CREATED_AT_FIELD)
+            .arg(session.created_at.to_rfc3339())
+            .arg(LAST_SEEN_AT_FIELD)
+            .arg(session.last_seen_at.to_rfc3339());
+        if let Some(user_agent) = &session.user_agent {
+            invocation.arg(USER_AGENT_FIELD).arg(user_agent);
+        }
+        if let Some(ip_address) = &session.ip_address {
+            invocation.arg(IP_ADDRESS_FIELD).arg(ip_address);
+        }
+
+        let _: u32 = invocation
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &Uuid) -> Result<Session, SessionStoreError> {
+        let entry: HashMap<String, String> = self
+            .conn
+            .clone()
+            .hgetall(session_key(id))
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+
+        if entry.is_empty() {
+            return Err(SessionStoreError::SessionNotFound);
+        }
+
+        parse_session(&entry).ok_or(SessionStoreError::UnexpectedError)
+    }
+
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        let mut conn = self.conn.clone();
+        let ids: Vec<String> = conn
+            .smembers(user_key(user_id))
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            pipe.hgetall(format!("{}{}", SESSION_KEY_PREFIX, id));
+        }
+        let entries: Vec<HashMap<String, String>> = pipe
+            .query_async(&mut conn)
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+
+        // Sessions that expired since they were listed come back empty
+        let mut sessions = entries
+            .iter()
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| parse_session(entry).ok_or(SessionStoreError::UnexpectedError))
+            .collect::<Result<Vec<Session>, SessionStoreError>>()?;
+        sessions.sort_by_key(|session| session.created_at);
+        Ok(sessions)
+    }
+
+    async fn touch_session(&mut self, id: &Uuid, last_seen_at: DateTime<Utc>) -> Result<(), SessionStoreError> {
+        let expires_at = last_seen_at + chrono::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS);
+        let ttl_seconds = (expires_at - Utc::now()).num_seconds();
+
+        let touched: u32 = Script::new(TOUCH_SESSION_SCRIPT)
+            .key(session_key(id))
+            .arg(USER_ID_FIELD)
+            .arg(LAST_SEEN_AT_FIELD)
+            .arg(last_seen_at.to_rfc3339())
+            .arg(ttl_seconds.max(1))
+            .arg(USER_KEY_PREFIX)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+
+        match touched {
+            0 => Err(SessionStoreError::SessionNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_session(&mut self, id: &Uuid) -> Result<(), SessionStoreError> {
+        let removed: u32 = Script::new(REMOVE_SESSION_SCRIPT)
+            .key(session_key(id))
+            .arg(USER_ID_FIELD)
+            .arg(USER_KEY_PREFIX)
+            .arg(id.to_string())
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+
+        match removed {
+            0 => Err(SessionStoreError::SessionNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_user_sessions(&mut self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        let entries: Vec<HashMap<String, String>> = Script::new(REMOVE_USER_SESSIONS_SCRIPT)
+            .key(user_key(user_id))
+            .arg(SESSION_KEY_PREFIX)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| SessionStoreError::UnexpectedError)?;
+
+        entries
+            .iter()
+            .map(|entry| parse_session(entry).ok_or(SessionStoreError::UnexpectedError))
+            .collect()
+    }
+
+    // Redis expires the keys itself
+    async fn prune_expired(&mut self) -> Result<usize, SessionStoreError> {
+        Ok(0)
+    }
+}
+
+fn ttl_seconds(session: &Session) -> i64 {
+    (session.expires_at() - Utc::now()).num_seconds()
+}
+
+fn parse_session(entry: &HashMap<String, String>) -> Option<Session> {
+    let timestamp = |field: &str| {
+        DateTime::parse_from_rfc3339(entry.get(field)?)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+    };
+
+    Some(Session {
+        id: Uuid::parse_str(entry.get(ID_FIELD)?).ok()?,
+        user_id: UserId::parse(entry.get(USER_ID_FIELD)?).ok()?,
+        created_at: timestamp(CREATED_AT_FIELD)?,
+        last_seen_at: timestamp(LAST_SEEN_AT_FIELD)?,
+        user_agent: entry.get(USER_AGENT_FIELD).cloned(),
+        ip_address: entry.get(IP_ADDRESS_FIELD).cloned(),
+    })
+}
+
+fn session_key(id: &Uuid) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, id)
+}
+
+fn user_key(user_id: &UserId) -> String {
+    format!("{}{}", USER_KEY_PREFIX, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    async fn configure_store() -> RedisSessionStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisSessionStore::new(conn)
+    }
+
+    fn session(user_id: &UserId, created_at: DateTime<Utc>) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            user_id: *user_id,
+            created_at,
+            last_seen_at: created_at,
+            user_agent: Some("test-agent".to_owned()),
+            ip_address: Some("127.0.0.1".to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_add_get_and_touch_sessions() {
+        let mut store = configure_store().await;
+        let now = Utc::now();
+        let user_id = UserId::default();
+        let newer = session(&user_id, now);
+        let older = Session {
+            user_agent: None,
+            ip_address: None,
+            ..session(&user_id, now - Duration::hours(1))
+        };
+        let other = session(&UserId::default(), now);
+
+        for session in [&newer, &older, &other] {
+            store.add_session(session.clone()).await.unwrap();
+        }
+
+        assert_eq!(store.get_sessions(&user_id).await, Ok(vec![older.clone(), newer.clone()]));
+        let ttl: i64 = store.conn.ttl(session_key(&older.id)).await.unwrap();
+        assert!(ttl > REFRESH_TOKEN_TTL_SECONDS - 3610 && ttl <= REFRESH_TOKEN_TTL_SECONDS - 3590);
+
+        let later = now + Duration::minutes(5);
+        assert_eq!(store.touch_session(&older.id, later).await, Ok(()));
+        assert_eq!(store.get_session(&older.id).await.unwrap().last_seen_at, later);
+        let ttl: i64 = store.conn.ttl(session_key(&older.id)).await.unwrap();
+        assert!(ttl > REFRESH_TOKEN_TTL_SECONDS && ttl <= REFRESH_TOKEN_TTL_SECONDS + 300);
+        assert_eq!(
+            store.touch_session(&Uuid::new_v4(), later).await,
+            Err(SessionStoreError::SessionNotFound)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_expired_sessions_are_not_kept() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let expired = session(&user_id, Utc::now() - Duration::seconds(REFRESH_TOKEN_TTL_SECONDS + 1));
+
+        store.add_session(expired.clone()).await.unwrap();
+
+        assert_eq!(store.get_session(&expired.id).await, Err(SessionStoreError::SessionNotFound));
+        assert!(store.get_sessions(&user_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_remove_sessions() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let first = session(&user_id, Utc::now());
+        let second = session(&user_id, Utc::now());
+        let other = session(&UserId::default(), Utc::now());
+
+        for session in [&first, &second, &other] {
+            store.add_session(session.clone()).await.unwrap();
+        }
+
+        assert_eq!(store.remove_session(&first.id).await, Ok(()));
+        assert_eq!(store.remove_session(&first.id).await, Err(SessionStoreError::SessionNotFound));
+
+        assert_eq!(store.remove_user_sessions(&user_id).await, Ok(vec![second]));
+        assert!(store.get_sessions(&user_id).await.unwrap().is_empty());
+        assert_eq!(store.get_session(&other.id).await, Ok(other));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+
+use crate::{
+    domain::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, UserId},
+    utils::constants::{TWO_FA_CODE_MAX_ATTEMPTS, TWO_FA_CODE_TTL_SECONDS},
+};
+
+const TWO_FA_CODE_KEY_PREFIX: &str = "two_fa_code:";
+
+const LOGIN_ATTEMPT_ID_FIELD: &str = "login_attempt_id";
+const CODE_FIELD: &str = "code";
+const FAILED_ATTEMPTS_FIELD: &str = "failed_attempts";
+const EXPIRES_AT_FIELD: &str = "expires_at";
+
+// How long an expired code is kept around, so it is reported as expired rather than
+// not found
+const EXPIRED_CODE_RETENTION_SECONDS: i64 = 3600;
+
+// Only count the attempt if the code still exists, so an expired code isn't
+// brought back as a hash without a TTL
+const RECORD_FAILED_ATTEMPT_SCRIPT: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return redis.call('HINCRBY', KEYS[1], ARGV[1], 1)
+end
+return nil
+";
+
+// Pending codes are stored as a hash per user along with when they expire. The key
+// outlives the code by `EXPIRED_CODE_RETENTION_SECONDS`, so an expired code is still
+// found and reported as expired, and Redis cleans it up on its own after that.
+pub struct RedisTwoFACodeStore {
+    conn: ConnectionManager,
+    ttl: Duration,
+    max_attempts: u32,
+}
+
+impl RedisTwoFACodeStore {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self {
+            conn,
+            ttl: Duration::seconds(TWO_FA_CODE_TTL_SECONDS),
+            max_attempts: TWO_FA_CODE_MAX_ATTEMPTS,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TwoFACodeStore for RedisTwoFACodeStore {
+    async fn add_code(
+        &mut self,
+        user_id: UserId,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let key = get_key(&user_id);
+        let expires_at = (Utc::now() + self.ttl).to_rfc3339();
+
+        // Replace any previous code, including its failed attempts, in one transaction
+        redis::pipe()
+            .atomic()
+            .del(&key)
+            .hset_multiple(
+                &key,
+                &[
+                    (LOGIN_ATTEMPT_ID_FIELD, login_attempt_id.as_ref()),
+                    (CODE_FIELD, code.as_ref()),
+                    (FAILED_ATTEMPTS_FIELD, "0"),
+                    (EXPIRES_AT_FIELD, expires_at.as_str()),
+                ],
+            )
+            .expire(&key, self.ttl.num_seconds() + EXPIRED_CODE_RETENTION_SECONDS)
+            .exec_async(&mut self.conn)
+            .await
+            .map_err(|_| TwoFACodeStoreError::UnexpectedError)
+    }
+
+    async fn remove_code(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError> {
+        let removed: u32 = self
+            .conn
+            .del(get_key(user_id))
+            .await
+            .map_err(|_| TwoFACodeStoreError::UnexpectedError)?;
+
+        match removed {
+            0 => Err(TwoFACodeStoreError::UnexpectedError),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_code(
+        &self,
+        user_id: &UserId,
+    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        let entry: HashMap<String, String> = self
+            .conn
+            .clone()
+            .hgetall(get_key(user_id))
+            .await
+            .map_err(|_| TwoFACodeStoreError::UnexpectedError)?;
+
+        if entry.is_empty() {
+            return Err(TwoFACodeStoreError::LoginAttemptIdNotFound);
+        }
+
+        let failed_attempts: u32 = entry
+            .get(FAILED_ATTEMPTS_FIELD)
+            .and_then(|attempts| attempts.parse().ok())
+            .ok_or(TwoFACodeStoreError::UnexpectedError)?;
+        if failed_attempts >= self.max_attempts {
+            return Err(TwoFACodeStoreError::TooManyAttempts);
+        }
+
+        let expires_at = entry
+            .get(EXPIRES_AT_FIELD)
+            .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+            .ok_or(TwoFACodeStoreError::UnexpectedError)?;
+        if Utc::now() >= expires_at {
+            return Err(TwoFACodeStoreError::CodeExpired);
+        }
+
+        let login_attempt_id = entry
+            .get(LOGIN_ATTEMPT_ID_FIELD)
+            .and_then(|id| LoginAttemptId::parse(id.to_owned()).ok())
+            .ok_or(TwoFACodeStoreError::UnexpectedError)?;
+        let code = entry
+            .get(CODE_FIELD)
+            .and_then(|code| TwoFACode::parse(code.to_owned()).ok())
+            .ok_or(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok((login_attempt_id, code))
+    }
+
+    async fn record_failed_attempt(&mut self, user_id: &UserId) -> Result<(), TwoFACodeStoreError> {
+        let failed_attempts: Option<u32> = Script::new(RECORD_FAILED_ATTEMPT_SCRIPT)
+            .key(get_key(user_id))
+            .arg(FAILED_ATTEMPTS_FIELD)
+            .invoke_async(&mut self.conn)
+            .await
+            .map_err(|_| TwoFACodeStoreError::UnexpectedError)?;
+
+        failed_attempts
+            .map(|_| ())
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
+    }
+
+}
+
+fn get_key(user_id: &UserId) -> String {
+    format!("{}{}", TWO_FA_CODE_KEY_PREFIX, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_redis_connection, utils::constants::test};
+
+    async fn configure_store() -> RedisTwoFACodeStore {
+        let conn = get_redis_connection(test::REDIS_HOST_NAME)
+            .await
+            .expect("Failed to connect to Redis");
+
+        RedisTwoFACodeStore::new(conn)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_add_and_get_code() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+        let login_attempt_id = LoginAttemptId::default();
+        let code = TwoFACode::default();
+
+        store
+            .add_code(user_id, login_attempt_id.clone(), code.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_code(&user_id).await, Ok((login_attempt_id, code)));
+
+        let ttl: i64 = store.conn.ttl(get_key(&user_id)).await.unwrap();
+        assert!(
+            ttl > TWO_FA_CODE_TTL_SECONDS
+                && ttl <= TWO_FA_CODE_TTL_SECONDS + EXPIRED_CODE_RETENTION_SECONDS
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_remove_code() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+
+        store
+            .add_code(user_id, LoginAttemptId::default(), TwoFACode::default())
+            .await
+            .unwrap();
+
+        assert_eq!(store.remove_code(&user_id).await, Ok(()));
+        assert_eq!(
+            store.get_code(&user_id).await,
+            Err(TwoFACodeStoreError::LoginAttemptIdNotFound)
+        );
+        assert_eq!(
+            store.remove_code(&user_id).await,
+            Err(TwoFACodeStoreError::UnexpectedError)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_code_expires() {
+        let mut store = configure_store().await;
+        store.ttl = Duration::seconds(1);
+        let user_id = UserId::default();
+
+        store
+            .add_code(user_id, LoginAttemptId::default(), TwoFACode::default())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        assert_eq!(
+            store.get_code(&user_id).await,
+            Err(TwoFACodeStoreError::CodeExpired)
+        );
+        // A late wrong guess is counted without extending the code's retention
+        store.record_failed_attempt(&user_id).await.unwrap();
+        assert_eq!(
+            store.get_code(&user_id).await,
+            Err(TwoFACodeStoreError::CodeExpired)
+        );
+        let ttl: i64 = store.conn.ttl(get_key(&user_id)).await.unwrap();
+        assert!(ttl > 0 && ttl <= EXPIRED_CODE_RETENTION_SECONDS + 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_code_invalidated_after_max_failed_attempts() {
+        let mut store = configure_store().await;
+        let user_id = UserId::default();
+
+        store
+            .add_code(user_id, LoginAttemptId::default(), TwoFACode::default())
+            .await
+            .unwrap();
+
+        for _ in 0..TWO_FA_CODE_MAX_ATTEMPTS - 1 {
+            store.record_failed_attempt(&user_id).await.unwrap();
+        }
+        assert!(store.get_code(&user_id).await.is_ok());
+
+        store.record_failed_attempt(&user_id).await.unwrap();
+        assert_eq!(
+            store.get_code(&user_id).await,
+            Err(TwoFACodeStoreError::TooManyAttempts)
+        );
+
+        // A new code starts over
+        store
+            .add_code(user_id, LoginAttemptId::default(), TwoFACode::default())
+            .await
+            .unwrap();
+        assert!(store.get_code(&user_id).await.is_ok());
+    }
+}
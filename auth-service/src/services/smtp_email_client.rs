@@ -0,0 +1,452 @@
+use lettre::{
+    message::{Mailbox, MultiPart, SinglePart},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Certificate, Tls, TlsParameters},
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::domain::{Email, EmailClient};
+
+// How the connection to the SMTP server is encrypted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpTls {
+    // Starts in plain text and upgrades with STARTTLS, refusing to go on without it
+    StartTls,
+    // TLS from the first byte, sometimes called SMTPS
+    Implicit,
+    // No encryption at all, only meant for a relay on the same host or a test server
+    None,
+}
+
+impl SmtpTls {
+    pub fn parse(tls: &str) -> Result<SmtpTls, String> {
+        match tls {
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Implicit),
+            "none" => Ok(SmtpTls::None),
+            _ => Err(format!("Unknown SMTP TLS mode: {}", tls)),
+        }
+    }
+
+    // The port servers conventionally use for each mode
+    pub fn default_port(&self) -> u16 {
+        match self {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Implicit => 465,
+            SmtpTls::None => 25,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    // Username and password, for servers that require authentication
+    pub credentials: Option<(String, String)>,
+    // The From address, e.g. "Auth Service <no-reply@example.com>"
+    pub sender: String,
+    // PEM encoded CA certificate to trust besides the usual web PKI roots,
+    // for servers with a certificate from a private CA
+    pub root_certificate: Option<String>,
+}
+
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: Mailbox,
+}
+
+impl SmtpEmailClient {
+    pub fn new(config: SmtpConfig) -> Result<Self, String> {
+        let sender = config
+            .sender
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid sender address: {}", e))?;
+
+        let tls = match config.tls {
+            SmtpTls::StartTls => Tls::Required(tls_parameters(&config)?),
+            SmtpTls::Implicit => Tls::Wrapper(tls_parameters(&config)?),
+            SmtpTls::None => Tls::None,
+        };
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            .port(config.port)
+            .tls(tls);
+        if let Some((username, password)) = config.credentials {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            sender,
+        })
+    }
+
+    async fn send(&self, recipient: &Email, subject: &str, body: Body) -> Result<(), String> {
+        let recipient = recipient
+            .as_ref()
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid recipient address: {}", e))?;
+
+        let message = Message::builder()
+            .from(self.sender.clone())
+            .to(recipient)
+            .subject(subject);
+        let message = match body {
+            Body::Text(text) => message.singlepart(SinglePart::plain(text)),
+            Body::TextAndHtml { text, html } => {
+                message.multipart(MultiPart::alternative_plain_html(text, html))
+            }
+        }
+        .map_err(|e| format!("Failed to build email: {}", e))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send email: {}", e))
+    }
+}
+
+enum Body {
+    Text(String),
+    TextAndHtml { text: String, html: String },
+}
+
+fn tls_parameters(config: &SmtpConfig) -> Result<TlsParameters, String> {
+    let mut parameters = TlsParameters::builder(config.host.clone());
+    if let Some(pem) = &config.root_certificate {
+        let certificate = Certificate::from_pem(pem.as_bytes())
+            .map_err(|e| format!("Invalid SMTP root certificate: {}", e))?;
+        parameters = parameters.add_root_certificate(certificate);
+    }
+    parameters
+        .build_rustls()
+        .map_err(|e| format!("Failed to set up SMTP TLS: {}", e))
+}
+
+#[async_trait::async_trait]
+impl EmailClient for SmtpEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        self.send(recipient, subject, Body::Text(content.to_owned()))
+            .await
+    }
+
+    async fn send_html_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        text_content: &str,
+        html_content: &str,
+    ) -> Result<(), String> {
+        let body = Body::TextAndHtml {
+            text: text_content.to_owned(),
+            html: html_content.to_owned(),
+        };
+        self.send(recipient, subject, body).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use rcgen::{generate_simple_self_signed, CertifiedKey};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{
+        rustls::{
+            crypto::ring::default_provider,
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            ServerConfig,
+        },
+        TlsAcceptor,
+    };
+
+    use super::*;
+
+    #[derive(Clone, Debug, Default)]
+    struct ReceivedEmail {
+        mail_from: String,
+        rcpt_to: Vec<String>,
+        // Decoded AUTH PLAIN credentials, if the client authenticated
+        credentials: Option<(String, String)>,
+        // Whether the message came in over TLS
+        encrypted: bool,
+        data: String,
+    }
+
+    // Just enough of an SMTP server to accept mail from lettre and keep it for inspection
+    struct SmtpSink {
+        port: u16,
+        received: Arc<Mutex<Vec<ReceivedEmail>>>,
+        certificate_pem: String,
+    }
+
+    impl SmtpSink {
+        async fn spawn(tls: SmtpTls) -> Self {
+            let CertifiedKey { cert, key_pair } =
+                generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+            let server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+                )
+                .unwrap();
+            let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(Vec::new()));
+
+            let sink_received = received.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve_connection(stream, tls, acceptor.clone(), sink_received.clone()));
+                }
+            });
+
+            Self {
+                port,
+                received,
+                certificate_pem: cert.pem(),
+            }
+        }
+
+        // Trusts the sink's self-signed certificate
+        fn client_config(&self, tls: SmtpTls) -> SmtpConfig {
+            SmtpConfig {
+                host: "localhost".to_owned(),
+                port: self.port,
+                tls,
+                credentials: None,
+                sender: "Auth Service <no-reply@example.com>".to_owned(),
+                root_certificate: Some(self.certificate_pem.clone()),
+            }
+        }
+
+        fn received(&self) -> Vec<ReceivedEmail> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    async fn serve_connection(
+        stream: TcpStream,
+        tls: SmtpTls,
+        acceptor: TlsAcceptor,
+        received: Arc<Mutex<Vec<ReceivedEmail>>>,
+    ) {
+        match tls {
+            SmtpTls::Implicit => {
+                let stream = acceptor.accept(stream).await.unwrap();
+                serve_session(stream, Session::Encrypted, &received).await;
+            }
+            SmtpTls::StartTls => {
+                // The session hands the stream back once the client asks for STARTTLS
+                if let Some(stream) = serve_session(stream, Session::OfferingStartTls, &received).await {
+                    let stream = acceptor.accept(stream).await.unwrap();
+                    serve_session(stream, Session::UpgradedToTls, &received).await;
+                }
+            }
+            SmtpTls::None => {
+                serve_session(stream, Session::Plain, &received).await;
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Session {
+        Plain,
+        OfferingStartTls,
+        UpgradedToTls,
+        Encrypted,
+    }
+
+    async fn serve_session<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: S,
+        session: Session,
+        received: &Mutex<Vec<ReceivedEmail>>,
+    ) -> Option<S> {
+        let mut stream = BufReader::new(stream);
+        let mut email = ReceivedEmail {
+            encrypted: matches!(session, Session::UpgradedToTls | Session::Encrypted),
+            ..Default::default()
+        };
+
+        // After STARTTLS the client says EHLO again, but the server doesn't greet twice
+        if session != Session::UpgradedToTls {
+            stream.write_all(b"220 localhost ESMTP sink\r\n").await.ok()?;
+        }
+
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            let command = line.split(' ').next().unwrap_or_default().to_uppercase();
+
+            let reply = match command.as_str() {
+                "EHLO" | "HELO" => {
+                    let starttls = match session {
+                        Session::OfferingStartTls => "250-STARTTLS\r\n",
+                        _ => "",
+                    };
+                    format!("250-localhost\r\n{}250-AUTH PLAIN\r\n250 8BITMIME\r\n", starttls)
+                }
+                "STARTTLS" => {
+                    stream.write_all(b"220 Ready to start TLS\r\n").await.ok()?;
+                    return Some(stream.into_inner());
+                }
+                "AUTH" => {
+                    let encoded = line.split(' ').nth(2).unwrap_or_default();
+                    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+                    let mut parts = decoded.split('\0').skip(1);
+                    email.credentials = Some((
+                        parts.next()?.to_owned(),
+                        parts.next()?.to_owned(),
+                    ));
+                    "235 Authenticated\r\n".to_owned()
+                }
+                "MAIL" => {
+                    email.mail_from = line.to_owned();
+                    "250 OK\r\n".to_owned()
+                }
+                "RCPT" => {
+                    email.rcpt_to.push(line.to_owned());
+                    "250 OK\r\n".to_owned()
+                }
+                "DATA" => {
+                    stream.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.ok()?;
+                    loop {
+                        let mut data_line = String::new();
+                        stream.read_line(&mut data_line).await.ok()?;
+                        if data_line == ".\r\n" {
+                            break;
+                        }
+                        email.data.push_str(&data_line);
+                    }
+                    received.lock().unwrap().push(email.clone());
+                    "250 Queued\r\n".to_owned()
+                }
+                "RSET" | "NOOP" => "250 OK\r\n".to_owned(),
+                "QUIT" => {
+                    stream.write_all(b"221 Bye\r\n").await.ok()?;
+                    return None;
+                }
+                _ => "502 Command not implemented\r\n".to_owned(),
+            };
+            stream.write_all(reply.as_bytes()).await.ok()?;
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::parse("user@example.com").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_send_multipart_email_over_starttls() {
+        let sink = SmtpSink::spawn(SmtpTls::StartTls).await;
+        let credentials = Some(("smtp-user".to_owned(), "smtp-password".to_owned()));
+        let client = SmtpEmailClient::new(SmtpConfig {
+            credentials: credentials.clone(),
+            ..sink.client_config(SmtpTls::StartTls)
+        })
+        .unwrap();
+
+        client
+            .send_html_email(&recipient(), "Your code", "Your code is 123456", "<p>Your code is <b>123456</b></p>")
+            .await
+            .unwrap();
+
+        let received = sink.received();
+        assert_eq!(received.len(), 1);
+        let email = &received[0];
+        assert!(email.encrypted);
+        assert_eq!(email.credentials, credentials);
+        assert_eq!(email.mail_from, "MAIL FROM:<no-reply@example.com>");
+        assert_eq!(email.rcpt_to, vec!["RCPT TO:<user@example.com>"]);
+
+        assert!(email.data.contains("From: \"Auth Service\" <no-reply@example.com>"));
+        assert!(email.data.contains("To: user@example.com"));
+        assert!(email.data.contains("Subject: Your code"));
+        assert!(email.data.contains("Content-Type: multipart/alternative"));
+        assert!(email.data.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(email.data.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(email.data.contains("Your code is 123456"));
+        assert!(email.data.contains("<p>Your code is <b>123456</b></p>"));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_over_implicit_tls() {
+        let sink = SmtpSink::spawn(SmtpTls::Implicit).await;
+        let client = SmtpEmailClient::new(sink.client_config(SmtpTls::Implicit)).unwrap();
+
+        client
+            .send_email(&recipient(), "Your code", "Your code is 123456")
+            .await
+            .unwrap();
+
+        let received = sink.received();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].encrypted);
+        assert_eq!(received[0].credentials, None);
+        assert!(received[0].data.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(!received[0].data.contains("multipart"));
+        assert!(received[0].data.contains("Your code is 123456"));
+    }
+
+    #[tokio::test]
+    async fn test_starttls_is_required() {
+        // A server that doesn't offer STARTTLS must not get the message in plain text
+        let sink = SmtpSink::spawn(SmtpTls::None).await;
+        let client = SmtpEmailClient::new(sink.client_config(SmtpTls::StartTls)).unwrap();
+
+        assert!(client.send_email(&recipient(), "Your code", "Your code is 123456").await.is_err());
+        assert!(sink.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_untrusted_certificate() {
+        let sink = SmtpSink::spawn(SmtpTls::Implicit).await;
+        let client = SmtpEmailClient::new(SmtpConfig {
+            root_certificate: None,
+            ..sink.client_config(SmtpTls::Implicit)
+        })
+        .unwrap();
+
+        assert!(client.send_email(&recipient(), "Your code", "Your code is 123456").await.is_err());
+        assert!(sink.received().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(SmtpTls::parse("tls"), Ok(SmtpTls::Implicit));
+        assert!(SmtpTls::parse("ssl").is_err());
+
+        let config = SmtpConfig {
+            host: "localhost".to_owned(),
+            port: 25,
+            tls: SmtpTls::None,
+            credentials: None,
+            sender: "not an address".to_owned(),
+            root_certificate: None,
+        };
+        assert!(SmtpEmailClient::new(config).is_err());
+    }
+}
@@ -1,14 +1,45 @@
-use axum_extra::extract::cookie::{Cookie, SameSite};
-use chrono::Utc;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, Validation};
+use minijinja::context;
+use ring::digest;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use super::constants::{JWT_COOKIE_NAME, JWT_SECRET};
-use crate::{app_state::BannedTokenStoreType, domain::{BannedTokenStore, Email}};
+use super::{
+    constants::{
+        ADMIN_API_KEY_HEADER, EMAIL_VERIFICATION_AUDIENCE, EMAIL_VERIFICATION_TOKEN_TTL_SECONDS,
+        JWT_AUDIENCE, JWT_COOKIE_NAME, JWT_ISSUER, REFRESH_TOKEN_COOKIE_NAME,
+    },
+    email_templates::{queue_templated_email, EmailTemplate, Locale},
+    keyring::{reload_keyring_for_kid, JwtKeyring},
+    signing_key::JwtSigningKey,
+};
+use crate::{
+    app_state::{AppState, BannedTokenStoreType, RefreshTokenStoreType, UserStoreType},
+    domain::{
+        AuthAPIError, Email, HashedPassword, Password, RefreshToken, RefreshTokenRecord, Session,
+        SessionStoreError, User, UserId, UserStoreError,
+    },
+};
 
-// Create cookie with a new JWT auth token
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>, GenerateTokenError> {
-    let token = generate_auth_token(email)?;
+// Create cookie with a new JWT auth token belonging to session `session_id`
+pub fn generate_auth_cookie(
+    user: &User,
+    session_id: &Uuid,
+    keyring: &JwtKeyring,
+) -> Result<Cookie<'static>, GenerateTokenError> {
+    let token = generate_auth_token(user, session_id, keyring)?;
     Ok(create_auth_cookie(token))
 }
 
@@ -23,6 +54,203 @@ fn create_auth_cookie(token: String) -> Cookie<'static> {
     cookie
 }
 
+// Issue a new refresh token belonging to `family_id` and wrap it in a cookie.
+// Logins start a new family; `/refresh` passes the family of the token being rotated.
+pub async fn generate_refresh_cookie(
+    user_id: &UserId,
+    family_id: &str,
+    refresh_token_store: RefreshTokenStoreType,
+) -> Result<Cookie<'static>, GenerateTokenError> {
+    let delta = chrono::Duration::try_seconds(REFRESH_TOKEN_TTL_SECONDS)
+        .ok_or(GenerateTokenError::UnexpectedError)?;
+
+    let expires_at = Utc::now()
+        .checked_add_signed(delta)
+        .ok_or(GenerateTokenError::UnexpectedError)?;
+
+    let token = RefreshToken::default();
+    let record = RefreshTokenRecord {
+        user_id: *user_id,
+        family_id: family_id.to_owned(),
+        expires_at,
+        used: false,
+    };
+
+    refresh_token_store
+        .write()
+        .await
+        .add_token(token.clone(), record)
+        .await
+        .map_err(|_| GenerateTokenError::UnexpectedError)?;
+
+    Ok(create_refresh_cookie(token))
+}
+
+// Create cookie and set the value to the passed-in refresh token
+fn create_refresh_cookie(token: RefreshToken) -> Cookie<'static> {
+    Cookie::build((REFRESH_TOKEN_COOKIE_NAME, token.as_ref().to_owned()))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        // unlike the JWT cookie, this one has to outlive the browser session
+        .max_age(time::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS))
+        .build()
+}
+
+// Where a session was started from, as shown in the session list
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SessionClient {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        // Present whenever the server was started with connect info, as `Application` does
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string());
+
+        Ok(SessionClient { user_agent, ip_address })
+    }
+}
+
+// Log `user` in on a new session and let them know about it by email. Returns the
+// session's JWT cookie and its first refresh token cookie; the session's refresh
+// tokens form one family.
+pub async fn start_session(
+    user: &User,
+    client: SessionClient,
+    locale: &Locale,
+    state: &AppState,
+) -> Result<(Cookie<'static>, Cookie<'static>), GenerateTokenError> {
+    let now = Utc::now();
+    let session = Session {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        created_at: now,
+        last_seen_at: now,
+        user_agent: client.user_agent,
+        ip_address: client.ip_address,
+    };
+    state
+        .session_store
+        .write()
+        .await
+        .add_session(session.clone())
+        .await
+        .map_err(|_| GenerateTokenError::UnexpectedError)?;
+
+    let auth_cookie = generate_auth_cookie(user, &session.id, &*state.jwt_keyring.read().await)?;
+    let refresh_cookie = generate_refresh_cookie(
+        &user.id,
+        &session.id.to_string(),
+        state.refresh_token_store.clone(),
+    )
+    .await?;
+
+    let context = context! {
+        logged_in_at => now.format("%Y-%m-%d %H:%M UTC").to_string(),
+        ip_address => session.ip_address.as_deref().unwrap_or("unknown"),
+        user_agent => session.user_agent.as_deref().unwrap_or("unknown"),
+    };
+    // The session has started either way, so a failed send only gets logged
+    if let Err(e) =
+        queue_templated_email(state, &user.email, EmailTemplate::NewLoginAlert, locale, context).await
+    {
+        eprintln!("Failed to send new login alert: {}", e);
+    }
+
+    Ok((auth_cookie, refresh_cookie))
+}
+
+// Ends a session: its refresh tokens are revoked and its JWTs are banned by `sid`.
+// Ending a session that is already gone succeeds.
+pub async fn end_session(session_id: &Uuid, state: &AppState) -> Result<(), AuthAPIError> {
+    match state.session_store.write().await.remove_session(session_id).await {
+        Ok(()) | Err(SessionStoreError::SessionNotFound) => {}
+        Err(_) => return Err(AuthAPIError::UnexpectedError),
+    }
+
+    state
+        .refresh_token_store
+        .write()
+        .await
+        .revoke_family(&session_id.to_string())
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    ban_session_tokens(session_id, state).await
+}
+
+// Ends every session of `user_id` except `current_session_id`
+pub async fn end_other_sessions(
+    user_id: &UserId,
+    current_session_id: &Uuid,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let sessions = state
+        .session_store
+        .read()
+        .await
+        .get_sessions(user_id)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    for session in sessions.iter().filter(|session| session.id != *current_session_id) {
+        end_session(&session.id, state).await?;
+    }
+    Ok(())
+}
+
+// Ends every session of `user_id`, logging the user out everywhere
+pub async fn end_user_sessions(user_id: &UserId, state: &AppState) -> Result<(), AuthAPIError> {
+    let sessions = state
+        .session_store
+        .write()
+        .await
+        .remove_user_sessions(user_id)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    state
+        .refresh_token_store
+        .write()
+        .await
+        .revoke_user_tokens(user_id)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)?;
+
+    for session in sessions {
+        ban_session_tokens(&session.id, state).await?;
+    }
+    Ok(())
+}
+
+// JWTs can't be recalled, so ban the session ID until the last one issued has expired
+async fn ban_session_tokens(session_id: &Uuid, state: &AppState) -> Result<(), AuthAPIError> {
+    let expires_at = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
+        .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+        .ok_or(AuthAPIError::UnexpectedError)?;
+
+    state
+        .banned_token_store
+        .write()
+        .await
+        .add_token(session_id.to_string(), expires_at)
+        .await
+        .map_err(|_| AuthAPIError::UnexpectedError)
+}
+
 #[derive(Debug)]
 pub enum GenerateTokenError {
     TokenError(jsonwebtoken::errors::Error),
@@ -32,64 +260,336 @@ pub enum GenerateTokenError {
 // This value determines how long the JWT auth token is valid for
 pub const TOKEN_TTL_SECONDS: i64 = 600; // 10 minutes
 
+// This value determines how long a refresh token can be exchanged for a new JWT
+pub const REFRESH_TOKEN_TTL_SECONDS: i64 = 1_209_600; // 14 days
+
 // Create JWT auth token
-fn generate_auth_token(email: &Email) -> Result<String, GenerateTokenError> {
-    let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
+fn generate_auth_token(
+    user: &User,
+    session_id: &Uuid,
+    keyring: &JwtKeyring,
+) -> Result<String, GenerateTokenError> {
+    let (iat, exp) = issued_and_expiry_timestamps(TOKEN_TTL_SECONDS)?;
+
+    let claims = Claims {
+        sub: user.email.as_ref().to_owned(),
+        uid: *user.id.as_ref(),
+        exp,
+        iat,
+        jti: Uuid::new_v4().to_string(),
+        sid: *session_id,
+        token_version: user.token_version,
+        iss: JWT_ISSUER.to_owned(),
+        aud: JWT_AUDIENCE.to_owned(),
+        roles: user.roles.clone(),
+    };
+
+    let signing_key = keyring
+        .signing_key(Utc::now())
+        .ok_or(GenerateTokenError::UnexpectedError)?;
+
+    create_token(&claims, &signing_key).map_err(GenerateTokenError::TokenError)
+}
+
+// Create a signed link token proving the holder can read mail sent to `email`
+pub fn generate_email_verification_token(
+    email: &Email,
+    keyring: &JwtKeyring,
+) -> Result<String, GenerateTokenError> {
+    let (iat, exp) = issued_and_expiry_timestamps(EMAIL_VERIFICATION_TOKEN_TTL_SECONDS)?;
+
+    let claims = EmailVerificationClaims {
+        sub: email.as_ref().to_owned(),
+        exp,
+        iat,
+        iss: JWT_ISSUER.to_owned(),
+        aud: EMAIL_VERIFICATION_AUDIENCE.to_owned(),
+    };
+
+    let signing_key = keyring
+        .signing_key(Utc::now())
+        .ok_or(GenerateTokenError::UnexpectedError)?;
+
+    create_token(&claims, &signing_key).map_err(GenerateTokenError::TokenError)
+}
+
+// Returns the email address an unexpired verification token was issued for
+pub fn validate_email_verification_token(
+    token: &str,
+    keyring: &JwtKeyring,
+) -> Result<Email, jsonwebtoken::errors::Error> {
+    let signing_key = verification_key_for(token, keyring)?;
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[EMAIL_VERIFICATION_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+    let claims = decode::<EmailVerificationClaims>(token, signing_key.decoding_key(), &validation)?.claims;
+
+    Email::parse(&claims.sub).map_err(|_| {
+        jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidSubject)
+    })
+}
+
+// Checks `password` against the user's stored hash. Argon2 runs without holding the
+// user store lock; it's only taken briefly to upgrade an outdated hash.
+pub async fn verify_password(
+    user: &User,
+    password: &Password,
+    user_store: &UserStoreType,
+) -> Result<(), UserStoreError> {
+    user.password
+        .verify_raw_password(password)
+        .await
+        .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+    if user.password.needs_rehash() {
+        let upgraded = HashedPassword::parse(password.clone())
+            .await
+            .map_err(|_| UserStoreError::UnexpectedError)?;
+        user_store
+            .write()
+            .await
+            .upgrade_password_hash(&user.email, &user.password, upgraded)
+            .await?;
+    }
+    Ok(())
+}
+
+// Looks up `email` and checks its password. An unknown email is checked against a
+// dummy hash, so it takes as long as a wrong password and reports the same error.
+pub async fn validate_user(
+    email: &Email,
+    password: &Password,
+    user_store: &UserStoreType,
+) -> Result<User, UserStoreError> {
+    let user = user_store.read().await.get_user(email).await;
+    let user = match user {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => {
+            HashedPassword::verify_dummy_password(password).await;
+            return Err(UserStoreError::InvalidCredentials);
+        }
+        Err(e) => return Err(e),
+    };
+
+    verify_password(&user, password, user_store).await?;
+    Ok(user)
+}
+
+// Unix timestamps for a token issued now that lives for `ttl_seconds`
+fn issued_and_expiry_timestamps(ttl_seconds: i64) -> Result<(usize, usize), GenerateTokenError> {
+    let delta = chrono::Duration::try_seconds(ttl_seconds)
         .ok_or(GenerateTokenError::UnexpectedError)?;
 
+    let now = Utc::now();
+
     // Create JWT expiration time
-    let exp = Utc::now()
+    let exp = now
         .checked_add_signed(delta)
         .ok_or(GenerateTokenError::UnexpectedError)?
         .timestamp();
 
-    // Cast exp to a usize, which is what Claims expects
+    // Cast exp and iat to a usize, which is what the claims expect
     let exp: usize = exp
         .try_into()
         .map_err(|_| GenerateTokenError::UnexpectedError)?;
+    let iat: usize = now
+        .timestamp()
+        .try_into()
+        .map_err(|_| GenerateTokenError::UnexpectedError)?;
+
+    Ok((iat, exp))
+}
 
-    let sub = email.as_ref().to_owned();
+// Reject tokens that weren't signed by one of our live keys before doing any signature work
+fn verification_key_for(
+    token: &str,
+    keyring: &JwtKeyring,
+) -> Result<Arc<JwtSigningKey>, jsonwebtoken::errors::Error> {
+    let header = decode_header(token)?;
+    header
+        .kid
+        .and_then(|kid| keyring.verification_key(&kid, Utc::now()))
+        .ok_or_else(|| {
+            jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken)
+        })
+}
 
-    let claims = Claims { sub, exp };
+// Call before validating a token: if it names a key we don't know, the keyring is
+// reloaded in case another replica has just added that key
+pub async fn reload_keyring_for_unknown_kid(token: &str, state: &AppState) {
+    let Some(kid) = decode_header(token).ok().and_then(|header| header.kid) else {
+        return;
+    };
 
-    create_token(&claims).map_err(GenerateTokenError::TokenError)
+    if let Err(e) = reload_keyring_for_kid(&state.jwt_keyring, &state.signing_key_store, &kid).await {
+        eprintln!("Failed to reload the JWT keyring: {}", e);
+    }
 }
 
-// Check if JWT auth token is valid by decoding it using the JWT secret
+// Check if JWT auth token is valid by verifying its signature with the key named by its `kid`
 pub async fn validate_token(
     token: &str,
+    keyring: &JwtKeyring,
     banned_token_store: BannedTokenStoreType,
+    user_store: UserStoreType,
 ) -> Result<Claims, jsonwebtoken::errors::Error> {
-    // Only check banned tokens if a store is provided
-    let store =  banned_token_store.read().await;
-    let is_banned = store.is_banned_token(&token).await.unwrap();
+    let signing_key = verification_key_for(token, keyring)?;
+
+    let claims = decode::<Claims>(token, signing_key.decoding_key(), &validation())?.claims;
+
+    // Logged out tokens are banned by their `jti`, and every token of an ended session by its `sid`
+    let banned_token_store = banned_token_store.read().await;
+    let is_banned = banned_token_store
+        .is_banned_token(&claims.jti)
+        .await
+        .unwrap_or(true)
+        || banned_token_store
+            .is_banned_token(&claims.sid.to_string())
+            .await
+            .unwrap_or(true);
     if is_banned {
         return Err(jsonwebtoken::errors::Error::from(
             jsonwebtoken::errors::ErrorKind::InvalidToken,
         ));
     }
 
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
+    // Bumping the user's token version rejects every token issued before, including
+    // ones we never saw again. Tokens of deleted accounts are rejected too, even once
+    // someone else has signed up with the same address.
+    let current_version = user_store
+        .read()
+        .await
+        .get_user_by_id(&UserId::from(claims.uid))
+        .await
+        .ok()
+        .map(|user| user.token_version);
+    if current_version != Some(claims.token_version) {
+        return Err(jsonwebtoken::errors::Error::from(
+            jsonwebtoken::errors::ErrorKind::InvalidToken,
+        ));
+    }
+
+    Ok(claims)
 }
 
-// Create JWT auth token by encoding claims using the JWT secret
-fn create_token(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
-    encode(
-        &jsonwebtoken::Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
-    )
+// Tokens must be ours (issuer) and meant for our services (audience), besides having a valid signature
+fn validation() -> Validation {
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[JWT_AUDIENCE.as_str()]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+    validation
+}
+
+// Create JWT auth token by signing the claims with the given private key
+fn create_token<T: Serialize>(claims: &T, signing_key: &JwtSigningKey) -> Result<String, jsonwebtoken::errors::Error> {
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(signing_key.kid().to_owned());
+
+    encode(&header, &claims, signing_key.encoding_key())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
+    // The user's email address when the token was issued
     pub sub: String,
+    // The user's id, which unlike `sub` never changes
+    pub uid: Uuid,
     pub exp: usize,
+    pub iat: usize,
+    // Unique per token, so a single token can be told apart from others issued to the same user
+    pub jti: String,
+    // The session the token was issued to, see `SessionStore`
+    pub sid: Uuid,
+    // The user's `token_version` when the token was issued
+    pub token_version: u32,
+    pub iss: String,
+    pub aud: String,
+    pub roles: Vec<String>,
+}
+
+// Verification links are signed with the same keys as auth tokens, but carry their own
+// audience so neither kind of token can stand in for the other
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerificationClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+impl Claims {
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.exp.try_into().ok()?, 0)
+    }
+}
+
+// Extractor guarding admin-only routes. The caller has to send the configured admin
+// API key in a header; without a configured key every admin request is refused.
+pub struct RequireAdmin;
+
+#[async_trait]
+impl FromRequestParts<AppState> for RequireAdmin {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let expected = state
+            .admin_api_key
+            .as_deref()
+            .ok_or(AuthAPIError::InvalidAdminKey)?;
+
+        let provided = parts
+            .headers
+            .get(ADMIN_API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(AuthAPIError::InvalidAdminKey)?;
+
+        // Compare digests so the comparison time doesn't depend on how much of the key matched
+        let digest_of = |key: &str| digest::digest(&digest::SHA256, key.as_bytes());
+        if digest_of(expected).as_ref() != digest_of(provided).as_ref() {
+            return Err(AuthAPIError::InvalidAdminKey);
+        }
+
+        Ok(RequireAdmin)
+    }
+}
+
+// Extractor for routes that need a logged in user, taken from a valid JWT cookie
+pub struct AuthenticatedUser {
+    pub id: UserId,
+    pub email: Email,
+    pub claims: Claims,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthenticatedUser {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let jar = CookieJar::from_headers(&parts.headers);
+        let token = jar
+            .get(JWT_COOKIE_NAME)
+            .ok_or(AuthAPIError::MissingToken)?
+            .value()
+            .to_owned();
+
+        reload_keyring_for_unknown_kid(&token, state).await;
+        let claims = validate_token(
+            &token,
+            &*state.jwt_keyring.read().await,
+            state.banned_token_store.clone(),
+            state.user_store.clone(),
+        )
+        .await
+            .map_err(|_| AuthAPIError::InvalidToken)?;
+        let email = Email::parse(&claims.sub).map_err(|_| AuthAPIError::InvalidToken)?;
+
+        Ok(AuthenticatedUser { id: UserId::from(claims.uid), email, claims })
+    }
 }
 
 #[cfg(test)]
@@ -98,14 +598,31 @@ mod tests {
 
     use tokio::sync::RwLock;
 
-    use crate::services::hashset_banned_token_store::HashsetBannedTokenStore;
+    use crate::services::{
+        hashmap_refresh_token_store::HashmapRefreshTokenStore,
+        hashmap_banned_token_store::HashmapBannedTokenStore,
+        hashmap_user_store::HashmapUserStore,
+    };
 
     use super::*;
+    use crate::domain::{hash_with_weak_params, Password, TwoFactorMethod, UserStore};
+
+    fn test_user() -> User {
+        let password = hash_with_weak_params(&Password::parse("password123").unwrap());
+        User::new(Email::parse("test@example.com").unwrap(), password, TwoFactorMethod::None)
+    }
+
+    async fn user_store_with(user: &User) -> UserStoreType {
+        let mut user_store = HashmapUserStore::default();
+        user_store.add_user(user.clone()).await.unwrap();
+        Arc::new(RwLock::new(user_store))
+    }
 
     #[tokio::test]
     async fn test_generate_auth_cookie() {
-        let email = Email::parse(&"test@example.com".to_owned()).unwrap();
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let cookie = generate_auth_cookie(&user, &Uuid::new_v4(), &keyring).unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -124,19 +641,83 @@ mod tests {
         assert_eq!(cookie.same_site(), Some(SameSite::Lax));
     }
 
+    #[tokio::test]
+    async fn test_generate_refresh_cookie() {
+        let user_id = UserId::default();
+        let refresh_token_store: RefreshTokenStoreType =
+            Arc::new(RwLock::new(HashmapRefreshTokenStore::default()));
+
+        let cookie = generate_refresh_cookie(&user_id, "family", refresh_token_store.clone())
+            .await
+            .unwrap();
+        assert_eq!(cookie.name(), REFRESH_TOKEN_COOKIE_NAME);
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.max_age(), Some(time::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS)));
+
+        let token = RefreshToken::parse(cookie.value().to_owned()).unwrap();
+        let record = refresh_token_store.read().await.get_token(&token).await.unwrap();
+        assert_eq!(record.user_id, user_id);
+        assert_eq!(record.family_id, "family");
+        assert!(!record.used);
+    }
+
     #[tokio::test]
     async fn test_generate_auth_token() {
-        let email = Email::parse(&"test@example.com".to_owned()).unwrap();
-        let result = generate_auth_token(&email).unwrap();
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let result = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
         assert_eq!(result.split('.').count(), 3);
+
+        let header = decode_header(&result).unwrap();
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert_eq!(
+            header.kid.as_deref(),
+            Some(keyring.signing_key(Utc::now()).unwrap().kid())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_user() {
+        let user = test_user();
+        let user_store = user_store_with(&user).await;
+        let password = Password::parse("password123").unwrap();
+
+        let validated = validate_user(&user.email, &password, &user_store).await.unwrap();
+        assert_eq!(validated.email, user.email);
+        assert_eq!(
+            validate_user(&user.email, &Password::parse("wrong_password").unwrap(), &user_store).await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+        assert_eq!(
+            validate_user(&Email::parse("unknown@example.com").unwrap(), &password, &user_store).await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_upgrades_outdated_hash() {
+        let user = test_user();
+        let user_store = user_store_with(&user).await;
+        let password = Password::parse("password123").unwrap();
+        assert!(user.password.needs_rehash());
+
+        verify_password(&user, &password, &user_store).await.unwrap();
+
+        let upgraded = user_store.read().await.get_user(&user.email).await.unwrap().password;
+        assert_ne!(upgraded, user.password);
+        assert!(!upgraded.needs_rehash());
+        assert!(upgraded.verify_raw_password(&password).await.is_ok());
     }
 
     #[tokio::test]
     async fn test_validate_token_with_valid_token() {
-        let email = Email::parse(&"test@example.com".to_owned()).unwrap();
-        let token = generate_auth_token(&email).unwrap();
-        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
-        let result = validate_token(&token, banned_token_store).await.unwrap();
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+        let result = validate_token(&token, &keyring, banned_token_store, user_store).await.unwrap();
         assert_eq!(result.sub, "test@example.com");
 
         let exp = Utc::now()
@@ -147,11 +728,190 @@ mod tests {
         assert!(result.exp > exp as usize);
     }
 
+    #[tokio::test]
+    async fn test_validate_token_of_ended_session() {
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let session_id = Uuid::new_v4();
+        let token = generate_auth_token(&user, &session_id, &keyring).unwrap();
+        let other_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let claims = validate_token(&token, &keyring, banned_token_store.clone(), user_store.clone()).await.unwrap();
+        assert_eq!(claims.sid, session_id);
+
+        // Banning the session ID rejects every token of the session, and only those
+        banned_token_store
+            .write()
+            .await
+            .add_token(session_id.to_string(), Utc::now() + chrono::Duration::minutes(10))
+            .await
+            .unwrap();
+        assert!(validate_token(&token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_err());
+        assert!(validate_token(&other_token, &keyring, banned_token_store, user_store).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_with_outdated_token_version() {
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let old_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        assert!(validate_token(&old_token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_ok());
+
+        let token_version = user_store.write().await.bump_token_version(&user.email).await.unwrap();
+        assert!(validate_token(&old_token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_err());
+
+        let new_token = generate_auth_token(&User { token_version, ..user }, &Uuid::new_v4(), &keyring).unwrap();
+        assert!(validate_token(&new_token, &keyring, banned_token_store.clone(), user_store).await.is_ok());
+
+        // Tokens of unknown users are rejected as well
+        let empty_user_store: UserStoreType = Arc::new(RwLock::new(HashmapUserStore::default()));
+        assert!(validate_token(&new_token, &keyring, banned_token_store, empty_user_store).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_validate_token_with_invalid_token() {
         let token = "invalid_token".to_owned();
-        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
-        let result = validate_token(&token, banned_token_store).await;
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&test_user()).await;
+        let result = validate_token(&token, &keyring, banned_token_store, user_store).await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_validate_token_signed_with_other_key() {
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let other_keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let token = generate_auth_token(&user, &Uuid::new_v4(), &other_keyring).unwrap();
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+        let result = validate_token(&token, &keyring, banned_token_store, user_store).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_across_key_rotation() {
+        let user = test_user();
+        let old_key = JwtSigningKey::generate().unwrap();
+        let old_kid = old_key.kid().to_owned();
+        let mut keyring = JwtKeyring::new(old_key);
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let old_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+
+        let new_key = JwtSigningKey::generate().unwrap();
+        let new_kid = new_key.kid().to_owned();
+        keyring.add_key(new_key, Utc::now(), None).unwrap();
+
+        // New tokens use the new key, while tokens from the old key keep working
+        let new_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        assert_eq!(decode_header(&new_token).unwrap().kid, Some(new_kid));
+        assert!(validate_token(&new_token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_ok());
+        assert!(validate_token(&old_token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_ok());
+
+        // ...until the old key is retired
+        keyring.retire_key(&old_kid, Utc::now()).unwrap();
+        assert!(validate_token(&old_token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_err());
+        assert!(validate_token(&new_token, &keyring, banned_token_store, user_store).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_generate_auth_token_claims() {
+        let mut user = test_user();
+        user.roles.push("admin".to_owned());
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let token_1 = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        let token_2 = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+
+        let claims_1 = validate_token(&token_1, &keyring, banned_token_store.clone(), user_store.clone()).await.unwrap();
+        let claims_2 = validate_token(&token_2, &keyring, banned_token_store, user_store).await.unwrap();
+
+        assert_eq!(claims_1.iss, JWT_ISSUER.as_str());
+        assert_eq!(claims_1.aud, JWT_AUDIENCE.as_str());
+        assert_eq!(claims_1.roles, vec!["user".to_owned(), "admin".to_owned()]);
+        assert_eq!(claims_1.exp - claims_1.iat, TOKEN_TTL_SECONDS as usize);
+        assert_ne!(claims_1.jti, claims_2.jti);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_wrong_issuer_or_audience() {
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let signing_key = keyring.signing_key(Utc::now()).unwrap();
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user = test_user();
+        let user_store = user_store_with(&user).await;
+
+        let claims = |iss: &str, aud: &str| Claims {
+            sub: "test@example.com".to_owned(),
+            uid: *user.id.as_ref(),
+            exp: (Utc::now().timestamp() + TOKEN_TTL_SECONDS) as usize,
+            iat: Utc::now().timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            sid: Uuid::new_v4(),
+            token_version: 0,
+            iss: iss.to_owned(),
+            aud: aud.to_owned(),
+            roles: vec![],
+        };
+
+        let valid = create_token(&claims(&JWT_ISSUER, &JWT_AUDIENCE), &signing_key).unwrap();
+        let wrong_issuer = create_token(&claims("someone-else", &JWT_AUDIENCE), &signing_key).unwrap();
+        let wrong_audience = create_token(&claims(&JWT_ISSUER, "other-service"), &signing_key).unwrap();
+
+        assert!(validate_token(&valid, &keyring, banned_token_store.clone(), user_store.clone()).await.is_ok());
+        assert!(validate_token(&wrong_issuer, &keyring, banned_token_store.clone(), user_store.clone()).await.is_err());
+        assert!(validate_token(&wrong_audience, &keyring, banned_token_store, user_store).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_with_banned_jti() {
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        let other_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        let claims = validate_token(&token, &keyring, banned_token_store.clone(), user_store.clone()).await.unwrap();
+
+        banned_token_store
+            .write()
+            .await
+            .add_token(claims.jti.clone(), claims.expires_at().unwrap())
+            .await
+            .unwrap();
+
+        assert!(validate_token(&token, &keyring, banned_token_store.clone(), user_store.clone()).await.is_err());
+        // Other tokens of the same user are unaffected
+        assert!(validate_token(&other_token, &keyring, banned_token_store, user_store).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_email_verification_token() {
+        let user = test_user();
+        let keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        let banned_token_store : BannedTokenStoreType = Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let user_store = user_store_with(&user).await;
+
+        let token = generate_email_verification_token(&user.email, &keyring).unwrap();
+        assert_eq!(validate_email_verification_token(&token, &keyring), Ok(user.email.clone()));
+
+        // Verification tokens and auth tokens can't be swapped for each other
+        assert!(validate_token(&token, &keyring, banned_token_store, user_store).await.is_err());
+        let auth_token = generate_auth_token(&user, &Uuid::new_v4(), &keyring).unwrap();
+        assert!(validate_email_verification_token(&auth_token, &keyring).is_err());
+
+        let other_keyring = JwtKeyring::new(JwtSigningKey::generate().unwrap());
+        assert!(validate_email_verification_token(&token, &other_keyring).is_err());
+    }
 }
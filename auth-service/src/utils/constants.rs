@@ -2,26 +2,285 @@ use dotenvy::dotenv;
 use lazy_static::lazy_static;
 use std::env as std_env;
 
+use crate::{
+    domain::RateLimit,
+    services::smtp_email_client::{SmtpConfig, SmtpTls},
+};
+
 // Define a lazily evaluated static. lazy_static is needed because std_env::var is not a const function.
 lazy_static! {
-    pub static ref JWT_SECRET: String = set_token();
+    pub static ref JWT_SIGNING_KEY: String = set_signing_key();
+    pub static ref DATABASE_URL: String = set_database_url();
+    pub static ref ADMIN_API_KEY: Option<String> = set_admin_api_key();
+    pub static ref JWT_ISSUER: String = set_jwt_issuer();
+    pub static ref JWT_AUDIENCE: String = set_jwt_audience();
+    pub static ref REDIS_HOST_NAME: Option<String> = set_redis_host();
+    pub static ref AUTH_SERVICE_URL: String = set_auth_service_url();
+    pub static ref RATE_LIMIT_PER_IP: RateLimit =
+        set_rate_limit(env::RATE_LIMIT_PER_IP_ENV_VAR, DEFAULT_RATE_LIMIT_PER_IP);
+    pub static ref RATE_LIMIT_PER_EMAIL: RateLimit =
+        set_rate_limit(env::RATE_LIMIT_PER_EMAIL_ENV_VAR, DEFAULT_RATE_LIMIT_PER_EMAIL);
+    pub static ref SMTP_CONFIG: Option<SmtpConfig> = set_smtp_config();
+    pub static ref EMAIL_TEMPLATES_DIR: String = set_email_templates_dir();
+    pub static ref EMAIL_MAILDIR: Option<String> = set_email_maildir();
+    pub static ref WEBAUTHN_ORIGIN: String = origin_of(&AUTH_SERVICE_URL);
+    pub static ref WEBAUTHN_RP_ID: String = set_webauthn_rp_id();
 }
 
+// PEM encoded PKCS#8 Ed25519 private key used to sign JWTs
+fn set_signing_key() -> String {
+    dotenv().ok(); // Load environment variables
+    let key = std_env::var(env::JWT_SIGNING_KEY_ENV_VAR).expect("JWT_SIGNING_KEY must be set.");
+    if key.is_empty() {
+        panic!("JWT_SIGNING_KEY must not be empty.");
+    }
+    key
+}
 
-fn set_token() -> String {
+fn set_database_url() -> String {
     dotenv().ok(); // Load environment variables
-    let secret = std_env::var(env::JWT_SECRET_ENV_VAR).expect("JWT_SECRET must be set.");
-    if secret.is_empty() {
-        panic!("JWT_SECRET must not be empty.");
+    let url = std_env::var(env::DATABASE_URL_ENV_VAR).expect("DATABASE_URL must be set.");
+    if url.is_empty() {
+        panic!("DATABASE_URL must not be empty.");
     }
-    secret
+    url
+}
+
+// Optional: the admin routes stay disabled unless a key is configured
+fn set_admin_api_key() -> Option<String> {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::ADMIN_API_KEY_ENV_VAR)
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+// Value of the `iss` claim. Tokens with any other issuer are rejected.
+fn set_jwt_issuer() -> String {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::JWT_ISSUER_ENV_VAR)
+        .ok()
+        .filter(|issuer| !issuer.is_empty())
+        .unwrap_or_else(|| DEFAULT_JWT_ISSUER.to_owned())
+}
+
+// Value of the `aud` claim, i.e. the service our tokens are meant for
+fn set_jwt_audience() -> String {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::JWT_AUDIENCE_ENV_VAR)
+        .ok()
+        .filter(|audience| !audience.is_empty())
+        .unwrap_or_else(|| DEFAULT_JWT_AUDIENCE.to_owned())
+}
+
+// Optional: banned tokens and 2FA codes are kept in Redis when a host is configured,
+// which lets several replicas share them. Otherwise they live in process memory.
+fn set_redis_host() -> Option<String> {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::REDIS_HOST_NAME_ENV_VAR)
+        .ok()
+        .filter(|host| !host.is_empty())
+}
+
+// Public base URL of the service, used to build the links we email to users
+fn set_auth_service_url() -> String {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::AUTH_SERVICE_URL_ENV_VAR)
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| url.trim_end_matches('/').to_owned())
+        .unwrap_or_else(|| DEFAULT_AUTH_SERVICE_URL.to_owned())
+}
+
+// Scheme, host and port of a URL, which is what browsers put in WebAuthn client data
+fn origin_of(url: &str) -> String {
+    let host_start = url.find("://").map_or(0, |i| i + 3);
+    match url[host_start..].find('/') {
+        Some(path_start) => url[..host_start + path_start].to_owned(),
+        None => url.to_owned(),
+    }
+}
+
+// Passkeys are bound to this domain. It defaults to the host of AUTH_SERVICE_URL and
+// can be set to a parent domain, so passkeys work across its subdomains.
+fn set_webauthn_rp_id() -> String {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::WEBAUTHN_RP_ID_ENV_VAR)
+        .ok()
+        .filter(|rp_id| !rp_id.is_empty())
+        .unwrap_or_else(|| {
+            let origin = origin_of(&AUTH_SERVICE_URL);
+            let host = origin.split_once("://").map_or(origin.as_str(), |(_, host)| host);
+            // RP IDs are domain names, so the port goes
+            host.split(':').next().unwrap_or(host).to_owned()
+        })
+}
+
+// Limits on login, signup and verify-2fa, written as "<requests>/<seconds>"
+fn set_rate_limit(env_var: &str, default: &str) -> RateLimit {
+    dotenv().ok(); // Load environment variables
+    let limit = std_env::var(env_var)
+        .ok()
+        .filter(|limit| !limit.is_empty())
+        .unwrap_or_else(|| default.to_owned());
+    RateLimit::parse(&limit).unwrap_or_else(|e| panic!("{} is invalid: {}", env_var, e))
+}
+
+// Optional: emails are sent through this SMTP server when SMTP_HOST is set,
+// and only printed to stdout otherwise
+fn set_smtp_config() -> Option<SmtpConfig> {
+    dotenv().ok(); // Load environment variables
+    let var = |name: &str| std_env::var(name).ok().filter(|value| !value.is_empty());
+
+    let host = var(env::SMTP_HOST_ENV_VAR)?;
+    let tls = var(env::SMTP_TLS_ENV_VAR)
+        .map(|tls| SmtpTls::parse(&tls).expect("SMTP_TLS must be starttls, tls or none."))
+        .unwrap_or(SmtpTls::StartTls);
+    let port = var(env::SMTP_PORT_ENV_VAR)
+        .map(|port| port.parse().expect("SMTP_PORT must be a port number."))
+        .unwrap_or_else(|| tls.default_port());
+    let credentials = match (var(env::SMTP_USERNAME_ENV_VAR), var(env::SMTP_PASSWORD_ENV_VAR)) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (None, None) => None,
+        _ => panic!("SMTP_USERNAME and SMTP_PASSWORD must be set together."),
+    };
+    let sender = var(env::EMAIL_SENDER_ENV_VAR).expect("EMAIL_SENDER must be set when SMTP_HOST is.");
+
+    Some(SmtpConfig {
+        host,
+        port,
+        tls,
+        credentials,
+        sender,
+        root_certificate: var(env::SMTP_ROOT_CERTIFICATE_ENV_VAR),
+    })
+}
+
+// Directory holding the email templates, one subdirectory per locale
+fn set_email_templates_dir() -> String {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::EMAIL_TEMPLATES_DIR_ENV_VAR)
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| DEFAULT_EMAIL_TEMPLATES_DIR.to_owned())
+}
+
+// Optional: without SMTP, emails are written to this Maildir when it is set
+fn set_email_maildir() -> Option<String> {
+    dotenv().ok(); // Load environment variables
+    std_env::var(env::EMAIL_MAILDIR_ENV_VAR)
+        .ok()
+        .filter(|dir| !dir.is_empty())
 }
 
 pub mod env {
-    pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
+    pub const JWT_SIGNING_KEY_ENV_VAR: &str = "JWT_SIGNING_KEY";
+    pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
+    pub const ADMIN_API_KEY_ENV_VAR: &str = "ADMIN_API_KEY";
+    pub const JWT_ISSUER_ENV_VAR: &str = "JWT_ISSUER";
+    pub const JWT_AUDIENCE_ENV_VAR: &str = "JWT_AUDIENCE";
+    pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME";
+    pub const AUTH_SERVICE_URL_ENV_VAR: &str = "AUTH_SERVICE_URL";
+    pub const WEBAUTHN_RP_ID_ENV_VAR: &str = "WEBAUTHN_RP_ID";
+    pub const RATE_LIMIT_PER_IP_ENV_VAR: &str = "RATE_LIMIT_PER_IP";
+    pub const RATE_LIMIT_PER_EMAIL_ENV_VAR: &str = "RATE_LIMIT_PER_EMAIL";
+    pub const SMTP_HOST_ENV_VAR: &str = "SMTP_HOST";
+    pub const SMTP_PORT_ENV_VAR: &str = "SMTP_PORT";
+    pub const SMTP_TLS_ENV_VAR: &str = "SMTP_TLS";
+    pub const SMTP_USERNAME_ENV_VAR: &str = "SMTP_USERNAME";
+    pub const SMTP_PASSWORD_ENV_VAR: &str = "SMTP_PASSWORD";
+    pub const SMTP_ROOT_CERTIFICATE_ENV_VAR: &str = "SMTP_ROOT_CERTIFICATE";
+    pub const EMAIL_SENDER_ENV_VAR: &str = "EMAIL_SENDER";
+    pub const EMAIL_TEMPLATES_DIR_ENV_VAR: &str = "EMAIL_TEMPLATES_DIR";
+    pub const EMAIL_MAILDIR_ENV_VAR: &str = "EMAIL_MAILDIR";
 }
 
+pub const DEFAULT_JWT_ISSUER: &str = "auth-service";
+pub const DEFAULT_JWT_AUDIENCE: &str = "app-service";
+pub const DEFAULT_AUTH_SERVICE_URL: &str = "http://localhost:3000";
+// Each rate limited route has its own buckets, so these apply per route
+pub const DEFAULT_RATE_LIMIT_PER_IP: &str = "30/60";
+pub const DEFAULT_RATE_LIMIT_PER_EMAIL: &str = "10/60";
+pub const DEFAULT_EMAIL_TEMPLATES_DIR: &str = "templates/emails";
+// Emails are rendered in this locale when none of the recipient's languages has a template.
+// It must have every template.
+pub const DEFAULT_EMAIL_LOCALE: &str = "en";
+
+// Audience of the signed tokens in email verification links
+pub const EMAIL_VERIFICATION_AUDIENCE: &str = "email-verification";
+
 pub const JWT_COOKIE_NAME: &str = "jwt";
+pub const REFRESH_TOKEN_COOKIE_NAME: &str = "refresh_token";
+pub const ADMIN_API_KEY_HEADER: &str = "x-admin-key";
+
+// How long an emailed 2FA code stays valid, and how many wrong guesses it tolerates
+pub const TWO_FA_CODE_TTL_SECONDS: i64 = 600; // 10 minutes
+pub const TWO_FA_CODE_MAX_ATTEMPTS: u32 = 5;
+
+// RFC 6238 parameters for authenticator app codes
+pub const TOTP_DIGITS: usize = 6;
+pub const TOTP_STEP_SECONDS: u64 = 30;
+// How many steps a code may be early or late, to allow for clock drift
+pub const TOTP_SKEW_STEPS: u8 = 1;
+
+// How many recovery codes a 2FA enrollment hands out, and at how many the login flow starts warning
+pub const RECOVERY_CODE_COUNT: usize = 10;
+pub const RECOVERY_CODES_LOW_THRESHOLD: usize = 3;
+
+// Wrong passwords in a row before an account is locked. The first lockout lasts
+// ACCOUNT_LOCKOUT_BASE_SECONDS and every further one twice as long as the last.
+pub const ACCOUNT_LOCKOUT_THRESHOLD: u32 = 5;
+pub const ACCOUNT_LOCKOUT_BASE_SECONDS: i64 = 300; // 5 minutes
+pub const ACCOUNT_LOCKOUT_MAX_SECONDS: i64 = 86_400; // 24 hours
+
+// How long the unlock link emailed on a lockout stays valid
+pub const ACCOUNT_UNLOCK_TOKEN_TTL_SECONDS: i64 = 86_400; // 24 hours
+
+// How long an emailed password reset link stays valid
+pub const PASSWORD_RESET_TOKEN_TTL_SECONDS: i64 = 1800; // 30 minutes
+
+// How long an emailed sign-in link stays valid
+pub const MAGIC_LINK_TOKEN_TTL_SECONDS: i64 = 900; // 15 minutes
+
+// How long a WebAuthn ceremony may take between its start and finish requests
+pub const WEBAUTHN_CHALLENGE_TTL_SECONDS: i64 = 300; // 5 minutes
+
+// How long an emailed verification link stays valid, and how often a new one can be requested
+pub const EMAIL_VERIFICATION_TOKEN_TTL_SECONDS: i64 = 86_400; // 24 hours
+pub const VERIFICATION_EMAIL_RESEND_INTERVAL_SECONDS: i64 = 60;
+
+// How long the confirmation link sent to a new email address stays valid, and how long
+// the old address can still call the change off or undo it
+pub const EMAIL_CHANGE_TOKEN_TTL_SECONDS: i64 = 86_400; // 24 hours
+pub const EMAIL_CHANGE_CANCEL_TOKEN_TTL_SECONDS: i64 = 604_800; // 7 days
+
+// Requests to the rate limited routes are buffered to find the email in them, up to this size
+pub const RATE_LIMITED_BODY_MAX_BYTES: usize = 64 * 1024;
+
+// How often the outbox worker looks for due emails, and how many it sends per round at most
+pub const EMAIL_OUTBOX_POLL_INTERVAL_MILLIS: u64 = 1000;
+pub const EMAIL_OUTBOX_BATCH_SIZE: u32 = 50;
+// A send that takes longer than this is given up on and counts as a failed attempt
+pub const EMAIL_SEND_TIMEOUT_SECONDS: u64 = 30;
+// A claimed email isn't handed to another worker for this long. Emails are claimed one
+// at a time, right before they are sent, so this only has to outlast a single send.
+pub const EMAIL_OUTBOX_LEASE_SECONDS: i64 = 2 * EMAIL_SEND_TIMEOUT_SECONDS as i64;
+// Failed sends are retried after EMAIL_OUTBOX_RETRY_BASE_SECONDS, doubling every time up to
+// EMAIL_OUTBOX_RETRY_MAX_SECONDS. With these values the last attempt is made about 3 hours after the first.
+pub const EMAIL_OUTBOX_MAX_ATTEMPTS: u32 = 10;
+pub const EMAIL_OUTBOX_RETRY_BASE_SECONDS: i64 = 30;
+pub const EMAIL_OUTBOX_RETRY_MAX_SECONDS: i64 = 3600; // 1 hour
+
+// How often expired entries are swept out of the in-memory token and session stores
+pub const SWEEP_INTERVAL_SECONDS: u64 = 60;
+
+// How often the JWT keyring is reloaded from the signing key store, so key rotations
+// made on other replicas are picked up, and how long to wait at least between reloads
+// for tokens signed by a key we don't know
+pub const KEYRING_RELOAD_INTERVAL_SECONDS: u64 = 30;
+pub const KEYRING_MIN_RELOAD_INTERVAL_SECONDS: i64 = 5;
+
+// How often the gauges reported by /metrics are counted again
+pub const METRICS_REFRESH_INTERVAL_SECONDS: u64 = 15;
 
 pub mod prod {
     pub const APP_ADDRESS: &str = "0.0.0.0:3000";
@@ -29,4 +288,6 @@ pub mod prod {
 
 pub mod test {
     pub const APP_ADDRESS: &str = "127.0.0.1:0";
-}
\ No newline at end of file
+    pub const REDIS_HOST_NAME: &str = "127.0.0.1";
+    pub const EMAIL_OUTBOX_POLL_INTERVAL_MILLIS: u64 = 20;
+}
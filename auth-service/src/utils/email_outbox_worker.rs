@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use super::constants::{
+    EMAIL_OUTBOX_BATCH_SIZE, EMAIL_OUTBOX_LEASE_SECONDS, EMAIL_OUTBOX_MAX_ATTEMPTS,
+    EMAIL_OUTBOX_RETRY_BASE_SECONDS, EMAIL_OUTBOX_RETRY_MAX_SECONDS, EMAIL_SEND_TIMEOUT_SECONDS,
+};
+use crate::{
+    app_state::{EmailClientType, EmailOutboxStoreType},
+    domain::EmailOutboxStoreError,
+};
+
+// Delivers the emails queued in the outbox, checking for due ones every `interval`
+pub fn spawn_email_outbox_worker(
+    email_outbox: EmailOutboxStoreType,
+    email_client: EmailClientType,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            let send_timeout = Duration::from_secs(EMAIL_SEND_TIMEOUT_SECONDS);
+            if let Err(e) = deliver_due_emails(&email_outbox, &email_client, Utc::now(), send_timeout).await {
+                eprintln!("Failed to deliver queued emails: {:?}", e);
+            }
+        }
+    })
+}
+
+// Tries each message due by `now` once, up to EMAIL_OUTBOX_BATCH_SIZE of them. A failed
+// one is retried after an exponential backoff, and dead-lettered once it has used up
+// EMAIL_OUTBOX_MAX_ATTEMPTS. Sends taking longer than `send_timeout` count as failed.
+// Returns how many messages were sent.
+pub async fn deliver_due_emails(
+    email_outbox: &EmailOutboxStoreType,
+    email_client: &EmailClientType,
+    now: DateTime<Utc>,
+    send_timeout: Duration,
+) -> Result<usize, EmailOutboxStoreError> {
+    let started_at = Utc::now();
+
+    let mut sent = 0;
+    for _ in 0..EMAIL_OUTBOX_BATCH_SIZE {
+        // Claimed one at a time, so each lease starts right before its own send however
+        // long the ones before it took, and other workers can take the rest meanwhile.
+        // `now` is when the round started, so the time spent since is added to the lease.
+        let lease = chrono::Duration::seconds(EMAIL_OUTBOX_LEASE_SECONDS) + (Utc::now() - started_at);
+        let Some(message) = email_outbox.write().await.claim_due(now, lease, 1).await?.pop() else {
+            break;
+        };
+
+        // The outbox isn't locked while sending, so a slow server doesn't hold up enqueuing
+        let send = async {
+            email_client
+                .read()
+                .await
+                .send_html_email(&message.recipient, &message.subject, &message.text, &message.html)
+                .await
+        };
+        let result = tokio::time::timeout(send_timeout, send)
+            .await
+            .unwrap_or_else(|_| Err(format!("Timed out after {} seconds", send_timeout.as_secs_f64())));
+
+        let mut email_outbox = email_outbox.write().await;
+        match result {
+            Ok(()) => {
+                email_outbox.mark_sent(&message.id).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                let attempts = message.attempts + 1;
+                let retry_at = (attempts < EMAIL_OUTBOX_MAX_ATTEMPTS)
+                    .then(|| now + retry_delay(attempts));
+                if retry_at.is_none() {
+                    eprintln!("Dead-lettered email {} after {} attempts: {}", message.id, attempts, e);
+                }
+                email_outbox.record_failure(&message.id, &e, retry_at).await?;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+// Wait after the `attempts`th failure: the base delay, doubled for every failure
+// before it, up to the maximum
+fn retry_delay(attempts: u32) -> chrono::Duration {
+    let doublings = attempts.saturating_sub(1).min(30);
+    let seconds = EMAIL_OUTBOX_RETRY_BASE_SECONDS.saturating_mul(1 << doublings);
+    chrono::Duration::seconds(seconds.min(EMAIL_OUTBOX_RETRY_MAX_SECONDS))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::{
+        domain::{Email, EmailClient, OutboxMessage},
+        services::{
+            hashmap_email_outbox_store::HashmapEmailOutboxStore, mock_email_client::MockEmailClient,
+        },
+    };
+
+    // Fails every send and counts the attempts
+    struct FailingEmailClient {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailClient for FailingEmailClient {
+        async fn send_email(&self, _: &Email, _: &str, _: &str) -> Result<(), String> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err("connection refused".to_owned())
+        }
+    }
+
+    // Never answers within the send timeout
+    struct SlowEmailClient;
+
+    #[async_trait::async_trait]
+    impl EmailClient for SlowEmailClient {
+        async fn send_email(&self, _: &Email, _: &str, _: &str) -> Result<(), String> {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        }
+    }
+
+    // Sends fine, but while sending the first email another worker polls the outbox
+    // and records how many emails it could claim
+    struct RacingEmailClient {
+        email_outbox: EmailOutboxStoreType,
+        claimed_by_other_worker: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailClient for RacingEmailClient {
+        async fn send_email(&self, _: &Email, _: &str, _: &str) -> Result<(), String> {
+            if self.claimed_by_other_worker.load(Ordering::SeqCst) == 0 {
+                let claimed = self
+                    .email_outbox
+                    .write()
+                    .await
+                    .claim_due(Utc::now(), chrono::Duration::seconds(EMAIL_OUTBOX_LEASE_SECONDS), 10)
+                    .await
+                    .unwrap();
+                self.claimed_by_other_worker.store(claimed.len(), Ordering::SeqCst);
+            }
+            Ok(())
+        }
+    }
+
+    async fn deliver(
+        email_outbox: &EmailOutboxStoreType,
+        email_client: &EmailClientType,
+        now: DateTime<Utc>,
+    ) -> Result<usize, EmailOutboxStoreError> {
+        deliver_due_emails(email_outbox, email_client, now, Duration::from_secs(EMAIL_SEND_TIMEOUT_SECONDS)).await
+    }
+
+    fn message() -> OutboxMessage {
+        OutboxMessage::new(
+            Email::parse("test@example.com").unwrap(),
+            "Hello".to_owned(),
+            "text".to_owned(),
+            "<p>html</p>".to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let base = EMAIL_OUTBOX_RETRY_BASE_SECONDS;
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(base));
+        assert_eq!(retry_delay(2), chrono::Duration::seconds(base * 2));
+        assert_eq!(retry_delay(3), chrono::Duration::seconds(base * 4));
+        assert_eq!(retry_delay(100), chrono::Duration::seconds(EMAIL_OUTBOX_RETRY_MAX_SECONDS));
+    }
+
+    #[tokio::test]
+    async fn test_sent_messages_leave_the_outbox() {
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(HashmapEmailOutboxStore::default()));
+        let email_client: EmailClientType = Arc::new(RwLock::new(MockEmailClient));
+        email_outbox.write().await.enqueue(message()).await.unwrap();
+
+        let now = Utc::now();
+        assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(1));
+        assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(0));
+        assert!(email_outbox.read().await.dead_letters().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failing_messages_back_off_then_dead_letter() {
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(HashmapEmailOutboxStore::default()));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let email_client: EmailClientType = Arc::new(RwLock::new(FailingEmailClient {
+            attempts: attempts.clone(),
+        }));
+        let message = message();
+        email_outbox.write().await.enqueue(message.clone()).await.unwrap();
+
+        let mut now = Utc::now();
+        assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(0));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        for attempt in 2..=EMAIL_OUTBOX_MAX_ATTEMPTS {
+            // Not due again until the backoff has passed
+            let delay = retry_delay(attempt - 1);
+            now += delay - chrono::Duration::seconds(1);
+            assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(0));
+            assert_eq!(attempts.load(Ordering::SeqCst), attempt as usize - 1);
+
+            now += chrono::Duration::seconds(1);
+            assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(0));
+            assert_eq!(attempts.load(Ordering::SeqCst), attempt as usize);
+        }
+
+        // Dead letters are never tried again
+        now += chrono::Duration::days(1);
+        assert_eq!(deliver(&email_outbox, &email_client, now).await, Ok(0));
+
+        assert_eq!(attempts.load(Ordering::SeqCst), EMAIL_OUTBOX_MAX_ATTEMPTS as usize);
+        let dead_letters = email_outbox.read().await.dead_letters().await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].id, message.id);
+        assert_eq!(dead_letters[0].attempts, EMAIL_OUTBOX_MAX_ATTEMPTS);
+        assert_eq!(dead_letters[0].last_error.as_deref(), Some("connection refused"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_sends_time_out_and_count_as_failed() {
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(HashmapEmailOutboxStore::default()));
+        let email_client: EmailClientType = Arc::new(RwLock::new(SlowEmailClient));
+        let message = message();
+        email_outbox.write().await.enqueue(message.clone()).await.unwrap();
+
+        let now = Utc::now();
+        assert_eq!(
+            deliver_due_emails(&email_outbox, &email_client, now, Duration::from_millis(10)).await,
+            Ok(0)
+        );
+
+        // Due again once the backoff has passed
+        let claimed = email_outbox
+            .write()
+            .await
+            .claim_due(now + retry_delay(1), chrono::Duration::seconds(1), 10)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].attempts, 1);
+        assert!(claimed[0].last_error.as_deref().unwrap().starts_with("Timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_claimed_one_at_a_time() {
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(HashmapEmailOutboxStore::default()));
+        let claimed_by_other_worker = Arc::new(AtomicUsize::new(0));
+        let email_client: EmailClientType = Arc::new(RwLock::new(RacingEmailClient {
+            email_outbox: email_outbox.clone(),
+            claimed_by_other_worker: claimed_by_other_worker.clone(),
+        }));
+        let mut first = message();
+        first.created_at -= chrono::Duration::seconds(1);
+        first.next_attempt_at = first.created_at;
+        email_outbox.write().await.enqueue(first).await.unwrap();
+        email_outbox.write().await.enqueue(message()).await.unwrap();
+
+        // Only the email being sent is leased, so the other worker can take the second one
+        assert_eq!(deliver(&email_outbox, &email_client, Utc::now()).await, Ok(1));
+        assert_eq!(claimed_by_other_worker.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{convert::Infallible, path::Path};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::ACCEPT_LANGUAGE, request::Parts},
+};
+use minijinja::{path_loader, Environment, ErrorKind, UndefinedBehavior};
+use serde::Serialize;
+
+use super::constants::DEFAULT_EMAIL_LOCALE;
+use crate::{
+    app_state::AppState,
+    domain::{Email, OutboxMessage},
+};
+
+// Every email the service sends. Each one is a set of three files in a locale
+// directory: `<name>.subject.txt`, `<name>.txt` and `<name>.html`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailTemplate {
+    TwoFACode,
+    EmailVerification,
+    PasswordReset,
+    AccountUnlock,
+    MagicLink,
+    NewLoginAlert,
+    AccountDeleted,
+    PasswordChanged,
+    EmailChangeConfirmation,
+    EmailChangeRequested,
+}
+
+impl EmailTemplate {
+    pub const ALL: [EmailTemplate; 10] = [
+        EmailTemplate::TwoFACode,
+        EmailTemplate::EmailVerification,
+        EmailTemplate::PasswordReset,
+        EmailTemplate::AccountUnlock,
+        EmailTemplate::MagicLink,
+        EmailTemplate::NewLoginAlert,
+        EmailTemplate::AccountDeleted,
+        EmailTemplate::PasswordChanged,
+        EmailTemplate::EmailChangeConfirmation,
+        EmailTemplate::EmailChangeRequested,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailTemplate::TwoFACode => "two_fa_code",
+            EmailTemplate::EmailVerification => "email_verification",
+            EmailTemplate::PasswordReset => "password_reset",
+            EmailTemplate::AccountUnlock => "account_unlock",
+            EmailTemplate::MagicLink => "magic_link",
+            EmailTemplate::NewLoginAlert => "new_login_alert",
+            EmailTemplate::AccountDeleted => "account_deleted",
+            EmailTemplate::PasswordChanged => "password_changed",
+            EmailTemplate::EmailChangeConfirmation => "email_change_confirmation",
+            EmailTemplate::EmailChangeRequested => "email_change_requested",
+        }
+    }
+
+    fn files(&self, locale: &str) -> [String; 3] {
+        [
+            format!("{}/{}.subject.txt", locale, self.name()),
+            format!("{}/{}.txt", locale, self.name()),
+            format!("{}/{}.html", locale, self.name()),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EmailTemplateError {
+    MissingTemplate(String),
+    RenderFailed(String),
+}
+
+// Languages the recipient asked for, most preferred first, as lowercased tags like "pt-br"
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Locale(Vec<String>);
+
+impl Locale {
+    // Parses an Accept-Language header such as "fr-CH, fr;q=0.9, en;q=0.8"
+    pub fn from_accept_language(header: &str) -> Self {
+        let mut tags: Vec<(String, f32)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let tag = normalize_tag(parts.next()?)?;
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(Some(1.0))?;
+                (quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // A stable sort keeps the header's order between equal weights
+        tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Self(tags.into_iter().map(|(tag, _)| tag).collect())
+    }
+
+    // The locale directories to try in order: each tag, then its base language,
+    // then the default locale
+    fn fallbacks(&self) -> Vec<String> {
+        let mut chain: Vec<String> = Vec::new();
+        for tag in &self.0 {
+            let base = tag.split('-').next().unwrap_or(tag).to_owned();
+            for candidate in [tag.clone(), base] {
+                if !chain.contains(&candidate) {
+                    chain.push(candidate);
+                }
+            }
+        }
+        if !chain.iter().any(|locale| locale == DEFAULT_EMAIL_LOCALE) {
+            chain.push(DEFAULT_EMAIL_LOCALE.to_owned());
+        }
+        chain
+    }
+}
+
+// The tag ends up in a file path, so only letters, digits and dashes get through.
+// "*" means any language, which the default locale already covers.
+fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_ascii_lowercase().replace('_', "-");
+    let valid = !tag.is_empty()
+        && tag.len() <= 35
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !tag.starts_with('-');
+    valid.then_some(tag)
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|header| header.to_str().ok())
+            .map(Locale::from_accept_language)
+            .unwrap_or_default())
+    }
+}
+
+// Renders the email templates found under one directory, which holds a directory
+// per locale plus any layouts they share. Files are read the first time they are
+// used, so edited copy shows up after a restart without a rebuild.
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+impl EmailTemplates {
+    // Fails unless the default locale has every template, so a missing file shows
+    // up at startup rather than when the first email goes out
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, EmailTemplateError> {
+        let mut env = Environment::new();
+        env.set_loader(path_loader(dir.as_ref()));
+        // A variable missing from the context is a bug, not something to render as blank
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+
+        for template in EmailTemplate::ALL {
+            for file in template.files(DEFAULT_EMAIL_LOCALE) {
+                env.get_template(&file).map_err(|e| match e.kind() {
+                    ErrorKind::TemplateNotFound => EmailTemplateError::MissingTemplate(file.clone()),
+                    _ => EmailTemplateError::RenderFailed(format!("{}: {}", file, e)),
+                })?;
+            }
+        }
+
+        Ok(Self { env })
+    }
+
+    // Uses the first locale in the fallback chain that has the template. HTML
+    // variants are autoescaped, text variants and subjects are not.
+    pub fn render(
+        &self,
+        template: EmailTemplate,
+        locale: &Locale,
+        context: impl Serialize,
+    ) -> Result<RenderedEmail, EmailTemplateError> {
+        let context = minijinja::Value::from_serialize(context);
+
+        for candidate in locale.fallbacks() {
+            let [subject_file, text_file, html_file] = template.files(&candidate);
+            let text = match self.env.get_template(&text_file) {
+                Ok(text) => text,
+                Err(e) if e.kind() == ErrorKind::TemplateNotFound => continue,
+                Err(e) => return Err(EmailTemplateError::RenderFailed(format!("{}: {}", text_file, e))),
+            };
+
+            let render = |file: &str| {
+                self.env
+                    .get_template(file)
+                    .and_then(|t| t.render(&context))
+                    .map_err(|e| match e.kind() {
+                        ErrorKind::TemplateNotFound => {
+                            EmailTemplateError::MissingTemplate(file.to_owned())
+                        }
+                        _ => EmailTemplateError::RenderFailed(format!("{}: {}", file, e)),
+                    })
+            };
+
+            let text = text
+                .render(&context)
+                .map_err(|e| EmailTemplateError::RenderFailed(format!("{}: {}", text_file, e)))?;
+            return Ok(RenderedEmail {
+                // Headers can't span lines
+                subject: render(&subject_file)?.lines().next().unwrap_or_default().trim().to_owned(),
+                text,
+                html: render(&html_file)?,
+            });
+        }
+
+        Err(EmailTemplateError::MissingTemplate(template.name().to_owned()))
+    }
+}
+
+// Render `template` in the recipient's language and queue both variants in the
+// outbox, which delivers them in the background and retries failed sends
+pub async fn queue_templated_email(
+    state: &AppState,
+    recipient: &Email,
+    template: EmailTemplate,
+    locale: &Locale,
+    context: impl Serialize,
+) -> Result<(), String> {
+    let email = state
+        .email_templates
+        .render(template, locale, context)
+        .map_err(|e| format!("Failed to render {} email: {:?}", template.name(), e))?;
+
+    state
+        .email_outbox
+        .write()
+        .await
+        .enqueue(OutboxMessage::new(recipient.clone(), email.subject, email.text, email.html))
+        .await
+        .map_err(|e| format!("Failed to queue {} email: {:?}", template.name(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use minijinja::context;
+
+    use super::*;
+    use crate::utils::constants::DEFAULT_EMAIL_TEMPLATES_DIR;
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(DEFAULT_EMAIL_TEMPLATES_DIR).expect("Failed to load email templates")
+    }
+
+    // Every locale shipped in the templates directory, not just the default one
+    fn shipped_locales() -> Vec<String> {
+        let mut locales: Vec<String> = fs::read_dir(DEFAULT_EMAIL_TEMPLATES_DIR)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_dir())
+            .map(|entry| entry.file_name().into_string().unwrap())
+            .collect();
+        locales.sort();
+        locales
+    }
+
+    #[test]
+    fn test_every_template_renders_in_every_shipped_locale() {
+        let templates = templates();
+        let context = context! {
+            code => "123456",
+            link => "http://localhost:3000/some-link?token=abc",
+            expires_in_minutes => 10,
+            expires_in_hours => 24,
+            locked_until => "2024-01-01 12:00 UTC",
+            logged_in_at => "2024-01-01 12:00 UTC",
+            ip_address => "127.0.0.1",
+            user_agent => "curl/8.0",
+            deleted_at => "2024-01-01 12:00 UTC",
+            changed_at => "2024-01-01 12:00 UTC",
+            new_email => "new@example.com",
+            expires_in_days => 7,
+        };
+
+        let locales = shipped_locales();
+        assert!(locales.len() > 1, "Only {:?} is shipped", locales);
+
+        for locale in locales {
+            for template in EmailTemplate::ALL {
+                for file in template.files(&locale) {
+                    assert!(
+                        Path::new(DEFAULT_EMAIL_TEMPLATES_DIR).join(&file).is_file(),
+                        "{} is missing",
+                        file
+                    );
+                }
+
+                let email = templates
+                    .render(template, &Locale(vec![locale.clone()]), &context)
+                    .unwrap_or_else(|e| panic!("{}/{} failed to render: {:?}", locale, template.name(), e));
+                assert!(!email.subject.is_empty(), "{}/{} has no subject", locale, template.name());
+                assert!(!email.text.is_empty(), "{}/{} has no text body", locale, template.name());
+                assert!(email.html.contains("<html>"), "{}/{} has no HTML body", locale, template.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_picks_requested_locale() {
+        let templates = templates();
+        let render = |header| {
+            templates
+                .render(
+                    EmailTemplate::TwoFACode,
+                    &Locale::from_accept_language(header),
+                    context! { code => "654321", expires_in_minutes => 10 },
+                )
+                .unwrap()
+                .subject
+        };
+
+        assert_eq!(render("fr-CA, en;q=0.5"), "Votre code de connexion");
+        assert_eq!(render("de, en;q=0.5"), "Your login code");
+    }
+
+    #[test]
+    fn test_render_fills_in_variables() {
+        let email = templates()
+            .render(
+                EmailTemplate::TwoFACode,
+                &Locale::default(),
+                context! { code => "654321", expires_in_minutes => 10 },
+            )
+            .unwrap();
+
+        assert_eq!(email.subject, "Your login code");
+        assert!(email.text.contains("654321"));
+        assert!(email.text.contains("10 minutes"));
+        assert!(email.html.contains("654321"));
+    }
+
+    #[test]
+    fn test_html_is_escaped_but_text_is_not() {
+        let email = templates()
+            .render(
+                EmailTemplate::PasswordReset,
+                &Locale::default(),
+                context! { link => "http://localhost/reset?a=1&b=<2>", expires_in_minutes => 30 },
+            )
+            .unwrap();
+
+        assert!(email.text.contains("http://localhost/reset?a=1&b=<2>"));
+        assert!(email.html.contains("a=1&amp;b=&lt;2&gt;"));
+        assert!(!email.html.contains("<2>"));
+    }
+
+    #[test]
+    fn test_missing_variable_fails_to_render() {
+        let result = templates().render(EmailTemplate::TwoFACode, &Locale::default(), context! {});
+        assert!(matches!(result, Err(EmailTemplateError::RenderFailed(_))));
+    }
+
+    #[test]
+    fn test_locale_falls_back_to_base_language_then_default() {
+        let dir = std::env::temp_dir().join(format!("email-templates-{}", uuid::Uuid::new_v4()));
+        for locale in ["en", "de"] {
+            fs::create_dir_all(dir.join(locale)).unwrap();
+        }
+        for template in EmailTemplate::ALL {
+            let [subject, text, html] = template.files("en");
+            fs::write(dir.join(subject), "Hello").unwrap();
+            fs::write(dir.join(text), "Hello {{ name }}").unwrap();
+            fs::write(dir.join(html), "<html>Hello {{ name }}</html>").unwrap();
+        }
+        let [subject, text, html] = EmailTemplate::TwoFACode.files("de");
+        fs::write(dir.join(subject), "Hallo").unwrap();
+        fs::write(dir.join(text), "Hallo {{ name }}").unwrap();
+        fs::write(dir.join(html), "<html>Hallo {{ name }}</html>").unwrap();
+
+        let templates = EmailTemplates::load(&dir).unwrap();
+        let render = |template, header| {
+            templates
+                .render(template, &Locale::from_accept_language(header), context! { name => "Ada" })
+                .unwrap()
+                .text
+        };
+
+        assert_eq!(render(EmailTemplate::TwoFACode, "de-AT, en;q=0.5"), "Hallo Ada");
+        assert_eq!(render(EmailTemplate::TwoFACode, "fr, de;q=0.8"), "Hallo Ada");
+        assert_eq!(render(EmailTemplate::TwoFACode, "fr"), "Hello Ada");
+        assert_eq!(render(EmailTemplate::PasswordReset, "de"), "Hello Ada");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_fails_without_default_locale() {
+        let dir = std::env::temp_dir().join(format!("email-templates-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("de")).unwrap();
+
+        let result = EmailTemplates::load(&dir);
+        assert!(matches!(result, Err(EmailTemplateError::MissingTemplate(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_accept_language_parsing() {
+        let locale = Locale::from_accept_language("en;q=0.5, pt-BR, fr;q=0.8, *;q=0.1, ../x, de;q=0");
+        assert_eq!(locale, Locale(vec!["pt-br".to_owned(), "fr".to_owned(), "en".to_owned()]));
+        assert_eq!(
+            locale.fallbacks(),
+            vec!["pt-br".to_owned(), "pt".to_owned(), "fr".to_owned(), "en".to_owned()]
+        );
+        assert_eq!(Locale::from_accept_language("").fallbacks(), vec!["en".to_owned()]);
+    }
+}
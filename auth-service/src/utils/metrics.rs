@@ -0,0 +1,115 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::app_state::{BannedTokenStoreType, EmailOutboxStoreType};
+
+// The gauges /metrics reports. Counting them takes a Redis SCAN and database queries,
+// so they are counted in the background and scrapes only read the last values,
+// however often they come.
+#[derive(Default)]
+pub struct Gauges {
+    banned_tokens: AtomicUsize,
+    queued_emails: AtomicUsize,
+    dead_lettered_emails: AtomicUsize,
+}
+
+impl Gauges {
+    pub async fn refresh(
+        &self,
+        banned_token_store: &BannedTokenStoreType,
+        email_outbox: &EmailOutboxStoreType,
+    ) -> Result<(), String> {
+        let banned_tokens = banned_token_store
+            .read()
+            .await
+            .count()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        let (queued_emails, dead_lettered_emails) = {
+            let email_outbox = email_outbox.read().await;
+            let queued = email_outbox.count_queued().await;
+            let dead_letters = email_outbox.count_dead_letters().await;
+            (
+                queued.map_err(|e| format!("{:?}", e))?,
+                dead_letters.map_err(|e| format!("{:?}", e))?,
+            )
+        };
+
+        self.banned_tokens.store(banned_tokens, Ordering::Relaxed);
+        self.queued_emails.store(queued_emails, Ordering::Relaxed);
+        self.dead_lettered_emails.store(dead_lettered_emails, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn banned_tokens(&self) -> usize {
+        self.banned_tokens.load(Ordering::Relaxed)
+    }
+
+    pub fn queued_emails(&self) -> usize {
+        self.queued_emails.load(Ordering::Relaxed)
+    }
+
+    pub fn dead_lettered_emails(&self) -> usize {
+        self.dead_lettered_emails.load(Ordering::Relaxed)
+    }
+}
+
+// Counts the gauges again every `interval`
+pub fn spawn_gauge_refresher(
+    gauges: std::sync::Arc<Gauges>,
+    banned_token_store: BannedTokenStoreType,
+    email_outbox: EmailOutboxStoreType,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Err(e) = gauges.refresh(&banned_token_store, &email_outbox).await {
+                eprintln!("Failed to refresh the metrics gauges: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::services::{
+        hashmap_banned_token_store::HashmapBannedTokenStore,
+        hashmap_email_outbox_store::HashmapEmailOutboxStore,
+    };
+
+    #[tokio::test]
+    async fn test_gauges_only_change_when_refreshed() {
+        let banned_token_store: BannedTokenStoreType =
+            Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(HashmapEmailOutboxStore::default()));
+        let gauges = Gauges::default();
+
+        banned_token_store
+            .write()
+            .await
+            .add_token("jti".to_owned(), Utc::now() + chrono::Duration::minutes(10))
+            .await
+            .unwrap();
+        assert_eq!(gauges.banned_tokens(), 0);
+
+        gauges.refresh(&banned_token_store, &email_outbox).await.unwrap();
+        assert_eq!(gauges.banned_tokens(), 1);
+        assert_eq!(gauges.queued_emails(), 0);
+        assert_eq!(gauges.dead_lettered_emails(), 0);
+    }
+}
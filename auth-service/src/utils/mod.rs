@@ -1,2 +1,13 @@
 pub mod constants;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod email_outbox_worker;
+pub mod email_templates;
+pub mod keyring;
+pub mod metrics;
+pub mod rate_limit;
+pub mod signing_key;
+pub mod sweeper;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_database;
+pub mod totp;
+pub mod webauthn;
\ No newline at end of file
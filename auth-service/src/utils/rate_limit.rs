@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use super::constants::{RATE_LIMITED_BODY_MAX_BYTES, RATE_LIMIT_PER_EMAIL, RATE_LIMIT_PER_IP};
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, RateLimit, RateLimitStore, RateLimitStoreError},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    pub per_ip: RateLimit,
+    pub per_email: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_ip: *RATE_LIMIT_PER_IP,
+            per_email: *RATE_LIMIT_PER_EMAIL,
+        }
+    }
+}
+
+// Takes a token from the client IP's bucket and, if the body names one, from the
+// target email's bucket. Counting per email as well stops a single account being
+// brute-forced from many addresses. Every route gets its own buckets.
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    ConnectInfo(client_address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthAPIError> {
+    let route = request.uri().path().to_owned();
+
+    // The email is in the JSON body, so buffer it and hand the handler a copy
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, RATE_LIMITED_BODY_MAX_BYTES)
+        .await
+        .map_err(|_| AuthAPIError::BadRequest)?;
+
+    {
+        let mut rate_limit_store = state.rate_limit_store.write().await;
+
+        let ip_key = format!("{}:ip:{}", route, client_address.ip());
+        take_token(&mut *rate_limit_store, &ip_key, &state.rate_limits.per_ip).await?;
+
+        if let Some(email) = target_email(&body) {
+            let email_key = format!("{}:email:{}", route, email);
+            take_token(&mut *rate_limit_store, &email_key, &state.rate_limits.per_email).await?;
+        }
+    }
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+// Malformed bodies are left for the handler to reject
+fn target_email(body: &[u8]) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let email = body.get("email")?.as_str()?;
+    Some(email.trim().to_lowercase())
+}
+
+async fn take_token(
+    rate_limit_store: &mut (dyn RateLimitStore + Send + Sync),
+    key: &str,
+    limit: &RateLimit,
+) -> Result<(), AuthAPIError> {
+    rate_limit_store
+        .take_token(key, limit)
+        .await
+        .map_err(|e| match e {
+            // Retry-After is in whole seconds, rounded up so retrying right then succeeds
+            RateLimitStoreError::RateLimited { retry_after } => AuthAPIError::TooManyRequests {
+                retry_after_seconds: retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0),
+            },
+            RateLimitStoreError::UnexpectedError => AuthAPIError::UnexpectedError,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_email() {
+        assert_eq!(
+            target_email(br#"{"email": " User@Example.com ", "password": "password123"}"#),
+            Some("user@example.com".to_owned())
+        );
+        assert_eq!(target_email(br#"{"password": "password123"}"#), None);
+        assert_eq!(target_email(br#"{"email": 42}"#), None);
+        assert_eq!(target_email(b"not json"), None);
+    }
+}
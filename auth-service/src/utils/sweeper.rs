@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::app_state::{
+    BannedTokenStoreType, OneTimeTokenStoreType, RefreshTokenStoreType, SessionStoreType,
+};
+
+// A store whose entries are of no use once they expire, but which keeps them until
+// told otherwise
+pub enum SweptStore {
+    BannedTokens(BannedTokenStoreType),
+    RefreshTokens(RefreshTokenStoreType),
+    OneTimeTokens(OneTimeTokenStoreType),
+    Sessions(SessionStoreType),
+}
+
+impl SweptStore {
+    async fn prune_expired(&self) -> Result<usize, String> {
+        match self {
+            SweptStore::BannedTokens(store) => store.write().await.prune_expired().await.map_err(|e| format!("{:?}", e)),
+            SweptStore::RefreshTokens(store) => store.write().await.prune_expired().await.map_err(|e| format!("{:?}", e)),
+            SweptStore::OneTimeTokens(store) => store.write().await.prune_expired().await.map_err(|e| format!("{:?}", e)),
+            SweptStore::Sessions(store) => store.write().await.prune_expired().await.map_err(|e| format!("{:?}", e)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SweptStore::BannedTokens(_) => "banned tokens",
+            SweptStore::RefreshTokens(_) => "refresh tokens",
+            SweptStore::OneTimeTokens(_) => "one-time tokens",
+            SweptStore::Sessions(_) => "sessions",
+        }
+    }
+}
+
+// Drops the expired entries from each of `stores` every `interval`, so the in-memory
+// stores don't grow without bound
+pub fn spawn_sweeper(stores: Vec<SweptStore>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            for store in &stores {
+                if let Err(e) = store.prune_expired().await {
+                    eprintln!("Failed to prune {}: {}", store.name(), e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        domain::{Session, UserId},
+        services::{
+            hashmap_banned_token_store::HashmapBannedTokenStore,
+            hashmap_session_store::HashmapSessionStore,
+        },
+        utils::auth::REFRESH_TOKEN_TTL_SECONDS,
+    };
+
+    #[tokio::test]
+    async fn test_sweeper_prunes_every_store() {
+        let banned_token_store: BannedTokenStoreType =
+            Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let session_store: SessionStoreType = Arc::new(RwLock::new(HashmapSessionStore::default()));
+        let user_id = UserId::default();
+
+        {
+            let mut store = banned_token_store.write().await;
+            store
+                .add_token("expired".to_owned(), Utc::now() - chrono::Duration::seconds(1))
+                .await
+                .unwrap();
+            store
+                .add_token("live".to_owned(), Utc::now() + chrono::Duration::minutes(10))
+                .await
+                .unwrap();
+        }
+        let last_seen_at = Utc::now() - chrono::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS + 1);
+        session_store
+            .write()
+            .await
+            .add_session(Session {
+                id: Uuid::new_v4(),
+                user_id,
+                created_at: last_seen_at,
+                last_seen_at,
+                user_agent: None,
+                ip_address: None,
+            })
+            .await
+            .unwrap();
+
+        let sweeper = spawn_sweeper(
+            vec![
+                SweptStore::BannedTokens(banned_token_store.clone()),
+                SweptStore::Sessions(session_store.clone()),
+            ],
+            Duration::from_millis(10),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sweeper.abort();
+
+        let store = banned_token_store.read().await;
+        assert_eq!(store.count().await, Ok(1));
+        assert!(store.is_banned_token("live").await.unwrap());
+        assert!(session_store.read().await.get_sessions(&user_id).await.unwrap().is_empty());
+    }
+}
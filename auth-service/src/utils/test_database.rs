@@ -0,0 +1,49 @@
+use std::str::FromStr;
+
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, Executor, PgConnection, PgPool,
+};
+use uuid::Uuid;
+
+use super::constants::DATABASE_URL;
+
+// Creates and migrates a database with a random name on the server at DATABASE_URL.
+// Giving every test its own database lets the Postgres store tests and the
+// integration tests run in parallel.
+pub async fn create_test_database() -> (PgPool, String) {
+    let db_name = Uuid::new_v4().to_string();
+    let options = PgConnectOptions::from_str(&DATABASE_URL).expect("Invalid DATABASE_URL");
+
+    let mut connection = PgConnection::connect_with(&options)
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, db_name).as_str())
+        .await
+        .expect("Failed to create database.");
+
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options.database(&db_name))
+        .await
+        .expect("Failed to create Postgres connection pool!");
+    crate::run_migrations(&pool)
+        .await
+        .expect("Failed to migrate the database");
+
+    (pool, db_name)
+}
+
+pub async fn delete_test_database(db_name: &str) {
+    let options = PgConnectOptions::from_str(&DATABASE_URL).expect("Invalid DATABASE_URL");
+    let mut connection = PgConnection::connect_with(&options)
+        .await
+        .expect("Failed to connect to Postgres");
+
+    // FORCE terminates the connections that pools still have open
+    connection
+        .execute(format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#, db_name).as_str())
+        .await
+        .expect("Failed to drop the database.");
+}
@@ -0,0 +1,160 @@
+use std::{
+    io::Cursor,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use super::constants::{JWT_ISSUER, TOTP_DIGITS, TOTP_SKEW_STEPS, TOTP_STEP_SECONDS};
+use crate::domain::Email;
+
+#[derive(Debug, PartialEq)]
+pub enum TotpError {
+    InvalidSecret,
+    QrCodeError,
+    UnexpectedError,
+}
+
+// A fresh 160 bit secret, base32 encoded the way authenticator apps expect it
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+// RFC 6238 with the parameters every common authenticator app supports: SHA-1,
+// 6 digits and a 30 second step, accepting one step of clock drift either way
+fn totp(secret: &str, email: &Email) -> Result<TOTP, TotpError> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_| TotpError::InvalidSecret)?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW_STEPS,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(JWT_ISSUER.to_owned()),
+        email.as_ref().to_owned(),
+    )
+    .map_err(|_| TotpError::InvalidSecret)
+}
+
+// otpauth:// URI that authenticator apps import, usually by scanning it as a QR code
+pub fn otpauth_uri(secret: &str, email: &Email) -> Result<String, TotpError> {
+    Ok(totp(secret, email)?.get_url())
+}
+
+pub fn qr_code_png(data: &str) -> Result<Vec<u8>, TotpError> {
+    let image = QrCode::new(data.as_bytes())
+        .map_err(|_| TotpError::QrCodeError)?
+        .render::<Luma<u8>>()
+        .build();
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|_| TotpError::QrCodeError)?;
+    Ok(png)
+}
+
+// Returns the time step `code` belongs to, or `None` if it isn't valid right now. Callers
+// record the step with `UserStore::record_totp_step`, so each code is only accepted once.
+pub fn verify_totp_code(secret: &str, email: &Email, code: &str) -> Result<Option<u64>, TotpError> {
+    let mut totp = totp(secret, email)?;
+    let current_step = unix_time()? / TOTP_STEP_SECONDS;
+
+    // Check one step at a time to find out which one matched
+    totp.skew = 0;
+    let skew = u64::from(TOTP_SKEW_STEPS);
+    Ok((current_step.saturating_sub(skew)..=current_step + skew)
+        .find(|step| totp.check(code, step * TOTP_STEP_SECONDS)))
+}
+
+// The code an authenticator app would show right now
+pub fn current_totp_code(secret: &str, email: &Email) -> Result<String, TotpError> {
+    totp_code_at(secret, email, unix_time()?)
+}
+
+// The code an authenticator app would show at `time`, in seconds since the Unix epoch
+pub fn totp_code_at(secret: &str, email: &Email, time: u64) -> Result<String, TotpError> {
+    Ok(totp(secret, email)?.generate(time))
+}
+
+fn unix_time() -> Result<u64, TotpError> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .map_err(|_| TotpError::UnexpectedError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email::parse("test@example.com").unwrap()
+    }
+
+    #[test]
+    fn test_current_code_verifies() {
+        let secret = generate_totp_secret();
+        let code = current_totp_code(&secret, &email()).unwrap();
+
+        assert_eq!(code.len(), TOTP_DIGITS);
+        assert!(verify_totp_code(&secret, &email(), &code).unwrap().is_some());
+
+        let other_secret = generate_totp_secret();
+        assert_eq!(verify_totp_code(&other_secret, &email(), &code), Ok(None));
+    }
+
+    #[test]
+    fn test_verify_returns_matching_step() {
+        let secret = generate_totp_secret();
+        let now = unix_time().unwrap();
+        let current_step = now / TOTP_STEP_SECONDS;
+
+        let next_code = totp_code_at(&secret, &email(), now + TOTP_STEP_SECONDS).unwrap();
+        let step = verify_totp_code(&secret, &email(), &next_code).unwrap();
+        // The step may have moved on between the two calls
+        assert!(step == Some(current_step + 1) || step == Some(current_step));
+
+        let stale_code = totp_code_at(&secret, &email(), now - 3 * TOTP_STEP_SECONDS).unwrap();
+        assert_eq!(verify_totp_code(&secret, &email(), &stale_code), Ok(None));
+    }
+
+    #[test]
+    fn test_rfc_6238_test_vector() {
+        // SHA-1 secret "12345678901234567890" from RFC 6238 appendix B, truncated to 6 digits
+        let secret = Secret::Raw(b"12345678901234567890".to_vec()).to_encoded().to_string();
+        let totp = totp(&secret, &email()).unwrap();
+
+        assert_eq!(totp.generate(59), "287082");
+        assert_eq!(totp.generate(1_111_111_109), "081804");
+        assert_eq!(totp.generate(2_000_000_000), "279037");
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let secret = generate_totp_secret();
+        let uri = otpauth_uri(&secret, &email()).unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(&format!("secret={}", secret)));
+        assert!(uri.contains("test%40example.com"));
+    }
+
+    #[test]
+    fn test_qr_code_png() {
+        let png = qr_code_png("otpauth://totp/test").unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn test_invalid_secret() {
+        assert_eq!(otpauth_uri("not base32!", &email()), Err(TotpError::InvalidSecret));
+        // RFC 4226 requires at least 128 bits
+        assert_eq!(otpauth_uri("GEZDGNBV", &email()), Err(TotpError::InvalidSecret));
+    }
+}
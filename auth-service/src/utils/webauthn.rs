@@ -0,0 +1,358 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use ring::{digest, signature};
+use serde::Deserialize;
+
+use super::constants::{WEBAUTHN_ORIGIN, WEBAUTHN_RP_ID};
+use crate::domain::OneTimeToken;
+
+// COSE algorithm identifier for ECDSA with P-256 and SHA-256, the only one we accept.
+// Every platform authenticator and security key supports it.
+pub const COSE_ALG_ES256: i64 = -7;
+
+const COSE_KEY_KTY: i64 = 1;
+const COSE_KEY_ALG: i64 = 3;
+const COSE_KEY_CRV: i64 = -1;
+const COSE_KEY_X: i64 = -2;
+const COSE_KEY_Y: i64 = -3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_CRV_P256: i64 = 1;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug, PartialEq)]
+pub enum WebauthnError {
+    Malformed,
+    WrongCeremony,
+    WrongOrigin,
+    WrongRelyingParty,
+    UserNotPresent,
+    // The authenticator didn't check a PIN or biometric, so it only proves possession
+    UserNotVerified,
+    UnsupportedAlgorithm,
+    InvalidSignature,
+    // The authenticator's counter went backwards, which suggests it was cloned
+    SignCountRegressed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Registration => "webauthn.create",
+            Ceremony::Authentication => "webauthn.get",
+        }
+    }
+}
+
+// What an authenticator created during registration
+#[derive(Debug, PartialEq)]
+pub struct NewCredential {
+    pub id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+// Challenges are one-time tokens, so the token store takes care of expiry and single use
+pub fn encode_challenge(token: &OneTimeToken) -> String {
+    URL_SAFE_NO_PAD.encode(token.as_ref())
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, WebauthnError> {
+    URL_SAFE_NO_PAD.decode(value).map_err(|_| WebauthnError::Malformed)
+}
+
+// Checks the client data the browser signed over and returns the challenge in it,
+// which the caller still has to consume
+pub fn verify_client_data(client_data_json: &[u8], ceremony: Ceremony) -> Result<OneTimeToken, WebauthnError> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| WebauthnError::Malformed)?;
+
+    if client_data.ceremony != ceremony.client_data_type() {
+        return Err(WebauthnError::WrongCeremony);
+    }
+    // A phishing site gets its own origin in here, which is what makes WebAuthn phishing resistant
+    if client_data.origin != *WEBAUTHN_ORIGIN {
+        return Err(WebauthnError::WrongOrigin);
+    }
+
+    let challenge = String::from_utf8(decode(&client_data.challenge)?).map_err(|_| WebauthnError::Malformed)?;
+    OneTimeToken::parse(challenge).map_err(|_| WebauthnError::Malformed)
+}
+
+// Reads the new credential out of an attestation object. The attestation statement
+// itself isn't checked: we ask for "none", so we don't care which authenticator it is.
+pub fn verify_registration(attestation_object: &[u8]) -> Result<NewCredential, WebauthnError> {
+    let attestation: Value =
+        ciborium::de::from_reader(attestation_object).map_err(|_| WebauthnError::Malformed)?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|map| {
+            map.iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+                .and_then(|(_, value)| value.as_bytes())
+        })
+        .ok_or(WebauthnError::Malformed)?;
+
+    let (flags, sign_count) = parse_authenticator_data(auth_data)?;
+    if flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(WebauthnError::Malformed);
+    }
+
+    // After the 37 bytes above: a 16 byte AAGUID, the credential ID's length and the ID
+    let id_length = auth_data
+        .get(53..55)
+        .map(|length| usize::from(u16::from_be_bytes([length[0], length[1]])))
+        .ok_or(WebauthnError::Malformed)?;
+    let id = auth_data.get(55..55 + id_length).ok_or(WebauthnError::Malformed)?;
+
+    // The public key follows as a COSE key, possibly with extensions after it
+    let mut rest = &auth_data[55 + id_length..];
+    let cose_key: Value = ciborium::de::from_reader(&mut rest).map_err(|_| WebauthnError::Malformed)?;
+
+    Ok(NewCredential {
+        id: id.to_vec(),
+        public_key: parse_cose_key(&cose_key)?,
+        sign_count,
+    })
+}
+
+// Checks a login assertion against a stored credential and returns the new sign count.
+// A passkey login stands in for both the password and 2FA, so the authenticator must
+// have verified the user as well as seen them.
+pub fn verify_assertion(
+    public_key: &[u8],
+    stored_sign_count: u32,
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> Result<u32, WebauthnError> {
+    let (flags, sign_count) = parse_authenticator_data(authenticator_data)?;
+    if flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebauthnError::UserNotVerified);
+    }
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(digest::digest(&digest::SHA256, client_data_json).as_ref());
+    signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public_key)
+        .verify(&signed, signature)
+        .map_err(|_| WebauthnError::InvalidSignature)?;
+
+    // Authenticators without a counter always send 0
+    if (sign_count != 0 || stored_sign_count != 0) && sign_count <= stored_sign_count {
+        return Err(WebauthnError::SignCountRegressed);
+    }
+
+    Ok(sign_count)
+}
+
+// The fixed 37 byte header: a hash of the RP ID, flags and the sign count
+fn parse_authenticator_data(data: &[u8]) -> Result<(u8, u32), WebauthnError> {
+    if data.len() < 37 {
+        return Err(WebauthnError::Malformed);
+    }
+    if data[..32] != *digest::digest(&digest::SHA256, WEBAUTHN_RP_ID.as_bytes()).as_ref() {
+        return Err(WebauthnError::WrongRelyingParty);
+    }
+
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebauthnError::UserNotPresent);
+    }
+
+    Ok((flags, u32::from_be_bytes([data[33], data[34], data[35], data[36]])))
+}
+
+// Turns an ES256 COSE key into an uncompressed P-256 point
+fn parse_cose_key(key: &Value) -> Result<Vec<u8>, WebauthnError> {
+    let map = key.as_map().ok_or(WebauthnError::Malformed)?;
+    let get = |label: i64| {
+        map.iter()
+            .find(|(key, _)| key.as_integer().map(i128::from) == Some(i128::from(label)))
+            .map(|(_, value)| value)
+    };
+    let integer = |label: i64| get(label).and_then(Value::as_integer).map(i128::from);
+
+    if integer(COSE_KEY_KTY) != Some(i128::from(COSE_KTY_EC2))
+        || integer(COSE_KEY_ALG) != Some(i128::from(COSE_ALG_ES256))
+        || integer(COSE_KEY_CRV) != Some(i128::from(COSE_CRV_P256))
+    {
+        return Err(WebauthnError::UnsupportedAlgorithm);
+    }
+
+    let x = get(COSE_KEY_X).and_then(Value::as_bytes).ok_or(WebauthnError::Malformed)?;
+    let y = get(COSE_KEY_Y).and_then(Value::as_bytes).ok_or(WebauthnError::Malformed)?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(WebauthnError::Malformed);
+    }
+
+    let mut public_key = vec![0x04];
+    public_key.extend_from_slice(x);
+    public_key.extend_from_slice(y);
+    Ok(public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    use super::*;
+
+    fn key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes()).as_ref().to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    fn client_data(ceremony: &str, token: &OneTimeToken, origin: &str) -> Vec<u8> {
+        serde_json::json!({
+            "type": ceremony,
+            "challenge": encode_challenge(token),
+            "origin": origin,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn attestation_object(key_pair: &EcdsaKeyPair, alg: i64) -> Vec<u8> {
+        let point = key_pair.public_key().as_ref();
+        let cose_key = Value::Map(vec![
+            (COSE_KEY_KTY.into(), COSE_KTY_EC2.into()),
+            (COSE_KEY_ALG.into(), alg.into()),
+            (COSE_KEY_CRV.into(), COSE_CRV_P256.into()),
+            (COSE_KEY_X.into(), Value::Bytes(point[1..33].to_vec())),
+            (COSE_KEY_Y.into(), Value::Bytes(point[33..].to_vec())),
+        ]);
+
+        let mut auth_data = authenticator_data(
+            &WEBAUTHN_RP_ID,
+            FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA,
+            0,
+        );
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&3u16.to_be_bytes());
+        auth_data.extend_from_slice(b"abc");
+        ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let attestation = Value::Map(vec![
+            ("fmt".into(), "none".into()),
+            ("attStmt".into(), Value::Map(vec![])),
+            ("authData".into(), Value::Bytes(auth_data)),
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&attestation, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn sign(key_pair: &EcdsaKeyPair, authenticator_data: &[u8], client_data_json: &[u8]) -> Vec<u8> {
+        let mut signed = authenticator_data.to_vec();
+        signed.extend_from_slice(digest::digest(&digest::SHA256, client_data_json).as_ref());
+        key_pair.sign(&SystemRandom::new(), &signed).unwrap().as_ref().to_vec()
+    }
+
+    #[test]
+    fn test_verify_client_data() {
+        let token = OneTimeToken::default();
+
+        let data = client_data("webauthn.create", &token, &WEBAUTHN_ORIGIN);
+        assert_eq!(verify_client_data(&data, Ceremony::Registration), Ok(token.clone()));
+        assert_eq!(verify_client_data(&data, Ceremony::Authentication), Err(WebauthnError::WrongCeremony));
+
+        let data = client_data("webauthn.create", &token, "https://phishing.example.com");
+        assert_eq!(verify_client_data(&data, Ceremony::Registration), Err(WebauthnError::WrongOrigin));
+
+        assert_eq!(verify_client_data(b"{}", Ceremony::Registration), Err(WebauthnError::Malformed));
+    }
+
+    #[test]
+    fn test_verify_registration() {
+        let key_pair = key_pair();
+
+        let credential = verify_registration(&attestation_object(&key_pair, COSE_ALG_ES256)).unwrap();
+        assert_eq!(credential.id, b"abc");
+        assert_eq!(credential.public_key, key_pair.public_key().as_ref());
+        assert_eq!(credential.sign_count, 0);
+
+        // RS256
+        assert_eq!(
+            verify_registration(&attestation_object(&key_pair, -257)),
+            Err(WebauthnError::UnsupportedAlgorithm)
+        );
+        assert_eq!(verify_registration(b"not cbor"), Err(WebauthnError::Malformed));
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let key_pair = key_pair();
+        let public_key = key_pair.public_key().as_ref();
+        let client_data_json = client_data("webauthn.get", &OneTimeToken::default(), &WEBAUTHN_ORIGIN);
+
+        let auth_data = authenticator_data(&WEBAUTHN_RP_ID, FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 5);
+        let signature = sign(&key_pair, &auth_data, &client_data_json);
+        assert_eq!(verify_assertion(public_key, 4, &auth_data, &client_data_json, &signature), Ok(5));
+        assert_eq!(
+            verify_assertion(public_key, 5, &auth_data, &client_data_json, &signature),
+            Err(WebauthnError::SignCountRegressed)
+        );
+
+        // The signature covers the client data
+        let other_client_data = client_data("webauthn.get", &OneTimeToken::default(), &WEBAUTHN_ORIGIN);
+        assert_eq!(
+            verify_assertion(public_key, 4, &auth_data, &other_client_data, &signature),
+            Err(WebauthnError::InvalidSignature)
+        );
+
+        // Authenticators without a counter keep sending 0
+        let auth_data = authenticator_data(&WEBAUTHN_RP_ID, FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 0);
+        let signature = sign(&key_pair, &auth_data, &client_data_json);
+        assert_eq!(verify_assertion(public_key, 0, &auth_data, &client_data_json, &signature), Ok(0));
+
+        let auth_data = authenticator_data("phishing.example.com", FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 0);
+        let signature = sign(&key_pair, &auth_data, &client_data_json);
+        assert_eq!(
+            verify_assertion(public_key, 0, &auth_data, &client_data_json, &signature),
+            Err(WebauthnError::WrongRelyingParty)
+        );
+
+        let auth_data = authenticator_data(&WEBAUTHN_RP_ID, 0, 0);
+        let signature = sign(&key_pair, &auth_data, &client_data_json);
+        assert_eq!(
+            verify_assertion(public_key, 0, &auth_data, &client_data_json, &signature),
+            Err(WebauthnError::UserNotPresent)
+        );
+
+        // Touching the key isn't enough without a PIN or biometric
+        let auth_data = authenticator_data(&WEBAUTHN_RP_ID, FLAG_USER_PRESENT, 6);
+        let signature = sign(&key_pair, &auth_data, &client_data_json);
+        assert_eq!(
+            verify_assertion(public_key, 5, &auth_data, &client_data_json, &signature),
+            Err(WebauthnError::UserNotVerified)
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use auth_service::{
+    routes::{ChangeEmailResponse, ChangePasswordResponse, DeleteAccountResponse, SessionsResponse},
+    utils::constants::JWT_COOKIE_NAME,
+};
+
+use crate::helpers::{get_random_email, link_token, TestApp};
+
+async fn signup(app: &TestApp) -> String {
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(&email).await;
+
+    email
+}
+
+// Logs in with `password`, which starts a new session when it is right
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": password,
+    });
+    app.post_login(&login_body).await
+}
+
+fn jwt(response: &reqwest::Response) -> String {
+    response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie returned")
+        .value()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": "password123",
+            "new_password": "new_password123",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_422_if_malformed_input() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let test_cases = [
+        serde_json::json!({ "current_password": "password123" }),
+        serde_json::json!({ "new_password": "new_password123" }),
+        serde_json::json!({ "current_password": 123, "new_password": "new_password123" }),
+    ];
+
+    for test_case in test_cases.iter() {
+        let response = app.post_change_password(test_case).await;
+        assert_eq!(response.status().as_u16(), 422, "Failed for input: {:?}", test_case);
+    }
+}
+
+#[tokio::test]
+async fn should_return_401_if_current_password_incorrect() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    for current_password in ["wrong_password123", "short"] {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": current_password,
+                "new_password": "new_password123",
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // The password is unchanged
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_new_password_invalid() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": "password123",
+            "new_password": "short",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_change_password_and_end_other_sessions() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+
+    let other_session_jwt = jwt(&login(&app, &email, "password123").await);
+    let old_jwt = jwt(&login(&app, &email, "password123").await);
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": "password123",
+            "new_password": "new_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_jwt = jwt(&response);
+    assert_eq!(
+        response
+            .json::<ChangePasswordResponse>()
+            .await
+            .expect("Could not deserialize response body to ChangePasswordResponse")
+            .message,
+        "Password has been changed"
+    );
+
+    // Every earlier JWT is rejected, but this session carries on with its new one
+    assert_eq!(app.verify_token(other_session_jwt).await.status().as_u16(), 401);
+    assert_eq!(app.verify_token(old_jwt).await.status().as_u16(), 401);
+    assert_eq!(app.verify_token(new_jwt).await.status().as_u16(), 200);
+
+    let response = app.get_sessions().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let sessions = response.json::<SessionsResponse>().await.unwrap().sessions;
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+
+    // ...and can still be refreshed
+    assert_eq!(app.post_refresh().await.status().as_u16(), 200);
+
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 401);
+    assert_eq!(login(&app, &email, "new_password123").await.status().as_u16(), 200);
+
+    let message = app
+        .last_email_to(&email, "Your password was changed")
+        .await
+        .expect("No security notification sent");
+    assert!(message.text.contains("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn should_return_401_if_password_incorrect_when_deleting_account() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let session_jwt = jwt(&login(&app, &email, "password123").await);
+
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "wrong_password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    assert_eq!(app.verify_token(session_jwt).await.status().as_u16(), 200);
+    assert!(app.last_email_to(&email, "Your account has been deleted").await.is_none());
+}
+
+#[tokio::test]
+async fn should_delete_account_and_end_every_session() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+
+    let other_session_jwt = jwt(&login(&app, &email, "password123").await);
+    let session_jwt = jwt(&login(&app, &email, "password123").await);
+
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response
+            .json::<DeleteAccountResponse>()
+            .await
+            .expect("Could not deserialize response body to DeleteAccountResponse")
+            .message,
+        "Account has been deleted"
+    );
+
+    assert_eq!(app.verify_token(other_session_jwt).await.status().as_u16(), 401);
+    assert_eq!(app.verify_token(session_jwt).await.status().as_u16(), 401);
+    // The auth cookies were cleared
+    assert_eq!(app.post_refresh().await.status().as_u16(), 400);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 401);
+
+    app.last_email_to(&email, "Your account has been deleted")
+        .await
+        .expect("No account deleted email sent");
+
+    // The address is free for a new account
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+}
+
+// Asks to move the logged in account to `new_email` and returns the tokens from the
+// confirmation and cancel links
+async fn request_email_change(app: &TestApp, email: &str, new_email: &str) -> (String, String) {
+    let response = app
+        .post_change_email(&serde_json::json!({
+            "new_email": new_email,
+            "password": "password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let confirmation = app
+        .last_email_to(new_email, "Confirm your new email address")
+        .await
+        .expect("No confirmation sent to the new address");
+    let notice = app
+        .last_email_to(email, "Your email address is being changed")
+        .await
+        .expect("No notice sent to the old address");
+    assert!(notice.text.contains(new_email));
+
+    (link_token(&confirmation), link_token(&notice))
+}
+
+#[tokio::test]
+async fn should_reject_invalid_email_change_requests() {
+    let app = TestApp::new().await;
+    let change = |new_email: &str, password: &str| {
+        serde_json::json!({ "new_email": new_email, "password": password })
+    };
+
+    let response = app.post_change_email(&change(&get_random_email(), "password123")).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let email = signup(&app).await;
+    let other_email = signup(&app).await;
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let test_cases = [
+        (change(&get_random_email(), "wrong_password123"), 401),
+        (change("not-an-email", "password123"), 400),
+        (change(&email, "password123"), 400),
+        // Another account's address
+        (change(&other_email, "password123"), 409),
+        (serde_json::json!({ "new_email": get_random_email() }), 422),
+    ];
+    for (body, status) in test_cases.iter() {
+        let response = app.post_change_email(body).await;
+        assert_eq!(response.status().as_u16(), *status, "Failed for input: {:?}", body);
+    }
+
+    assert!(app
+        .last_email_to(&email, "Your email address is being changed")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn should_change_email_once_confirmed() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let new_email = get_random_email();
+    let old_jwt = jwt(&login(&app, &email, "password123").await);
+
+    let (confirm_token, _) = request_email_change(&app, &email, &new_email).await;
+
+    // Nothing changes until the new address is confirmed
+    assert_eq!(app.verify_token(old_jwt.clone()).await.status().as_u16(), 200);
+
+    let response = app.get_confirm_email_change(&confirm_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response
+            .json::<ChangeEmailResponse>()
+            .await
+            .expect("Could not deserialize response body to ChangeEmailResponse")
+            .message,
+        "Email address has been changed"
+    );
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 401);
+
+    // JWTs naming the old address are rejected, but the session moved with the account
+    // and refreshing it carries on under the new one
+    assert_eq!(app.verify_token(old_jwt).await.status().as_u16(), 401);
+    let response = app.post_refresh().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.verify_token(jwt(&response)).await.status().as_u16(), 200);
+    assert_eq!(app.get_sessions().await.json::<SessionsResponse>().await.unwrap().sessions.len(), 1);
+
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 401);
+    assert_eq!(login(&app, &new_email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_cancel_a_pending_email_change() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let new_email = get_random_email();
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let (confirm_token, cancel_token) = request_email_change(&app, &email, &new_email).await;
+
+    let response = app.get_cancel_email_change(&cancel_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_cancel_email_change(&cancel_token).await.status().as_u16(), 401);
+
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 401);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+    assert_eq!(login(&app, &new_email, "password123").await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_undo_a_confirmed_email_change_and_log_out_everywhere() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let new_email = get_random_email();
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let (confirm_token, cancel_token) = request_email_change(&app, &email, &new_email).await;
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 200);
+    let new_jwt = jwt(&login(&app, &new_email, "password123").await);
+
+    let response = app.get_cancel_email_change(&cancel_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Whoever made the change is logged out
+    assert_eq!(app.verify_token(new_jwt).await.status().as_u16(), 401);
+    assert_eq!(app.post_refresh().await.status().as_u16(), 401);
+
+    assert_eq!(login(&app, &new_email, "password123").await.status().as_u16(), 401);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_409_if_new_email_taken_before_confirmation() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let new_email = get_random_email();
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let (confirm_token, _) = request_email_change(&app, &email, &new_email).await;
+
+    let signup_body = serde_json::json!({
+        "email": new_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 409);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_keep_the_old_email_reserved_after_a_confirmed_change() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let new_email = get_random_email();
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let (confirm_token, cancel_token) = request_email_change(&app, &email, &new_email).await;
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 200);
+
+    // Nobody else can take the old address while the change can still be undone
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 409);
+
+    assert_eq!(app.get_cancel_email_change(&cancel_token).await.status().as_u16(), 200);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_undo_a_chain_of_email_changes_from_the_first_address() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let second_email = get_random_email();
+    let third_email = get_random_email();
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    let (confirm_token, first_cancel_token) = request_email_change(&app, &email, &second_email).await;
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 200);
+    assert_eq!(app.post_refresh().await.status().as_u16(), 200);
+
+    let (confirm_token, second_cancel_token) =
+        request_email_change(&app, &second_email, &third_email).await;
+    assert_eq!(app.get_confirm_email_change(&confirm_token).await.status().as_u16(), 200);
+
+    let response = app.get_cancel_email_change(&first_cancel_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.post_refresh().await.status().as_u16(), 401);
+
+    // The later change can't be used to move the account away again
+    assert_eq!(app.get_cancel_email_change(&second_cancel_token).await.status().as_u16(), 401);
+
+    assert_eq!(login(&app, &third_email, "password123").await.status().as_u16(), 401);
+    assert_eq!(login(&app, &second_email, "password123").await.status().as_u16(), 401);
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+}
@@ -0,0 +1,124 @@
+use auth_service::{
+    routes::UnlockAccountResponse,
+    utils::constants::{ACCOUNT_LOCKOUT_BASE_SECONDS, ACCOUNT_LOCKOUT_THRESHOLD, JWT_COOKIE_NAME},
+    ErrorResponse,
+};
+use reqwest::header::RETRY_AFTER;
+
+use crate::helpers::{get_random_email, link_token, TestApp};
+
+async fn signup(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(email).await;
+}
+
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "email": email,
+        "password": password,
+    }))
+    .await
+}
+
+async fn lock_account(app: &TestApp, email: &str) -> reqwest::Response {
+    for _ in 0..ACCOUNT_LOCKOUT_THRESHOLD - 1 {
+        let response = login(app, email, "wrong-password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    login(app, email, "wrong-password").await
+}
+
+// The token from the latest unlock email sent to `email`
+async fn get_unlock_token(app: &TestApp, email: &str) -> Option<String> {
+    app.last_email_to(email, "Your account has been locked")
+        .await
+        .map(|message| link_token(&message))
+}
+
+#[tokio::test]
+async fn should_lock_account_after_repeated_wrong_passwords() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    signup(&app, &email).await;
+
+    let response = lock_account(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 423);
+    assert_eq!(
+        response.headers().get(RETRY_AFTER).unwrap().to_str().unwrap(),
+        ACCOUNT_LOCKOUT_BASE_SECONDS.to_string()
+    );
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Account locked after too many failed logins, check your email to unlock it"
+    );
+
+    // Even the right password is turned away until the lock ends
+    let response = login(&app, &email, "password123").await;
+    assert_eq!(response.status().as_u16(), 423);
+    let retry_after: i64 = response.headers().get(RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= ACCOUNT_LOCKOUT_BASE_SECONDS);
+
+    assert!(get_unlock_token(&app, &email).await.is_some());
+}
+
+#[tokio::test]
+async fn should_unlock_account_through_emailed_link() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    signup(&app, &email).await;
+
+    assert_eq!(lock_account(&app, &email).await.status().as_u16(), 423);
+
+    let token = get_unlock_token(&app, &email).await.expect("No unlock token");
+    let response = app.get_unlock_account(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.json::<UnlockAccountResponse>().await.unwrap(),
+        UnlockAccountResponse {
+            message: "Account unlocked".to_owned()
+        }
+    );
+
+    let response = login(&app, &email, "password123").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.cookies().any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    // The link only works once
+    assert_eq!(app.get_unlock_account(&token).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_401_if_unlock_token_invalid() {
+    let app = TestApp::new().await;
+
+    assert_eq!(app.get_unlock_account("not-a-token").await.status().as_u16(), 401);
+    assert_eq!(
+        app.get_unlock_account(&"a".repeat(64)).await.status().as_u16(),
+        401
+    );
+}
+
+#[tokio::test]
+async fn should_only_count_consecutive_failures() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    signup(&app, &email).await;
+
+    for _ in 0..ACCOUNT_LOCKOUT_THRESHOLD - 1 {
+        assert_eq!(login(&app, &email, "wrong-password").await.status().as_u16(), 401);
+    }
+    assert_eq!(login(&app, &email, "password123").await.status().as_u16(), 200);
+
+    // A successful login starts the count over
+    assert_eq!(login(&app, &email, "wrong-password").await.status().as_u16(), 401);
+    assert!(get_unlock_token(&app, &email).await.is_none());
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use auth_service::{
+    domain::{Email, OutboxMessage},
+    routes::DeadLetterResponse,
+};
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, TestApp, ADMIN_API_KEY};
+
+// Queues a message and dead-letters it straight away, as if it had run out of attempts
+async fn add_dead_letter(app: &TestApp) -> OutboxMessage {
+    let message = OutboxMessage::new(
+        Email::parse(&get_random_email()).unwrap(),
+        "Your login code".to_owned(),
+        "Your login code is 123456".to_owned(),
+        "<p>Your login code is 123456</p>".to_owned(),
+    );
+
+    let mut email_outbox = app.email_outbox.write().await;
+    email_outbox.enqueue(message.clone()).await.unwrap();
+    email_outbox
+        .record_failure(&message.id, "550 mailbox unavailable", None)
+        .await
+        .unwrap();
+    message
+}
+
+async fn dead_letters(app: &TestApp) -> Vec<DeadLetterResponse> {
+    let response = app.get_email_dead_letters(ADMIN_API_KEY).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<Vec<DeadLetterResponse>>()
+        .await
+        .expect("Could not deserialize response body to Vec<DeadLetterResponse>")
+}
+
+#[tokio::test]
+async fn should_return_401_without_valid_admin_key() {
+    let app = TestApp::new().await;
+
+    let response = app.get_email_dead_letters("wrong-key").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let body = serde_json::json!({ "id": Uuid::new_v4().to_string() });
+    let response = app.post_replay_email("wrong-key", &body).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_list_dead_letters_without_their_bodies() {
+    let app = TestApp::new().await;
+
+    let message = add_dead_letter(&app).await;
+
+    let response = app.get_email_dead_letters(ADMIN_API_KEY).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("123456"));
+
+    let dead_letters: Vec<DeadLetterResponse> = serde_json::from_str(&body).unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].id, message.id.to_string());
+    assert_eq!(dead_letters[0].recipient, message.recipient.as_ref());
+    assert_eq!(dead_letters[0].subject, "Your login code");
+    assert_eq!(dead_letters[0].attempts, 1);
+    assert_eq!(dead_letters[0].last_error.as_deref(), Some("550 mailbox unavailable"));
+    assert!(dead_letters[0].dead_lettered_at.is_some());
+}
+
+#[tokio::test]
+async fn should_deliver_replayed_dead_letters() {
+    let app = TestApp::new().await;
+
+    let message = add_dead_letter(&app).await;
+
+    let body = serde_json::json!({ "id": message.id.to_string() });
+    let response = app.post_replay_email(ADMIN_API_KEY, &body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(dead_letters(&app).await.is_empty());
+
+    // Once the worker has sent it, the message is gone and can't be replayed again
+    let mut delivered = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if app.email_outbox.read().await.count_queued().await == Ok(0) {
+            delivered = true;
+            break;
+        }
+    }
+    assert!(delivered, "The replayed email was not delivered");
+
+    let response = app.post_replay_email(ADMIN_API_KEY, &body).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_400_for_unknown_or_malformed_ids() {
+    let app = TestApp::new().await;
+
+    for id in [Uuid::new_v4().to_string(), "not-a-uuid".to_owned()] {
+        let body = serde_json::json!({ "id": id });
+        let response = app.post_replay_email(ADMIN_API_KEY, &body).await;
+        assert_eq!(response.status().as_u16(), 400, "Failed for id: {}", id);
+    }
+}
@@ -1,46 +1,125 @@
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-};
+use std::{sync::Arc, time::Duration};
 
 use auth_service::{
-    Application, app_state::{AppState, TwoFACodeStoreType}, services::{
-        hashmap_two_fa_code_store::HashmapTwoFACodeStore, hashmap_user_store::HashmapUserStore,
-        hashset_banned_token_store::HashsetBannedTokenStore, mock_email_client::MockEmailClient,
-    }, utils::constants::test
+    domain::{Email, UserId},
+    Application, app_state::{
+        AppState, BannedTokenStoreType, EmailOutboxStoreType, GaugesType, JwtKeyringType,
+        SigningKeyStoreType, UserStoreType,
+    }, services::{
+        hashmap_one_time_token_store::HashmapOneTimeTokenStore,
+        hashmap_refresh_token_store::HashmapRefreshTokenStore,
+        hashmap_session_store::HashmapSessionStore,
+        hashmap_rate_limit_store::HashmapRateLimitStore,
+        hashmap_two_fa_code_store::HashmapTwoFACodeStore,
+        hashmap_banned_token_store::HashmapBannedTokenStore,
+        maildir_email_client::{MaildirEmailClient, MaildirMessage},
+        postgres_credential_store::PostgresCredentialStore,
+        postgres_signing_key_store::PostgresSigningKeyStore,
+        postgres_email_outbox_store::PostgresEmailOutboxStore, postgres_user_store::PostgresUserStore,
+    }, utils::{
+        email_outbox_worker::spawn_email_outbox_worker,
+        constants::{test, ADMIN_API_KEY_HEADER, DEFAULT_EMAIL_TEMPLATES_DIR},
+        email_templates::EmailTemplates,
+        keyring::JwtKeyring,
+        metrics::Gauges,
+        rate_limit::RateLimitConfig,
+        signing_key::JwtSigningKey,
+        test_database::{create_test_database, delete_test_database},
+    }
 };
 use reqwest::{self, cookie::Jar};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+pub const ADMIN_API_KEY: &str = "test-admin-key";
+
 pub struct TestApp {
     pub address: String,
     pub cookie_jar: Arc<Jar>,
     pub http_client: reqwest::Client,
-    pub two_fa_code_store: TwoFACodeStoreType,
+    pub user_store: UserStoreType,
+    pub two_fa_code_store: Arc<RwLock<HashmapTwoFACodeStore>>,
+    pub one_time_token_store: Arc<RwLock<HashmapOneTimeTokenStore>>,
+    pub jwt_keyring: JwtKeyringType,
+    pub signing_key_store: SigningKeyStoreType,
+    pub email_outbox: EmailOutboxStoreType,
+    banned_token_store: BannedTokenStoreType,
+    gauges: GaugesType,
+    maildir: MaildirEmailClient,
+    db_name: String,
 }
 
 impl TestApp {
     pub async fn new() -> Self {
-        let user_store = Arc::new(RwLock::new(HashmapUserStore {
-            users: HashMap::new(),
-        }));
+        Self::with_rate_limits(RateLimitConfig::default()).await
+    }
+
+    pub async fn with_rate_limits(rate_limits: RateLimitConfig) -> Self {
+        let (pg_pool, db_name) = create_test_database().await;
+
+        let user_store: UserStoreType = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool.clone())));
+
+        let credential_store = Arc::new(RwLock::new(PostgresCredentialStore::new(pg_pool.clone())));
+
+        let mut signing_key_store = PostgresSigningKeyStore::new(pg_pool.clone());
+
+        let banned_token_store: BannedTokenStoreType =
+            Arc::new(RwLock::new(HashmapBannedTokenStore::default()));
+        let gauges: GaugesType = Arc::new(Gauges::default());
+
+        let two_fa_code_store = Arc::new(RwLock::new(HashmapTwoFACodeStore::default()));
 
-        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore {
-            tokens: HashSet::new(),
-        }));
+        // Emails end up in a Maildir of the test's own, where tests read them like a user would
+        let maildir = MaildirEmailClient::new(maildir_path(&db_name)).expect("Failed to create Maildir");
+        let email_client = Arc::new(RwLock::new(maildir.clone()));
 
-        let two_fa_code_store = Arc::new(RwLock::new(HashmapTwoFACodeStore {
-            codes: HashMap::new(),
-        }));
+        let email_outbox: EmailOutboxStoreType =
+            Arc::new(RwLock::new(PostgresEmailOutboxStore::new(pg_pool)));
+        spawn_email_outbox_worker(
+            email_outbox.clone(),
+            email_client.clone(),
+            Duration::from_millis(test::EMAIL_OUTBOX_POLL_INTERVAL_MILLIS),
+        );
 
-        let email_client = Arc::new(RwLock::new(MockEmailClient));
+        let refresh_token_store = Arc::new(RwLock::new(HashmapRefreshTokenStore::default()));
+
+        let one_time_token_store = Arc::new(RwLock::new(HashmapOneTimeTokenStore::default()));
+
+        let session_store = Arc::new(RwLock::new(HashmapSessionStore::default()));
+
+        let rate_limit_store = Arc::new(RwLock::new(HashmapRateLimitStore::default()));
+
+        let email_templates = Arc::new(
+            EmailTemplates::load(DEFAULT_EMAIL_TEMPLATES_DIR).expect("Failed to load email templates"),
+        );
+
+        let jwt_keyring = Arc::new(RwLock::new(
+            JwtKeyring::load(
+                JwtSigningKey::generate().expect("Failed to generate signing key"),
+                &mut signing_key_store,
+            )
+            .await
+            .expect("Failed to load the JWT keyring"),
+        ));
+        let signing_key_store: SigningKeyStoreType = Arc::new(RwLock::new(signing_key_store));
 
         let app_state = AppState {
-            user_store: user_store,
-            banned_token_store: banned_token_store,
+            user_store: user_store.clone(),
+            banned_token_store: banned_token_store.clone(),
             two_fa_code_store: two_fa_code_store.clone(),
             email_client,
+            email_outbox: email_outbox.clone(),
+            refresh_token_store,
+            one_time_token_store: one_time_token_store.clone(),
+            jwt_keyring: jwt_keyring.clone(),
+            signing_key_store: signing_key_store.clone(),
+            admin_api_key: Some(ADMIN_API_KEY.to_owned()),
+            rate_limit_store,
+            rate_limits,
+            email_templates,
+            credential_store,
+            session_store,
+            gauges: gauges.clone(),
         };
 
         let app = Application::build(app_state, test::APP_ADDRESS)
@@ -65,19 +144,53 @@ impl TestApp {
             address,
             cookie_jar,
             http_client,
-            two_fa_code_store: two_fa_code_store.clone()
+            user_store,
+            two_fa_code_store: two_fa_code_store.clone(),
+            one_time_token_store,
+            jwt_keyring,
+            signing_key_store,
+            email_outbox,
+            banned_token_store,
+            gauges,
+            maildir,
+            db_name,
         }
     }
 
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/", &self.address))
+            .get(format!("{}/", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // The app doesn't run the task that keeps the gauges up to date, so they are counted
+    // right before each scrape
+    pub async fn get_metrics(&self, admin_key: &str) -> reqwest::Response {
+        self.gauges
+            .refresh(&self.banned_token_store, &self.email_outbox)
+            .await
+            .expect("Failed to refresh the gauges");
+
+        self.http_client
+            .get(format!("{}/metrics", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_jwks(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/.well-known/jwks.json", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
     // TODO: Implement helper functions for all other routes (signup, login, logout, verify-2fa, and verify-token)
+    #[allow(dead_code)]
     pub async fn signup(
         &self,
         email: String,
@@ -96,7 +209,6 @@ impl TestApp {
             password,
             requires2fa,
         };
-        println!("{}", format!("{}{}", &self.address, "/signup"));
         let response = self
             .http_client
             .post(format!("{}{}", &self.address, "/signup"))
@@ -139,6 +251,7 @@ impl TestApp {
         response
     }
 
+    #[allow(dead_code)]
     pub async fn verify_2fa(
         &self,
         email: String,
@@ -169,6 +282,7 @@ impl TestApp {
         response
     }
 
+    #[allow(dead_code)]
     pub async fn verify_token(&self, token: String) -> reqwest::Response {
         #[derive(serde::Serialize)]
         struct VerifyToken {
@@ -191,7 +305,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/signup", &self.address))
+            .post(format!("{}/signup", &self.address))
             .json(body)
             .send()
             .await
@@ -203,7 +317,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .json(body)
             .send()
             .await
@@ -222,6 +336,310 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_refresh(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/refresh", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_signing_keys(&self, admin_key: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/signing-keys", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_signing_key<Body>(&self, admin_key: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/signing-keys", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_retire_signing_key<Body>(&self, admin_key: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/signing-keys/retire", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_email_dead_letters(&self, admin_key: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/email-outbox/dead-letters", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_replay_email<Body>(&self, admin_key: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/email-outbox/replay", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_disable_user<Body>(&self, admin_key: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/users/disable", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_enable_user<Body>(&self, admin_key: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/users/enable", &self.address))
+            .header(ADMIN_API_KEY_HEADER, admin_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset_request<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/password-reset/request", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset_confirm<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/password-reset/confirm", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_verify_email(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/verify-email", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_unlock_account(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/unlock-account", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_magic_link<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/magic-link", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_magic_link_verify(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/login/magic-link/verify", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_verification_email<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/verify-email/resend", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Follows the link from the latest verification email, so new accounts can log in
+    pub async fn verify_email(&self, email: &str) {
+        let message = self
+            .last_email_to(email, "Verify your email address")
+            .await
+            .expect("No verification email sent");
+
+        let response = self.get_verify_email(&link_token(&message)).await;
+        assert_eq!(response.status().as_u16(), 200, "Failed to verify {}", email);
+    }
+
+    // The ID the user store gave the account at `email`
+    pub async fn user_id(&self, email: &str) -> UserId {
+        self.user_store
+            .read()
+            .await
+            .get_user(&Email::parse(email).unwrap())
+            .await
+            .expect("No user with that email")
+            .id
+    }
+
+    // Every email sent to `recipient` so far, oldest first. Emails are sent in the
+    // background, so this waits for the outbox to empty before reading the Maildir.
+    pub async fn emails_to(&self, recipient: &str) -> Vec<MaildirMessage> {
+        for _ in 0..100 {
+            if self.email_outbox.read().await.count_queued().await == Ok(0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(test::EMAIL_OUTBOX_POLL_INTERVAL_MILLIS)).await;
+        }
+
+        self.maildir
+            .messages()
+            .expect("Failed to read the Maildir")
+            .into_iter()
+            .filter(|message| message.recipient == recipient)
+            .collect()
+    }
+
+    pub async fn last_email_to(&self, recipient: &str, subject: &str) -> Option<MaildirMessage> {
+        self.emails_to(recipient)
+            .await
+            .into_iter()
+            .rev()
+            .find(|message| message.subject == subject)
+    }
+
+    // Some emails are sent from a background task, so they may not be queued yet when the
+    // response arrives. Waits for the `count`th email to `recipient` with `subject`.
+    pub async fn wait_for_email_to(&self, recipient: &str, subject: &str, count: usize) -> MaildirMessage {
+        for _ in 0..100 {
+            let mut messages: Vec<MaildirMessage> = self
+                .emails_to(recipient)
+                .await
+                .into_iter()
+                .filter(|message| message.subject == subject)
+                .collect();
+            if messages.len() >= count {
+                return messages.swap_remove(count - 1);
+            }
+            tokio::time::sleep(Duration::from_millis(test::EMAIL_OUTBOX_POLL_INTERVAL_MILLIS)).await;
+        }
+        panic!("No email \"{}\" number {} to {}", subject, count, recipient);
+    }
+
+    pub async fn post_totp_enroll(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/2fa/totp/enroll", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_totp_confirm<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/totp/confirm", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_regenerate_recovery_codes(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/2fa/recovery-codes", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_webauthn_register_start(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/webauthn/register/start", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_webauthn_register_finish<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/register/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_webauthn_login_start<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/start", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_webauthn_login_finish<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_verify_2fa<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -233,10 +651,125 @@ impl TestApp {
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn get_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_session(&self, id: &str) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/sessions/{}", &self.address, id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/account/password", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_account<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .delete(format!("{}/account", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_change_email<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/account/email", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_confirm_email_change(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/account/email/confirm", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_cancel_email_change(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/account/email/cancel", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
-pub fn get_random_email() -> String {
-    format!("{}@example.com", Uuid::new_v4())
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        // Dropping the database needs an async connection, so do it on a
+        // dedicated runtime rather than the one driving the test.
+        let db_name = self.db_name.clone();
+        let _ = std::fs::remove_dir_all(maildir_path(&db_name));
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .expect("Failed to create runtime")
+                .block_on(delete_test_database(&db_name));
+        })
+        .join()
+        .expect("Failed to delete test database");
+    }
 }
 
+fn maildir_path(db_name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join("auth-service-test-mail").join(db_name)
+}
 
+// The token from the link in an email, e.g. `.../verify-email?token=<token>`
+pub fn link_token(message: &MaildirMessage) -> String {
+    let (_, rest) = message.text.split_once("?token=").expect("No link in email");
+    rest.split(|c: char| c.is_whitespace() || c == '&')
+        .next()
+        .unwrap()
+        .to_owned()
+}
+
+// The first six digit number in an email, i.e. the 2FA code
+pub fn email_code(message: &MaildirMessage) -> String {
+    message
+        .text
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == 6)
+        .expect("No code in email")
+        .to_owned()
+}
+
+pub fn get_random_email() -> String {
+    format!("{}@example.com", Uuid::new_v4())
+}
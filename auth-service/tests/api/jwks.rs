@@ -0,0 +1,64 @@
+use auth_service::utils::{auth::Claims, constants::{JWT_AUDIENCE, JWT_COOKIE_NAME}};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_return_published_signing_key() {
+    let app = TestApp::new().await;
+
+    let response = app.get_jwks().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let jwks: JwkSet = response.json().await.expect("Could not deserialize JWKS");
+    assert_eq!(jwks.keys.len(), 1);
+    assert!(jwks.keys[0].common.key_id.is_some());
+    assert!(jwks.keys[0].is_supported());
+}
+
+#[tokio::test]
+async fn should_verify_issued_token_with_published_key() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    // Verify the token the way another backend would: only with what the JWKS exposes
+    let jwks: JwkSet = app.get_jwks().await.json().await.unwrap();
+    let kid = decode_header(&token).unwrap().kid.expect("Token has no kid");
+    let jwk = jwks.find(&kid).expect("Signing key is not published");
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_audience(&[JWT_AUDIENCE.as_str()]);
+
+    let claims = decode::<Claims>(&token, &DecodingKey::from_jwk(jwk).unwrap(), &validation)
+    .expect("Token does not verify against the published key")
+    .claims;
+
+    assert_eq!(claims.sub, random_email);
+}
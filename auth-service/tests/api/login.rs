@@ -1,7 +1,6 @@
-use auth_service::Application;
-use auth_service::domain::Email;
+use auth_service::domain::{TwoFACodeStore, TwoFactorMethod};
 use auth_service::routes::TwoFactorAuthResponse;
-use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use auth_service::utils::constants::JWT_COOKIE_NAME;
 use crate::helpers::{get_random_email, TestApp};
 
 #[tokio::test]
@@ -84,6 +83,7 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -100,6 +100,12 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
         .expect("No auth cookie found");
 
     assert!(!auth_cookie.value().is_empty());
+
+    let message = app
+        .last_email_to(&random_email, "New login to your account")
+        .await
+        .expect("No new login alert sent");
+    assert!(message.text.contains("127.0.0.1"));
 }
 
 #[tokio::test]
@@ -117,6 +123,7 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -133,10 +140,11 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
         .expect("Could not deserialize response body to TwoFactorAuthResponse");
 
     assert_eq!(json_body.message, "2FA required".to_owned());
+    assert_eq!(json_body.method, TwoFactorMethod::Email);
 
     // TODO: assert that `json_body.login_attempt_id` is stored inside `app.two_fa_code_store`
     let store = app.two_fa_code_store.write().await;
 
-    let result = store.get_code(&(Email::parse(&random_email)).unwrap()).await.unwrap();
+    let result = store.get_code(&app.user_id(&random_email).await).await.unwrap();
     assert_eq!(result.0.as_ref(), json_body.login_attempt_id)
 }
\ No newline at end of file
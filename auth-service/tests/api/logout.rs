@@ -1,5 +1,5 @@
 use auth_service::utils::constants::JWT_COOKIE_NAME;
-use reqwest::{cookie::CookieStore, Url};
+use reqwest::Url;
 
 use crate::helpers::{get_random_email, TestApp};
 
@@ -46,6 +46,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -66,7 +67,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
     let url = Url::parse(&app.address).expect("Failed to parse URL");
     
     app.cookie_jar.add_cookie_str(
-        &auth_cookie.value().to_string(),
+        auth_cookie.value(),
         &url,
     );
 
@@ -100,6 +101,7 @@ async fn should_return_400_if_logout_called_twice_in_a_row() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -120,7 +122,7 @@ async fn should_return_400_if_logout_called_twice_in_a_row() {
     let url = Url::parse(&app.address).expect("Failed to parse URL");
     
     app.cookie_jar.add_cookie_str(
-        &auth_cookie.value().to_string(),
+        auth_cookie.value(),
         &url,
     );
     
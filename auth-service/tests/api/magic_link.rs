@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use auth_service::{
+    domain::{Email, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord, RateLimit},
+    routes::{MagicLinkResponse, TwoFactorAuthResponse},
+    utils::{constants::JWT_COOKIE_NAME, rate_limit::RateLimitConfig},
+};
+
+use crate::helpers::{email_code, get_random_email, link_token, TestApp};
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": requires_2fa
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+}
+
+// The token from the `count`th sign-in email sent to `email`
+async fn get_magic_link_token(app: &TestApp, email: &str, count: usize) -> String {
+    link_token(&app.wait_for_email_to(email, "Your sign-in link", count).await)
+}
+
+async fn request_magic_link_token(app: &TestApp, email: &str) -> String {
+    let sent = app
+        .emails_to(email)
+        .await
+        .iter()
+        .filter(|message| message.subject == "Your sign-in link")
+        .count();
+
+    let response = app.post_magic_link(&serde_json::json!({ "email": email })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    get_magic_link_token(app, email, sent + 1).await
+}
+
+#[tokio::test]
+async fn should_return_422_if_malformed_input() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "mail": "test@example.com" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn should_return_400_if_invalid_email() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_not_reveal_whether_account_exists() {
+    let app = TestApp::new().await;
+
+    let existing_email = get_random_email();
+    signup(&app, &existing_email, false).await;
+    let unknown_email = get_random_email();
+
+    let existing = app.post_magic_link(&serde_json::json!({ "email": existing_email })).await;
+    let unknown = app.post_magic_link(&serde_json::json!({ "email": unknown_email })).await;
+
+    assert_eq!(existing.status().as_u16(), 200);
+    assert_eq!(unknown.status().as_u16(), 200);
+    assert_eq!(
+        existing.json::<MagicLinkResponse>().await.unwrap(),
+        unknown.json::<MagicLinkResponse>().await.unwrap()
+    );
+
+    get_magic_link_token(&app, &existing_email, 1).await;
+    assert!(app.last_email_to(&unknown_email, "Your sign-in link").await.is_none());
+}
+
+#[tokio::test]
+async fn should_log_in_with_a_single_use_link() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+    app.verify_email(&email).await;
+
+    let token = request_magic_link_token(&app, &email).await;
+
+    let response = app.get_magic_link_verify(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+
+    let response = app.get_magic_link_verify(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_verify_email_of_unverified_accounts() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let token = request_magic_link_token(&app, &email).await;
+    assert_eq!(app.get_magic_link_verify(&token).await.status().as_u16(), 200);
+
+    // Following the link proved the address, so the password works now too
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_require_2fa_if_enabled() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup(&app, &email, true).await;
+    app.verify_email(&email).await;
+
+    let token = request_magic_link_token(&app, &email).await;
+
+    let response = app.get_magic_link_verify(&token).await;
+    assert_eq!(response.status().as_u16(), 206);
+    assert!(response.cookies().all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let message = app
+        .last_email_to(&email, "Your login code")
+        .await
+        .expect("No 2FA code sent");
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": email_code(&message),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_only_accept_latest_link() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+    app.verify_email(&email).await;
+
+    let first_token = request_magic_link_token(&app, &email).await;
+    let second_token = request_magic_link_token(&app, &email).await;
+    assert_ne!(first_token, second_token);
+
+    assert_eq!(app.get_magic_link_verify(&first_token).await.status().as_u16(), 401);
+    assert_eq!(app.get_magic_link_verify(&second_token).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_401_if_token_expired_or_invalid() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let expired_token = OneTimeToken::default();
+    let password_reset_token = OneTimeToken::default();
+    {
+        let mut one_time_token_store = app.one_time_token_store.write().await;
+        one_time_token_store.tokens.insert(
+            expired_token.clone(),
+            OneTimeTokenRecord {
+                email: Email::parse(&email).unwrap(),
+                purpose: OneTimeTokenPurpose::MagicLinkLogin,
+                expires_at: chrono::Utc::now() - chrono::Duration::seconds(1),
+                email_change: None,
+            },
+        );
+        // Tokens issued for something else don't log anyone in
+        one_time_token_store.tokens.insert(
+            password_reset_token.clone(),
+            OneTimeTokenRecord {
+                email: Email::parse(&email).unwrap(),
+                purpose: OneTimeTokenPurpose::PasswordReset,
+                expires_at: chrono::Utc::now() + chrono::Duration::minutes(5),
+                email_change: None,
+            },
+        );
+    }
+
+    let test_cases = [
+        expired_token.as_ref().to_owned(),
+        password_reset_token.as_ref().to_owned(),
+        OneTimeToken::default().as_ref().to_owned(),
+        "invalid".to_owned(),
+    ];
+
+    for token in test_cases.iter() {
+        let response = app.get_magic_link_verify(token).await;
+        assert_eq!(response.status().as_u16(), 401, "Failed for token: {}", token);
+    }
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_links_for_one_email() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: RateLimit { capacity: 100, period: Duration::from_secs(60) },
+        per_email: RateLimit { capacity: 3, period: Duration::from_secs(60) },
+    })
+    .await;
+    let email = get_random_email();
+
+    for _ in 0..3 {
+        let response = app.post_magic_link(&serde_json::json!({ "email": email })).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_magic_link(&serde_json::json!({ "email": email })).await;
+    assert_eq!(response.status().as_u16(), 429);
+}
@@ -1,7 +1,23 @@
 mod helpers;
 mod routes;
+mod account;
+mod account_lockout;
+mod email_outbox;
+mod jwks;
 mod login;
 mod logout;
+mod magic_link;
+mod metrics;
+mod password_reset;
+mod rate_limit;
+mod recovery_codes;
+mod refresh;
+mod sessions;
+mod signing_keys;
 mod signup;
+mod totp;
+mod users;
 mod verify_2fa;
-mod verify_token;
\ No newline at end of file
+mod verify_email;
+mod verify_token;
+mod webauthn;
\ No newline at end of file
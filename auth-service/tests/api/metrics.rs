@@ -0,0 +1,63 @@
+use crate::helpers::{get_random_email, TestApp, ADMIN_API_KEY};
+
+fn banned_tokens_gauge(body: &str) -> u64 {
+    body.lines()
+        .find_map(|line| line.strip_prefix("auth_banned_tokens "))
+        .expect("auth_banned_tokens gauge missing")
+        .parse()
+        .expect("auth_banned_tokens is not a number")
+}
+
+#[tokio::test]
+async fn should_return_401_without_valid_admin_key() {
+    let app = TestApp::new().await;
+
+    for admin_key in ["wrong-key", ""] {
+        let response = app.get_metrics(admin_key).await;
+        assert_eq!(response.status().as_u16(), 401, "Failed for key: {}", admin_key);
+    }
+}
+
+#[tokio::test]
+async fn should_report_banned_token_count() {
+    let app = TestApp::new().await;
+
+    let response = app.get_metrics(ADMIN_API_KEY).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    assert_eq!(banned_tokens_gauge(&response.text().await.unwrap()), 0);
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    // Logging out bans the session's tokens by their sid
+    assert_eq!(app.logout().await.status().as_u16(), 200);
+
+    let body = app.get_metrics(ADMIN_API_KEY).await.text().await.unwrap();
+    assert_eq!(banned_tokens_gauge(&body), 1);
+}
+
+#[tokio::test]
+async fn should_report_email_outbox_gauges() {
+    let app = TestApp::new().await;
+
+    let body = app.get_metrics(ADMIN_API_KEY).await.text().await.unwrap();
+    assert!(body.lines().any(|line| line == "auth_email_outbox_queued 0"));
+    assert!(body.lines().any(|line| line == "auth_email_outbox_dead_letters 0"));
+}
@@ -0,0 +1,227 @@
+use auth_service::{
+    domain::{Email, OneTimeToken, OneTimeTokenPurpose, OneTimeTokenRecord},
+    routes::PasswordResetResponse,
+    utils::constants::JWT_COOKIE_NAME,
+};
+
+use crate::helpers::{get_random_email, link_token, TestApp};
+
+// Returns the JWT from the login
+async fn signup_and_login(app: &TestApp, email: &str) -> String {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(email).await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let jwt = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie returned")
+        .value()
+        .to_owned();
+    jwt
+}
+
+// The token from the `count`th reset email sent to `email`
+async fn get_reset_token(app: &TestApp, email: &str, count: usize) -> String {
+    link_token(&app.wait_for_email_to(email, "Reset your password", count).await)
+}
+
+#[tokio::test]
+async fn should_return_422_if_malformed_input() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_password_reset_request(&serde_json::json!({ "mail": "test@example.com" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 422);
+
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({ "token": "token" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn should_return_400_if_invalid_email() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_password_reset_request(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_not_reveal_whether_account_exists() {
+    let app = TestApp::new().await;
+
+    let existing_email = get_random_email();
+    signup_and_login(&app, &existing_email).await;
+
+    let unknown_email = get_random_email();
+
+    let existing = app
+        .post_password_reset_request(&serde_json::json!({ "email": existing_email }))
+        .await;
+    let unknown = app
+        .post_password_reset_request(&serde_json::json!({ "email": unknown_email }))
+        .await;
+
+    assert_eq!(existing.status().as_u16(), 200);
+    assert_eq!(unknown.status().as_u16(), 200);
+    assert_eq!(
+        existing.json::<PasswordResetResponse>().await.unwrap(),
+        unknown.json::<PasswordResetResponse>().await.unwrap()
+    );
+
+    get_reset_token(&app, &existing_email, 1).await;
+    assert!(app.last_email_to(&unknown_email, "Reset your password").await.is_none());
+}
+
+#[tokio::test]
+async fn should_reset_password_and_revoke_sessions() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    let jwt = signup_and_login(&app, &email).await;
+
+    let response = app
+        .post_password_reset_request(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = get_reset_token(&app, &email, 1).await;
+
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": token,
+            "new_password": "new_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Neither the JWT nor the refresh token from the earlier login work any more
+    assert_eq!(app.verify_token(jwt).await.status().as_u16(), 401);
+    assert_eq!(app.post_refresh().await.status().as_u16(), 401);
+
+    let old_login = serde_json::json!({ "email": email, "password": "password123" });
+    assert_eq!(app.post_login(&old_login).await.status().as_u16(), 401);
+
+    let new_login = serde_json::json!({ "email": email, "password": "new_password123" });
+    assert_eq!(app.post_login(&new_login).await.status().as_u16(), 200);
+
+    // Reset tokens are single-use
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": token,
+            "new_password": "another_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_only_accept_latest_reset_token() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup_and_login(&app, &email).await;
+
+    let body = serde_json::json!({ "email": email });
+    app.post_password_reset_request(&body).await;
+    let first_token = get_reset_token(&app, &email, 1).await;
+
+    app.post_password_reset_request(&body).await;
+    let second_token = get_reset_token(&app, &email, 2).await;
+    assert_ne!(first_token, second_token);
+
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": first_token,
+            "new_password": "new_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": second_token,
+            "new_password": "new_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_401_if_token_expired_or_invalid() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup_and_login(&app, &email).await;
+
+    let expired_token = OneTimeToken::default();
+    app.one_time_token_store.write().await.tokens.insert(
+        expired_token.clone(),
+        OneTimeTokenRecord {
+            email: Email::parse(&email).unwrap(),
+            purpose: OneTimeTokenPurpose::PasswordReset,
+            expires_at: chrono::Utc::now() - chrono::Duration::seconds(1),
+            email_change: None,
+        },
+    );
+
+    let test_cases = [
+        expired_token.as_ref().to_owned(),
+        OneTimeToken::default().as_ref().to_owned(),
+        "invalid".to_owned(),
+    ];
+
+    for token in test_cases.iter() {
+        let response = app
+            .post_password_reset_confirm(&serde_json::json!({
+                "token": token,
+                "new_password": "new_password123",
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401, "Failed for token: {}", token);
+    }
+}
+
+#[tokio::test]
+async fn should_return_400_if_new_password_invalid() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    signup_and_login(&app, &email).await;
+
+    app.post_password_reset_request(&serde_json::json!({ "email": email }))
+        .await;
+    let token = get_reset_token(&app, &email, 1).await;
+
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": token,
+            "new_password": "short",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // The token wasn't used up by the failed attempt
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "token": token,
+            "new_password": "new_password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use auth_service::{domain::RateLimit, utils::rate_limit::RateLimitConfig, ErrorResponse};
+use reqwest::header::RETRY_AFTER;
+
+use crate::helpers::{get_random_email, TestApp};
+
+fn limit(capacity: u32) -> RateLimit {
+    RateLimit {
+        capacity,
+        period: Duration::from_secs(60),
+    }
+}
+
+fn login_body(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "email": email,
+        "password": "wrong-password",
+    })
+}
+
+async fn assert_rate_limited(response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 429);
+
+    let retry_after: u64 = response
+        .headers()
+        .get(RETRY_AFTER)
+        .expect("No Retry-After header")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    // One request in a 60 second window of 3 comes back every 20 seconds
+    assert!(retry_after > 0 && retry_after <= 20);
+
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Too many requests, try again later"
+    );
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_logins_for_one_email() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: limit(100),
+        per_email: limit(3),
+    })
+    .await;
+    let email = get_random_email();
+
+    for _ in 0..3 {
+        let response = app.post_login(&login_body(&email)).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    assert_rate_limited(app.post_login(&login_body(&email)).await).await;
+
+    // Changing the case of the address doesn't get around the limit
+    assert_rate_limited(app.post_login(&login_body(&email.to_uppercase())).await).await;
+
+    // Other accounts aren't affected
+    let response = app.post_login(&login_body(&get_random_email())).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_requests_from_one_ip() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: limit(3),
+        per_email: limit(100),
+    })
+    .await;
+
+    for _ in 0..3 {
+        let response = app.post_login(&login_body(&get_random_email())).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    assert_rate_limited(app.post_login(&login_body(&get_random_email())).await).await;
+
+    // Each route has its own buckets
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "password123",
+            "requires_2fa": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_2fa_attempts_for_one_email() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: limit(100),
+        per_email: limit(3),
+    })
+    .await;
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "loginAttemptId": uuid::Uuid::new_v4().to_string(),
+        "2FACode": "123456"
+    });
+
+    for _ in 0..3 {
+        let response = app.post_verify_2fa(&body).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    assert_rate_limited(app.post_verify_2fa(&body).await).await;
+}
+
+#[tokio::test]
+async fn should_count_requests_without_an_email_against_the_ip() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: limit(3),
+        per_email: limit(100),
+    })
+    .await;
+
+    for _ in 0..3 {
+        let response = app.post_signup(&serde_json::json!({ "password": "password123" })).await;
+        assert_eq!(response.status().as_u16(), 422);
+    }
+    assert_rate_limited(app.post_signup(&serde_json::json!({ "password": "password123" })).await).await;
+}
@@ -0,0 +1,191 @@
+use auth_service::{
+    routes::{RecoveryCodesResponse, SignupResponse, TwoFactorAuthResponse, Verify2FAResponse},
+    utils::constants::{JWT_COOKIE_NAME, RECOVERY_CODE_COUNT},
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Signs up a 2FA user and returns the recovery codes handed out at signup
+async fn signup_with_2fa(app: &TestApp, email: &str) -> Vec<String> {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires_2fa": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(email).await;
+
+    response
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("No recovery codes in the signup response")
+}
+
+async fn login(app: &TestApp, email: &str) -> TwoFactorAuthResponse {
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+}
+
+async fn verify_with(app: &TestApp, email: &str, login_attempt_id: &str, code: &str) -> reqwest::Response {
+    app.post_verify_2fa(&serde_json::json!({
+        "email": email,
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn should_log_in_once_with_a_recovery_code() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let recovery_codes = signup_with_2fa(&app, &email).await;
+
+    let attempt = login(&app, &email).await;
+    assert_eq!(attempt.warning, None);
+
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.cookies().any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+    assert_eq!(
+        response.json::<Verify2FAResponse>().await.unwrap(),
+        Verify2FAResponse { warning: None }
+    );
+
+    // The same code doesn't work a second time
+    let attempt = login(&app, &email).await;
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_accept_recovery_codes_without_dashes_or_case() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let recovery_codes = signup_with_2fa(&app, &email).await;
+
+    let attempt = login(&app, &email).await;
+    let typed = recovery_codes[1].replace('-', "").to_uppercase();
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &typed).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_not_spend_a_recovery_code_on_a_wrong_login_attempt_id() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let recovery_codes = signup_with_2fa(&app, &email).await;
+
+    let attempt = login(&app, &email).await;
+    let wrong_attempt_id = uuid::Uuid::new_v4().to_string();
+    let response = verify_with(&app, &email, &wrong_attempt_id, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_warn_when_few_recovery_codes_remain() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let recovery_codes = signup_with_2fa(&app, &email).await;
+
+    // Use up all but three of them
+    for code in &recovery_codes[..RECOVERY_CODE_COUNT - 3] {
+        let attempt = login(&app, &email).await;
+        let response = verify_with(&app, &email, &attempt.login_attempt_id, code).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let attempt = login(&app, &email).await;
+    let warning = attempt.warning.expect("No warning in the login response");
+    assert!(warning.contains('3'));
+
+    let response = verify_with(
+        &app,
+        &email,
+        &attempt.login_attempt_id,
+        &recovery_codes[RECOVERY_CODE_COUNT - 3],
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.json::<Verify2FAResponse>().await.unwrap();
+    assert!(body.warning.expect("No warning in the verify-2fa response").contains('2'));
+}
+
+#[tokio::test]
+async fn should_return_400_if_not_logged_in() {
+    let app = TestApp::new().await;
+
+    let response = app.post_regenerate_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_400_if_2fa_is_off() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires_2fa": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&email).await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_regenerate_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_replace_old_codes_when_regenerating() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let old_codes = signup_with_2fa(&app, &email).await;
+
+    let attempt = login(&app, &email).await;
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &old_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_regenerate_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_codes = response
+        .json::<RecoveryCodesResponse>()
+        .await
+        .expect("Could not deserialize response body to RecoveryCodesResponse")
+        .recovery_codes;
+    assert_eq!(new_codes.len(), RECOVERY_CODE_COUNT);
+
+    let attempt = login(&app, &email).await;
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &old_codes[1]).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let attempt = login(&app, &email).await;
+    let response = verify_with(&app, &email, &attempt.login_attempt_id, &new_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,140 @@
+use auth_service::utils::constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME};
+use reqwest::Url;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup_and_login(app: &TestApp) -> reqwest::Response {
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    response
+}
+
+fn get_cookie(response: &reqwest::Response, name: &str) -> String {
+    response
+        .cookies()
+        .find(|cookie| cookie.name() == name)
+        .unwrap_or_else(|| panic!("No {} cookie found", name))
+        .value()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn should_return_400_if_refresh_cookie_missing() {
+    let app = TestApp::new().await;
+
+    let response = app.post_refresh().await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_401_if_invalid_refresh_token() {
+    let app = TestApp::new().await;
+
+    let url = Url::parse(&app.address).expect("Failed to parse URL");
+    app.cookie_jar.add_cookie_str(
+        &format!("{}=invalid; HttpOnly; SameSite=Lax; Secure; Path=/", REFRESH_TOKEN_COOKIE_NAME),
+        &url,
+    );
+
+    let response = app.post_refresh().await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_set_refresh_cookie_on_login() {
+    let app = TestApp::new().await;
+
+    let response = signup_and_login(&app).await;
+
+    assert!(!get_cookie(&response, REFRESH_TOKEN_COOKIE_NAME).is_empty());
+}
+
+#[tokio::test]
+async fn should_return_200_and_rotate_tokens() {
+    let app = TestApp::new().await;
+
+    let login_response = signup_and_login(&app).await;
+    let first_refresh_token = get_cookie(&login_response, REFRESH_TOKEN_COOKIE_NAME);
+
+    let response = app.post_refresh().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(!get_cookie(&response, JWT_COOKIE_NAME).is_empty());
+    assert_ne!(get_cookie(&response, REFRESH_TOKEN_COOKIE_NAME), first_refresh_token);
+
+    // The rotated token keeps working
+    let response = app.post_refresh().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_revoke_token_family_on_reuse() {
+    let app = TestApp::new().await;
+
+    let login_response = signup_and_login(&app).await;
+    let first_refresh_token = get_cookie(&login_response, REFRESH_TOKEN_COOKIE_NAME);
+
+    let response = app.post_refresh().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let second_refresh_token = get_cookie(&response, REFRESH_TOKEN_COOKIE_NAME);
+
+    // Replay the already rotated token, as an attacker holding a stolen copy would
+    let url = Url::parse(&app.address).expect("Failed to parse URL");
+    app.cookie_jar.add_cookie_str(
+        &format!("{}={}; HttpOnly; SameSite=Lax; Secure; Path=/", REFRESH_TOKEN_COOKIE_NAME, first_refresh_token),
+        &url,
+    );
+
+    let response = app.post_refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // The legitimate holder's newer token has been revoked as well
+    app.cookie_jar.add_cookie_str(
+        &format!("{}={}; HttpOnly; SameSite=Lax; Secure; Path=/", REFRESH_TOKEN_COOKIE_NAME, second_refresh_token),
+        &url,
+    );
+
+    let response = app.post_refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_401_after_logout() {
+    let app = TestApp::new().await;
+
+    let login_response = signup_and_login(&app).await;
+    let refresh_token = get_cookie(&login_response, REFRESH_TOKEN_COOKIE_NAME);
+
+    let response = app.logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let url = Url::parse(&app.address).expect("Failed to parse URL");
+    app.cookie_jar.add_cookie_str(
+        &format!("{}={}; HttpOnly; SameSite=Lax; Secure; Path=/", REFRESH_TOKEN_COOKIE_NAME, refresh_token),
+        &url,
+    );
+
+    let response = app.post_refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,189 @@
+use auth_service::{
+    routes::SessionsResponse,
+    utils::constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+};
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup(app: &TestApp) -> String {
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    random_email
+}
+
+// Logs in on a new session and returns its JWT and refresh token
+async fn login(app: &TestApp, email: &str) -> (String, String) {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let cookie = |name: &str| {
+        response
+            .cookies()
+            .find(|cookie| cookie.name() == name)
+            .unwrap_or_else(|| panic!("No {} cookie found", name))
+            .value()
+            .to_owned()
+    };
+    (cookie(JWT_COOKIE_NAME), cookie(REFRESH_TOKEN_COOKIE_NAME))
+}
+
+async fn get_sessions(app: &TestApp) -> SessionsResponse {
+    let response = app.get_sessions().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<SessionsResponse>()
+        .await
+        .expect("Could not deserialize response body to SessionsResponse")
+}
+
+async fn assert_token_revoked(app: &TestApp, token: String) {
+    assert_eq!(app.verify_token(token).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let app = TestApp::new().await;
+
+    assert_eq!(app.get_sessions().await.status().as_u16(), 400);
+    assert_eq!(app.delete_sessions().await.status().as_u16(), 400);
+    assert_eq!(app.delete_session(&Uuid::new_v4().to_string()).await.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_list_sessions_and_mark_the_current_one() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+
+    login(&app, &email).await;
+    login(&app, &email).await;
+
+    let sessions = get_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 2);
+    assert!(sessions[0].created_at <= sessions[1].created_at);
+    assert!(!sessions[0].current);
+    assert!(sessions[1].current);
+    for session in &sessions {
+        assert_eq!(session.ip_address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(session.last_seen_at, session.created_at);
+    }
+
+    // Other users' sessions aren't listed
+    let other_email = signup(&app).await;
+    login(&app, &other_email).await;
+    assert_eq!(get_sessions(&app).await.sessions.len(), 1);
+}
+
+#[tokio::test]
+async fn should_update_last_seen_on_refresh() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    login(&app, &email).await;
+
+    assert_eq!(app.post_refresh().await.status().as_u16(), 200);
+
+    // The refreshed tokens still belong to the same session
+    let sessions = get_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+    assert!(sessions[0].last_seen_at > sessions[0].created_at);
+}
+
+#[tokio::test]
+async fn should_revoke_another_session() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+
+    let (first_token, first_refresh_token) = login(&app, &email).await;
+    let (current_token, _) = login(&app, &email).await;
+
+    let first_session = get_sessions(&app).await.sessions[0].id;
+    let response = app.delete_session(&first_session.to_string()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_token_revoked(&app, first_token).await;
+    assert_eq!(app.verify_token(current_token).await.status().as_u16(), 200);
+
+    let sessions = get_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+
+    // The revoked session can't be refreshed back to life
+    let url = Url::parse(&app.address).expect("Failed to parse URL");
+    app.cookie_jar.add_cookie_str(
+        &format!("{}={}; HttpOnly; SameSite=Lax; Path=/", REFRESH_TOKEN_COOKIE_NAME, first_refresh_token),
+        &url,
+    );
+    assert_eq!(app.post_refresh().await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_revoke_the_current_session() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let (token, _) = login(&app, &email).await;
+
+    let current_session = get_sessions(&app).await.sessions[0].id;
+    let response = app.delete_session(&current_session.to_string()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The cookies are cleared, as on logout
+    let cleared = response
+        .cookies()
+        .filter(|cookie| cookie.name() == JWT_COOKIE_NAME || cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .all(|cookie| cookie.value().is_empty());
+    assert!(cleared);
+    assert_token_revoked(&app, token).await;
+}
+
+#[tokio::test]
+async fn should_return_404_for_unknown_or_other_users_session() {
+    let app = TestApp::new().await;
+
+    let email = signup(&app).await;
+    let (other_users_token, _) = login(&app, &email).await;
+    let other_users_session = get_sessions(&app).await.sessions[0].id;
+
+    let other_email = signup(&app).await;
+    login(&app, &other_email).await;
+
+    let response = app.delete_session(&other_users_session.to_string()).await;
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(app.verify_token(other_users_token).await.status().as_u16(), 200);
+
+    let response = app.delete_session(&Uuid::new_v4().to_string()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn should_log_out_everywhere() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+
+    let (first_token, _) = login(&app, &email).await;
+    let (second_token, _) = login(&app, &email).await;
+
+    let response = app.delete_sessions().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_token_revoked(&app, first_token).await;
+    assert_token_revoked(&app, second_token).await;
+    // Cookies were cleared with the sessions
+    assert_eq!(app.get_sessions().await.status().as_u16(), 400);
+
+    login(&app, &email).await;
+    assert_eq!(get_sessions(&app).await.sessions.len(), 1);
+}
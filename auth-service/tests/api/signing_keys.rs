@@ -0,0 +1,231 @@
+use auth_service::domain::{Email, SigningKeyRecord};
+use auth_service::routes::SigningKeyResponse;
+use auth_service::utils::{
+    auth::generate_auth_cookie, constants::JWT_COOKIE_NAME, keyring::JwtKeyring,
+    signing_key::JwtSigningKey,
+};
+use jsonwebtoken::{decode_header, jwk::JwkSet};
+
+use crate::helpers::{get_random_email, TestApp, ADMIN_API_KEY};
+
+async fn signup_and_login(app: &TestApp, email: &str) -> String {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(email).await;
+
+    login(app, email).await
+}
+
+async fn login(app: &TestApp, email: &str) -> String {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+    token
+}
+
+#[tokio::test]
+async fn should_return_401_without_valid_admin_key() {
+    let app = TestApp::new().await;
+
+    let response = app.get_signing_keys("wrong-key").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_signing_key("wrong-key", &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_retire_signing_key("", &serde_json::json!({ "kid": "kid" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_keep_old_tokens_valid_until_key_is_retired() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+
+    let old_token = signup_and_login(&app, &email).await;
+    let old_kid = decode_header(&old_token).unwrap().kid.unwrap();
+
+    let response = app
+        .post_signing_key(ADMIN_API_KEY, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let new_key: SigningKeyResponse = response.json().await.unwrap();
+
+    // New logins are signed by the newest key, and both keys are published
+    let new_token = login(&app, &email).await;
+    assert_eq!(decode_header(&new_token).unwrap().kid, Some(new_key.kid.clone()));
+
+    let jwks: JwkSet = app.get_jwks().await.json().await.unwrap();
+    assert_eq!(jwks.keys.len(), 2);
+
+    for token in [&old_token, &new_token] {
+        let response = app
+            .post_verify_token(&serde_json::json!({ "token": token }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = app
+        .post_retire_signing_key(ADMIN_API_KEY, &serde_json::json!({ "kid": old_kid }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": old_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": new_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let jwks: JwkSet = app.get_jwks().await.json().await.unwrap();
+    assert_eq!(jwks.keys.len(), 1);
+    assert!(jwks.find(&new_key.kid).is_some());
+}
+
+#[tokio::test]
+async fn should_not_sign_with_key_before_not_before() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+
+    let not_before = chrono::Utc::now() + chrono::Duration::hours(1);
+    let response = app
+        .post_signing_key(ADMIN_API_KEY, &serde_json::json!({ "not_before": not_before }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let upcoming_key: SigningKeyResponse = response.json().await.unwrap();
+
+    let token = signup_and_login(&app, &email).await;
+    assert_ne!(decode_header(&token).unwrap().kid, Some(upcoming_key.kid.clone()));
+
+    // The upcoming key is already published so verifiers can cache it
+    let jwks: JwkSet = app.get_jwks().await.json().await.unwrap();
+    assert!(jwks.find(&upcoming_key.kid).is_some());
+
+    let keys: Vec<SigningKeyResponse> = app.get_signing_keys(ADMIN_API_KEY).await.json().await.unwrap();
+    assert_eq!(keys.len(), 2);
+}
+
+#[tokio::test]
+async fn should_keep_added_and_retired_keys_across_restarts() {
+    let app = TestApp::new().await;
+
+    let keys: Vec<SigningKeyResponse> = app.get_signing_keys(ADMIN_API_KEY).await.json().await.unwrap();
+    let old_kid = keys[0].kid.clone();
+
+    let response = app
+        .post_signing_key(ADMIN_API_KEY, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let new_key: SigningKeyResponse = response.json().await.unwrap();
+
+    let response = app
+        .post_retire_signing_key(ADMIN_API_KEY, &serde_json::json!({ "kid": old_kid }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A restarted service builds its keyring from the same store
+    let keyring = JwtKeyring::load(
+        JwtSigningKey::generate().unwrap(),
+        &mut *app.signing_key_store.write().await,
+    )
+    .await
+    .unwrap();
+    let now = chrono::Utc::now();
+    assert!(keyring.verification_key(&new_key.kid, now).is_some());
+    assert!(keyring.verification_key(&old_kid, now).is_none());
+}
+
+#[tokio::test]
+async fn should_return_400_for_invalid_key_operations() {
+    let app = TestApp::new().await;
+
+    let keys: Vec<SigningKeyResponse> = app.get_signing_keys(ADMIN_API_KEY).await.json().await.unwrap();
+    let current_kid = keys[0].kid.clone();
+
+    // Retiring the only key would leave nothing to sign with
+    let response = app
+        .post_retire_signing_key(ADMIN_API_KEY, &serde_json::json!({ "kid": current_kid }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let test_cases = [
+        serde_json::json!({ "private_key_pem": "not a key" }),
+        serde_json::json!({
+            "not_before": "2030-01-02T00:00:00Z",
+            "retire_after": "2030-01-01T00:00:00Z"
+        }),
+    ];
+    for test_case in test_cases.iter() {
+        let response = app.post_signing_key(ADMIN_API_KEY, test_case).await;
+        assert_eq!(response.status().as_u16(), 400, "Failed for input: {:?}", test_case);
+    }
+
+    let response = app
+        .post_retire_signing_key(ADMIN_API_KEY, &serde_json::json!({ "kid": "unknown" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_accept_tokens_signed_by_a_key_another_replica_added() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    signup_and_login(&app, &email).await;
+
+    // Another replica shares the store, so its new key only reaches us from there
+    let new_key = JwtSigningKey::generate().unwrap();
+    let new_kid = new_key.kid().to_owned();
+    app.signing_key_store
+        .write()
+        .await
+        .add_key(SigningKeyRecord {
+            kid: new_kid.clone(),
+            private_key_pem: new_key.to_pem(),
+            not_before: chrono::Utc::now(),
+            retire_after: None,
+        })
+        .await
+        .unwrap();
+
+    let user = app
+        .user_store
+        .read()
+        .await
+        .get_user(&Email::parse(&email).unwrap())
+        .await
+        .unwrap();
+    let token = generate_auth_cookie(&user, &uuid::Uuid::new_v4(), &JwtKeyring::new(new_key))
+        .unwrap()
+        .value()
+        .to_owned();
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Logins on this replica now sign with it too
+    let token = login(&app, &email).await;
+    assert_eq!(decode_header(&token).unwrap().kid, Some(new_kid));
+}
@@ -1,4 +1,4 @@
-use auth_service::{routes::SignupResponse, ErrorResponse};
+use auth_service::{routes::SignupResponse, utils::constants::RECOVERY_CODE_COUNT, ErrorResponse};
 
 use crate::helpers::{get_random_email, TestApp};
 
@@ -51,18 +51,17 @@ async fn should_return_201_if_valid_input() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    let expected_response = SignupResponse {
-        message: "User created successfully!".to_owned(),
-    };
+    let body = response
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to UserBody");
 
     // Assert that we are getting the correct response body!
-    assert_eq!(
-        response
-            .json::<SignupResponse>()
-            .await
-            .expect("Could not deserialize response body to UserBody"),
-        expected_response
-    );
+    assert_eq!(body.message, "User created successfully!");
+
+    // 2FA accounts get their recovery codes up front
+    let recovery_codes = body.recovery_codes.expect("No recovery codes in the response");
+    assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
 }
 
 #[tokio::test]
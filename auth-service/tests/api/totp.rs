@@ -0,0 +1,227 @@
+use auth_service::{
+    domain::{Email, TwoFACodeStore, TwoFactorMethod},
+    routes::{TotpEnrollmentResponse, TwoFactorAuthResponse},
+    utils::{
+        constants::{JWT_COOKIE_NAME, TOTP_STEP_SECONDS},
+        totp::{current_totp_code, totp_code_at},
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::Url;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup_and_login(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(email).await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+}
+
+async fn enroll(app: &TestApp) -> TotpEnrollmentResponse {
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<TotpEnrollmentResponse>()
+        .await
+        .expect("Could not deserialize response body to TotpEnrollmentResponse")
+}
+
+// A code that's guaranteed not to be the right one
+fn wrong_code(code: &str) -> String {
+    code.chars()
+        .map(|c| char::from_digit((c.to_digit(10).unwrap() + 5) % 10, 10).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn should_return_400_or_401_without_valid_auth_cookie() {
+    let app = TestApp::new().await;
+
+    assert_eq!(app.post_totp_enroll().await.status().as_u16(), 400);
+
+    app.cookie_jar.add_cookie_str(
+        &format!("{}=invalid; HttpOnly; SameSite=Lax; Secure; Path=/", JWT_COOKIE_NAME),
+        &Url::parse("http://127.0.0.1").expect("Failed to parse URL"),
+    );
+    assert_eq!(app.post_totp_enroll().await.status().as_u16(), 401);
+    assert_eq!(
+        app.post_totp_confirm(&serde_json::json!({ "code": "123456", "password": "password123" })).await.status().as_u16(),
+        401
+    );
+}
+
+#[tokio::test]
+async fn should_return_otpauth_uri_and_qr_code() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+
+    let enrollment = enroll(&app).await;
+
+    assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+    assert!(enrollment
+        .otpauth_uri
+        .contains(&format!("secret={}", enrollment.secret)));
+
+    let png = STANDARD.decode(&enrollment.qr_code_png).expect("QR code isn't base64");
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+}
+
+#[tokio::test]
+async fn should_return_400_if_confirmed_before_enrolling() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": "123456", "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_401_if_password_incorrect_when_confirming() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+    let email = Email::parse(&random_email).unwrap();
+
+    let enrollment = enroll(&app).await;
+    let code = current_totp_code(&enrollment.secret, &email).unwrap();
+
+    // A session alone isn't enough to swap in an authenticator app
+    for password in ["wrong_password", ""] {
+        let response = app
+            .post_totp_confirm(&serde_json::json!({ "code": code, "password": password }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401, "Failed for password: {}", password);
+    }
+
+    // The code wasn't used up by the refused attempts
+    let response = app.post_totp_confirm(&serde_json::json!({ "code": code, "password": "password123" })).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_require_totp_after_confirming() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+    let email = Email::parse(&random_email).unwrap();
+
+    let enrollment = enroll(&app).await;
+    let code = current_totp_code(&enrollment.secret, &email).unwrap();
+
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": wrong_code(&code), "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // Logins don't ask for TOTP until enrollment is confirmed
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    let response = app.post_totp_confirm(&serde_json::json!({ "code": code, "password": "password123" })).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Enrolling again would replace the secret the authenticator app holds
+    assert_eq!(app.post_totp_enroll().await.status().as_u16(), 409);
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(response.method, TwoFactorMethod::Totp);
+
+    let verify_body = |code: &str| {
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": response.login_attempt_id,
+            "2FACode": code,
+        })
+    };
+
+    // The code held by the 2FA store is never sent to TOTP users and isn't accepted
+    let (_, stored_code) = app.two_fa_code_store.read().await.get_code(&app.user_id(&random_email).await).await.unwrap();
+    let response = app.post_verify_2fa(&verify_body(stored_code.as_ref())).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // Confirming used up the current code, and the next one is accepted a step early
+    let code = next_totp_code(&enrollment.secret, &email);
+    let response = app.post_verify_2fa(&verify_body(&code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+}
+
+fn next_totp_code(secret: &str, email: &Email) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    totp_code_at(secret, email, now + TOTP_STEP_SECONDS).unwrap()
+}
+
+#[tokio::test]
+async fn should_not_accept_a_totp_code_twice() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+    let email = Email::parse(&random_email).unwrap();
+
+    let enrollment = enroll(&app).await;
+    let code = current_totp_code(&enrollment.secret, &email).unwrap();
+    let response = app.post_totp_confirm(&serde_json::json!({ "code": code, "password": "password123" })).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The code used to confirm enrollment can't log in
+    let response = login_with_code(&app, &random_email, &code).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let code = next_totp_code(&enrollment.secret, &email);
+    let response = login_with_code(&app, &random_email, &code).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = login_with_code(&app, &random_email, &code).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn login_with_code(app: &TestApp, email: &str, code: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response = response.json::<TwoFactorAuthResponse>().await.unwrap();
+
+    app.post_verify_2fa(&serde_json::json!({
+        "email": email,
+        "loginAttemptId": response.login_attempt_id,
+        "2FACode": code,
+    }))
+    .await
+}
@@ -0,0 +1,114 @@
+use auth_service::{domain::Email, utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+
+use crate::helpers::{get_random_email, TestApp, ADMIN_API_KEY};
+
+async fn signup_and_login(app: &TestApp) -> (String, String) {
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let response = app.login(random_email.clone(), "password123".to_owned()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    (random_email, token)
+}
+
+async fn assert_account_disabled(response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Account disabled"
+    );
+}
+
+#[tokio::test]
+async fn should_return_401_without_valid_admin_key() {
+    let app = TestApp::new().await;
+
+    let body = serde_json::json!({ "email": get_random_email() });
+    assert_eq!(app.post_disable_user("wrong-key", &body).await.status().as_u16(), 401);
+    assert_eq!(app.post_enable_user("wrong-key", &body).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_400_for_unknown_or_invalid_email() {
+    let app = TestApp::new().await;
+
+    for email in [get_random_email(), "not-an-email".to_owned()] {
+        let body = serde_json::json!({ "email": email });
+        assert_eq!(app.post_disable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 400);
+        assert_eq!(app.post_enable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn should_log_a_disabled_user_out_everywhere() {
+    let app = TestApp::new().await;
+
+    let (email, token) = signup_and_login(&app).await;
+
+    let body = serde_json::json!({ "email": email });
+    assert_eq!(app.post_disable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 200);
+
+    // Tokens issued before are rejected, and the session can't be refreshed
+    assert_eq!(app.verify_token(token).await.status().as_u16(), 401);
+    assert_eq!(app.post_refresh().await.status().as_u16(), 401);
+    assert_eq!(app.get_sessions().await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_refuse_login_until_enabled_again() {
+    let app = TestApp::new().await;
+
+    let (email, _) = signup_and_login(&app).await;
+
+    let body = serde_json::json!({ "email": email });
+    assert_eq!(app.post_disable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 200);
+    // Disabling twice is fine
+    assert_eq!(app.post_disable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 200);
+
+    assert_account_disabled(app.login(email.clone(), "password123".to_owned()).await).await;
+
+    // A wrong password still gets the usual answer, so the flag isn't revealed without it
+    let response = app.login(email.clone(), "wrong-password".to_owned()).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    assert_eq!(app.post_enable_user(ADMIN_API_KEY, &body).await.status().as_u16(), 200);
+
+    let response = app.login(email, "password123".to_owned()).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_refuse_refresh_of_a_disabled_user() {
+    let app = TestApp::new().await;
+
+    let (email, _) = signup_and_login(&app).await;
+
+    // Flag the account directly, without the sessions being ended, as if it had been
+    // disabled while the refresh was in flight
+    app.user_store
+        .write()
+        .await
+        .set_disabled(&Email::parse(&email).unwrap(), true)
+        .await
+        .unwrap();
+
+    assert_account_disabled(app.post_refresh().await).await;
+}
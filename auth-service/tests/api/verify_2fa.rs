@@ -1,8 +1,10 @@
-use auth_service::{domain::Email, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME};
-use axum::http::response;
-use reqwest::Url;
+use auth_service::{
+    routes::TwoFactorAuthResponse,
+    utils::constants::{JWT_COOKIE_NAME, TWO_FA_CODE_MAX_ATTEMPTS, TWO_FA_CODE_TTL_SECONDS},
+    ErrorResponse,
+};
 
-use crate::helpers::{TestApp, get_random_email};
+use crate::helpers::{email_code, get_random_email, TestApp};
 
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
@@ -53,6 +55,7 @@ async fn should_return_401_if_incorrect_credentials() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -80,6 +83,7 @@ async fn should_return_401_if_old_code() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -91,23 +95,22 @@ async fn should_return_401_if_old_code() {
     let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
     let first_attempt_id = two_factor_auth_response.login_attempt_id;
 
-    let email_parsed = Email::parse(&random_email).unwrap();
-    let first_code = {
-        let store = app.two_fa_code_store.read().await;
-        let (_, code) = store.get_code(&email_parsed).await.unwrap();
-        code.as_ref().to_string()
-    };
+    let first_code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
 
     let response = app.post_login(&login_body).await;
 
     let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
     let second_attempt_id = two_factor_auth_response.login_attempt_id;
 
-    let second_code = {
-        let store = app.two_fa_code_store.read().await;
-        let (_, code) = store.get_code(&email_parsed).await.unwrap();
-        code.as_ref().to_string()
-    };
+    let second_code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
 
     let response_from_verify_2fa = app.post_verify_2fa(&serde_json::json!({
        "email": random_email,
@@ -127,6 +130,62 @@ async fn should_return_401_if_old_code() {
 
 }
 
+#[tokio::test]
+async fn should_return_401_if_expired_code() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
+    let login_attempt_id = two_factor_auth_response.login_attempt_id;
+
+    let code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
+
+    // Age the stored code past its TTL rather than waiting it out
+    let user_id = app.user_id(&random_email).await;
+    app.two_fa_code_store
+        .write()
+        .await
+        .codes
+        .get_mut(&user_id)
+        .expect("No 2FA code stored")
+        .created_at -= chrono::Duration::seconds(TWO_FA_CODE_TTL_SECONDS + 1);
+
+    let response = app.post_verify_2fa(&serde_json::json!({
+        "email": random_email,
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
+    })).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.json::<ErrorResponse>().await.unwrap().error,
+        "2FA code expired".to_owned()
+    );
+}
+
 #[tokio::test]
 async fn should_return_200_if_correct_code() {
     // Make sure to assert the auth cookie gets set
@@ -143,6 +202,7 @@ async fn should_return_200_if_correct_code() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -154,12 +214,11 @@ async fn should_return_200_if_correct_code() {
     let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
     let first_attempt_id = two_factor_auth_response.login_attempt_id;
 
-    let email_parsed = Email::parse(&random_email).unwrap();
-    let first_code = {
-        let store = app.two_fa_code_store.read().await;
-        let (_, code) = store.get_code(&email_parsed).await.unwrap();
-        code.as_ref().to_string()
-    };
+    let first_code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
 
     let response_from_verify_2fa = app.post_verify_2fa(&serde_json::json!({
        "email": random_email,
@@ -194,6 +253,7 @@ async fn should_return_401_if_same_code_twice() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -205,12 +265,11 @@ async fn should_return_401_if_same_code_twice() {
     let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
     let first_attempt_id = two_factor_auth_response.login_attempt_id;
 
-    let email_parsed = Email::parse(&random_email).unwrap();
-    let first_code = {
-        let store = app.two_fa_code_store.read().await;
-        let (_, code) = store.get_code(&email_parsed).await.unwrap();
-        code.as_ref().to_string()
-    };
+    let first_code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
 
     let response_from_verify_2fa = app.post_verify_2fa(&serde_json::json!({
        "email": random_email,
@@ -234,4 +293,61 @@ async fn should_return_401_if_same_code_twice() {
     })).await;
 
     assert_eq!(response_from_verify_2fa.status().as_u16(), 401);
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn should_return_403_after_too_many_incorrect_codes() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires_2fa": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    let two_factor_auth_response : TwoFactorAuthResponse = response.json().await.unwrap();
+    let login_attempt_id = two_factor_auth_response.login_attempt_id;
+
+    let code = email_code(
+        &app.last_email_to(&random_email, "Your login code")
+            .await
+            .expect("No 2FA code sent"),
+    );
+
+    // Generated codes are always in 100000..1000000, so this never matches
+    for _ in 0..TWO_FA_CODE_MAX_ATTEMPTS {
+        let response = app.post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": "000000"
+        })).await;
+
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Even the correct code is rejected once the attempts are exhausted
+    let response = app.post_verify_2fa(&serde_json::json!({
+        "email": random_email,
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
+    })).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response.json::<ErrorResponse>().await.unwrap().error,
+        "Too many incorrect 2FA attempts".to_owned()
+    );
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use auth_service::{
+    domain::{Email, RateLimit},
+    utils::{auth::generate_email_verification_token, rate_limit::RateLimitConfig},
+    ErrorResponse,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn should_refuse_login_until_email_verified() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Email address not verified".to_owned()
+    );
+
+    // A wrong password doesn't reveal that the account is unverified
+    let wrong_password = serde_json::json!({
+        "email": random_email,
+        "password": "wrong_password",
+    });
+    assert_eq!(app.post_login(&wrong_password).await.status().as_u16(), 401);
+
+    app.verify_email(&random_email).await;
+
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_token_missing() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .http_client
+        .get(format!("{}/verify-email", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_return_401_if_invalid_token() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+    app.verify_email(&random_email).await;
+
+    // An auth JWT is signed by the same key, but isn't a verification token
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    let auth_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == "jwt")
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    // Signed correctly, but for an address that never signed up
+    let unknown_email = Email::parse(&get_random_email()).unwrap();
+    let unknown_token =
+        generate_email_verification_token(&unknown_email, &*app.jwt_keyring.read().await).unwrap();
+
+    for token in ["invalid", auth_token.as_str(), unknown_token.as_str()] {
+        let response = app.get_verify_email(token).await;
+        assert_eq!(response.status().as_u16(), 401, "Failed for token: {}", token);
+    }
+}
+
+#[tokio::test]
+async fn should_return_422_if_malformed_resend_request() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_resend_verification_email(&serde_json::json!({ "mail": "test@example.com" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn should_return_400_if_resend_email_invalid() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_resend_verification_email(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn should_throttle_resends_without_saying_so() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    // Signup has only just sent the first email, so this one is skipped
+    let response = app
+        .post_resend_verification_email(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.emails_to(&random_email).await.len(), 1);
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_resends_for_one_email() {
+    let app = TestApp::with_rate_limits(RateLimitConfig {
+        per_ip: RateLimit { capacity: 100, period: Duration::from_secs(60) },
+        per_email: RateLimit { capacity: 3, period: Duration::from_secs(60) },
+    })
+    .await;
+    let email = get_random_email();
+
+    for _ in 0..3 {
+        let response = app.post_resend_verification_email(&serde_json::json!({ "email": email })).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_resend_verification_email(&serde_json::json!({ "email": email })).await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn should_not_reveal_whether_account_exists_on_resend() {
+    let app = TestApp::new().await;
+
+    let verified_email = get_random_email();
+    signup(&app, &verified_email).await;
+    app.verify_email(&verified_email).await;
+
+    for email in [verified_email, get_random_email()] {
+        let response = app
+            .post_resend_verification_email(&serde_json::json!({ "email": email }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200, "Failed for email: {}", email);
+    }
+}
@@ -1,4 +1,7 @@
-use auth_service::utils::constants::JWT_COOKIE_NAME;
+use auth_service::utils::{
+    auth::Claims,
+    constants::{JWT_AUDIENCE, JWT_COOKIE_NAME, JWT_ISSUER},
+};
 use reqwest::Url;
 
 use crate::helpers::{get_random_email, TestApp};
@@ -40,6 +43,7 @@ async fn should_return_200_valid_token() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -64,6 +68,18 @@ async fn should_return_200_valid_token() {
         }))
         .await;
     assert_eq!(response.status().as_u16(), 200);
+
+    let claims = response
+        .json::<Claims>()
+        .await
+        .expect("Could not deserialize response body to Claims");
+
+    assert_eq!(claims.sub, random_email);
+    assert_eq!(claims.iss, JWT_ISSUER.as_str());
+    assert_eq!(claims.aud, JWT_AUDIENCE.as_str());
+    assert_eq!(claims.roles, vec!["user".to_owned()]);
+    assert!(!claims.jti.is_empty());
+    assert!(claims.iat < claims.exp);
 }
 
 #[tokio::test]
@@ -81,6 +97,7 @@ async fn should_return_401_if_invalid_token() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -122,6 +139,7 @@ async fn should_return_401_if_banned_token() {
     let response = app.post_signup(&signup_body).await;
 
     assert_eq!(response.status().as_u16(), 201);
+    app.verify_email(&random_email).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -142,7 +160,7 @@ async fn should_return_401_if_banned_token() {
     let url = Url::parse(&app.address).expect("Failed to parse URL");
     
     app.cookie_jar.add_cookie_str(
-        &auth_cookie.value().to_string(),
+        auth_cookie.value(),
         &url,
     );
 
@@ -0,0 +1,368 @@
+use auth_service::{
+    routes::{LoginOptionsResponse, RegistrationOptionsResponse, TwoFactorAuthResponse},
+    utils::constants::{JWT_COOKIE_NAME, WEBAUTHN_ORIGIN},
+    ErrorResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+
+use crate::helpers::{email_code, get_random_email, TestApp};
+
+// Plays the browser and a security key holding one ES256 credential
+struct SoftwareAuthenticator {
+    key_pair: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+    origin: String,
+    // Whether the key checks a PIN or biometric before signing
+    user_verified: bool,
+}
+
+impl SoftwareAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        let mut credential_id = vec![0; 16];
+        rng.fill(&mut credential_id).unwrap();
+
+        Self {
+            key_pair,
+            credential_id,
+            sign_count: 0,
+            origin: WEBAUTHN_ORIGIN.clone(),
+            user_verified: true,
+        }
+    }
+
+    fn client_data(&self, ceremony: &str, challenge: &str) -> Vec<u8> {
+        serde_json::json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": self.origin,
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+        let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes()).as_ref().to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    // navigator.credentials.create()
+    fn register(&self, options: &RegistrationOptionsResponse) -> serde_json::Value {
+        let options = &options.public_key;
+        let point = self.key_pair.public_key().as_ref();
+        let cose_key = Value::Map(vec![
+            (1.into(), 2.into()),
+            (3.into(), (-7).into()),
+            ((-1).into(), 1.into()),
+            ((-2).into(), Value::Bytes(point[1..33].to_vec())),
+            ((-3).into(), Value::Bytes(point[33..].to_vec())),
+        ]);
+
+        // User present and verified, with attested credential data
+        let mut auth_data = self.authenticator_data(&options.rp.id, 0x45);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let attestation = Value::Map(vec![
+            ("fmt".into(), "none".into()),
+            ("attStmt".into(), Value::Map(vec![])),
+            ("authData".into(), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        serde_json::json!({
+            "id": id,
+            "rawId": id,
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", &options.challenge)),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+            },
+        })
+    }
+
+    // navigator.credentials.get()
+    fn login(&mut self, options: &LoginOptionsResponse) -> serde_json::Value {
+        let options = &options.public_key;
+        self.sign_count += 1;
+
+        // User present, and verified unless the key can't
+        let flags = if self.user_verified { 0x05 } else { 0x01 };
+        let auth_data = self.authenticator_data(&options.rp_id, flags);
+        let client_data = self.client_data("webauthn.get", &options.challenge);
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest::digest(&digest::SHA256, &client_data).as_ref());
+        let signature = self.key_pair.sign(&SystemRandom::new(), &signed).unwrap();
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        serde_json::json!({
+            "id": id,
+            "rawId": id,
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            },
+        })
+    }
+}
+
+// Signs up and logs in a user, leaving their JWT cookie in the app's cookie jar
+async fn signup_and_login(app: &TestApp, requires_2fa: bool) -> String {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires_2fa": requires_2fa
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    app.verify_email(&email).await;
+
+    let response = app.login(email.clone(), "password123".to_owned()).await;
+    if !requires_2fa {
+        assert_eq!(response.status().as_u16(), 200);
+        return email;
+    }
+
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let message = app
+        .last_email_to(&email, "Your login code")
+        .await
+        .expect("No 2FA code sent");
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": email_code(&message),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    email
+}
+
+async fn registration_options(app: &TestApp) -> RegistrationOptionsResponse {
+    let response = app.post_webauthn_register_start().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json()
+        .await
+        .expect("Could not deserialize response body to RegistrationOptionsResponse")
+}
+
+async fn login_options(app: &TestApp, email: &str) -> LoginOptionsResponse {
+    let response = app.post_webauthn_login_start(&serde_json::json!({ "email": email })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json()
+        .await
+        .expect("Could not deserialize response body to LoginOptionsResponse")
+}
+
+async fn register(app: &TestApp, authenticator: &SoftwareAuthenticator) {
+    let credential = authenticator.register(&registration_options(app).await);
+    let response = app.post_webauthn_register_finish(&credential).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        error
+    );
+}
+
+#[tokio::test]
+async fn should_require_login_to_register() {
+    let app = TestApp::new().await;
+
+    assert_error(app.post_webauthn_register_start().await, 400, "Missing token").await;
+}
+
+#[tokio::test]
+async fn should_register_and_log_in_with_a_passkey() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, false).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    let options = login_options(&app, &email).await;
+    assert_eq!(options.public_key.allow_credentials.len(), 1);
+    assert_eq!(
+        options.public_key.allow_credentials[0].id,
+        URL_SAFE_NO_PAD.encode(&authenticator.credential_id)
+    );
+
+    let response = app.post_webauthn_login_finish(&authenticator.login(&options)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+
+    // The registered credential is left out of the next registration
+    let options = registration_options(&app).await;
+    assert_eq!(options.public_key.exclude_credentials.len(), 1);
+}
+
+#[tokio::test]
+async fn should_not_ask_passkey_users_for_a_2fa_code() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, true).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    // The passkey is something the user has, so it covers the second factor
+    let options = login_options(&app, &email).await;
+    let response = app.post_webauthn_login_finish(&authenticator.login(&options)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.cookies().any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+}
+
+#[tokio::test]
+async fn should_return_409_if_credential_already_registered() {
+    let app = TestApp::new().await;
+    signup_and_login(&app, false).await;
+
+    let authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+
+    let credential = authenticator.register(&registration_options(&app).await);
+    assert_error(
+        app.post_webauthn_register_finish(&credential).await,
+        409,
+        "This passkey is already registered",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn should_reject_replayed_or_foreign_assertions() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, false).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    // Each challenge works once
+    let assertion = authenticator.login(&login_options(&app, &email).await);
+    assert_eq!(app.post_webauthn_login_finish(&assertion).await.status().as_u16(), 200);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Invalid token used").await;
+
+    // A key that was never registered
+    let mut stranger = SoftwareAuthenticator::new();
+    let assertion = stranger.login(&login_options(&app, &email).await);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Incorrect credentials").await;
+
+    // A phishing site relaying the challenge gets its own origin signed
+    authenticator.origin = "https://phishing.example.com".to_owned();
+    let assertion = authenticator.login(&login_options(&app, &email).await);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_reject_assertions_without_user_verification() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, false).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    let options = login_options(&app, &email).await;
+    assert_eq!(options.public_key.user_verification, "required");
+
+    // Whoever holds the key still has to unlock it
+    authenticator.user_verified = false;
+    let assertion = authenticator.login(&options);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_reject_cloned_authenticators() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, false).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    let assertion = authenticator.login(&login_options(&app, &email).await);
+    assert_eq!(app.post_webauthn_login_finish(&assertion).await.status().as_u16(), 200);
+
+    // A copy of the key still has the old counter
+    authenticator.sign_count = 0;
+    let assertion = authenticator.login(&login_options(&app, &email).await);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_not_offer_credentials_to_other_accounts() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app, false).await;
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.logout().await;
+
+    let options = login_options(&app, &get_random_email()).await;
+    assert!(options.public_key.allow_credentials.is_empty());
+
+    // A challenge issued for another account doesn't work with this user's passkey
+    let other_email = signup_and_login(&app, false).await;
+    register(&app, &SoftwareAuthenticator::new()).await;
+    app.logout().await;
+    let assertion = authenticator.login(&login_options(&app, &other_email).await);
+    assert_error(app.post_webauthn_login_finish(&assertion).await, 401, "Incorrect credentials").await;
+
+    let assertion = authenticator.login(&login_options(&app, &email).await);
+    assert_eq!(app.post_webauthn_login_finish(&assertion).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_malformed_credential() {
+    let app = TestApp::new().await;
+
+    let body = serde_json::json!({
+        "id": "not base64!",
+        "response": {
+            "clientDataJSON": "e30",
+            "authenticatorData": "",
+            "signature": "",
+        },
+    });
+    assert_error(app.post_webauthn_login_finish(&body).await, 400, "Invalid Input").await;
+}